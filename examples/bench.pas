@@ -0,0 +1,21 @@
+{ A loop-heavy program to compare the code generated at -O0 and -O1 }
+var
+    i, j, x, total: integer;
+    high: boolean;
+
+begin
+    x := 0;
+    total := 0;
+    for i := 1 to 10000 do
+        for j := 1 to 10000 do begin
+            x := x + i + j;
+            if x > 1000000 then
+                x := x - 1000000;
+            high := x > 500000;
+            if high then
+                total := total + x
+            else
+                total := total - j;
+        end;
+    writeln('The total is: ', total);
+end
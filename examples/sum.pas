@@ -0,0 +1,10 @@
+var
+    a, b: integer;
+    result: integer;
+
+begin
+    a := 10;
+    b := 20;
+    result := a + b;
+    writeln('The result is: ', result);
+end
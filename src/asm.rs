@@ -0,0 +1,499 @@
+//! A model of the x86-64 assembly that code generation produces.
+//!
+//! An [`Assembly`] is the data of a program and its code as a list of
+//! [`Instr`]. Only the instructions and operand forms the compiler uses are
+//! modelled, all on 64-bit values apart from a few byte operations in the
+//! print routines. Passes can analyse and rewrite the code, and printers turn
+//! it into source for an assembler: [`Assembly::to_nasm`] gives nasm syntax
+//! and [`Assembly::to_gas`] the AT&T syntax of the GNU assembler, while
+//! [`encode`] turns it into machine code directly.
+
+pub mod encode;
+mod gas;
+
+use std::fmt;
+
+use crate::ir::BlockId;
+
+/// The assemblers the code can be printed for, as chosen by `--asm-syntax`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    #[default]
+    Nasm,
+    Gas,
+}
+
+/// The general purpose registers, in the order of their encoding numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Register {
+    Rax,
+    Rcx,
+    Rdx,
+    Rbx,
+    Rsp,
+    Rbp,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+impl Register {
+    /// The number that identifies the register in an instruction encoding.
+    pub fn number(self) -> u8 {
+        self as u8
+    }
+
+    pub fn name(self) -> &'static str {
+        const NAMES: [&str; 16] = [
+            "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13", "r14",
+            "r15",
+        ];
+        NAMES[self as usize]
+    }
+
+    /// The name of the register's low byte.
+    pub fn byte_name(self) -> &'static str {
+        const NAMES: [&str; 16] = [
+            "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b", "r12b", "r13b", "r14b",
+            "r15b",
+        ];
+        NAMES[self as usize]
+    }
+}
+
+/// The SSE registers, by number, which hold the doubles of real arithmetic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Xmm(pub u8);
+
+/// Something an instruction can refer to by address.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Label {
+    /// The code of a block of the IR.
+    Block(BlockId),
+    /// A string literal, by its index in the program.
+    String(usize),
+    /// The storage of a variable of the source program, written with a
+    /// `var_` prefix so names like `rax` or `newline` are not taken for a
+    /// register or a runtime label.
+    Variable(String),
+    /// The entry point and the routines and data of the print runtime.
+    Named(&'static str),
+}
+
+/// What a memory operand is addressed from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Base {
+    Register(Register),
+    Label(Label),
+}
+
+/// The address `base + displacement`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Memory {
+    pub base: Base,
+    pub displacement: i32,
+}
+
+impl Memory {
+    pub fn label(label: Label) -> Memory {
+        Memory { base: Base::Label(label), displacement: 0 }
+    }
+
+    pub fn register(register: Register, displacement: i32) -> Memory {
+        Memory { base: Base::Register(register), displacement }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Operand {
+    Register(Register),
+    /// The low byte of a register, as in `al`.
+    Byte(Register),
+    Immediate(i64),
+    /// The 64-bit value at an address.
+    Memory(Memory),
+    /// The byte at an address.
+    ByteMemory(Memory),
+    /// The address of a label itself, as in `mov rsi, string_0`.
+    Address(Label),
+}
+
+impl Operand {
+    /// Whether the operand is `register` or reads it to form an address.
+    pub fn uses(&self, register: Register) -> bool {
+        match self {
+            Operand::Register(used) | Operand::Byte(used) => *used == register,
+            Operand::Memory(memory) | Operand::ByteMemory(memory) => memory.base == Base::Register(register),
+            Operand::Immediate(_) | Operand::Address(_) => false,
+        }
+    }
+}
+
+/// The operations that share the encoding of `add`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AluOp {
+    Add,
+    Or,
+    And,
+    Sub,
+    Xor,
+    Cmp,
+}
+
+/// The scalar double operations of SSE2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SseOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShiftOp {
+    Shl,
+    Sar,
+}
+
+/// The flag conditions of conditional jumps, sets and moves. Zero and Equal
+/// are the same condition under two names, as are NotZero and NotEqual.
+/// Above and AboveEqual are the unsigned conditions that `ucomisd` sets, and
+/// Parity is set when a comparison of doubles is unordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Condition {
+    Zero,
+    NotZero,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Sign,
+    NotSign,
+    Above,
+    AboveEqual,
+    Parity,
+    NotParity,
+}
+
+impl Condition {
+    /// The suffix of the condition in mnemonics such as `jle`.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Condition::Zero => "z",
+            Condition::NotZero => "nz",
+            Condition::Equal => "e",
+            Condition::NotEqual => "ne",
+            Condition::Less => "l",
+            Condition::LessEqual => "le",
+            Condition::Greater => "g",
+            Condition::GreaterEqual => "ge",
+            Condition::Sign => "s",
+            Condition::NotSign => "ns",
+            Condition::Above => "a",
+            Condition::AboveEqual => "ae",
+            Condition::Parity => "p",
+            Condition::NotParity => "np",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Instr {
+    /// Marks the position of the next instruction.
+    Label(Label),
+    Mov(Operand, Operand),
+    /// Zero-extends the low byte of the second register into the first.
+    Movzx(Register, Register),
+    Lea(Register, Memory),
+    Alu(AluOp, Operand, Operand),
+    Imul(Register, Operand),
+    /// Shifts by a constant count, or by CL when there is none.
+    Shift(ShiftOp, Register, Option<u8>),
+    Neg(Register),
+    Dec(Register),
+    Test(Register, Register),
+    /// Sign-extends RAX into RDX:RAX.
+    Cqo,
+    /// Signed division of RDX:RAX, leaving the quotient in RAX and the
+    /// remainder in RDX.
+    Idiv(Register),
+    /// The unsigned division of RDX:RAX.
+    Div(Register),
+    /// Sets the low byte of the register to 1 if the condition holds, else 0.
+    Set(Condition, Register),
+    Cmov(Condition, Register, Register),
+    Jmp(Label),
+    Jcc(Condition, Label),
+    Call(Label),
+    Ret,
+    Syscall,
+    Push(Register),
+    Pop(Register),
+    /// Copies the bits of a register into the low half of an SSE register.
+    MovqToXmm(Xmm, Register),
+    /// Copies the low half of an SSE register into a register.
+    MovqFromXmm(Register, Xmm),
+    Sse(SseOp, Xmm, Xmm),
+    /// Compares two doubles, setting the flags like an unsigned comparison
+    /// of the first with the second, or ZF, PF and CF when either is NaN.
+    Ucomisd(Xmm, Xmm),
+    /// Converts the signed integer in a register to a double.
+    Cvtsi2sd(Xmm, Register),
+    /// Converts a double to a signed integer, truncating toward zero.
+    Cvttsd2si(Register, Xmm),
+}
+
+/// A named piece of initialised data, or of zeroed memory in .bss.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Data {
+    Quad(Label, i64),
+    Bytes(Label, Vec<u8>),
+    Reserve(Label, usize),
+}
+
+/// A whole program: its data and its code, entered at `_start` at the top.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Assembly {
+    /// Initialised data, in .data.
+    pub data: Vec<Data>,
+    /// Zeroed data, in .bss.
+    pub bss: Vec<Data>,
+    pub text: Vec<Instr>,
+}
+
+impl Assembly {
+    /// The program as source for the assembler of `syntax`.
+    pub fn to_source(&self, syntax: Syntax) -> String {
+        match syntax {
+            Syntax::Nasm => self.to_nasm(),
+            Syntax::Gas => self.to_gas(),
+        }
+    }
+
+    /// The program as nasm source for x86-64 Linux.
+    pub fn to_nasm(&self) -> String {
+        let mut source = String::from("section .data\n");
+        for data in &self.data {
+            source.push_str(&format!("{}\n", data));
+        }
+        source.push_str("section .bss\n");
+        for data in &self.bss {
+            source.push_str(&format!("{}\n", data));
+        }
+        source.push_str("section .text\nglobal _start\n");
+        for instr in &self.text {
+            source.push_str(&format!("{}\n", instr));
+        }
+        source
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for Xmm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "xmm{}", self.0)
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Label::Block(block) => write!(f, "block_{}", block.0),
+            Label::String(index) => write!(f, "string_{}", index),
+            Label::Variable(name) => write!(f, "var_{}", name),
+            Label::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl fmt::Display for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.base {
+            Base::Register(register) => write!(f, "[{}", register)?,
+            Base::Label(label) => write!(f, "[{}", label)?,
+        }
+        match self.displacement {
+            0 => write!(f, "]"),
+            displacement if displacement < 0 => write!(f, " - {}]", -(displacement as i64)),
+            displacement => write!(f, " + {}]", displacement),
+        }
+    }
+}
+
+// The operands are printed in nasm syntax.
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Register(register) => write!(f, "{}", register),
+            Operand::Byte(register) => write!(f, "{}", register.byte_name()),
+            Operand::Immediate(value) => write!(f, "{}", value),
+            Operand::Memory(memory) => write!(f, "{}", memory),
+            Operand::ByteMemory(memory) => write!(f, "byte {}", memory),
+            Operand::Address(label) => write!(f, "{}", label),
+        }
+    }
+}
+
+impl AluOp {
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            AluOp::Add => "add",
+            AluOp::Or => "or",
+            AluOp::And => "and",
+            AluOp::Sub => "sub",
+            AluOp::Xor => "xor",
+            AluOp::Cmp => "cmp",
+        }
+    }
+}
+
+impl SseOp {
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            SseOp::Add => "addsd",
+            SseOp::Sub => "subsd",
+            SseOp::Mul => "mulsd",
+            SseOp::Div => "divsd",
+        }
+    }
+}
+
+impl ShiftOp {
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            ShiftOp::Shl => "shl",
+            ShiftOp::Sar => "sar",
+        }
+    }
+}
+
+// Instructions are printed in nasm syntax.
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instr::Label(label) => write!(f, "{}:", label),
+            Instr::Mov(dest, source) => write!(f, "mov {}, {}", dest, source),
+            Instr::Movzx(dest, source) => write!(f, "movzx {}, {}", dest, source.byte_name()),
+            Instr::Lea(dest, memory) => write!(f, "lea {}, {}", dest, memory),
+            Instr::Alu(operation, dest, source) => write!(f, "{} {}, {}", operation.mnemonic(), dest, source),
+            Instr::Imul(dest, source) => write!(f, "imul {}, {}", dest, source),
+            Instr::Shift(operation, dest, Some(count)) => write!(f, "{} {}, {}", operation.mnemonic(), dest, count),
+            Instr::Shift(operation, dest, None) => write!(f, "{} {}, cl", operation.mnemonic(), dest),
+            Instr::Neg(register) => write!(f, "neg {}", register),
+            Instr::Dec(register) => write!(f, "dec {}", register),
+            Instr::Test(left, right) => write!(f, "test {}, {}", left, right),
+            Instr::Cqo => write!(f, "cqo"),
+            Instr::Idiv(divisor) => write!(f, "idiv {}", divisor),
+            Instr::Div(divisor) => write!(f, "div {}", divisor),
+            Instr::Set(condition, register) => write!(f, "set{} {}", condition.suffix(), register.byte_name()),
+            Instr::Cmov(condition, dest, source) => write!(f, "cmov{} {}, {}", condition.suffix(), dest, source),
+            Instr::Jmp(label) => write!(f, "jmp {}", label),
+            Instr::Jcc(condition, label) => write!(f, "j{} {}", condition.suffix(), label),
+            Instr::Call(label) => write!(f, "call {}", label),
+            Instr::Ret => write!(f, "ret"),
+            Instr::Syscall => write!(f, "syscall"),
+            Instr::Push(register) => write!(f, "push {}", register),
+            Instr::Pop(register) => write!(f, "pop {}", register),
+            Instr::MovqToXmm(dest, source) => write!(f, "movq {}, {}", dest, source),
+            Instr::MovqFromXmm(dest, source) => write!(f, "movq {}, {}", dest, source),
+            Instr::Sse(operation, dest, source) => write!(f, "{} {}, {}", operation.mnemonic(), dest, source),
+            Instr::Ucomisd(left, right) => write!(f, "ucomisd {}, {}", left, right),
+            Instr::Cvtsi2sd(dest, source) => write!(f, "cvtsi2sd {}, {}", dest, source),
+            Instr::Cvttsd2si(dest, source) => write!(f, "cvttsd2si {}, {}", dest, source),
+        }
+    }
+}
+
+impl fmt::Display for Data {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Data::Quad(label, value) => write!(f, "{} dq {}", label, value),
+            // A label alone for an empty string
+            Data::Bytes(label, bytes) if bytes.is_empty() => write!(f, "{}:", label),
+            Data::Bytes(label, bytes) => {
+                let bytes: Vec<String> = bytes.iter().map(|byte| byte.to_string()).collect();
+                write!(f, "{} db {}", label, bytes.join(", "))
+            }
+            Data::Reserve(label, size) => write!(f, "{} resb {}", label, size),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_print_nasm() {
+        let assembly = Assembly {
+            data: vec![Data::Quad(Label::Variable("x".to_string()), 0), Data::Bytes(Label::String(0), b"hi".to_vec())],
+            bss: vec![Data::Reserve(Label::Named("int_buffer"), 20)],
+            text: vec![
+                Instr::Label(Label::Named("_start")),
+                Instr::Mov(
+                    Operand::Register(Register::Rax),
+                    Operand::Memory(Memory::label(Label::Variable("x".to_string()))),
+                ),
+                Instr::Mov(Operand::Memory(Memory::register(Register::Rbp, -16)), Operand::Register(Register::R12)),
+                Instr::Lea(Register::Rsi, Memory { base: Base::Label(Label::Named("int_buffer")), displacement: 20 }),
+                Instr::Mov(Operand::ByteMemory(Memory::register(Register::Rsi, 0)), Operand::Byte(Register::Rdx)),
+                Instr::Alu(AluOp::Cmp, Operand::Register(Register::Rax), Operand::Immediate(-1)),
+                Instr::Set(Condition::LessEqual, Register::Rax),
+                Instr::Movzx(Register::Rax, Register::Rax),
+                Instr::Shift(ShiftOp::Sar, Register::R9, None),
+                Instr::Jcc(Condition::NotZero, Label::Block(BlockId(3))),
+                Instr::Mov(Operand::Register(Register::Rsi), Operand::Address(Label::String(0))),
+                Instr::MovqToXmm(Xmm(1), Register::Rbx),
+                Instr::Sse(SseOp::Div, Xmm(0), Xmm(1)),
+                Instr::Ucomisd(Xmm(1), Xmm(0)),
+                Instr::Set(Condition::NotParity, Register::Rcx),
+                Instr::Cvtsi2sd(Xmm(0), Register::Rax),
+                Instr::Cvttsd2si(Register::Rax, Xmm(0)),
+                Instr::MovqFromXmm(Register::Rax, Xmm(0)),
+            ],
+        };
+
+        let expected = "\
+section .data
+var_x dq 0
+string_0 db 104, 105
+section .bss
+int_buffer resb 20
+section .text
+global _start
+_start:
+mov rax, [var_x]
+mov [rbp - 16], r12
+lea rsi, [int_buffer + 20]
+mov byte [rsi], dl
+cmp rax, -1
+setle al
+movzx rax, al
+sar r9, cl
+jnz block_3
+mov rsi, string_0
+movq xmm1, rbx
+divsd xmm0, xmm1
+ucomisd xmm1, xmm0
+setnp cl
+cvtsi2sd xmm0, rax
+cvttsd2si rax, xmm0
+movq rax, xmm0
+";
+        assert_eq!(assembly.to_nasm(), expected);
+    }
+}
@@ -0,0 +1,465 @@
+//! Machine code for an [`Assembly`].
+//!
+//! Every instruction is encoded in a fixed form: jumps and calls always take a
+//! 32-bit displacement, and labels in .data and .bss are addressed relative
+//! to RIP, so the code works wherever the sections are placed. Those
+//! addresses are left as [`Relocation`]s for the ELF writer to fill in;
+//! jumps within the code are resolved here.
+
+use std::collections::HashMap;
+
+use super::{AluOp, Assembly, Base, Condition, Data, Instr, Label, Memory, Operand, Register, ShiftOp, SseOp, Xmm};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Section {
+    Text,
+    Data,
+    Bss,
+}
+
+/// A 32-bit field at `offset` in the code that must hold the distance from
+/// the end of the field to `addend` bytes into `section`, like
+/// `R_X86_64_PC32` against the section's symbol.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    pub offset: usize,
+    pub section: Section,
+    pub addend: i64,
+}
+
+/// The encoded sections of a program and where its labels are in them.
+#[derive(Debug, Clone, Default)]
+pub struct MachineCode {
+    pub text: Vec<u8>,
+    pub data: Vec<u8>,
+    pub bss_size: usize,
+    pub labels: HashMap<Label, (Section, usize)>,
+    pub relocations: Vec<Relocation>,
+}
+
+/// Encodes `assembly`. Panics on an operand combination that x86-64 has no
+/// instruction for, which code generation never produces.
+pub fn encode(assembly: &Assembly) -> MachineCode {
+    let mut code = MachineCode::default();
+    for item in &assembly.data {
+        match item {
+            Data::Quad(label, value) => {
+                code.data.resize(code.data.len().next_multiple_of(8), 0);
+                code.labels.insert(label.clone(), (Section::Data, code.data.len()));
+                code.data.extend(value.to_le_bytes());
+            }
+            Data::Bytes(label, bytes) => {
+                code.labels.insert(label.clone(), (Section::Data, code.data.len()));
+                code.data.extend(bytes);
+            }
+            Data::Reserve(..) => unreachable!("reserved space belongs in .bss"),
+        }
+    }
+    for item in &assembly.bss {
+        match item {
+            Data::Reserve(label, size) => {
+                code.labels.insert(label.clone(), (Section::Bss, code.bss_size));
+                code.bss_size += size;
+            }
+            _ => unreachable!("only reserved space belongs in .bss"),
+        }
+    }
+
+    // Addresses of labels as (field offset, label, addend) for the end of the
+    // field, resolved once every label in the code is known
+    let mut encoder = Encoder { bytes: Vec::new(), fixups: Vec::new() };
+    for instr in &assembly.text {
+        match instr {
+            Instr::Label(label) => {
+                code.labels.insert(label.clone(), (Section::Text, encoder.bytes.len()));
+            }
+            instr => encoder.instruction(instr),
+        }
+    }
+    for (offset, label, addend) in encoder.fixups {
+        let (section, position) = code.labels[&label];
+        if section == Section::Text {
+            let distance = position as i64 + addend - (offset as i64 + 4);
+            encoder.bytes[offset..offset + 4].copy_from_slice(&(distance as i32).to_le_bytes());
+        } else {
+            // Relative to the start of the field, as the linker sees it
+            code.relocations.push(Relocation { offset, section, addend: position as i64 + addend - 4 });
+        }
+    }
+    code.text = encoder.bytes;
+    code
+}
+
+// The operand in the r/m field of the ModRM byte.
+enum Rm<'a> {
+    Register(Register),
+    Memory(&'a Memory),
+}
+
+struct Encoder {
+    bytes: Vec<u8>,
+    fixups: Vec<(usize, Label, i64)>,
+}
+
+impl Encoder {
+    fn instruction(&mut self, instr: &Instr) {
+        use Operand::{Byte, ByteMemory, Immediate, Memory as Mem, Register as Reg};
+
+        match instr {
+            Instr::Label(_) => {}
+            Instr::Mov(Reg(dest), Reg(source)) => self.op(true, &[0x89], *source as u8, Rm::Register(*dest), &[]),
+            Instr::Mov(Reg(dest), Mem(source)) => self.op(true, &[0x8b], *dest as u8, Rm::Memory(source), &[]),
+            Instr::Mov(Mem(dest), Reg(source)) => self.op(true, &[0x89], *source as u8, Rm::Memory(dest), &[]),
+            Instr::Mov(Reg(dest), Immediate(value)) if let Ok(value) = i32::try_from(*value) => {
+                self.op(true, &[0xc7], 0, Rm::Register(*dest), &value.to_le_bytes())
+            }
+            Instr::Mov(Reg(dest), Immediate(value)) => {
+                self.rex(true, 0, *dest as u8, false);
+                self.bytes.push(0xb8 + (*dest as u8 & 7));
+                self.bytes.extend(value.to_le_bytes());
+            }
+            Instr::Mov(Mem(dest), Immediate(value)) => {
+                self.op(true, &[0xc7], 0, Rm::Memory(dest), &immediate_32(*value))
+            }
+            // The address of a label is formed as lea relative to RIP
+            Instr::Mov(Reg(dest), Operand::Address(label)) => {
+                self.op(true, &[0x8d], *dest as u8, Rm::Memory(&Memory::label(label.clone())), &[])
+            }
+            Instr::Mov(Byte(dest), Byte(source)) => {
+                self.op_byte(&[0x88], *source as u8, &[*source, *dest], Rm::Register(*dest), &[])
+            }
+            Instr::Mov(ByteMemory(dest), Byte(source)) => {
+                self.op_byte(&[0x88], *source as u8, &[*source], Rm::Memory(dest), &[])
+            }
+            Instr::Mov(Byte(dest), ByteMemory(source)) => {
+                self.op_byte(&[0x8a], *dest as u8, &[*dest], Rm::Memory(source), &[])
+            }
+            Instr::Mov(ByteMemory(dest), Immediate(value)) => {
+                self.op(false, &[0xc6], 0, Rm::Memory(dest), &[*value as u8])
+            }
+            Instr::Mov(Byte(dest), Immediate(value)) => {
+                self.op_byte(&[0xc6], 0, &[*dest], Rm::Register(*dest), &[*value as u8])
+            }
+            Instr::Movzx(dest, source) => self.op(true, &[0x0f, 0xb6], *dest as u8, Rm::Register(*source), &[]),
+            Instr::Lea(dest, address) => self.op(true, &[0x8d], *dest as u8, Rm::Memory(address), &[]),
+            Instr::Alu(operation, dest, source) => {
+                let base = alu_extension(*operation) << 3;
+                match (dest, source) {
+                    (Reg(dest), Reg(source)) => self.op(true, &[base + 1], *source as u8, Rm::Register(*dest), &[]),
+                    (Mem(dest), Reg(source)) => self.op(true, &[base + 1], *source as u8, Rm::Memory(dest), &[]),
+                    (Reg(dest), Mem(source)) => self.op(true, &[base + 3], *dest as u8, Rm::Memory(source), &[]),
+                    (Reg(dest), Immediate(value)) => self.alu_immediate(base >> 3, Rm::Register(*dest), *value),
+                    (Mem(dest), Immediate(value)) => self.alu_immediate(base >> 3, Rm::Memory(dest), *value),
+                    (Byte(dest), Byte(source)) => {
+                        self.op_byte(&[base], *source as u8, &[*source, *dest], Rm::Register(*dest), &[])
+                    }
+                    (Byte(dest), Immediate(value)) => {
+                        self.op_byte(&[0x80], base >> 3, &[*dest], Rm::Register(*dest), &[*value as u8])
+                    }
+                    (ByteMemory(dest), Immediate(value)) => {
+                        self.op(false, &[0x80], base >> 3, Rm::Memory(dest), &[*value as u8])
+                    }
+                    _ => panic!("cannot encode {}", instr),
+                }
+            }
+            Instr::Imul(dest, Reg(source)) => self.op(true, &[0x0f, 0xaf], *dest as u8, Rm::Register(*source), &[]),
+            Instr::Imul(dest, Mem(source)) => self.op(true, &[0x0f, 0xaf], *dest as u8, Rm::Memory(source), &[]),
+            Instr::Imul(dest, Immediate(value)) => match i8::try_from(*value) {
+                Ok(value) => self.op(true, &[0x6b], *dest as u8, Rm::Register(*dest), &[value as u8]),
+                Err(_) => self.op(true, &[0x69], *dest as u8, Rm::Register(*dest), &immediate_32(*value)),
+            },
+            Instr::Shift(operation, dest, count) => {
+                let extension = match operation {
+                    ShiftOp::Shl => 4,
+                    ShiftOp::Sar => 7,
+                };
+                match count {
+                    Some(count) => self.op(true, &[0xc1], extension, Rm::Register(*dest), &[*count]),
+                    None => self.op(true, &[0xd3], extension, Rm::Register(*dest), &[]),
+                }
+            }
+            Instr::Neg(register) => self.op(true, &[0xf7], 3, Rm::Register(*register), &[]),
+            Instr::Dec(register) => self.op(true, &[0xff], 1, Rm::Register(*register), &[]),
+            Instr::Test(left, right) => self.op(true, &[0x85], *right as u8, Rm::Register(*left), &[]),
+            Instr::Cqo => self.bytes.extend([0x48, 0x99]),
+            Instr::Idiv(divisor) => self.op(true, &[0xf7], 7, Rm::Register(*divisor), &[]),
+            Instr::Div(divisor) => self.op(true, &[0xf7], 6, Rm::Register(*divisor), &[]),
+            Instr::Set(condition, dest) => {
+                self.op_byte(&[0x0f, 0x90 + code(*condition)], 0, &[*dest], Rm::Register(*dest), &[])
+            }
+            Instr::Cmov(condition, dest, source) => {
+                self.op(true, &[0x0f, 0x40 + code(*condition)], *dest as u8, Rm::Register(*source), &[])
+            }
+            Instr::Jmp(label) => self.relative(&[0xe9], label),
+            Instr::Jcc(condition, label) => self.relative(&[0x0f, 0x80 + code(*condition)], label),
+            Instr::Call(label) => self.relative(&[0xe8], label),
+            Instr::Ret => self.bytes.push(0xc3),
+            Instr::Syscall => self.bytes.extend([0x0f, 0x05]),
+            Instr::Push(register) => {
+                self.rex(false, 0, *register as u8, false);
+                self.bytes.push(0x50 + (*register as u8 & 7));
+            }
+            Instr::Pop(register) => {
+                self.rex(false, 0, *register as u8, false);
+                self.bytes.push(0x58 + (*register as u8 & 7));
+            }
+            Instr::MovqToXmm(dest, source) => self.sse(0x66, true, 0x6e, dest.0, *source as u8),
+            Instr::MovqFromXmm(dest, source) => self.sse(0x66, true, 0x7e, source.0, *dest as u8),
+            Instr::Sse(operation, dest, source) => {
+                let opcode = match operation {
+                    SseOp::Add => 0x58,
+                    SseOp::Mul => 0x59,
+                    SseOp::Sub => 0x5c,
+                    SseOp::Div => 0x5e,
+                };
+                self.sse(0xf2, false, opcode, dest.0, source.0)
+            }
+            Instr::Ucomisd(left, right) => self.sse(0x66, false, 0x2e, left.0, right.0),
+            Instr::Cvtsi2sd(dest, source) => self.sse(0xf2, true, 0x2a, dest.0, *source as u8),
+            Instr::Cvttsd2si(dest, Xmm(source)) => self.sse(0xf2, true, 0x2c, *dest as u8, *source),
+            _ => panic!("cannot encode {}", instr),
+        }
+    }
+
+    // An SSE instruction between registers: the mandatory prefix comes
+    // before any REX prefix, and the opcode follows the 0F escape.
+    fn sse(&mut self, prefix: u8, wide: bool, opcode: u8, reg: u8, rm: u8) {
+        self.bytes.push(prefix);
+        self.rex(wide, reg, rm, false);
+        self.bytes.extend([0x0f, opcode, 0xc0 | (reg & 7) << 3 | (rm & 7)]);
+    }
+
+    // Emits a REX prefix if the operand size is 64 bits, a register number
+    // needs its fourth bit, or `force` is set.
+    fn rex(&mut self, wide: bool, reg: u8, base: u8, force: bool) {
+        let rex = 0x40 | (wide as u8) << 3 | (reg >> 3) << 2 | base >> 3;
+        if rex != 0x40 || force {
+            self.bytes.push(rex);
+        }
+    }
+
+    // An instruction with a ModRM byte: `reg` is a register number or an
+    // opcode extension, and `immediate` follows the address.
+    fn op(&mut self, wide: bool, opcode: &[u8], reg: u8, rm: Rm, immediate: &[u8]) {
+        self.op_with_rex(wide, false, opcode, reg, rm, immediate);
+    }
+
+    // Like `op` with a byte register in `registers`, the byte registers that
+    // appear in the instruction. SPL, BPL, SIL and DIL are only reachable
+    // with a REX prefix; without one their numbers mean AH, CH, DH and BH.
+    fn op_byte(&mut self, opcode: &[u8], reg: u8, registers: &[Register], rm: Rm, immediate: &[u8]) {
+        let force = registers.iter().any(|register| (4..8).contains(&(*register as u8)));
+        self.op_with_rex(false, force, opcode, reg, rm, immediate);
+    }
+
+    fn op_with_rex(&mut self, wide: bool, force: bool, opcode: &[u8], reg: u8, rm: Rm, immediate: &[u8]) {
+        let base = match &rm {
+            Rm::Register(register) => *register as u8,
+            Rm::Memory(Memory { base: Base::Register(register), .. }) => *register as u8,
+            Rm::Memory(_) => 0,
+        };
+        self.rex(wide, reg, base, force);
+        self.bytes.extend(opcode);
+        self.modrm(reg, rm, immediate.len());
+        self.bytes.extend(immediate);
+    }
+
+    fn modrm(&mut self, reg: u8, rm: Rm, immediate_size: usize) {
+        let reg = (reg & 7) << 3;
+        match rm {
+            Rm::Register(register) => self.bytes.push(0xc0 | reg | (register as u8 & 7)),
+            Rm::Memory(Memory { base: Base::Register(base), displacement }) => {
+                let low = *base as u8 & 7;
+                // RBP and R13 have no form without a displacement
+                let mode = match *displacement {
+                    0 if low != 5 => 0x00,
+                    displacement if i8::try_from(displacement).is_ok() => 0x40,
+                    _ => 0x80,
+                };
+                self.bytes.push(mode | reg | low);
+                // RSP and R12 are only reachable through a SIB byte
+                if low == 4 {
+                    self.bytes.push(0x24);
+                }
+                match mode {
+                    0x40 => self.bytes.push(*displacement as u8),
+                    0x80 => self.bytes.extend(displacement.to_le_bytes()),
+                    _ => {}
+                }
+            }
+            Rm::Memory(Memory { base: Base::Label(label), displacement }) => {
+                self.bytes.push(0x05 | reg);
+                // RIP points past the immediate when the address is used
+                let field = self.bytes.len();
+                self.fixups.push((field, label.clone(), *displacement as i64 - immediate_size as i64));
+                self.bytes.extend([0; 4]);
+            }
+        }
+    }
+
+    // The short form takes an immediate that fits in a signed byte.
+    fn alu_immediate(&mut self, extension: u8, rm: Rm, value: i64) {
+        match i8::try_from(value) {
+            Ok(value) => self.op(true, &[0x83], extension, rm, &[value as u8]),
+            Err(_) => self.op(true, &[0x81], extension, rm, &immediate_32(value)),
+        }
+    }
+
+    fn relative(&mut self, opcode: &[u8], label: &Label) {
+        self.bytes.extend(opcode);
+        self.fixups.push((self.bytes.len(), label.clone(), 0));
+        self.bytes.extend([0; 4]);
+    }
+}
+
+fn immediate_32(value: i64) -> [u8; 4] {
+    i32::try_from(value).expect("immediate out of range").to_le_bytes()
+}
+
+fn alu_extension(operation: AluOp) -> u8 {
+    match operation {
+        AluOp::Add => 0,
+        AluOp::Or => 1,
+        AluOp::And => 4,
+        AluOp::Sub => 5,
+        AluOp::Xor => 6,
+        AluOp::Cmp => 7,
+    }
+}
+
+fn code(condition: Condition) -> u8 {
+    match condition {
+        Condition::AboveEqual => 0x3,
+        Condition::Zero | Condition::Equal => 0x4,
+        Condition::NotZero | Condition::NotEqual => 0x5,
+        Condition::Above => 0x7,
+        Condition::Sign => 0x8,
+        Condition::NotSign => 0x9,
+        Condition::Parity => 0xa,
+        Condition::NotParity => 0xb,
+        Condition::Less => 0xc,
+        Condition::GreaterEqual => 0xd,
+        Condition::LessEqual => 0xe,
+        Condition::Greater => 0xf,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Register::*;
+
+    fn text(code: &MachineCode) -> Vec<String> {
+        code.text.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn test_encode_instructions() {
+        let register = Operand::Register;
+        let memory = |base, displacement| Operand::Memory(Memory::register(base, displacement));
+        let instructions = vec![
+            Instr::Mov(register(Rax), register(Rbx)),
+            Instr::Mov(register(Rax), memory(Rbp, -16)),
+            Instr::Mov(memory(Rsp, 8), register(Rax)),
+            Instr::Mov(register(R13), memory(R13, 0)),
+            Instr::Mov(register(R12), memory(R12, -1000)),
+            Instr::Mov(register(Rax), Operand::Immediate(60)),
+            Instr::Mov(register(Rbx), Operand::Immediate(1 << 40)),
+            Instr::Mov(Operand::ByteMemory(Memory::register(Rsi, 0)), Operand::Byte(Rdx)),
+            Instr::Mov(Operand::ByteMemory(Memory::register(Rsi, 0)), Operand::Immediate(45)),
+            Instr::Mov(Operand::Byte(Rsi), Operand::Byte(Rax)),
+            Instr::Alu(AluOp::Add, register(R9), Operand::Immediate(1)),
+            Instr::Alu(AluOp::Sub, register(Rsp), Operand::Immediate(1024)),
+            Instr::Alu(AluOp::Cmp, register(Rax), Operand::Immediate(-1)),
+            Instr::Alu(AluOp::Xor, register(R15), register(R15)),
+            Instr::Alu(AluOp::Add, Operand::Byte(Rdx), Operand::Immediate(48)),
+            Instr::Imul(Rax, register(Rbx)),
+            Instr::Imul(R10, Operand::Immediate(7)),
+            Instr::Shift(ShiftOp::Shl, Rax, Some(3)),
+            Instr::Shift(ShiftOp::Sar, R9, None),
+            Instr::Neg(Rax),
+            Instr::Dec(Rsi),
+            Instr::Test(R12, R12),
+            Instr::Cqo,
+            Instr::Idiv(Rbx),
+            Instr::Div(R8),
+            Instr::Set(Condition::LessEqual, Rax),
+            Instr::Set(Condition::Greater, Rdi),
+            Instr::Movzx(Rax, Rax),
+            Instr::Cmov(Condition::Zero, Rdx, Rax),
+            Instr::Push(R12),
+            Instr::Pop(Rbx),
+            Instr::Syscall,
+            Instr::Ret,
+        ];
+        let code = encode(&Assembly { text: instructions, ..Assembly::default() });
+
+        // As GNU as encodes them
+        let expected = "48 89 d8 48 8b 45 f0 48 89 44 24 08 4d 8b 6d 00 4d 8b a4 24 18 fc ff ff 48 c7 c0 3c 00 00 00 \
+                        48 bb 00 00 00 00 00 01 00 00 88 16 c6 06 2d 40 88 c6 49 83 c1 01 48 81 ec 00 04 00 00 \
+                        48 83 f8 ff 4d 31 ff 80 c2 30 48 0f af c3 4d 6b d2 07 48 c1 e0 03 49 d3 f9 48 f7 d8 \
+                        48 ff ce 4d 85 e4 48 99 48 f7 fb 49 f7 f0 0f 9e c0 40 0f 9f c7 48 0f b6 c0 48 0f 44 d0 \
+                        41 54 5b 0f 05 c3";
+        assert_eq!(text(&code).join(" "), expected);
+        assert!(code.relocations.is_empty());
+    }
+
+    #[test]
+    fn test_encode_sse_instructions() {
+        let instructions = vec![
+            Instr::MovqToXmm(Xmm(0), Rax),
+            Instr::MovqToXmm(Xmm(9), R10),
+            Instr::MovqFromXmm(Rbx, Xmm(1)),
+            Instr::MovqFromXmm(R11, Xmm(8)),
+            Instr::Sse(SseOp::Add, Xmm(0), Xmm(1)),
+            Instr::Sse(SseOp::Sub, Xmm(2), Xmm(10)),
+            Instr::Sse(SseOp::Mul, Xmm(9), Xmm(0)),
+            Instr::Sse(SseOp::Div, Xmm(0), Xmm(1)),
+            Instr::Ucomisd(Xmm(1), Xmm(0)),
+            Instr::Cvtsi2sd(Xmm(0), R9),
+            Instr::Cvttsd2si(Rdi, Xmm(2)),
+            Instr::Set(Condition::Above, Rax),
+            Instr::Set(Condition::AboveEqual, Rcx),
+            Instr::Set(Condition::Parity, Rdx),
+            Instr::Set(Condition::NotParity, Rbx),
+        ];
+        let code = encode(&Assembly { text: instructions, ..Assembly::default() });
+
+        // As GNU as encodes them
+        let expected = "66 48 0f 6e c0 66 4d 0f 6e ca 66 48 0f 7e cb 66 4d 0f 7e c3 f2 0f 58 c1 f2 41 0f 5c d2 \
+                        f2 44 0f 59 c8 f2 0f 5e c1 66 0f 2e c8 f2 49 0f 2a c1 f2 48 0f 2c fa \
+                        0f 97 c0 0f 93 c1 0f 9a c2 0f 9b c3";
+        assert_eq!(text(&code).join(" "), expected);
+    }
+
+    #[test]
+    fn test_resolve_labels() {
+        let x = Label::Variable("x".to_string());
+        let assembly = Assembly {
+            data: vec![Data::Bytes(Label::String(0), b"hi".to_vec()), Data::Quad(x.clone(), 5)],
+            bss: vec![Data::Reserve(Label::Named("int_buffer"), 20)],
+            text: vec![
+                Instr::Label(Label::Named("_start")),
+                Instr::Mov(Operand::Register(Rax), Operand::Memory(Memory::label(x.clone()))),
+                Instr::Lea(Rsi, Memory { displacement: 20, ..Memory::label(Label::Named("int_buffer")) }),
+                Instr::Jcc(Condition::NotZero, Label::Named("_start")),
+                Instr::Call(Label::Named("done")),
+                Instr::Label(Label::Named("done")),
+                Instr::Ret,
+            ],
+        };
+        let code = encode(&assembly);
+
+        // Quads are aligned to 8 bytes
+        assert_eq!(code.data, [b'h', b'i', 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(code.labels[&x], (Section::Data, 8));
+        assert_eq!(code.bss_size, 20);
+        let relocations = vec![
+            Relocation { offset: 3, section: Section::Data, addend: 4 },
+            Relocation { offset: 10, section: Section::Bss, addend: 16 },
+        ];
+        assert_eq!(code.relocations, relocations);
+        // jnz back 20 bytes to the start, call the next instruction
+        assert_eq!(text(&code)[14..].join(" "), "0f 85 ec ff ff ff e8 00 00 00 00 c3");
+    }
+}
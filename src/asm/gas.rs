@@ -0,0 +1,176 @@
+//! Printing in the AT&T syntax of the GNU assembler.
+//!
+//! Operands come source first, registers take a `%` and immediates a `$`, and
+//! instructions that touch memory or take an immediate get a size suffix:
+//! `q` for 64 bits and `b` where a byte operand is involved.
+
+use super::{Assembly, Base, Data, Instr, Memory, Operand};
+
+impl Assembly {
+    /// The program as GNU as source for x86-64 Linux.
+    pub fn to_gas(&self) -> String {
+        let mut source = String::from(".section .data\n");
+        for data in &self.data {
+            source.push_str(&format!("{}\n", data_directive(data)));
+        }
+        source.push_str(".section .bss\n");
+        for data in &self.bss {
+            source.push_str(&format!("{}\n", data_directive(data)));
+        }
+        source.push_str(".section .text\n.globl _start\n");
+        for instr in &self.text {
+            source.push_str(&format!("{}\n", instruction(instr)));
+        }
+        source
+    }
+}
+
+fn data_directive(data: &Data) -> String {
+    match data {
+        Data::Quad(label, value) => format!("{}: .quad {}", label, value),
+        Data::Bytes(label, bytes) if bytes.is_empty() => format!("{}:", label),
+        Data::Bytes(label, bytes) => {
+            let bytes: Vec<String> = bytes.iter().map(|byte| byte.to_string()).collect();
+            format!("{}: .byte {}", label, bytes.join(", "))
+        }
+        Data::Reserve(label, size) => format!("{}: .skip {}", label, size),
+    }
+}
+
+fn memory(memory: &Memory) -> String {
+    match (&memory.base, memory.displacement) {
+        (Base::Label(label), 0) => label.to_string(),
+        (Base::Label(label), displacement) => format!("{}{:+}", label, displacement),
+        (Base::Register(register), 0) => format!("(%{})", register),
+        (Base::Register(register), displacement) => format!("{}(%{})", displacement, register),
+    }
+}
+
+fn operand(operand: &Operand) -> String {
+    match operand {
+        Operand::Register(register) => format!("%{}", register),
+        Operand::Byte(register) => format!("%{}", register.byte_name()),
+        Operand::Immediate(value) => format!("${}", value),
+        Operand::Memory(address) | Operand::ByteMemory(address) => memory(address),
+        Operand::Address(label) => format!("${}", label),
+    }
+}
+
+fn suffix(operands: &[&Operand]) -> &'static str {
+    if operands.iter().any(|operand| matches!(operand, Operand::Byte(_) | Operand::ByteMemory(_))) {
+        "b"
+    } else {
+        "q"
+    }
+}
+
+fn instruction(instr: &Instr) -> String {
+    match instr {
+        Instr::Label(label) => format!("{}:", label),
+        // A 64-bit immediate needs the long form of the move
+        Instr::Mov(dest @ Operand::Register(_), Operand::Immediate(value)) if i32::try_from(*value).is_err() => {
+            format!("movabsq ${}, {}", value, operand(dest))
+        }
+        Instr::Mov(dest, source) => format!("mov{} {}, {}", suffix(&[dest, source]), operand(source), operand(dest)),
+        Instr::Movzx(dest, source) => format!("movzbq %{}, %{}", source.byte_name(), dest),
+        Instr::Lea(dest, address) => format!("leaq {}, %{}", memory(address), dest),
+        Instr::Alu(operation, dest, source) => {
+            format!("{}{} {}, {}", operation.mnemonic(), suffix(&[dest, source]), operand(source), operand(dest))
+        }
+        Instr::Imul(dest, source) => format!("imulq {}, %{}", operand(source), dest),
+        Instr::Shift(operation, dest, Some(count)) => format!("{}q ${}, %{}", operation.mnemonic(), count, dest),
+        Instr::Shift(operation, dest, None) => format!("{}q %cl, %{}", operation.mnemonic(), dest),
+        Instr::Neg(register) => format!("negq %{}", register),
+        Instr::Dec(register) => format!("decq %{}", register),
+        Instr::Test(left, right) => format!("testq %{}, %{}", right, left),
+        Instr::Cqo => "cqto".to_string(),
+        Instr::Idiv(divisor) => format!("idivq %{}", divisor),
+        Instr::Div(divisor) => format!("divq %{}", divisor),
+        Instr::Set(condition, register) => format!("set{} %{}", condition.suffix(), register.byte_name()),
+        Instr::Cmov(condition, dest, source) => format!("cmov{} %{}, %{}", condition.suffix(), source, dest),
+        Instr::Jmp(label) => format!("jmp {}", label),
+        Instr::Jcc(condition, label) => format!("j{} {}", condition.suffix(), label),
+        Instr::Call(label) => format!("call {}", label),
+        Instr::Ret => "ret".to_string(),
+        Instr::Syscall => "syscall".to_string(),
+        Instr::Push(register) => format!("pushq %{}", register),
+        Instr::Pop(register) => format!("popq %{}", register),
+        Instr::MovqToXmm(dest, source) => format!("movq %{}, %{}", source, dest),
+        Instr::MovqFromXmm(dest, source) => format!("movq %{}, %{}", source, dest),
+        Instr::Sse(operation, dest, source) => format!("{} %{}, %{}", operation.mnemonic(), source, dest),
+        Instr::Ucomisd(left, right) => format!("ucomisd %{}, %{}", right, left),
+        Instr::Cvtsi2sd(dest, source) => format!("cvtsi2sdq %{}, %{}", source, dest),
+        Instr::Cvttsd2si(dest, source) => format!("cvttsd2si %{}, %{}", source, dest),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{AluOp, Condition, Label, Register, ShiftOp, SseOp, Xmm};
+    use crate::ir::BlockId;
+
+    #[test]
+    fn test_print_gas() {
+        let assembly = Assembly {
+            data: vec![Data::Quad(Label::Variable("x".to_string()), 0), Data::Bytes(Label::String(0), b"hi".to_vec())],
+            bss: vec![Data::Reserve(Label::Named("int_buffer"), 20)],
+            text: vec![
+                Instr::Label(Label::Named("_start")),
+                Instr::Mov(
+                    Operand::Register(Register::Rax),
+                    Operand::Memory(Memory::label(Label::Variable("x".to_string()))),
+                ),
+                Instr::Mov(Operand::Memory(Memory::register(Register::Rbp, -16)), Operand::Register(Register::R12)),
+                Instr::Mov(Operand::Register(Register::Rbx), Operand::Immediate(1 << 40)),
+                Instr::Lea(Register::Rsi, Memory { base: Base::Label(Label::Named("int_buffer")), displacement: 20 }),
+                Instr::Mov(Operand::ByteMemory(Memory::register(Register::Rsi, 0)), Operand::Immediate(45)),
+                Instr::Alu(AluOp::Cmp, Operand::Register(Register::Rax), Operand::Immediate(-1)),
+                Instr::Set(Condition::LessEqual, Register::Rax),
+                Instr::Movzx(Register::Rax, Register::Rax),
+                Instr::Shift(ShiftOp::Sar, Register::R9, None),
+                Instr::Cmov(Condition::Zero, Register::Rdx, Register::Rax),
+                Instr::Jcc(Condition::NotZero, Label::Block(BlockId(3))),
+                Instr::Mov(Operand::Register(Register::Rsi), Operand::Address(Label::String(0))),
+                Instr::MovqToXmm(Xmm(1), Register::Rbx),
+                Instr::Sse(SseOp::Div, Xmm(0), Xmm(1)),
+                Instr::Ucomisd(Xmm(1), Xmm(0)),
+                Instr::Set(Condition::Above, Register::Rax),
+                Instr::Cvtsi2sd(Xmm(0), Register::Rax),
+                Instr::Cvttsd2si(Register::Rax, Xmm(0)),
+                Instr::MovqFromXmm(Register::Rax, Xmm(0)),
+            ],
+        };
+
+        let expected = "\
+.section .data
+var_x: .quad 0
+string_0: .byte 104, 105
+.section .bss
+int_buffer: .skip 20
+.section .text
+.globl _start
+_start:
+movq var_x, %rax
+movq %r12, -16(%rbp)
+movabsq $1099511627776, %rbx
+leaq int_buffer+20, %rsi
+movb $45, (%rsi)
+cmpq $-1, %rax
+setle %al
+movzbq %al, %rax
+sarq %cl, %r9
+cmovz %rax, %rdx
+jnz block_3
+movq $string_0, %rsi
+movq %rbx, %xmm1
+divsd %xmm1, %xmm0
+ucomisd %xmm0, %xmm1
+seta %al
+cvtsi2sdq %rax, %xmm0
+cvttsd2si %xmm0, %rax
+movq %xmm0, %rax
+";
+        assert_eq!(assembly.to_gas(), expected);
+    }
+}
@@ -1,45 +1,520 @@
-#[allow(dead_code)]
-#[derive(Debug)]
-pub enum ASTNode {
-    Program {
-        variable_section: Vec<VariableDeclaration>,
-        statement_section: Vec<Statement>,
+use crate::span::Span;
+
+/// Identifies a node of the tree. The parser gives every node a unique id,
+/// so later passes can attach information to a node in a side table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct NodeId(pub u32);
+
+/// `var <declarations> <body>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub id: NodeId,
+    pub declarations: Vec<Decl>,
+    pub body: Block,
+    pub span: Span,
+}
+
+/// `begin <statements> end`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub id: NodeId,
+    pub statements: Vec<Stmt>,
+    pub span: Span,
+}
+
+/// `a, b: integer;`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decl {
+    pub id: NodeId,
+    pub names: Vec<String>,
+    pub type_name: TypeName,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TypeName {
+    Integer,
+    Int64,
+    Boolean,
+    Real,
+}
+
+impl TypeName {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TypeName::Integer => "integer",
+            TypeName::Int64 => "int64",
+            TypeName::Boolean => "boolean",
+            TypeName::Real => "real",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub id: NodeId,
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    Assign {
+        target: String,
+        value: Expr,
     },
-    VariableDeclaration {
-        names: Vec<String>,
-        type_name: String,
+    Print {
+        items: Vec<PrintItem>,
+        newline: bool, // writeln ends the line, write does not
     },
-    Assignment {
-        variable: String,
-        expression: Expression,
+    /// `begin ... end` used as a single statement
+    Compound(Block),
+    If {
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
     },
-    Print {
-        items: Vec<Expression>,
+    While {
+        condition: Expr,
+        body: Box<Stmt>,
     },
+    /// `for variable := start to end do body`, counting down with `downto`.
+    /// The bounds are evaluated once, before the first iteration.
+    For {
+        variable: String,
+        start: Expr,
+        end: Expr,
+        descending: bool,
+        body: Box<Stmt>,
+    },
+    /// Ends the program.
+    Exit,
 }
-#[allow(dead_code)]
-#[derive(Debug)]
-pub struct VariableDeclaration {
-    pub names: Vec<String>,
-    pub type_name: String,
+
+/// One argument of write/writeln, optionally formatted as `value:width:precision`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrintItem {
+    pub value: Expr,
+    pub width: Option<Expr>,
+    pub precision: Option<Expr>,
 }
 
-#[allow(dead_code)]
-#[derive(Debug)]
-pub(crate) enum Statement {
-    Assignment(String, Expression),
-    Print(Vec<Expression>),
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub id: NodeId,
+    pub kind: ExprKind,
+    pub span: Span,
 }
 
-#[allow(dead_code)]
-#[derive(Debug)]
-pub(crate) enum Expression {
-    IntegerLiteral(i32),
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Integer(i64),
+    Real(f64),
+    Boolean(bool),
+    String(Vec<u8>),
     Variable(String),
-    StringLiteral(String), // Add this variant
-    BinaryOperation {
-        left: Box<Expression>,
-        operator: char,
-        right: Box<Expression>,
-    },
-}
\ No newline at end of file
+    Unary { operator: UnaryOperator, operand: Box<Expr> },
+    Binary { left: Box<Expr>, operator: BinaryOperator, right: Box<Expr> },
+}
+
+impl Stmt {
+    /// A statement without an id or source location, for building trees by hand.
+    pub fn new(kind: StmtKind) -> Self {
+        Stmt { id: NodeId::default(), kind, span: Span::default() }
+    }
+}
+
+impl Expr {
+    /// An expression without an id or source location, for building trees by hand.
+    pub fn new(kind: ExprKind) -> Self {
+        Expr { id: NodeId::default(), kind, span: Span::default() }
+    }
+}
+
+impl Program {
+    /// Returns the tree with every id and span reset to its default, so trees
+    /// parsed from differently laid out sources can be compared.
+    pub fn without_locations(mut self) -> Self {
+        LocationEraser.visit_program(&mut self);
+        self
+    }
+}
+
+struct LocationEraser;
+
+impl VisitorMut for LocationEraser {
+    fn visit_program(&mut self, program: &mut Program) {
+        program.id = NodeId::default();
+        program.span = Span::default();
+        walk_program_mut(self, program);
+    }
+
+    fn visit_decl(&mut self, decl: &mut Decl) {
+        decl.id = NodeId::default();
+        decl.span = Span::default();
+    }
+
+    fn visit_block(&mut self, block: &mut Block) {
+        block.id = NodeId::default();
+        block.span = Span::default();
+        walk_block_mut(self, block);
+    }
+
+    fn visit_stmt(&mut self, stmt: &mut Stmt) {
+        stmt.id = NodeId::default();
+        stmt.span = Span::default();
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &mut Expr) {
+        expr.id = NodeId::default();
+        expr.span = Span::default();
+        walk_expr_mut(self, expr);
+    }
+}
+
+/// Read-only traversal of the tree. Every method defaults to visiting the
+/// node's children through the matching `walk_*` function, so an
+/// implementation only overrides the nodes it is interested in and calls the
+/// `walk_*` function itself when it still wants the children visited.
+pub trait Visitor {
+    fn visit_program(&mut self, program: &Program) {
+        walk_program(self, program);
+    }
+
+    fn visit_decl(&mut self, _decl: &Decl) {}
+
+    fn visit_block(&mut self, block: &Block) {
+        walk_block(self, block);
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_print_item(&mut self, item: &PrintItem) {
+        walk_print_item(self, item);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr);
+    }
+}
+
+pub fn walk_program<V: Visitor + ?Sized>(visitor: &mut V, program: &Program) {
+    for decl in &program.declarations {
+        visitor.visit_decl(decl);
+    }
+    visitor.visit_block(&program.body);
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, block: &Block) {
+    for stmt in &block.statements {
+        visitor.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_stmt<V: Visitor + ?Sized>(visitor: &mut V, stmt: &Stmt) {
+    match &stmt.kind {
+        StmtKind::Assign { value, .. } => visitor.visit_expr(value),
+        StmtKind::Print { items, .. } => {
+            for item in items {
+                visitor.visit_print_item(item);
+            }
+        }
+        StmtKind::Compound(block) => visitor.visit_block(block),
+        StmtKind::If { condition, then_branch, else_branch } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_stmt(else_branch);
+            }
+        }
+        StmtKind::While { condition, body } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(body);
+        }
+        StmtKind::For { start, end, body, .. } => {
+            visitor.visit_expr(start);
+            visitor.visit_expr(end);
+            visitor.visit_stmt(body);
+        }
+        StmtKind::Exit => {}
+    }
+}
+
+pub fn walk_print_item<V: Visitor + ?Sized>(visitor: &mut V, item: &PrintItem) {
+    visitor.visit_expr(&item.value);
+    if let Some(width) = &item.width {
+        visitor.visit_expr(width);
+    }
+    if let Some(precision) = &item.precision {
+        visitor.visit_expr(precision);
+    }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match &expr.kind {
+        ExprKind::Unary { operand, .. } => visitor.visit_expr(operand),
+        ExprKind::Binary { left, right, .. } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        ExprKind::Integer(_)
+        | ExprKind::Real(_)
+        | ExprKind::Boolean(_)
+        | ExprKind::String(_)
+        | ExprKind::Variable(_) => {}
+    }
+}
+
+/// Like [`Visitor`], but with mutable access so a pass can rewrite the tree in place.
+pub trait VisitorMut {
+    fn visit_program(&mut self, program: &mut Program) {
+        walk_program_mut(self, program);
+    }
+
+    fn visit_decl(&mut self, _decl: &mut Decl) {}
+
+    fn visit_block(&mut self, block: &mut Block) {
+        walk_block_mut(self, block);
+    }
+
+    fn visit_stmt(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_print_item(&mut self, item: &mut PrintItem) {
+        walk_print_item_mut(self, item);
+    }
+
+    fn visit_expr(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+    }
+}
+
+pub fn walk_program_mut<V: VisitorMut + ?Sized>(visitor: &mut V, program: &mut Program) {
+    for decl in &mut program.declarations {
+        visitor.visit_decl(decl);
+    }
+    visitor.visit_block(&mut program.body);
+}
+
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(visitor: &mut V, block: &mut Block) {
+    for stmt in &mut block.statements {
+        visitor.visit_stmt(stmt);
+    }
+}
+
+pub fn walk_stmt_mut<V: VisitorMut + ?Sized>(visitor: &mut V, stmt: &mut Stmt) {
+    match &mut stmt.kind {
+        StmtKind::Assign { value, .. } => visitor.visit_expr(value),
+        StmtKind::Print { items, .. } => {
+            for item in items {
+                visitor.visit_print_item(item);
+            }
+        }
+        StmtKind::Compound(block) => visitor.visit_block(block),
+        StmtKind::If { condition, then_branch, else_branch } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_stmt(else_branch);
+            }
+        }
+        StmtKind::While { condition, body } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(body);
+        }
+        StmtKind::For { start, end, body, .. } => {
+            visitor.visit_expr(start);
+            visitor.visit_expr(end);
+            visitor.visit_stmt(body);
+        }
+        StmtKind::Exit => {}
+    }
+}
+
+pub fn walk_print_item_mut<V: VisitorMut + ?Sized>(visitor: &mut V, item: &mut PrintItem) {
+    visitor.visit_expr(&mut item.value);
+    if let Some(width) = &mut item.width {
+        visitor.visit_expr(width);
+    }
+    if let Some(precision) = &mut item.precision {
+        visitor.visit_expr(precision);
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match &mut expr.kind {
+        ExprKind::Unary { operand, .. } => visitor.visit_expr(operand),
+        ExprKind::Binary { left, right, .. } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        ExprKind::Integer(_)
+        | ExprKind::Real(_)
+        | ExprKind::Boolean(_)
+        | ExprKind::String(_)
+        | ExprKind::Variable(_) => {}
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnaryOperator {
+    Negate,
+    Not,
+}
+
+impl UnaryOperator {
+    /// The operator as written in the source.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnaryOperator::Negate => "-",
+            UnaryOperator::Not => "not",
+        }
+    }
+
+    /// Evaluates the operator with the same semantics as the generated code;
+    /// booleans are 0 and 1.
+    pub fn apply(self, operand: i64) -> i64 {
+        match self {
+            UnaryOperator::Negate => operand.wrapping_neg(),
+            UnaryOperator::Not => operand ^ 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide, // '/', like div on integers, and real division when an operand is real
+    Div,    // Pascal div, truncates toward zero
+    Mod,    // Ada mod, result has the sign of the right operand
+    Rem,    // Ada rem, result has the sign of the left operand
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And, // Short-circuit, like Free Pascal's default
+    Or,
+}
+
+impl BinaryOperator {
+    /// The operator as written in the source.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Div => "div",
+            BinaryOperator::Mod => "mod",
+            BinaryOperator::Rem => "rem",
+            BinaryOperator::Equal => "=",
+            BinaryOperator::NotEqual => "<>",
+            BinaryOperator::Less => "<",
+            BinaryOperator::LessEqual => "<=",
+            BinaryOperator::Greater => ">",
+            BinaryOperator::GreaterEqual => ">=",
+            BinaryOperator::And => "and",
+            BinaryOperator::Or => "or",
+        }
+    }
+
+    /// True for the operators that compare two values and yield a boolean.
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinaryOperator::Equal
+                | BinaryOperator::NotEqual
+                | BinaryOperator::Less
+                | BinaryOperator::LessEqual
+                | BinaryOperator::Greater
+                | BinaryOperator::GreaterEqual
+        )
+    }
+
+    /// Binding strength; a higher value binds tighter. All binary operators are
+    /// left associative. As in Pascal, `and` and `or` bind tighter than the
+    /// comparisons, so `(a < b) and (c < d)` needs its parentheses.
+    pub fn precedence(self) -> u8 {
+        match self {
+            _ if self.is_comparison() => 0,
+            BinaryOperator::Add | BinaryOperator::Subtract | BinaryOperator::Or => 1,
+            _ => 2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_visitors_reach_every_expression() {
+        struct VariableCollector(Vec<String>);
+
+        impl Visitor for VariableCollector {
+            fn visit_expr(&mut self, expr: &Expr) {
+                if let ExprKind::Variable(name) = &expr.kind {
+                    self.0.push(name.clone());
+                }
+                walk_expr(self, expr);
+            }
+        }
+
+        struct Renamer;
+
+        impl VisitorMut for Renamer {
+            fn visit_expr(&mut self, expr: &mut Expr) {
+                if let ExprKind::Variable(name) = &mut expr.kind {
+                    name.make_ascii_uppercase();
+                }
+                walk_expr_mut(self, expr);
+            }
+        }
+
+        let variable = |name: &str| Expr::new(ExprKind::Variable(name.to_string()));
+        let mut program = Program {
+            id: NodeId::default(),
+            declarations: Vec::new(),
+            body: Block {
+                id: NodeId::default(),
+                statements: vec![
+                    Stmt::new(StmtKind::Assign {
+                        target: "a".to_string(),
+                        value: Expr::new(ExprKind::Unary {
+                            operator: UnaryOperator::Negate,
+                            operand: Box::new(variable("b")),
+                        }),
+                    }),
+                    Stmt::new(StmtKind::Print {
+                        items: vec![PrintItem {
+                            value: Expr::new(ExprKind::Binary {
+                                left: Box::new(variable("c")),
+                                operator: BinaryOperator::Add,
+                                right: Box::new(variable("d")),
+                            }),
+                            width: Some(variable("e")),
+                            precision: Some(variable("f")),
+                        }],
+                        newline: true,
+                    }),
+                ],
+                span: Span::default(),
+            },
+            span: Span::default(),
+        };
+
+        Renamer.visit_program(&mut program);
+        let mut collector = VariableCollector(Vec::new());
+        collector.visit_program(&program);
+
+        assert_eq!(collector.0, ["B", "C", "D", "E", "F"]);
+    }
+}
@@ -1,135 +1,1272 @@
-use crate::ast::{ASTNode, Expression, Statement};
-
-pub fn generate_code(ast: ASTNode) -> String {
-    let mut assembly = String::new();
-    let mut data_section = String::from("section .data\n");
-    let mut text_section = String::from("section .text\n");
-    text_section.push_str("global _start\n_start:\n");
-
-    let mut string_literals = Vec::new(); // To store string literals
-
-    match ast {
-        ASTNode::Program { variable_section, statement_section } => {
-            // Add variable declarations to the .data section
-            for var in variable_section {
-                for name in &var.names {
-                    data_section.push_str(&format!("{} dq 0\n", name));
-                }
-            }
+pub mod aarch64;
+pub mod c;
+pub mod riscv64;
+pub mod wasm;
 
-            // Add the buffer to the .data section
-            data_section.push_str("buffer db 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0\n");
+use std::marker::PhantomData;
 
-            // Generate assembly for statements
-            for stmt in statement_section {
-                text_section.push_str(&generate_statement(&stmt, &mut string_literals));
-            }
+use crate::asm::{AluOp, Assembly, Condition, Data, Instr, Label, Memory, Operand, Register, ShiftOp, SseOp, Xmm};
+use crate::ir::{self, BinaryOperator, BlockId, Instruction, PrintValue, Program, Terminator, UnaryOperator, Var};
+use crate::peephole;
+use crate::regalloc::Allocation;
+
+/// Generates x86-64 Linux code from the IR. Variables live where `allocation`
+/// puts them: in registers, named ones in .data and temporaries in a stack
+/// frame addressed from RBP. With `peephole`, the code for the program goes
+/// through [`peephole::optimize`].
+pub fn generate_code(program: &Program, allocation: &Allocation, peephole: bool) -> Assembly {
+    let mut generator = CodeGenerator { text: Vec::new(), allocation };
+    for (index, block) in program.blocks.iter().enumerate() {
+        generator.emit(Instr::Label(Label::Block(BlockId(index))));
+        for instruction in &block.instructions {
+            generator.instruction(instruction, program);
         }
-        _ => unimplemented!(),
+        generator.terminator(&block.terminator, BlockId(index + 1));
+    }
+    if peephole {
+        peephole::optimize(&mut generator.text);
     }
 
-    // Add string literals to the .data section
-    for (i, literal) in string_literals.iter().enumerate() {
-        data_section.push_str(&format!("string_{} db '{}', 0\n", i, literal));
+    let mut data = Vec::new();
+    for name in &program.variables {
+        if !allocation.registers.contains_key(&Var::Named(name.clone())) {
+            data.push(Data::Quad(Label::Variable(name.clone()), 0));
+        }
     }
+    // Lengths are passed explicitly to print_str, so no terminator is needed
+    for (i, literal) in program.strings.iter().enumerate() {
+        data.push(Data::Bytes(Label::String(i), literal.clone()));
+    }
+    data.push(Data::Bytes(Label::Named("newline"), b"\n".to_vec()));
+    data.push(Data::Bytes(Label::Named("true_text"), b"TRUE".to_vec()));
+    data.push(Data::Bytes(Label::Named("false_text"), b"FALSE".to_vec()));
+    data.push(Data::Bytes(Label::Named("spaces"), vec![b' '; 16])); // For padding
+    data.push(Data::Bytes(Label::Named("nan_text"), b"NaN".to_vec()));
+    data.push(Data::Bytes(Label::Named("infinity_text"), b"-Inf".to_vec()));
 
-    // Add the number-to-string conversion routine
-    text_section.push_str(&number_to_string_routine());
+    let mut text = vec![Instr::Label(Label::Named("_start"))];
+    if allocation.slot_count > 0 {
+        text.push(Instr::Mov(Operand::Register(Register::Rbp), Operand::Register(Register::Rsp)));
+        let frame = Operand::Immediate(8 * allocation.slot_count as i64);
+        text.push(Instr::Alu(AluOp::Sub, Operand::Register(Register::Rsp), frame));
+    }
+    for register in &allocation.zeroed {
+        text.push(Instr::Alu(AluOp::Xor, Operand::Register(*register), Operand::Register(*register)));
+    }
+    text.append(&mut generator.text);
+    // Add the printing runtime after the code so it is only entered by call
+    text.extend(print_runtime());
+    let bss = vec![
+        Data::Reserve(Label::Named("int_buffer"), 20),
+        // Room for the 309 digits before the point of the largest double,
+        // the point, 18 digits after it and the sign
+        Data::Reserve(Label::Named("real_buffer"), 336),
+    ];
+    Assembly { data, bss, text }
+}
 
-    // Append the sections to the final assembly
-    assembly.push_str(&data_section);
-    assembly.push_str(&text_section);
-    assembly.push_str("mov rax, 60\nxor rdi, rdi\nsyscall\n"); // Exit syscall
-    assembly
+// Translates one IR instruction at a time. Results are computed in place when
+// the destination is a register; otherwise operands are loaded into RAX and RBX,
+// and the result is stored back from RAX.
+struct CodeGenerator<'a> {
+    text: Vec<Instr>,
+    allocation: &'a Allocation,
 }
 
-fn generate_statement(stmt: &Statement, string_literals: &mut Vec<String>) -> String {
-    let mut assembly = String::new();
-    match stmt {
-        Statement::Assignment(var, expr) => {
-            assembly.push_str(&generate_expression(expr, string_literals));
-            assembly.push_str(&format!("mov [{}], rax\n", var)); // Store result in variable
-        }
-        Statement::Print(items) => {
-            for item in items {
-                match item {
-                    Expression::StringLiteral(text) => {
-                        // Add the string literal to the list and reference it
-                        let index = string_literals.len();
-                        string_literals.push(text.clone());
-                        assembly.push_str(&format!(
-                            "mov rax, 1\nmov rdi, 1\nmov rsi, string_{}\nmov rdx, {}\nsyscall\n",
-                            index,
-                            text.len() + 1 // Include null terminator
+impl CodeGenerator<'_> {
+    fn emit(&mut self, instr: Instr) {
+        self.text.push(instr);
+    }
+
+    fn location(&self, var: &Var) -> Operand {
+        if let Some(register) = self.register(var) {
+            return Operand::Register(register);
+        }
+        match var {
+            Var::Named(name) => Operand::Memory(Memory::label(Label::Variable(name.clone()))),
+            Var::Temp(_) => {
+                let offset = 8 * (self.allocation.slots[var] as i32 + 1);
+                Operand::Memory(Memory::register(Register::Rbp, -offset))
+            }
+        }
+    }
+
+    fn register(&self, var: &Var) -> Option<Register> {
+        self.allocation.registers.get(var).copied()
+    }
+
+    fn operand(&self, operand: &ir::Operand) -> Operand {
+        match operand {
+            ir::Operand::Constant(value) => Operand::Immediate(*value),
+            ir::Operand::Var(var) => self.location(var),
+        }
+    }
+
+    fn load(&mut self, register: Register, operand: &ir::Operand) {
+        let source = self.operand(operand);
+        if source != Operand::Register(register) {
+            self.emit(Instr::Mov(Operand::Register(register), source));
+        }
+    }
+
+    fn store(&mut self, dest: &Var) {
+        let location = self.location(dest);
+        if location != Operand::Register(Register::Rax) {
+            self.emit(Instr::Mov(location, Operand::Register(Register::Rax)));
+        }
+    }
+
+    // The right operand of `operator` as it can be written in the
+    // instruction: a register, or a constant that fits in the 32-bit
+    // immediate of instructions other than idiv. Anything else is loaded
+    // into RBX first.
+    fn right_operand(&mut self, operator: BinaryOperator, right: &ir::Operand) -> Operand {
+        use BinaryOperator::*;
+
+        let immediate = !matches!(operator, Div | Divide | Mod | Rem);
+        match right {
+            ir::Operand::Var(var) if let Some(register) = self.register(var) => Operand::Register(register),
+            ir::Operand::Constant(value) if immediate && i32::try_from(*value).is_ok() => Operand::Immediate(*value),
+            _ => {
+                self.load(Register::Rbx, right);
+                Operand::Register(Register::Rbx)
+            }
+        }
+    }
+
+    fn instruction(&mut self, instruction: &Instruction, program: &Program) {
+        match instruction {
+            Instruction::Copy { dest, source } => {
+                let in_register = self.register(dest).is_some()
+                    || matches!(source, ir::Operand::Var(var) if self.register(var).is_some());
+                if in_register {
+                    let (dest, source) = (self.location(dest), self.operand(source));
+                    if dest != source {
+                        self.emit(Instr::Mov(dest, source));
+                    }
+                } else {
+                    self.load(Register::Rax, source);
+                    self.store(dest);
+                }
+            }
+            Instruction::Unary { dest, operator, operand } => {
+                let target = self.register(dest).unwrap_or(Register::Rax);
+                self.load(target, operand);
+                let code = match operator {
+                    UnaryOperator::Negate => vec![Instr::Neg(target)],
+                    UnaryOperator::Not => {
+                        vec![Instr::Alu(AluOp::Xor, Operand::Register(target), Operand::Immediate(1))]
+                    }
+                    // The sign bit does not fit in an immediate, so the mask is put in RCX
+                    UnaryOperator::RealNegate => vec![
+                        Instr::Mov(Operand::Register(Register::Rcx), Operand::Immediate(i64::MIN)),
+                        Instr::Alu(AluOp::Xor, Operand::Register(target), Operand::Register(Register::Rcx)),
+                    ],
+                    UnaryOperator::ToReal => vec![Instr::Cvtsi2sd(Xmm(0), target), Instr::MovqFromXmm(target, Xmm(0))],
+                };
+                for instr in code {
+                    self.emit(instr);
+                }
+                if target == Register::Rax {
+                    self.store(dest);
+                }
+            }
+            Instruction::Binary { dest, operator, left, right } => self.binary(dest, *operator, left, right),
+            Instruction::Print { value, width } => {
+                // The padded routines take the field width in RCX
+                let routine = |name: &'static str, padded: &'static str| {
+                    Label::Named(if width.is_some() { padded } else { name })
+                };
+                match value {
+                    PrintValue::String(index) => {
+                        let length = program.strings[*index].len() as i64;
+                        self.emit(Instr::Mov(
+                            Operand::Register(Register::Rsi),
+                            Operand::Address(Label::String(*index)),
                         ));
+                        self.emit(Instr::Mov(Operand::Register(Register::Rdx), Operand::Immediate(length)));
+                        if let Some(width) = width {
+                            self.load(Register::Rcx, width);
+                        }
+                        self.emit(Instr::Call(routine("print_str", "print_str_width")));
+                    }
+                    PrintValue::Integer(operand) | PrintValue::Boolean(operand) => {
+                        self.load(Register::Rax, operand);
+                        if let Some(width) = width {
+                            self.load(Register::Rcx, width);
+                        }
+                        self.emit(Instr::Call(match value {
+                            PrintValue::Boolean(_) => routine("print_bool", "print_bool_width"),
+                            _ => routine("print_int", "print_int_width"),
+                        }));
+                    }
+                    // print_real takes the number of digits after the point in
+                    // RDX, or -1 for the default
+                    PrintValue::Real(operand, precision) => {
+                        self.load(Register::Rax, operand);
+                        match precision {
+                            Some(precision) => self.load(Register::Rdx, precision),
+                            None => self.emit(Instr::Mov(Operand::Register(Register::Rdx), Operand::Immediate(-1))),
+                        }
+                        if let Some(width) = width {
+                            self.load(Register::Rcx, width);
+                        }
+                        self.emit(Instr::Call(routine("print_real", "print_real_width")));
                     }
+                }
+            }
+            Instruction::PrintNewline => self.emit(Instr::Call(Label::Named("print_newline"))),
+        }
+    }
+
+    fn binary(&mut self, dest: &Var, operator: BinaryOperator, left: &ir::Operand, right: &ir::Operand) {
+        use BinaryOperator::*;
+
+        if operator.is_real() {
+            return self.real_binary(dest, operator, left, right);
+        }
+        // Operations with a two-operand instruction are done directly in the
+        // destination register, unless that register holds the right operand
+        let two_operand = match operator {
+            Add | Subtract | Multiply | And | Or => true,
+            ShiftLeft | ShiftRight => matches!(right, ir::Operand::Constant(_)),
+            _ => false,
+        };
+        let target = match self.register(dest) {
+            Some(register) if two_operand && self.operand(right) != Operand::Register(register) => register,
+            _ => Register::Rax,
+        };
+        self.load(target, left);
+        // Divisors that may be 0 or -1 are passed to the divide routine in RBX
+        let checked = matches!(operator, Div | Divide | Mod | Rem)
+            && !matches!(right, ir::Operand::Constant(divisor) if *divisor != 0 && *divisor != -1);
+        let right = match (operator, right) {
+            (ShiftLeft | ShiftRight, ir::Operand::Constant(count)) => Operand::Immediate(count & 63),
+            _ if checked => {
+                self.load(Register::Rbx, right);
+                Operand::Register(Register::Rbx)
+            }
+            _ => self.right_operand(operator, right),
+        };
+        for instr in generate_operator(operator, target, right, checked) {
+            self.emit(instr);
+        }
+        if target == Register::Rax {
+            self.store(dest);
+        }
+    }
+
+    // Reals are always operated on in XMM0 and XMM1, copied from RAX and RBX.
+    fn real_binary(&mut self, dest: &Var, operator: BinaryOperator, left: &ir::Operand, right: &ir::Operand) {
+        self.load(Register::Rax, left);
+        self.load(Register::Rbx, right);
+        // Divisors that may be 0.0 or -0.0 are passed to the divide routine
+        let checked = !matches!(right, ir::Operand::Constant(divisor) if divisor << 1 != 0);
+        for instr in generate_real_operator(operator, checked) {
+            self.emit(instr);
+        }
+        self.store(dest);
+    }
+
+    // Ends a block. Jumps to the block laid out next are left out.
+    fn terminator(&mut self, terminator: &Terminator, next: BlockId) {
+        match terminator {
+            Terminator::Jump(target) if *target == next => {}
+            Terminator::Jump(target) => self.emit(Instr::Jmp(Label::Block(*target))),
+            Terminator::Branch { condition, if_true, if_false } => {
+                let register = match condition {
+                    ir::Operand::Var(var) if let Some(register) = self.register(var) => register,
                     _ => {
-                        assembly.push_str(&generate_expression(item, string_literals));
-                        assembly.push_str("mov rdi, buffer\n"); // Address of the buffer
-                        assembly.push_str("call int_to_string\n"); // Convert number to string
-                        assembly.push_str(
-                            "mov rax, 1\nmov rdi, 1\nmov rsi, buffer\nmov rdx, rbx\nsyscall\n",
-                        ); // Print the converted number
+                        self.load(Register::Rax, condition);
+                        Register::Rax
+                    }
+                };
+                self.emit(Instr::Test(register, register));
+                if *if_true == next {
+                    self.emit(Instr::Jcc(Condition::Zero, Label::Block(*if_false)));
+                } else {
+                    self.emit(Instr::Jcc(Condition::NotZero, Label::Block(*if_true)));
+                    if *if_false != next {
+                        self.emit(Instr::Jmp(Label::Block(*if_false)));
                     }
                 }
             }
+            Terminator::Exit => {
+                self.emit(Instr::Mov(Operand::Register(Register::Rax), Operand::Immediate(60)));
+                self.emit(Instr::Alu(AluOp::Xor, Operand::Register(Register::Rdi), Operand::Register(Register::Rdi)));
+                self.emit(Instr::Syscall);
+            }
         }
     }
-    assembly
 }
 
-fn generate_expression(expr: &Expression, string_literals: &mut Vec<String>) -> String {
-    match expr {
-        Expression::IntegerLiteral(value) => format!("mov rax, {}\n", value),
-        Expression::Variable(name) => format!("mov rax, [{}]\n", name),
-        Expression::BinaryOperation { left, operator, right } => {
-            let mut assembly = String::new();
-            assembly.push_str(&generate_expression(left, string_literals));
-            assembly.push_str("push rax\n"); // Save left operand
-            assembly.push_str(&generate_expression(right, string_literals));
-            assembly.push_str("pop rbx\n"); // Restore left operand
-            match operator {
-                '+' => assembly.push_str("add rax, rbx\n"),
-                '-' => assembly.push_str("sub rax, rbx\n"),
-                '*' => assembly.push_str("imul rax, rbx\n"),
-                '/' => assembly.push_str("xor rdx, rdx\nidiv rbx\n"),
-                _ => unimplemented!(),
-            }
-            assembly
-        }
-        Expression::StringLiteral(text) => {
-            // Add the string literal to the list and return its label
-            let index = string_literals.len();
-            string_literals.push(text.clone());
-            format!("mov rax, string_{}\n", index)
+// Applies `operator` to `target` (left) and `right`, leaving the result in
+// `target`. `right` is a register, or an immediate for operators other than
+// the divisions; constant shift counts are immediates too. Only the
+// operators with a two-operand instruction can have a target other than RAX.
+// `checked` divisions call the divide routine, with the divisor in RBX.
+fn generate_operator(operator: BinaryOperator, target: Register, right: Operand, checked: bool) -> Vec<Instr> {
+    use Register::{Rax, Rcx, Rdx};
+
+    let alu = |operation| vec![Instr::Alu(operation, Operand::Register(target), right.clone())];
+    // idiv divides RDX:RAX, so RAX must be sign-extended into RDX first.
+    // The quotient truncates toward zero and the remainder in RDX takes the
+    // sign of the dividend.
+    let divide = || match right {
+        _ if checked => vec![Instr::Call(Label::Named("divide"))],
+        Operand::Register(register) => vec![Instr::Cqo, Instr::Idiv(register)],
+        _ => unreachable!("divisors are in a register"),
+    };
+    match operator {
+        BinaryOperator::Add => alu(AluOp::Add),
+        BinaryOperator::Subtract => alu(AluOp::Sub),
+        BinaryOperator::Multiply => vec![Instr::Imul(target, right)],
+        BinaryOperator::Divide | BinaryOperator::Div => divide(),
+        BinaryOperator::Rem => {
+            let mut code = divide();
+            code.push(Instr::Mov(Operand::Register(Rax), Operand::Register(Rdx)));
+            code
         }
+        // mod takes the sign of the divisor: add the divisor to a non-zero
+        // remainder whose sign differs from it.
+        BinaryOperator::Mod => {
+            let mut code = divide();
+            code.extend([
+                Instr::Mov(Operand::Register(Rax), Operand::Register(Rdx)),
+                Instr::Alu(AluOp::Xor, Operand::Register(Rdx), right.clone()),
+                Instr::Shift(ShiftOp::Sar, Rdx, Some(63)),
+                Instr::Test(Rax, Rax),
+                Instr::Cmov(Condition::Zero, Rdx, Rax),
+                Instr::Alu(AluOp::And, Operand::Register(Rdx), right.clone()),
+                Instr::Alu(AluOp::Add, Operand::Register(Rax), Operand::Register(Rdx)),
+            ]);
+            code
+        }
+        BinaryOperator::Equal => compare(Condition::Equal, right),
+        BinaryOperator::NotEqual => compare(Condition::NotEqual, right),
+        BinaryOperator::Less => compare(Condition::Less, right),
+        BinaryOperator::LessEqual => compare(Condition::LessEqual, right),
+        BinaryOperator::Greater => compare(Condition::Greater, right),
+        BinaryOperator::GreaterEqual => compare(Condition::GreaterEqual, right),
+        BinaryOperator::And => alu(AluOp::And),
+        BinaryOperator::Or => alu(AluOp::Or),
+        BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight => {
+            let operation = if operator == BinaryOperator::ShiftLeft { ShiftOp::Shl } else { ShiftOp::Sar };
+            match right {
+                Operand::Immediate(count) => vec![Instr::Shift(operation, target, Some(count as u8))],
+                _ => vec![Instr::Mov(Operand::Register(Rcx), right), Instr::Shift(operation, target, None)],
+            }
+        }
+        _ => unreachable!("{} is an operator on reals", operator.symbol()),
+    }
+}
+
+// Applies the real `operator` to the doubles whose bits are in RAX (left) and
+// RBX (right), leaving the result in RAX. `checked` divisions call the
+// real_divide routine.
+fn generate_real_operator(operator: BinaryOperator, checked: bool) -> Vec<Instr> {
+    use Register::{Rax, Rbx, Rcx};
+
+    let (left, right) = (Xmm(0), Xmm(1));
+    if operator == BinaryOperator::RealDivide && checked {
+        return vec![Instr::Call(Label::Named("real_divide"))];
+    }
+    let mut code = vec![Instr::MovqToXmm(left, Rax), Instr::MovqToXmm(right, Rbx)];
+    let arithmetic = |operation| [Instr::Sse(operation, left, right), Instr::MovqFromXmm(Rax, left)];
+    // Comparisons with NaN are false, and ucomisd sets CF and ZF for them, so
+    // only "above" conditions are used, with the operands swapped for less
+    let compare =
+        |condition, first, second| [Instr::Ucomisd(first, second), Instr::Set(condition, Rax), Instr::Movzx(Rax, Rax)];
+    // Equality also needs PF, which is set for NaN
+    let equal = |condition, parity, combine| {
+        [
+            Instr::Ucomisd(left, right),
+            Instr::Set(condition, Rax),
+            Instr::Set(parity, Rcx),
+            Instr::Alu(combine, Operand::Byte(Rax), Operand::Byte(Rcx)),
+            Instr::Movzx(Rax, Rax),
+        ]
+    };
+    match operator {
+        BinaryOperator::RealAdd => code.extend(arithmetic(SseOp::Add)),
+        BinaryOperator::RealSubtract => code.extend(arithmetic(SseOp::Sub)),
+        BinaryOperator::RealMultiply => code.extend(arithmetic(SseOp::Mul)),
+        BinaryOperator::RealDivide => code.extend(arithmetic(SseOp::Div)),
+        BinaryOperator::RealEqual => code.extend(equal(Condition::Equal, Condition::NotParity, AluOp::And)),
+        BinaryOperator::RealNotEqual => code.extend(equal(Condition::NotEqual, Condition::Parity, AluOp::Or)),
+        BinaryOperator::RealLess => code.extend(compare(Condition::Above, right, left)),
+        BinaryOperator::RealLessEqual => code.extend(compare(Condition::AboveEqual, right, left)),
+        BinaryOperator::RealGreater => code.extend(compare(Condition::Above, left, right)),
+        BinaryOperator::RealGreaterEqual => code.extend(compare(Condition::AboveEqual, left, right)),
+        _ => unreachable!("{} is not an operator on reals", operator.symbol()),
     }
+    code
 }
 
-fn number_to_string_routine() -> String {
-    String::from(
-        r#"
-int_to_string:
-    xor rcx, rcx        ; Clear RCX (digit counter)
-    mov rbx, rdi        ; Save the buffer address in RBX
-    add rbx, 10         ; Point to the end of the buffer
-    mov byte [rbx], 0   ; Null-terminate the string
-
-convert_loop:
-    xor rdx, rdx        ; Clear RDX (remainder)
-    mov rcx, 10         ; Load the divisor (10) into RCX
-    div rcx             ; Divide RAX by RCX, quotient in RAX, remainder in RDX
-    add dl, '0'         ; Convert remainder to ASCII
-    dec rbx             ; Move buffer pointer backward
-    mov [rbx], dl       ; Store ASCII character in buffer
-    inc rcx             ; Increment digit counter
-    test rax, rax       ; Check if quotient is 0
-    jnz convert_loop    ; Repeat if RAX is not 0
-
-    mov rdi, rbx        ; Update RDI to point to the start of the string
-    mov rbx, 10         ; Return the string length in RBX
-    ret
-"#,
-    )
+// Sets RAX to 1 if the signed comparison of RAX with `right` satisfies
+// `condition`, else to 0.
+fn compare(condition: Condition, right: Operand) -> Vec<Instr> {
+    vec![
+        Instr::Alu(AluOp::Cmp, Operand::Register(Register::Rax), right),
+        Instr::Set(condition, Register::Rax),
+        Instr::Movzx(Register::Rax, Register::Rax),
+    ]
 }
 
+// Runtime routines shared by all print statements. print_str writes RDX bytes
+// starting at RSI, print_int writes the signed integer in RAX in decimal,
+// print_bool writes TRUE or FALSE for the boolean in RAX and print_newline
+// writes a single line feed. print_real writes the real whose bits are in RAX
+// with RDX digits after the point, or with up to 6 and no trailing zeros when
+// RDX is negative, and also clobbers RBX and XMM0 to XMM2. The *_width
+// variants right-align their output in a field of RCX characters. All of them
+// clobber RAX, RCX, RDX, RSI, RDI, R8 and R11. divide divides RAX by RBX,
+// leaving the quotient in RAX and the remainder in RDX; a zero divisor jumps
+// to runtime_error, which ends the program with exit status 1, and -1 negates
+// RAX, wrapping i64::MIN around rather than trapping in idiv. real_divide
+// divides the real in RAX by the one in RBX, and likewise ends the program
+// when the divisor is 0.0 or -0.0.
+fn print_runtime() -> Vec<Instr> {
+    use Register::*;
+
+    let label = |name| Instr::Label(Label::Named(name));
+    let register = Operand::Register;
+    let mov = |dest, source| Instr::Mov(Operand::Register(dest), source);
+    let jump = |condition, name| Instr::Jcc(condition, Label::Named(name));
+    let buffer_end = || Memory { displacement: 20, ..Memory::label(Label::Named("int_buffer")) };
+    let sys_write = [mov(Rax, Operand::Immediate(1)), mov(Rdi, Operand::Immediate(1)), Instr::Syscall];
+    let mut code = vec![
+        label("runtime_error"),
+        mov(Rax, Operand::Immediate(60)), // exit
+        mov(Rdi, Operand::Immediate(1)),
+        Instr::Syscall,
+    ];
+
+    code.push(label("divide"));
+    code.extend([Instr::Test(Rbx, Rbx), jump(Condition::Zero, "runtime_error")]);
+    code.push(Instr::Alu(AluOp::Cmp, register(Rbx), Operand::Immediate(-1)));
+    code.push(jump(Condition::Equal, "divide_by_minus_one"));
+    code.extend([Instr::Cqo, Instr::Idiv(Rbx), Instr::Ret]);
+
+    code.push(label("divide_by_minus_one"));
+    code.push(Instr::Neg(Rax));
+    code.extend([Instr::Alu(AluOp::Xor, register(Rdx), register(Rdx)), Instr::Ret]);
+
+    code.push(label("real_divide"));
+    code.push(mov(Rdx, register(Rbx)));
+    code.push(Instr::Shift(ShiftOp::Shl, Rdx, Some(1))); // Both zeros, without the sign bit
+    code.push(jump(Condition::Zero, "runtime_error"));
+    code.extend([Instr::MovqToXmm(Xmm(0), Rax), Instr::MovqToXmm(Xmm(1), Rbx)]);
+    code.push(Instr::Sse(SseOp::Div, Xmm(0), Xmm(1)));
+    code.extend([Instr::MovqFromXmm(Rax, Xmm(0)), Instr::Ret]);
+
+    code.push(label("print_str"));
+    code.extend(sys_write.clone());
+    code.push(Instr::Ret);
+
+    code.push(label("print_str_width"));
+    code.push(Instr::Alu(AluOp::Sub, register(Rcx), register(Rdx))); // Number of spaces needed
+    code.push(jump(Condition::LessEqual, "print_str"));
+    code.extend([Instr::Push(Rsi), Instr::Push(Rdx)]);
+
+    code.push(label("print_padding_loop"));
+    code.push(mov(Rdx, register(Rcx)));
+    code.push(Instr::Alu(AluOp::Cmp, register(Rdx), Operand::Immediate(16)));
+    code.push(jump(Condition::LessEqual, "print_padding_write"));
+    code.push(mov(Rdx, Operand::Immediate(16))); // At most one spaces buffer per write
+
+    code.push(label("print_padding_write"));
+    code.push(Instr::Alu(AluOp::Sub, register(Rcx), register(Rdx)));
+    code.push(Instr::Push(Rcx)); // syscall clobbers RCX
+    code.push(mov(Rsi, Operand::Address(Label::Named("spaces"))));
+    code.extend(sys_write.clone());
+    code.extend([Instr::Pop(Rcx), Instr::Test(Rcx, Rcx), jump(Condition::NotZero, "print_padding_loop")]);
+    code.extend([Instr::Pop(Rdx), Instr::Pop(Rsi), Instr::Jmp(Label::Named("print_str"))]);
+
+    code.push(label("print_newline"));
+    code.push(mov(Rsi, Operand::Address(Label::Named("newline"))));
+    code.push(mov(Rdx, Operand::Immediate(1)));
+    code.push(Instr::Jmp(Label::Named("print_str")));
+
+    code.push(label("print_bool"));
+    code.push(Instr::Alu(AluOp::Xor, register(Rcx), register(Rcx))); // No padding
+
+    code.push(label("print_bool_width"));
+    code.push(mov(Rsi, Operand::Address(Label::Named("true_text"))));
+    code.push(mov(Rdx, Operand::Immediate(4)));
+    code.extend([Instr::Test(Rax, Rax), jump(Condition::NotZero, "print_str_width")]);
+    code.push(mov(Rsi, Operand::Address(Label::Named("false_text"))));
+    code.push(mov(Rdx, Operand::Immediate(5)));
+    code.push(Instr::Jmp(Label::Named("print_str_width")));
+
+    code.push(label("print_int"));
+    code.push(Instr::Alu(AluOp::Xor, register(Rcx), register(Rcx))); // No padding
+
+    code.push(label("print_int_width"));
+    code.push(mov(Rdi, register(Rax))); // Keep the original value for the sign check
+    code.push(Instr::Lea(Rsi, buffer_end())); // Digits are written backwards from the end
+    code.push(mov(R8, Operand::Immediate(10))); // Divisor
+    code.extend([Instr::Test(Rax, Rax), jump(Condition::NotSign, "print_int_loop")]);
+    code.push(Instr::Neg(Rax)); // Magnitude; i64::MIN stays 2^63 when read unsigned
+
+    code.push(label("print_int_loop"));
+    code.push(Instr::Alu(AluOp::Xor, register(Rdx), register(Rdx))); // Clear RDX (remainder)
+    code.push(Instr::Div(R8)); // Unsigned divide RDX:RAX by 10
+    code.push(Instr::Alu(AluOp::Add, Operand::Byte(Rdx), Operand::Immediate(b'0' as i64))); // Remainder to ASCII
+    code.push(Instr::Dec(Rsi)); // Move buffer pointer backward
+    code.push(Instr::Mov(Operand::ByteMemory(Memory::register(Rsi, 0)), Operand::Byte(Rdx)));
+    code.extend([Instr::Test(Rax, Rax), jump(Condition::NotZero, "print_int_loop")]); // Until the quotient is 0
+
+    code.extend([Instr::Test(Rdi, Rdi), jump(Condition::NotSign, "print_int_write"), Instr::Dec(Rsi)]);
+    code.push(Instr::Mov(Operand::ByteMemory(Memory::register(Rsi, 0)), Operand::Immediate(b'-' as i64)));
+
+    code.push(label("print_int_write"));
+    code.push(Instr::Lea(Rdx, buffer_end()));
+    code.push(Instr::Alu(AluOp::Sub, register(Rdx), register(Rsi))); // Length of the converted number
+    code.push(Instr::Jmp(Label::Named("print_str_width")));
+    code.extend(print_real_runtime());
+    code
+}
+
+// print_real, which every target does the same way so that reals are written
+// alike everywhere. Numbers of 1e18 and more are divided by 10 until they fit
+// in an integer, with a zero written for each division. The part after the
+// point is scaled to an integer and rounded half up, which may carry into the
+// part before it. The text is built backwards in real_buffer and written by
+// print_str_width.
+fn print_real_runtime() -> Vec<Instr> {
+    use Register::*;
+
+    let label = |name| Instr::Label(Label::Named(name));
+    let register = Operand::Register;
+    let mov = |dest, source| Instr::Mov(Operand::Register(dest), source);
+    let jump = |condition, name| Instr::Jcc(condition, Label::Named(name));
+    let double = |value| Operand::Immediate(ir::to_bits(value));
+    let buffer_end = || Memory { displacement: 336, ..Memory::label(Label::Named("real_buffer")) };
+    let write_byte = |source| [Instr::Dec(Rsi), Instr::Mov(Operand::ByteMemory(Memory::register(Rsi, 0)), source)];
+    let mut code = vec![label("print_real")];
+    code.push(Instr::Alu(AluOp::Xor, register(Rcx), register(Rcx))); // No padding
+
+    code.push(label("print_real_width"));
+    code.push(mov(Rdi, register(Rax))); // Keep the sign bit
+    code.push(mov(Rbx, Operand::Immediate(i64::MAX)));
+    code.push(Instr::Alu(AluOp::And, register(Rax), register(Rbx))); // Magnitude
+    code.push(mov(Rbx, double(f64::INFINITY)));
+    code.push(Instr::Alu(AluOp::Cmp, register(Rax), register(Rbx)));
+    code.extend([jump(Condition::Greater, "print_real_nan"), jump(Condition::Equal, "print_real_infinity")]);
+    code.extend([Instr::Push(Rcx), Instr::Push(Rdi)]); // Width and sign, for the end
+    code.push(mov(R11, register(Rdx))); // Trailing zeros are left out while negative
+    code.push(mov(R8, Operand::Immediate(6))); // Digits after the point
+    code.extend([Instr::Test(Rdx, Rdx), jump(Condition::Sign, "print_real_digits")]);
+    code.push(mov(R8, Operand::Immediate(18)));
+    code.push(Instr::Alu(AluOp::Cmp, register(Rdx), register(R8)));
+    code.push(Instr::Cmov(Condition::Less, R8, Rdx));
+
+    code.push(label("print_real_digits"));
+    code.push(Instr::MovqToXmm(Xmm(0), Rax));
+    code.push(Instr::Alu(AluOp::Xor, register(Rcx), register(Rcx))); // Zeros before the point
+    code.push(mov(Rbx, double(1e18)));
+    code.push(Instr::MovqToXmm(Xmm(1), Rbx));
+    code.push(mov(Rbx, double(10.0)));
+    code.push(Instr::MovqToXmm(Xmm(2), Rbx));
+
+    code.push(label("print_real_scale"));
+    code.extend([Instr::Ucomisd(Xmm(1), Xmm(0)), jump(Condition::Above, "print_real_split")]);
+    code.push(Instr::Sse(SseOp::Div, Xmm(0), Xmm(2)));
+    code.push(Instr::Alu(AluOp::Add, register(Rcx), Operand::Immediate(1)));
+    code.push(Instr::Jmp(Label::Named("print_real_scale")));
+
+    code.push(label("print_real_split"));
+    code.push(Instr::Cvttsd2si(Rdi, Xmm(0))); // The part before the point
+    code.push(Instr::Cvtsi2sd(Xmm(1), Rdi));
+    code.push(Instr::Sse(SseOp::Sub, Xmm(0), Xmm(1)));
+    code.extend([Instr::Test(Rcx, Rcx), jump(Condition::Zero, "print_real_fraction")]);
+    code.push(Instr::Alu(AluOp::Xor, register(Rax), register(Rax)));
+    code.push(Instr::MovqToXmm(Xmm(0), Rax)); // Digits that were divided away are written as zeros
+
+    code.push(label("print_real_fraction"));
+    code.extend([mov(Rbx, Operand::Immediate(1)), mov(Rdx, register(R8))]);
+
+    code.push(label("print_real_power"));
+    code.extend([Instr::Test(Rdx, Rdx), jump(Condition::Zero, "print_real_round")]);
+    code.extend([Instr::Imul(Rbx, Operand::Immediate(10)), Instr::Dec(Rdx)]);
+    code.push(Instr::Jmp(Label::Named("print_real_power")));
+
+    code.push(label("print_real_round"));
+    code.push(Instr::Cvtsi2sd(Xmm(1), Rbx));
+    code.push(Instr::Sse(SseOp::Mul, Xmm(0), Xmm(1))); // Scaled by 10 to the number of digits
+    code.push(Instr::Cvttsd2si(Rax, Xmm(0)));
+    code.push(Instr::Cvtsi2sd(Xmm(1), Rax));
+    code.push(Instr::Sse(SseOp::Sub, Xmm(0), Xmm(1)));
+    code.push(mov(Rdx, double(0.5)));
+    code.push(Instr::MovqToXmm(Xmm(1), Rdx));
+    code.extend([Instr::Ucomisd(Xmm(1), Xmm(0)), jump(Condition::Above, "print_real_carry")]);
+    code.push(Instr::Alu(AluOp::Add, register(Rax), Operand::Immediate(1)));
+
+    code.push(label("print_real_carry"));
+    code.extend([Instr::Alu(AluOp::Cmp, register(Rax), register(Rbx)), jump(Condition::Less, "print_real_start")]);
+    code.push(Instr::Alu(AluOp::Sub, register(Rax), register(Rbx)));
+    code.push(Instr::Alu(AluOp::Add, register(Rdi), Operand::Immediate(1)));
+
+    code.push(label("print_real_start"));
+    code.push(Instr::Lea(Rsi, buffer_end())); // Written backwards from the end
+    code.push(mov(Rbx, Operand::Immediate(10))); // Divisor
+
+    code.push(label("print_real_fraction_loop"));
+    code.extend([Instr::Test(R8, R8), jump(Condition::Zero, "print_real_point")]);
+    code.push(Instr::Alu(AluOp::Xor, register(Rdx), register(Rdx)));
+    code.extend([Instr::Div(Rbx), Instr::Dec(R8)]);
+    code.extend([Instr::Test(R11, R11), jump(Condition::NotSign, "print_real_fraction_digit")]);
+    code.extend([Instr::Test(Rdx, Rdx), jump(Condition::NotZero, "print_real_fraction_digit")]);
+    code.extend([Instr::Test(R8, R8), jump(Condition::NotZero, "print_real_fraction_loop")]); // Keep the last digit
+
+    code.push(label("print_real_fraction_digit"));
+    code.push(Instr::Alu(AluOp::Xor, register(R11), register(R11))); // Zeros before a kept digit stay
+    code.push(Instr::Alu(AluOp::Add, Operand::Byte(Rdx), Operand::Immediate(b'0' as i64)));
+    code.extend(write_byte(Operand::Byte(Rdx)));
+    code.push(Instr::Jmp(Label::Named("print_real_fraction_loop")));
+
+    code.push(label("print_real_point"));
+    code.push(Instr::Lea(Rdx, buffer_end()));
+    code.extend([Instr::Alu(AluOp::Cmp, register(Rsi), register(Rdx)), jump(Condition::Equal, "print_real_zeros")]);
+    code.extend(write_byte(Operand::Immediate(b'.' as i64)));
+
+    code.push(label("print_real_zeros"));
+    code.extend([Instr::Test(Rcx, Rcx), jump(Condition::Zero, "print_real_whole")]);
+    code.extend(write_byte(Operand::Immediate(b'0' as i64)));
+    code.push(Instr::Dec(Rcx));
+    code.push(Instr::Jmp(Label::Named("print_real_zeros")));
+
+    code.push(label("print_real_whole"));
+    code.push(mov(Rax, register(Rdi)));
+
+    code.push(label("print_real_whole_loop"));
+    code.push(Instr::Alu(AluOp::Xor, register(Rdx), register(Rdx)));
+    code.push(Instr::Div(Rbx));
+    code.push(Instr::Alu(AluOp::Add, Operand::Byte(Rdx), Operand::Immediate(b'0' as i64)));
+    code.extend(write_byte(Operand::Byte(Rdx)));
+    code.extend([Instr::Test(Rax, Rax), jump(Condition::NotZero, "print_real_whole_loop")]);
+
+    code.extend([Instr::Pop(Rdi), Instr::Test(Rdi, Rdi), jump(Condition::NotSign, "print_real_write")]);
+    code.extend(write_byte(Operand::Immediate(b'-' as i64)));
+
+    code.push(label("print_real_write"));
+    code.push(Instr::Lea(Rdx, buffer_end()));
+    code.push(Instr::Alu(AluOp::Sub, register(Rdx), register(Rsi)));
+    code.push(Instr::Pop(Rcx));
+    code.push(Instr::Jmp(Label::Named("print_str_width")));
+
+    code.push(label("print_real_nan"));
+    code.push(mov(Rsi, Operand::Address(Label::Named("nan_text"))));
+    code.push(mov(Rdx, Operand::Immediate(3)));
+    code.push(Instr::Jmp(Label::Named("print_str_width")));
+
+    // -Inf, or Inf from the second byte on
+    code.push(label("print_real_infinity"));
+    code.push(mov(Rsi, Operand::Address(Label::Named("infinity_text"))));
+    code.push(mov(Rdx, Operand::Immediate(4)));
+    code.extend([Instr::Test(Rdi, Rdi), jump(Condition::Sign, "print_str_width")]);
+    code.extend([Instr::Alu(AluOp::Add, register(Rsi), Operand::Immediate(1)), Instr::Dec(Rdx)]);
+    code.push(Instr::Jmp(Label::Named("print_str_width")));
+    code
+}
+
+/// Instruction selection for a load/store machine whose code is GNU as text,
+/// which is all that differs between the AArch64 and RISC-V backends. The
+/// blocks, data and calling of the print routines are generated by
+/// [`generate_text`].
+trait Isa: Sized {
+    /// The scratch registers for the result and the left operand of an
+    /// instruction.
+    const RESULT: &'static str;
+    const LEFT: &'static str;
+    /// The frame pointer, and the scratch register used for addresses.
+    const FRAME: &'static str;
+    const ADDRESS: &'static str;
+    /// The registers the print routines take their arguments in.
+    const ARGUMENTS: [&'static str; 4];
+    /// A register that always reads as zero, if the machine has one.
+    const ZERO: Option<&'static str> = None;
+    const LOAD: &'static str;
+    const STORE: &'static str;
+    const MOVE: &'static str;
+    const CALL: &'static str;
+    const JUMP: &'static str;
+    const BRANCH_IF_ZERO: &'static str;
+    const BRANCH_IF_NOT_ZERO: &'static str;
+    /// The code that ends the program with exit status 0.
+    const EXIT: &'static str;
+    /// The print routines and runtime_error, appended after the program.
+    const RUNTIME: &'static str;
+
+    fn constant(generator: &mut TextGenerator<'_, Self>, register: &str, value: i64);
+    /// Loads or stores `register` with `instruction` from or to the memory of
+    /// `var`.
+    fn memory(generator: &mut TextGenerator<'_, Self>, instruction: &str, register: &str, var: &Var);
+    /// Puts the address of `label` in `register`.
+    fn address(generator: &mut TextGenerator<'_, Self>, register: &str, label: &Label);
+    fn unary(operator: UnaryOperator, target: &str, operand: &str) -> String;
+    /// Computes `left operator right` into `target`. Every sequence writes
+    /// `target` last, so it may be the register of an operand.
+    fn binary(
+        generator: &mut TextGenerator<'_, Self>,
+        operator: BinaryOperator,
+        target: &str,
+        left: &str,
+        right: &ir::Operand,
+    );
+}
+
+/// Generates GNU as assembly for `I` from the IR, with variables where
+/// `allocation` puts them: in registers, named ones in .data and temporaries
+/// in a stack frame addressed from `I::FRAME`.
+fn generate_text<I: Isa>(program: &Program, allocation: &Allocation<&'static str>) -> String {
+    let mut generator = TextGenerator::<I>::new(allocation);
+    // The stack pointer must stay 16-byte aligned
+    let frame = (8 * allocation.slot_count as i64 + 15) / 16 * 16;
+    if frame > 0 {
+        I::constant(&mut generator, I::ADDRESS, frame);
+        generator.emit(&format!("sub sp, sp, {}", I::ADDRESS));
+        generator.emit(&format!("{} {}, sp", I::MOVE, I::FRAME));
+    }
+    for register in &allocation.zeroed {
+        I::constant(&mut generator, register, 0);
+    }
+    for (index, block) in program.blocks.iter().enumerate() {
+        generator.emit(&format!("{}:", Label::Block(BlockId(index))));
+        for instruction in &block.instructions {
+            generator.instruction(instruction, program);
+        }
+        generator.terminator(&block.terminator, BlockId(index + 1));
+    }
+
+    let mut assembly = String::from(".data\n");
+    for name in &program.variables {
+        if !allocation.registers.contains_key(&Var::Named(name.clone())) {
+            // AArch64 needs 64-bit loads aligned for the low 12 bits of the
+            // address
+            assembly.push_str(&format!(".balign 8\n{}: .quad 0\n", Label::Variable(name.clone())));
+        }
+    }
+    for (i, literal) in program.strings.iter().enumerate() {
+        assembly.push_str(&bytes(&Label::String(i), literal));
+    }
+    assembly.push_str(&bytes(&Label::Named("newline"), b"\n"));
+    assembly.push_str(&bytes(&Label::Named("true_text"), b"TRUE"));
+    assembly.push_str(&bytes(&Label::Named("false_text"), b"FALSE"));
+    assembly.push_str(&bytes(&Label::Named("spaces"), &[b' '; 16]));
+    assembly.push_str(&bytes(&Label::Named("nan_text"), b"NaN"));
+    assembly.push_str(&bytes(&Label::Named("infinity_text"), b"-Inf"));
+    assembly.push_str(".bss\nint_buffer: .skip 20\nreal_buffer: .skip 336\n");
+    assembly.push_str(".text\n.globl _start\n_start:\n");
+    assembly.push_str(&generator.text);
+    assembly.push_str(I::RUNTIME);
+    assembly
+}
+
+fn bytes(label: &Label, bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return format!("{}:\n", label);
+    }
+    let bytes: Vec<String> = bytes.iter().map(|byte| byte.to_string()).collect();
+    format!("{}: .byte {}\n", label, bytes.join(", "))
+}
+
+// Translates one IR instruction at a time into the text of `I`. Values are
+// computed in the scratch registers of `I` unless they have a register.
+struct TextGenerator<'a, I> {
+    text: String,
+    allocation: &'a Allocation<&'static str>,
+    isa: PhantomData<I>,
+}
+
+impl<'a, I: Isa> TextGenerator<'a, I> {
+    fn new(allocation: &'a Allocation<&'static str>) -> Self {
+        TextGenerator { text: String::new(), allocation, isa: PhantomData }
+    }
+
+    fn emit(&mut self, line: &str) {
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    fn register(&self, var: &Var) -> Option<&'static str> {
+        self.allocation.registers.get(var).copied()
+    }
+
+    // The register holding `operand`: its own, the zero register for 0, or
+    // `scratch` loaded with it.
+    fn load(&mut self, scratch: &'static str, operand: &ir::Operand) -> &'static str {
+        match operand {
+            ir::Operand::Var(var) if let Some(register) = self.register(var) => register,
+            ir::Operand::Var(var) => {
+                I::memory(self, I::LOAD, scratch, var);
+                scratch
+            }
+            ir::Operand::Constant(0) if let Some(zero) = I::ZERO => zero,
+            ir::Operand::Constant(value) => {
+                I::constant(self, scratch, *value);
+                scratch
+            }
+        }
+    }
+
+    // Loads `operand` into exactly `register`.
+    fn load_into(&mut self, register: &'static str, operand: &ir::Operand) {
+        match operand {
+            ir::Operand::Constant(value) => I::constant(self, register, *value),
+            _ => {
+                let source = self.load(register, operand);
+                if source != register {
+                    self.emit(&format!("{} {}, {}", I::MOVE, register, source));
+                }
+            }
+        }
+    }
+
+    // Where the result for `dest` is computed: its register, or I::RESULT.
+    fn target(&self, dest: &Var) -> &'static str {
+        self.register(dest).unwrap_or(I::RESULT)
+    }
+
+    // Stores the result computed in `target` if `dest` lives in memory.
+    fn store(&mut self, dest: &Var, target: &str) {
+        if self.register(dest).is_none() {
+            I::memory(self, I::STORE, target, dest);
+        }
+    }
+
+    // Ends the program with runtime_error when `divisor`, held in `register`,
+    // is zero, as the division instructions of these machines do not trap
+    // like on x86-64. Divisions by other constants need no check.
+    fn check_divisor(&mut self, divisor: &ir::Operand, register: &str) {
+        if !matches!(divisor, ir::Operand::Constant(value) if *value != 0) {
+            self.emit(&format!("{} {}, runtime_error", I::BRANCH_IF_ZERO, register));
+        }
+    }
+
+    fn instruction(&mut self, instruction: &Instruction, program: &Program) {
+        match instruction {
+            Instruction::Copy { dest, source } => {
+                let target = self.target(dest);
+                self.load_into(target, source);
+                self.store(dest, target);
+            }
+            Instruction::Unary { dest, operator, operand } => {
+                let target = self.target(dest);
+                let operand = self.load(I::LEFT, operand);
+                self.emit(&I::unary(*operator, target, operand));
+                self.store(dest, target);
+            }
+            Instruction::Binary { dest, operator, left, right } => {
+                let target = self.target(dest);
+                let left = self.load(I::LEFT, left);
+                I::binary(self, *operator, target, left, right);
+                self.store(dest, target);
+            }
+            Instruction::Print { value, width } => {
+                // The padded routines take the field width as the fourth
+                // argument, and print_real the number of digits after the
+                // point as the third, or -1 for the default
+                let [value_register, address, length, width_register] = I::ARGUMENTS;
+                let suffix = if width.is_some() { "_width" } else { "" };
+                let routine = match value {
+                    PrintValue::String(index) => {
+                        I::address(self, address, &Label::String(*index));
+                        I::constant(self, length, program.strings[*index].len() as i64);
+                        "print_str"
+                    }
+                    PrintValue::Integer(operand) => {
+                        self.load_into(value_register, operand);
+                        "print_int"
+                    }
+                    PrintValue::Boolean(operand) => {
+                        self.load_into(value_register, operand);
+                        "print_bool"
+                    }
+                    PrintValue::Real(operand, precision) => {
+                        self.load_into(value_register, operand);
+                        match precision {
+                            Some(precision) => self.load_into(length, precision),
+                            None => I::constant(self, length, -1),
+                        }
+                        "print_real"
+                    }
+                };
+                if let Some(width) = width {
+                    self.load_into(width_register, width);
+                }
+                self.emit(&format!("{} {}{}", I::CALL, routine, suffix));
+            }
+            Instruction::PrintNewline => self.emit(&format!("{} print_newline", I::CALL)),
+        }
+    }
+
+    // Ends a block. Jumps to the block laid out next are left out.
+    fn terminator(&mut self, terminator: &Terminator, next: BlockId) {
+        match terminator {
+            Terminator::Jump(target) if *target == next => {}
+            Terminator::Jump(target) => self.emit(&format!("{} {}", I::JUMP, Label::Block(*target))),
+            Terminator::Branch { condition, if_true, if_false } => {
+                let register = self.load(I::RESULT, condition);
+                if *if_true == next {
+                    self.emit(&format!("{} {}, {}", I::BRANCH_IF_ZERO, register, Label::Block(*if_false)));
+                } else {
+                    self.emit(&format!("{} {}, {}", I::BRANCH_IF_NOT_ZERO, register, Label::Block(*if_true)));
+                    if *if_false != next {
+                        self.emit(&format!("{} {}", I::JUMP, Label::Block(*if_false)));
+                    }
+                }
+            }
+            Terminator::Exit => self.emit(I::EXIT),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::Syntax;
+    use crate::testing::{
+        exit_status, run_builtin, run_builtin_status, run_program, run_with_options, CONFORMANCE_OUTPUT,
+        CONFORMANCE_PROGRAM, REAL_DIVISION_BY_ZERO, REAL_OUTPUT, REAL_PROGRAM,
+    };
+    use crate::{compile, Options, Target};
+
+    #[test]
+    fn test_write_and_writeln_output() {
+        let source = r#"
+            var
+                a, b: integer;
+            begin
+                a := 10;
+                b := -20;
+                write('a=', a);
+                writeln(' b=', b);
+                writeln;
+                writeln(0, ' ', a * b * 1000000000);
+            end
+        "#;
+
+        let stdout = run_program(source);
+        assert_eq!(stdout, b"a=10 b=-20\n\n0 -200000000000\n");
+    }
+
+    #[test]
+    fn test_division_results_for_all_sign_combinations() {
+        let operators = [
+            (BinaryOperator::Div, "div"),
+            (BinaryOperator::Mod, "mod"),
+            (BinaryOperator::Rem, "rem"),
+            (BinaryOperator::Divide, "/"),
+        ];
+        let operands = [(7, 2), (-7, 2), (7, -2), (-7, -2), (6, 3), (-6, -3), (0, -5), (-1, 5), (1, -5)];
+
+        let mut source = String::from("var a, b: integer;\nbegin\n");
+        let mut expected = String::new();
+        for (left, right) in operands {
+            source.push_str(&format!("a := {}; b := {};\n", left, right));
+            for (operator, symbol) in operators {
+                source.push_str(&format!("writeln(a {} b);\n", symbol));
+                expected.push_str(&format!("{}\n", operator.apply(left, right).unwrap()));
+            }
+        }
+        source.push_str("end");
+
+        // At -O0 the divisions are done by the generated code, not folded.
+        let stdout = run_builtin(&source, &Options::default());
+        assert_eq!(String::from_utf8(stdout).unwrap(), expected);
+    }
+
+    #[test]
+    #[ignore = "needs cc, the aarch64 and riscv64 binutils, qemu-user, wat2wasm and wasmtime"]
+    fn test_conformance_program_on_every_target() {
+        let targets = [Target::X86_64Linux, Target::Aarch64Linux, Target::Riscv64Linux, Target::C, Target::Wasm];
+        for target in targets {
+            for opt_level in [0, 1, 2] {
+                let options = Options { opt_level, target, ..Options::default() };
+                let stdout = run_with_options(CONFORMANCE_PROGRAM, &options);
+                let stdout = String::from_utf8(stdout).unwrap();
+                assert_eq!(stdout, CONFORMANCE_OUTPUT, "{:?} at -O{}", target, opt_level);
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs cc, the aarch64 and riscv64 binutils, qemu-user, wat2wasm and wasmtime"]
+    fn test_strings_keep_every_byte_on_every_target() {
+        let source = "var i: integer; begin writeln('caf'#233, #128#200#255:5, 'é', 'a'#0'b', 'x'#0:i + 4); end";
+        let targets = [Target::X86_64Linux, Target::Aarch64Linux, Target::Riscv64Linux, Target::C, Target::Wasm];
+        for target in targets {
+            let options = Options { target, ..Options::default() };
+            let stdout = run_with_options(source, &options);
+            assert_eq!(stdout, b"caf\xe9  \x80\xc8\xff\xc3\xa9a\0b  x\0\n", "{:?}", target);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs cc, the aarch64 and riscv64 binutils, qemu-user, wat2wasm and wasmtime"]
+    fn test_division_overflow_and_by_zero_on_every_target() {
+        let overflow = "var a, b: integer; begin a := -9223372036854775807 - 1; b := -1; \
+                        writeln(a div b, ' ', a mod b, ' ', a rem b, ' ', a / b); end";
+        let by_zero = "var a, b: integer; begin a := 7; writeln(a div 2); writeln(a mod b); end";
+
+        // i64::MIN div -1 wraps around, and dividing by zero exits with status 1
+        let targets = [Target::X86_64Linux, Target::Aarch64Linux, Target::Riscv64Linux, Target::C, Target::Wasm];
+        for target in targets {
+            for opt_level in [0, 1, 2] {
+                let options = Options { opt_level, target, ..Options::default() };
+                let stdout = run_with_options(overflow, &options);
+                let stdout = String::from_utf8(stdout).unwrap();
+                assert_eq!(
+                    stdout, "-9223372036854775808 0 0 -9223372036854775808\n",
+                    "{:?} at -O{}",
+                    target, opt_level
+                );
+                let status = exit_status(by_zero, &options);
+                assert_eq!(status.code(), Some(1), "{:?} at -O{}", target, opt_level);
+            }
+        }
+    }
+
+    #[test]
+    fn test_binary_operation_keeps_left_operand_in_rax() {
+        let instruction = Instruction::Binary {
+            dest: Var::Temp(1),
+            operator: BinaryOperator::Div,
+            left: ir::Operand::Var(Var::Named("a".to_string())),
+            right: ir::Operand::Var(Var::Named("b".to_string())),
+        };
+
+        let allocation = Allocation::in_memory(&Program { temp_count: 2, ..Program::default() });
+        let mut generator = CodeGenerator { text: Vec::new(), allocation: &allocation };
+        generator.instruction(&instruction, &Program::default());
+
+        let variable = |name: &str| Operand::Memory(Memory::label(Label::Variable(name.to_string())));
+        let expected = [
+            Instr::Mov(Operand::Register(Register::Rax), variable("a")),
+            Instr::Mov(Operand::Register(Register::Rbx), variable("b")),
+            Instr::Call(Label::Named("divide")),
+            Instr::Mov(Operand::Memory(Memory::register(Register::Rbp, -16)), Operand::Register(Register::Rax)),
+        ];
+        assert_eq!(generator.text, expected);
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let source = r#"
+            var
+                i, n, sum: integer;
+                found: boolean;
+            begin
+                n := 5;
+                for i := 1 to n do begin
+                    n := 0; { The bounds are only evaluated once }
+                    write(i);
+                end;
+                for i := 3 downto 1 do write(' ', i);
+                writeln;
+                i := 0;
+                sum := 0;
+                while i < 10 do begin
+                    i := i + 1;
+                    if i mod 2 = 0 then
+                        sum := sum + i
+                    else if i = 7 then
+                        writeln('seven')
+                    else
+                        sum := sum - 1;
+                end;
+                writeln(sum, ' ', i);
+                found := (n <> 0) and (10 div n > 1); { No division by zero }
+                writeln(found, ' ', (n = 0) or (10 div n > 1), ' ', not found, ' ', 1 <= 1, ' ', -1 >= 0);
+                for i := 9223372036854775806 to 9223372036854775807 do write(i > 0);
+                writeln;
+            end
+        "#;
+
+        let expected = "12345 3 2 1\nseven\n26 10\nFALSE TRUE TRUE TRUE FALSE\nTRUETRUE\n";
+        for opt_level in [0, 1] {
+            let stdout = run_with_options(source, &Options { opt_level, ..Options::default() });
+            assert_eq!(String::from_utf8(stdout).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_field_widths_right_align_output() {
+        let source = r#"
+            var
+                n, w: integer;
+                ok: boolean;
+            begin
+                n := -42;
+                w := 6;
+                ok := true;
+                writeln('[', n:6, '][', 'ab':4, '][', ok:w, '][', false:2, ']');
+                writeln('[', 12345:3, '][', 7:w * 4, ']');
+            end
+        "#;
+
+        let stdout = run_program(source);
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            "[   -42][  ab][  TRUE][FALSE]\n[12345][                       7]\n"
+        );
+    }
+
+    #[test]
+    fn test_reals_with_width_and_precision() {
+        let stdout = run_program(REAL_PROGRAM);
+        assert_eq!(String::from_utf8(stdout).unwrap(), REAL_OUTPUT);
+        assert!(compile("var x: integer; begin writeln(x:8:2); end", &Options::default()).is_err());
+    }
+
+    #[test]
+    fn test_reals_at_every_level() {
+        for opt_level in [0, 1, 2] {
+            let options = Options { opt_level, ..Options::default() };
+            let stdout = run_builtin(REAL_PROGRAM, &options);
+            assert_eq!(String::from_utf8(stdout).unwrap(), REAL_OUTPUT, "-O{}", opt_level);
+            let status = run_builtin_status(REAL_DIVISION_BY_ZERO, &options);
+            assert_eq!(status.code(), Some(1), "-O{}", opt_level);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs cc, the aarch64 and riscv64 binutils, qemu-user, wat2wasm and wasmtime"]
+    fn test_reals_on_every_target() {
+        let targets = [Target::X86_64Linux, Target::Aarch64Linux, Target::Riscv64Linux, Target::C, Target::Wasm];
+        for target in targets {
+            for opt_level in [0, 1, 2] {
+                let options = Options { opt_level, target, ..Options::default() };
+                let stdout = run_with_options(REAL_PROGRAM, &options);
+                assert_eq!(String::from_utf8(stdout).unwrap(), REAL_OUTPUT, "{:?} at -O{}", target, opt_level);
+                let status = exit_status(REAL_DIVISION_BY_ZERO, &options);
+                assert_eq!(status.code(), Some(1), "{:?} at -O{}", target, opt_level);
+            }
+        }
+    }
+
+    #[test]
+    fn test_string_literals_with_quotes_and_control_characters() {
+        let source = "var x: integer; begin write('It''s ', 'a;b', ''); writeln(#9'tab'#13#10'end'); end";
+
+        let stdout = run_program(source);
+        assert_eq!(stdout, b"It's a;b\ttab\r\nend\n");
+    }
+
+    #[test]
+    fn test_string_literals_keep_every_byte() {
+        let source = "var x: integer; begin writeln('caf'#233, #128#200#255:5, 'é'); end";
+
+        let stdout = run_program(source);
+        assert_eq!(stdout, b"caf\xe9  \x80\xc8\xff\xc3\xa9\n");
+    }
+
+    #[test]
+    fn test_string_data_is_emitted_as_bytes() {
+        let program = Program { strings: vec![b"a'b".to_vec(), Vec::new()], ..Program::default() };
+        let assembly = generate_code(&program, &Allocation::default(), false).to_nasm();
+
+        assert!(assembly.contains("string_0 db 97, 39, 98\nstring_1:\n"), "{}", assembly);
+    }
+
+    #[test]
+    fn test_int64_literals() {
+        let source = r#"
+            var
+                big: int64;
+            begin
+                big := 3_000_000_000 * $10;
+                writeln(big, ' ', 16#7FFF_FFFF_FFFF_FFFF#, ' ', -9223372036854775807 - 1);
+            end
+        "#;
+
+        let stdout = run_program(source);
+        assert_eq!(stdout, b"48000000000 9223372036854775807 -9223372036854775808\n");
+    }
+
+    #[test]
+    fn test_register_allocation_spills_when_out_of_registers() {
+        // The loop hides the values from constant folding, and the nested
+        // products keep more temporaries live than there are registers
+        let source = r#"
+            var i, a, b, c, d, e, f, g: integer;
+            begin
+                for i := 1 to 1 do begin
+                    a := i; b := i + 1; c := i + 2; d := i + 3; e := i + 4; f := i + 5; g := i + 6;
+                end;
+                writeln((a + 1) * ((b + 1) * ((c + 1) * ((d + 1) *
+                    ((e + 1) * ((f + 1) * ((g + 1) * (a + b + c + d + e + f + g))))))));
+                writeln(a, b, c, d, e, f, g);
+            end
+        "#;
+
+        let options = Options { opt_level: 1, ..Options::default() };
+        let assembly = compile(source, &options).unwrap().assembly;
+        assert!(assembly.contains("mov rbp, rsp\n") && assembly.contains("r15"), "{}", assembly);
+        let stdout = run_with_options(source, &options);
+        assert_eq!(stdout, b"1128960\n1234567\n");
+    }
+
+    #[test]
+    fn test_gas_syntax_runs_like_nasm() {
+        let source = r#"
+            var
+                i, a, b: integer;
+            begin
+                for i := 1 to 2 do begin
+                    a := i * 7; b := i - 4;
+                end;
+                writeln('[', a div b:4, '][', a mod b, '][', a * 1099511627776, '][', a = 14:6, ']');
+                writeln(-9223372036854775807 - 1, ' ', a * 1_000_000_000_000);
+            end
+        "#;
+
+        for opt_level in [0, 1, 2] {
+            let options = Options { opt_level, asm_syntax: Syntax::Gas, ..Options::default() };
+            assert!(compile(source, &options).unwrap().assembly.contains("movq "));
+            let stdout = run_with_options(source, &options);
+            assert_eq!(stdout, b"[  -7][0][15393162788864][  TRUE]\n-9223372036854775808 14000000000000\n");
+        }
+    }
+
+    #[test]
+    fn test_variables_named_like_registers_and_runtime_labels() {
+        let source = "
+            var rax, newline, spaces, int_buffer, _start, block_0, string_0: integer;
+            begin
+                rax := 1; newline := 2; spaces := 3; int_buffer := 4; _start := 5; block_0 := 6; string_0 := 7;
+                writeln(rax, newline, spaces, int_buffer, _start, block_0, string_0:3);
+            end
+        ";
+
+        for asm_syntax in [Syntax::Nasm, Syntax::Gas] {
+            let options = Options { asm_syntax, ..Options::default() };
+            assert!(compile(source, &options).unwrap().assembly.contains("var_rax"));
+            let stdout = run_with_options(source, &options);
+            assert_eq!(stdout, b"123456  7\n");
+        }
+        assert_eq!(run_builtin(source, &Options::default()), b"123456  7\n");
+    }
+
+    #[test]
+    fn test_negation_in_a_register() {
+        let source = "var i, n: integer; begin for i := 1 to 3 do n := n + i; writeln(-n, ' ', -n div 4); end";
+
+        for opt_level in [0, 1] {
+            let options = Options { opt_level, ..Options::default() };
+            assert_eq!(run_builtin(source, &options), b"-6 -1\n");
+        }
+    }
+
+    #[test]
+    fn test_exit_ends_the_program() {
+        let source = "var i: integer; begin for i := 1 to 10 do begin write(i); if i = 3 then exit; end; writeln; end";
+
+        for opt_level in [0, 1] {
+            let stdout = run_with_options(source, &Options { opt_level, ..Options::default() });
+            assert_eq!(stdout, b"123");
+        }
+    }
+}
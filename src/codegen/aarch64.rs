@@ -0,0 +1,568 @@
+//! Code generation for AArch64 Linux, as GNU as source.
+//!
+//! Values are computed in scratch registers: X10 and X11 hold the operands,
+//! X9 the result, and X12, X13 and X16 the intermediate values and addresses
+//! of longer sequences. Reals are operated on in D0 and D1. Variables that get a register live in X19 to X28,
+//! which the print routines leave alone; the others are in .data or, for
+//! temporaries, in a stack frame addressed from X29.
+
+use crate::asm::Label;
+use crate::codegen::{generate_text, Isa, TextGenerator};
+use crate::ir::{BinaryOperator, Operand, Program, UnaryOperator, Var};
+use crate::regalloc::Allocation;
+
+/// The registers given to variables, all callee-saved in the procedure call
+/// standard.
+pub const REGISTERS: [&str; 10] = ["x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27", "x28"];
+
+/// Generates GNU as assembly for AArch64 Linux from the IR, with variables
+/// where `allocation` puts them.
+pub fn generate_code(program: &Program, allocation: &Allocation<&'static str>) -> String {
+    generate_text::<Aarch64>(program, allocation)
+}
+
+struct Aarch64;
+
+impl Isa for Aarch64 {
+    const RESULT: &'static str = "x9";
+    const LEFT: &'static str = "x10";
+    const FRAME: &'static str = "x29";
+    const ADDRESS: &'static str = "x16";
+    const ARGUMENTS: [&'static str; 4] = ["x0", "x1", "x2", "x3"];
+    const LOAD: &'static str = "ldr";
+    const STORE: &'static str = "str";
+    const MOVE: &'static str = "mov";
+    const CALL: &'static str = "bl";
+    const JUMP: &'static str = "b";
+    const BRANCH_IF_ZERO: &'static str = "cbz";
+    const BRANCH_IF_NOT_ZERO: &'static str = "cbnz";
+    const EXIT: &'static str = "mov x0, #0\nmov x8, #93\nsvc #0";
+    const RUNTIME: &'static str = PRINT_RUNTIME;
+
+    // Moves `value` into `register`: with a single mov where it fits in 16
+    // bits, else 16 bits at a time.
+    fn constant(generator: &mut TextGenerator<'_, Self>, register: &str, value: i64) {
+        if (-65536..65536).contains(&value) {
+            generator.emit(&format!("mov {}, #{}", register, value));
+            return;
+        }
+        generator.emit(&format!("movz {}, #{}", register, value & 0xffff));
+        for shift in [16, 32, 48] {
+            let chunk = (value >> shift) & 0xffff;
+            if chunk != 0 {
+                generator.emit(&format!("movk {}, #{}, lsl #{}", register, chunk, shift));
+            }
+        }
+    }
+
+    // Stack slots beyond the reach of an immediate offset are addressed
+    // through X16.
+    fn memory(generator: &mut TextGenerator<'_, Self>, instruction: &str, register: &str, var: &Var) {
+        match var {
+            Var::Named(name) => {
+                let label = Label::Variable(name.clone());
+                generator.emit(&format!("adrp x16, {}", label));
+                generator.emit(&format!("{} {}, [x16, :lo12:{}]", instruction, register, label));
+            }
+            Var::Temp(_) => {
+                let offset = 8 * generator.allocation.slots[var] as i64;
+                if offset <= 32760 {
+                    generator.emit(&format!("{} {}, [x29, #{}]", instruction, register, offset));
+                } else {
+                    Self::constant(generator, "x16", offset);
+                    generator.emit(&format!("{} {}, [x29, x16]", instruction, register));
+                }
+            }
+        }
+    }
+
+    fn address(generator: &mut TextGenerator<'_, Self>, register: &str, label: &Label) {
+        generator.emit(&format!("adrp {}, {}", register, label));
+        generator.emit(&format!("add {0}, {0}, :lo12:{1}", register, label));
+    }
+
+    fn unary(operator: UnaryOperator, target: &str, operand: &str) -> String {
+        match operator {
+            UnaryOperator::Negate => format!("neg {}, {}", target, operand),
+            UnaryOperator::Not => format!("eor {}, {}, #1", target, operand),
+            UnaryOperator::RealNegate => format!("fmov d0, {}\nfneg d0, d0\nfmov {}, d0", operand, target),
+            UnaryOperator::ToReal => format!("scvtf d0, {}\nfmov {}, d0", operand, target),
+        }
+    }
+
+    fn binary(
+        generator: &mut TextGenerator<'_, Self>,
+        operator: BinaryOperator,
+        target: &str,
+        left: &str,
+        right: &Operand,
+    ) {
+        use BinaryOperator::*;
+
+        if operator.is_real() {
+            return real_binary(generator, operator, target, left, right);
+        }
+        // Shift counts, and small constants added, subtracted or compared
+        // with, are immediates
+        let condition = match operator {
+            Equal => Some("eq"),
+            NotEqual => Some("ne"),
+            Less => Some("lt"),
+            LessEqual => Some("le"),
+            Greater => Some("gt"),
+            GreaterEqual => Some("ge"),
+            _ => None,
+        };
+        match (operator, right) {
+            (ShiftLeft | ShiftRight, Operand::Constant(count)) => {
+                let mnemonic = if operator == ShiftLeft { "lsl" } else { "asr" };
+                generator.emit(&format!("{} {}, {}, #{}", mnemonic, target, left, count & 63));
+                return;
+            }
+            (Add | Subtract, Operand::Constant(value)) if (0..4096).contains(value) => {
+                let mnemonic = if operator == Add { "add" } else { "sub" };
+                generator.emit(&format!("{} {}, {}, #{}", mnemonic, target, left, value));
+                return;
+            }
+            (_, Operand::Constant(value))
+                if let Some(condition) = condition
+                    && (0..4096).contains(value) =>
+            {
+                compare(generator, condition, target, left, &format!("#{}", value));
+                return;
+            }
+            _ => {}
+        }
+        let register = generator.load("x11", right);
+        if matches!(operator, Divide | Div | Rem | Mod) {
+            generator.check_divisor(right, register);
+        }
+        let right = register;
+        let three_operand = |mnemonic: &str| format!("{} {}, {}, {}", mnemonic, target, left, right);
+        match operator {
+            Add => generator.emit(&three_operand("add")),
+            Subtract => generator.emit(&three_operand("sub")),
+            Multiply => generator.emit(&three_operand("mul")),
+            And => generator.emit(&three_operand("and")),
+            Or => generator.emit(&three_operand("orr")),
+            // Shift counts are taken modulo 64, as on x86-64
+            ShiftLeft => generator.emit(&three_operand("lsl")),
+            ShiftRight => generator.emit(&three_operand("asr")),
+            // sdiv truncates toward zero
+            Divide | Div => generator.emit(&three_operand("sdiv")),
+            // The remainder left - quotient * right takes the sign of left
+            Rem => {
+                generator.emit(&format!("sdiv x12, {}, {}", left, right));
+                generator.emit(&format!("msub {}, x12, {}, {}", target, right, left));
+            }
+            // mod takes the sign of the divisor: add the divisor to a non-zero
+            // remainder whose sign differs from it
+            Mod => {
+                generator.emit(&format!("sdiv x12, {}, {}", left, right));
+                generator.emit(&format!("msub x12, x12, {}, {}", right, left));
+                generator.emit(&format!("eor x13, x12, {}", right));
+                generator.emit("asr x13, x13, #63");
+                generator.emit("cmp x12, #0");
+                generator.emit("csel x13, xzr, x13, eq");
+                generator.emit(&format!("and x13, x13, {}", right));
+                generator.emit(&format!("add {}, x12, x13", target));
+            }
+            Equal | NotEqual | Less | LessEqual | Greater | GreaterEqual => {
+                compare(generator, condition.unwrap(), target, left, right)
+            }
+            _ => unreachable!("{} is an operator on reals", operator.symbol()),
+        }
+    }
+}
+
+// Computes the real `left operator right` into `target` through D0 and D1.
+fn real_binary(
+    generator: &mut TextGenerator<'_, Aarch64>,
+    operator: BinaryOperator,
+    target: &str,
+    left: &str,
+    right: &Operand,
+) {
+    use BinaryOperator::*;
+
+    let register = generator.load("x11", right);
+    // Divisors that may be 0.0 or -0.0 end the program; the shift drops the sign
+    if operator == RealDivide && !matches!(right, Operand::Constant(divisor) if divisor << 1 != 0) {
+        generator.emit(&format!("lsl x12, {}, #1", register));
+        generator.emit("cbz x12, runtime_error");
+    }
+    generator.emit(&format!("fmov d0, {}", left));
+    generator.emit(&format!("fmov d1, {}", register));
+    // Comparisons with NaN are false apart from <>, which the conditions
+    // chosen for fcmp give: mi and ls rather than lt and le
+    let condition = match operator {
+        RealAdd => Err("fadd"),
+        RealSubtract => Err("fsub"),
+        RealMultiply => Err("fmul"),
+        RealDivide => Err("fdiv"),
+        RealEqual => Ok("eq"),
+        RealNotEqual => Ok("ne"),
+        RealLess => Ok("mi"),
+        RealLessEqual => Ok("ls"),
+        RealGreater => Ok("gt"),
+        RealGreaterEqual => Ok("ge"),
+        _ => unreachable!("{} is not an operator on reals", operator.symbol()),
+    };
+    match condition {
+        Ok(condition) => {
+            generator.emit("fcmp d0, d1");
+            generator.emit(&format!("cset {}, {}", target, condition));
+        }
+        Err(mnemonic) => {
+            generator.emit(&format!("{} d0, d0, d1", mnemonic));
+            generator.emit(&format!("fmov {}, d0", target));
+        }
+    }
+}
+
+// Sets `target` to 1 if the signed comparison of `left` with `right`, a
+// register or an immediate, satisfies `condition`, else to 0.
+fn compare(generator: &mut TextGenerator<'_, Aarch64>, condition: &str, target: &str, left: &str, right: &str) {
+    generator.emit(&format!("cmp {}, {}", left, right));
+    generator.emit(&format!("cset {}, {}", target, condition));
+}
+
+// Runtime routines shared by all print statements, with the same contracts
+// as on x86-64: print_str writes X2 bytes starting at X1, print_int writes the
+// signed integer in X0 in decimal, print_bool writes TRUE or FALSE for the
+// boolean in X0 and print_newline writes a single line feed. The *_width
+// variants right-align their output in a field of X3 characters. print_real
+// writes the real whose bits are in X0 with X2 digits after the point, or with
+// up to 6 and no trailing zeros when X2 is negative, as print_real_runtime in
+// codegen.rs describes, and also clobbers X12, X13 and D0 to D2. All of them
+// clobber X0 to X8. runtime_error ends the program with exit status 1.
+const PRINT_RUNTIME: &str = r#"
+runtime_error:
+    mov x0, #1
+    mov x8, #93             // exit
+    svc #0
+
+print_str:
+    mov x0, #1              // stdout
+    mov x8, #64             // write
+    svc #0
+    ret
+
+print_str_width:
+    subs x3, x3, x2         // Number of spaces needed
+    b.le print_str
+    mov x4, x1
+    mov x5, x2
+
+print_padding_loop:
+    mov x2, x3
+    cmp x2, #16
+    b.le print_padding_write
+    mov x2, #16             // At most one spaces buffer per write
+
+print_padding_write:
+    sub x3, x3, x2
+    mov x0, #1
+    adrp x1, spaces
+    add x1, x1, :lo12:spaces
+    mov x8, #64
+    svc #0
+    cbnz x3, print_padding_loop
+    mov x1, x4
+    mov x2, x5
+    b print_str
+
+print_newline:
+    adrp x1, newline
+    add x1, x1, :lo12:newline
+    mov x2, #1
+    b print_str
+
+print_bool:
+    mov x3, #0              // No padding
+
+print_bool_width:
+    adrp x1, true_text
+    add x1, x1, :lo12:true_text
+    mov x2, #4
+    cbnz x0, print_str_width
+    adrp x1, false_text
+    add x1, x1, :lo12:false_text
+    mov x2, #5
+    b print_str_width
+
+print_int:
+    mov x3, #0              // No padding
+
+print_int_width:
+    mov x4, x0              // Keep the original value for the sign check
+    adrp x1, int_buffer
+    add x1, x1, :lo12:int_buffer
+    add x1, x1, #20         // Digits are written backwards from the end
+    mov x5, #10
+    cmp x0, #0
+    cneg x0, x0, lt         // Magnitude; i64::MIN stays 2^63 when read unsigned
+
+print_int_loop:
+    udiv x6, x0, x5
+    msub x7, x6, x5, x0     // Remainder
+    add x7, x7, #48         // Convert remainder to ASCII
+    strb w7, [x1, #-1]!     // Move back and store
+    mov x0, x6
+    cbnz x0, print_int_loop
+
+    cmp x4, #0
+    b.ge print_int_write
+    mov x7, #45             // '-'
+    strb w7, [x1, #-1]!
+
+print_int_write:
+    adrp x2, int_buffer
+    add x2, x2, :lo12:int_buffer
+    add x2, x2, #20
+    sub x2, x2, x1          // Length of the converted number
+    b print_str_width
+
+print_real:
+    mov x3, #0              // No padding
+
+print_real_width:
+    and x4, x0, #0x7fffffffffffffff // Magnitude
+    mov x5, #0x7ff0000000000000 // Infinity
+    cmp x4, x5
+    b.gt print_real_nan
+    b.eq print_real_infinity
+    fmov d0, x4
+    mov x6, #6              // Digits after the point
+    tbnz x2, #63, print_real_digits // Trailing zeros are left out while X2 is negative
+    mov x6, #18
+    cmp x2, x6
+    csel x6, x2, x6, lt
+
+print_real_digits:
+    mov x7, #0              // Zeros before the point
+    movz x5, #0xa764, lsl #16
+    movk x5, #0xb6b3, lsl #32
+    movk x5, #0x0de0, lsl #48
+    scvtf d1, x5            // 1e18
+    fmov d2, #10.0
+
+print_real_scale:
+    fcmp d0, d1
+    b.lt print_real_split
+    fdiv d0, d0, d2
+    add x7, x7, #1
+    b print_real_scale
+
+print_real_split:
+    fcvtzs x4, d0           // The part before the point
+    scvtf d1, x4
+    fsub d0, d0, d1
+    cbz x7, print_real_fraction
+    fmov d0, xzr            // Digits that were divided away are written as zeros
+
+print_real_fraction:
+    mov x5, #1
+    mov x8, x6
+
+print_real_power:
+    cbz x8, print_real_round
+    add x5, x5, x5, lsl #2  // Times 10
+    lsl x5, x5, #1
+    sub x8, x8, #1
+    b print_real_power
+
+print_real_round:
+    scvtf d1, x5
+    fmul d0, d0, d1         // Scaled by 10 to the number of digits
+    fcvtzs x8, d0
+    scvtf d1, x8
+    fsub d0, d0, d1
+    fmov d1, #0.5
+    fcmp d0, d1
+    b.lt print_real_carry
+    add x8, x8, #1
+
+print_real_carry:
+    cmp x8, x5
+    b.lt print_real_start
+    sub x8, x8, x5
+    add x4, x4, #1
+
+print_real_start:
+    adrp x1, real_buffer
+    add x1, x1, :lo12:real_buffer
+    add x1, x1, #336        // Written backwards from the end
+    mov x5, #10
+
+print_real_fraction_loop:
+    cbz x6, print_real_point
+    udiv x12, x8, x5
+    msub x13, x12, x5, x8
+    mov x8, x12
+    sub x6, x6, #1
+    tbz x2, #63, print_real_fraction_digit
+    cbnz x13, print_real_fraction_digit
+    cbnz x6, print_real_fraction_loop // Keep the last digit
+
+print_real_fraction_digit:
+    mov x2, #0              // Zeros before a kept digit stay
+    add x13, x13, #48
+    strb w13, [x1, #-1]!
+    b print_real_fraction_loop
+
+print_real_point:
+    adrp x12, real_buffer
+    add x12, x12, :lo12:real_buffer
+    add x12, x12, #336
+    cmp x1, x12
+    b.eq print_real_zeros
+    mov x13, #46            // '.'
+    strb w13, [x1, #-1]!
+
+print_real_zeros:
+    cbz x7, print_real_whole
+    mov x13, #48
+    strb w13, [x1, #-1]!
+    sub x7, x7, #1
+    b print_real_zeros
+
+print_real_whole:
+    udiv x12, x4, x5
+    msub x13, x12, x5, x4
+    add x13, x13, #48
+    strb w13, [x1, #-1]!
+    mov x4, x12
+    cbnz x4, print_real_whole
+
+    tbz x0, #63, print_real_write
+    mov x13, #45            // '-'
+    strb w13, [x1, #-1]!
+
+print_real_write:
+    adrp x2, real_buffer
+    add x2, x2, :lo12:real_buffer
+    add x2, x2, #336
+    sub x2, x2, x1
+    b print_str_width
+
+print_real_nan:
+    adrp x1, nan_text
+    add x1, x1, :lo12:nan_text
+    mov x2, #3
+    b print_str_width
+
+print_real_infinity:
+    adrp x1, infinity_text
+    add x1, x1, :lo12:infinity_text
+    mov x2, #4
+    tbnz x0, #63, print_str_width
+    add x1, x1, #1          // Inf without the sign
+    mov x2, #3
+    b print_str_width
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::Instruction;
+    use crate::testing::exit_status;
+    use crate::{compile, Options, Target};
+
+    fn options(opt_level: u8) -> Options {
+        Options { opt_level, target: Target::Aarch64Linux, ..Options::default() }
+    }
+
+    #[test]
+    fn test_variables_in_memory_and_registers() {
+        let source = "var x1, sp: integer; begin for x1 := 1 to 3 do sp := sp + x1; writeln(sp:3); end";
+
+        let unoptimised = compile(source, &options(0)).unwrap().assembly;
+        assert!(unoptimised.contains(".balign 8\nvar_x1: .quad 0\n.balign 8\nvar_sp: .quad 0\n"));
+        assert!(unoptimised.contains("mov x9, #1\nadrp x16, var_x1\nstr x9, [x16, :lo12:var_x1]\n"));
+        assert!(unoptimised.contains("mov x3, #3\nbl print_int_width\n"));
+        assert!(unoptimised.ends_with("b print_str_width\n"));
+
+        let optimised = compile(source, &options(1)).unwrap().assembly;
+        assert!(!optimised.contains("var_"));
+        assert!(optimised.contains("add x20, x20, #1\n"));
+        assert!(optimised.contains("cmp x20, #3\ncset x22, eq\n"));
+    }
+
+    #[test]
+    fn test_mod_writes_target_last() {
+        let instruction = Instruction::Binary {
+            dest: Var::Temp(0),
+            operator: BinaryOperator::Mod,
+            left: Operand::Var(Var::Named("a".to_string())),
+            right: Operand::Constant(-8),
+        };
+
+        let allocation = Allocation::in_memory(&Program { temp_count: 1, ..Program::default() });
+        let mut generator = TextGenerator::<Aarch64>::new(&allocation);
+        generator.instruction(&instruction, &Program::default());
+
+        let expected = "\
+adrp x16, var_a
+ldr x10, [x16, :lo12:var_a]
+mov x11, #-8
+sdiv x12, x10, x11
+msub x12, x12, x11, x10
+eor x13, x12, x11
+asr x13, x13, #63
+cmp x12, #0
+csel x13, xzr, x13, eq
+and x13, x13, x11
+add x9, x12, x13
+str x9, [x29, #0]
+";
+        assert_eq!(generator.text, expected);
+    }
+
+    #[test]
+    fn test_large_constants_and_frames() {
+        let allocation = Allocation::in_memory(&Program { temp_count: 5000, ..Program::default() });
+        let mut generator = TextGenerator::<Aarch64>::new(&allocation);
+        Aarch64::constant(&mut generator, "x9", -9223372036854775807 - 1);
+        Aarch64::constant(&mut generator, "x9", 0x1_0000_ffff);
+        Aarch64::constant(&mut generator, "x9", -65536);
+        Aarch64::memory(&mut generator, "ldr", "x9", &Var::Temp(4095));
+        Aarch64::memory(&mut generator, "ldr", "x9", &Var::Temp(4096));
+        assert_eq!(
+            generator.text,
+            "movz x9, #0\nmovk x9, #32768, lsl #48\nmovz x9, #65535\nmovk x9, #1, lsl #32\nmov x9, #-65536\n\
+             ldr x9, [x29, #32760]\nmov x16, #32768\nldr x9, [x29, x16]\n"
+        );
+    }
+
+    #[test]
+    fn test_reals_use_the_floating_point_registers() {
+        let source = "var x, y: real; n: integer; begin x := n; y := -x / 2; writeln(y:8:n, x < y); end";
+
+        let assembly = compile(source, &options(0)).unwrap().assembly;
+        assert!(assembly.contains("scvtf d0, x10\nfmov x9, d0\n"));
+        assert!(assembly.contains("fmov d0, x10\nfneg d0, d0\nfmov x9, d0\n"));
+        assert!(assembly
+            .contains("lsl x12, x11, #1\ncbz x12, runtime_error\nfmov d0, x10\nfmov d1, x11\nfdiv d0, d0, d1\n"));
+        assert!(assembly.contains("fcmp d0, d1\ncset x9, mi\n"));
+        assert!(assembly.contains("bl print_real_width\n"));
+    }
+
+    const DIVISION_BY_ZERO: &str = "var a, b: integer; begin a := 7; writeln(a div 2, a mod 3); writeln(a rem b); end";
+
+    #[test]
+    fn test_only_divisions_by_variables_are_checked() {
+        let assembly = compile(DIVISION_BY_ZERO, &options(0)).unwrap().assembly;
+        assert_eq!(assembly.matches(", runtime_error").count(), 1);
+        assert!(assembly.contains("cbz x11, runtime_error\n"));
+    }
+
+    #[test]
+    #[ignore = "needs the aarch64-linux-gnu binutils and qemu-aarch64"]
+    fn test_division_by_zero_is_a_runtime_error() {
+        for opt_level in [0, 1, 2] {
+            let status = exit_status(DIVISION_BY_ZERO, &options(opt_level));
+            assert_eq!(status.code(), Some(1));
+        }
+    }
+}
@@ -0,0 +1,637 @@
+//! Translation of the AST into C99 source, for running programs anywhere a C
+//! compiler is available.
+//!
+//! The variables become locals of `main`: `int64_t` for integers, `bool`
+//! for booleans and `double` for reals, starting at zero like the .data of
+//! the native code. Each write or writeln is one `printf` call. `+`, `-` and
+//! `*` on integers go through small helpers that wrap around on overflow as
+//! the machine instructions do, since signed overflow is undefined in C, and
+//! the divisions through helpers that check the divisor. Reals are written by
+//! a helper that formats them like the print routines of the native code.
+//! `#line` directives map the statements back to the lines of the Pascal
+//! source.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::ast::{BinaryOperator, Block, Expr, ExprKind, PrintItem, Program, Stmt, StmtKind, TypeName, UnaryOperator};
+use crate::semantic::{Analysis, Type};
+
+const INDENT: &str = "    ";
+
+// Names a variable must not take: the C keywords and the identifiers the
+// generated code uses besides the helpers. Pascal names are lower case, so the
+// upper-case macros of the headers cannot clash.
+const RESERVED: [&str; 41] = [
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else", "enum", "extern", "float",
+    "for", "goto", "if", "inline", "int", "long", "register", "restrict", "return", "short", "signed", "sizeof",
+    "static", "struct", "switch", "typedef", "union", "unsigned", "void", "volatile", "while", "bool", "true", "false",
+    "int64_t", "uint64_t", "printf", "main",
+];
+
+// The steps of print_real on the native targets, building the text backwards
+// from the end of `buffer`.
+const REAL_HELPER: &str = r#"// `value` with `precision` digits after the point, or up to 6 without trailing zeros when it is negative
+static char *ccc_real(char *buffer, double value, int64_t precision) {
+    uint64_t bits, magnitude;
+    double a;
+    memcpy(&bits, &value, sizeof bits);
+    magnitude = bits & UINT64_C(0x7fffffffffffffff);
+    if (magnitude > UINT64_C(0x7ff0000000000000)) return "NaN";
+    if (magnitude == UINT64_C(0x7ff0000000000000)) return bits >> 63 ? "-Inf" : "Inf";
+    memcpy(&a, &magnitude, sizeof a);
+    int64_t digits = precision < 0 ? 6 : precision > 18 ? 18 : precision, zeros = 0, scale = 1;
+    while (a >= 1e18) {
+        a /= 10;
+        zeros++;
+    }
+    int64_t whole = (int64_t) a;
+    double fraction = zeros > 0 ? 0 : a - (double) whole;
+    for (int64_t i = 0; i < digits; i++) scale *= 10;
+    double scaled = fraction * (double) scale;
+    int64_t part = (int64_t) scaled;
+    if (scaled - (double) part >= 0.5) part++;
+    if (part >= scale) {
+        part -= scale;
+        whole++;
+    }
+    char *end = buffer + 335, *p = end;
+    *p = 0;
+    bool trim = precision < 0;
+    for (; digits > 0; digits--, part /= 10) {
+        if (trim && part % 10 == 0 && digits > 1) continue;
+        trim = false;
+        *--p = (char) ('0' + part % 10);
+    }
+    if (p != end) *--p = '.';
+    for (; zeros > 0; zeros--) *--p = '0';
+    do *--p = (char) ('0' + whole % 10); while ((whole /= 10) > 0);
+    if (bits >> 63) *--p = '-';
+    return p;
+}"#;
+
+// The helper functions, written into the output only when used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Helper {
+    Add,
+    Subtract,
+    Multiply,
+    Negate,
+    Div,
+    Rem,
+    Mod,
+    RealDivide,
+    Width,
+    Padding,
+    Real,
+}
+
+impl Helper {
+    fn name(self) -> &'static str {
+        match self {
+            Helper::Add => "ccc_add",
+            Helper::Subtract => "ccc_sub",
+            Helper::Multiply => "ccc_mul",
+            Helper::Negate => "ccc_neg",
+            Helper::Div => "ccc_div",
+            Helper::Rem => "ccc_rem",
+            Helper::Mod => "ccc_mod",
+            Helper::RealDivide => "ccc_fdiv",
+            Helper::Width => "ccc_width",
+            Helper::Padding => "ccc_padding",
+            Helper::Real => "ccc_real",
+        }
+    }
+
+    fn definition(self) -> &'static str {
+        match self {
+            Helper::Add => {
+                "static int64_t ccc_add(int64_t a, int64_t b) { return (int64_t) ((uint64_t) a + (uint64_t) b); }"
+            }
+            Helper::Subtract => {
+                "static int64_t ccc_sub(int64_t a, int64_t b) { return (int64_t) ((uint64_t) a - (uint64_t) b); }"
+            }
+            Helper::Multiply => {
+                "static int64_t ccc_mul(int64_t a, int64_t b) { return (int64_t) ((uint64_t) a * (uint64_t) b); }"
+            }
+            Helper::Negate => "static int64_t ccc_neg(int64_t a) { return (int64_t) -(uint64_t) a; }",
+            // Division by zero ends the program with exit status 1, as on
+            // the native targets, and dividing i64::MIN by -1 wraps around
+            // instead of overflowing
+            Helper::Div => {
+                "static int64_t ccc_div(int64_t a, int64_t b) { if (b == 0) exit(1); return b == -1 ? (int64_t) -(uint64_t) a : a / b; }"
+            }
+            Helper::Rem => "static int64_t ccc_rem(int64_t a, int64_t b) { if (b == 0) exit(1); return b == -1 ? 0 : a % b; }",
+            Helper::Mod => {
+                "// mod takes the sign of the divisor\n\
+                 static int64_t ccc_mod(int64_t a, int64_t b) { int64_t r = ccc_rem(a, b); return r != 0 && (r < 0) != (b < 0) ? r + b : r; }"
+            }
+            // 0.0 and -0.0 compare equal to 0
+            Helper::RealDivide => "static double ccc_fdiv(double a, double b) { if (b == 0) exit(1); return a / b; }",
+            Helper::Width => {
+                "// A field narrower than the value, even a negative one, means no padding\n\
+                 static int ccc_width(int64_t width) { return width < 0 ? 0 : width > INT_MAX ? INT_MAX : (int) width; }"
+            }
+            Helper::Padding => {
+                "// The spaces in front of `length` bytes in a field of `width` characters\n\
+                 static int ccc_padding(int64_t width, int64_t length) { return width <= length ? 0 : ccc_width(width - length); }"
+            }
+            Helper::Real => REAL_HELPER,
+        }
+    }
+}
+
+/// Translates `program` into a C99 program. The `#line` directives name
+/// `source_name` as the file, or leave the file name alone if there is none.
+pub fn generate_code(program: &Program, analysis: &Analysis, source_name: Option<&str>) -> String {
+    let mut generator = CGenerator {
+        analysis,
+        source_name,
+        names: c_names(program),
+        helpers: BTreeSet::new(),
+        out: String::new(),
+        line: None,
+    };
+    generator.emit(0, "int main(void) {");
+    for decl in &program.declarations {
+        generator.at(decl.span.line);
+        let zero = if decl.type_name == TypeName::Boolean { "false" } else { "0" };
+        let names: Vec<String> =
+            decl.names.iter().map(|name| format!("{} = {}", generator.names[name], zero)).collect();
+        let type_name = match decl.type_name {
+            TypeName::Boolean => "bool",
+            TypeName::Real => "double",
+            TypeName::Integer | TypeName::Int64 => "int64_t",
+        };
+        generator.emit(1, &format!("{} {};", type_name, names.join(", ")));
+    }
+    generator.block(&program.body, 1);
+    generator.emit(1, "return 0;");
+    generator.emit(0, "}");
+
+    let mut source = String::new();
+    if let Some(name) = source_name {
+        source.push_str(&format!("// Generated by ccc from {}\n", name));
+    }
+    source.push_str("#include <inttypes.h>\n");
+    if generator.helpers.contains(&Helper::Width) {
+        source.push_str("#include <limits.h>\n");
+    }
+    source.push_str("#include <stdbool.h>\n#include <stdio.h>\n");
+    if [Helper::Div, Helper::Rem, Helper::RealDivide].iter().any(|helper| generator.helpers.contains(helper)) {
+        source.push_str("#include <stdlib.h>\n");
+    }
+    if generator.helpers.contains(&Helper::Real) {
+        source.push_str("#include <string.h>\n");
+    }
+    source.push('\n');
+    if !generator.helpers.is_empty() {
+        for helper in &generator.helpers {
+            source.push_str(helper.definition());
+            source.push('\n');
+        }
+        source.push('\n');
+    }
+    source.push_str(&generator.out);
+    source
+}
+
+// The C name of every variable: its own, unless that is reserved or could be
+// a helper, in which case underscores are appended until it is free. No
+// helper name ends with an underscore.
+fn c_names(program: &Program) -> HashMap<String, String> {
+    let declared: Vec<&String> = program.declarations.iter().flat_map(|decl| &decl.names).collect();
+    let mut taken: HashSet<String> = declared.iter().map(|name| name.to_string()).collect();
+    taken.extend(RESERVED.iter().map(|name| name.to_string()));
+    let mut names = HashMap::new();
+    for name in declared {
+        let mut c_name = name.clone();
+        // Identifiers starting with two underscores are reserved as well
+        if RESERVED.contains(&name.as_str()) || name.starts_with("ccc_") || name.starts_with("__") {
+            if name.starts_with("__") {
+                c_name.insert(0, 'v');
+            }
+            while taken.contains(&c_name) {
+                c_name.push('_');
+            }
+            taken.insert(c_name.clone());
+        }
+        names.insert(name.clone(), c_name);
+    }
+    names
+}
+
+// A C expression, with what its enclosing expression needs to know.
+struct CExpr {
+    text: String,
+    /// Written with a binary operator at the top, so it needs parentheses as
+    /// the operand of another.
+    infix: bool,
+    /// Known to have type `int64_t`. Integer literals, and arithmetic on
+    /// literals only, are `int` or `long` in C.
+    wide: bool,
+}
+
+impl CExpr {
+    fn operand(&self) -> String {
+        if self.infix {
+            format!("({})", self.text)
+        } else {
+            self.text.clone()
+        }
+    }
+}
+
+struct CGenerator<'a> {
+    analysis: &'a Analysis,
+    source_name: Option<&'a str>,
+    names: HashMap<String, String>,
+    helpers: BTreeSet<Helper>,
+    out: String,
+    line: Option<usize>, // The source line the C compiler takes the next line to be
+}
+
+impl CGenerator<'_> {
+    fn emit(&mut self, depth: usize, text: &str) {
+        self.out.push_str(&INDENT.repeat(depth));
+        self.out.push_str(text);
+        self.out.push('\n');
+        self.line = self.line.map(|line| line + 1);
+    }
+
+    // Makes the next line count as `line` of the source, with a #line
+    // directive unless it already does.
+    fn at(&mut self, line: usize) {
+        if self.line == Some(line) {
+            return;
+        }
+        match self.source_name {
+            Some(name) => self.out.push_str(&format!("#line {} \"{}\"\n", line, escape(name.as_bytes(), false))),
+            None => self.out.push_str(&format!("#line {}\n", line)),
+        }
+        self.line = Some(line);
+    }
+
+    fn block(&mut self, block: &Block, depth: usize) {
+        for stmt in &block.statements {
+            self.stmt(stmt, depth);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, depth: usize) {
+        if !matches!(stmt.kind, StmtKind::Compound(_)) {
+            self.at(stmt.span.line);
+        }
+        match &stmt.kind {
+            StmtKind::Assign { target, value } => {
+                let value = self.expr(value);
+                self.emit(depth, &format!("{} = {};", self.names[target], value.text));
+            }
+            StmtKind::Print { items, newline } => self.print(items, *newline, depth),
+            // C needs no block of its own, as nothing is declared in it
+            StmtKind::Compound(block) => self.block(block, depth),
+            StmtKind::If { condition, then_branch, else_branch } => {
+                let condition = self.expr(condition);
+                self.emit(depth, &format!("if ({}) {{", condition.text));
+                self.stmt(then_branch, depth + 1);
+                let mut else_branch = else_branch.as_deref();
+                // else if chains stay flat
+                while let Some(Stmt { kind: StmtKind::If { condition, then_branch, else_branch: next }, .. }) =
+                    else_branch
+                {
+                    let condition = self.expr(condition);
+                    self.emit(depth, &format!("}} else if ({}) {{", condition.text));
+                    self.stmt(then_branch, depth + 1);
+                    else_branch = next.as_deref();
+                }
+                if let Some(else_branch) = else_branch {
+                    self.emit(depth, "} else {");
+                    self.stmt(else_branch, depth + 1);
+                }
+                self.emit(depth, "}");
+            }
+            StmtKind::While { condition, body } => {
+                let condition = self.expr(condition);
+                self.emit(depth, &format!("while ({}) {{", condition.text));
+                self.stmt(body, depth + 1);
+                self.emit(depth, "}");
+            }
+            StmtKind::For { variable, start, end, descending, body } => {
+                // The loop stops at the end value rather than stepping past
+                // it, so the variable keeps that value afterwards and cannot
+                // overflow. Unless it is a literal, the end value is copied
+                // first, as the body may change what it was computed from.
+                let variable = self.names[variable].clone();
+                let (start, end) = (self.expr(start), self.expr(end));
+                let (depth, end) = match end.text.parse::<i64>() {
+                    Ok(_) => (depth, end.text),
+                    Err(_) => {
+                        self.emit(depth, "{");
+                        self.emit(depth + 1, &format!("int64_t end = {};", end.text));
+                        (depth + 1, "end".to_string())
+                    }
+                };
+                let (past_end, step) = if *descending { (">=", "--") } else { ("<=", "++") };
+                let header =
+                    format!("for ({0} = {1}; {0} {2} {3}; {0}{4}) {{", variable, start.text, past_end, end, step);
+                self.emit(depth, &header);
+                self.stmt(body, depth + 1);
+                self.emit(depth + 1, &format!("if ({} == {}) break;", variable, end));
+                self.emit(depth, "}");
+                if end == "end" {
+                    self.emit(depth - 1, "}");
+                }
+            }
+            StmtKind::Exit => self.emit(depth, "return 0;"),
+        }
+    }
+
+    // One printf call for all items, with the text of string literals in the
+    // format.
+    fn print(&mut self, items: &[PrintItem], newline: bool, depth: usize) {
+        let mut format = String::new();
+        let mut arguments = Vec::new();
+        for item in items {
+            if let ExprKind::String(text) = &item.value.kind
+                && text.contains(&0)
+            {
+                self.print_with_nul(text, item.width.as_ref(), &mut format, &mut arguments);
+                continue;
+            }
+            // A field of width 0 pads nothing, and %0s would be the 0 flag
+            let width = match item.width.as_ref().map(|width| &width.kind) {
+                None | Some(ExprKind::Integer(0)) => String::new(),
+                Some(ExprKind::Integer(width)) if i32::try_from(*width).is_ok() => width.to_string(),
+                Some(_) => {
+                    let width = self.expr(item.width.as_ref().unwrap());
+                    arguments.push(self.helper(Helper::Width, &[width]).text);
+                    "*".to_string()
+                }
+            };
+            match (&item.value.kind, self.analysis.type_of(&item.value)) {
+                (ExprKind::String(text), _) if width.is_empty() => format.push_str(&escape(text, true)),
+                (ExprKind::String(text), _) => {
+                    format.push_str(&format!("%{}s", width));
+                    arguments.push(format!("\"{}\"", escape(text, false)));
+                }
+                (ExprKind::Boolean(value), _) if width.is_empty() => {
+                    format.push_str(if *value { "TRUE" } else { "FALSE" })
+                }
+                (ExprKind::Integer(value), _) if width.is_empty() => format.push_str(&value.to_string()),
+                (_, Type::Boolean) => {
+                    let value = self.expr(&item.value);
+                    format.push_str(&format!("%{}s", width));
+                    arguments.push(format!("{} ? \"TRUE\" : \"FALSE\"", value.operand()));
+                }
+                // Each real is written into a buffer of its own, a compound
+                // literal that lasts until the end of main
+                (_, Type::Real) => {
+                    let precision = match &item.precision {
+                        Some(precision) => self.expr(precision),
+                        None => CExpr { text: "-1".to_string(), infix: false, wide: false },
+                    };
+                    let value = self.expr(&item.value);
+                    format.push_str(&format!("%{}s", width));
+                    let buffer = CExpr { text: "(char[336]) {0}".to_string(), infix: false, wide: false };
+                    arguments.push(self.helper(Helper::Real, &[buffer, value, precision]).text);
+                }
+                _ => {
+                    let value = self.expr(&item.value);
+                    // The format piece is closed around the macro: "%6" PRId64 "
+                    format.push_str(&format!("%{}\" PRId64 \"", width));
+                    arguments.push(if value.wide { value.text } else { format!("(int64_t) {}", value.operand()) });
+                }
+            }
+        }
+        if newline {
+            format.push_str("\\n");
+        }
+        if format.is_empty() {
+            return;
+        }
+        let format = format!("\"{}\"", format);
+        arguments.insert(0, format.strip_suffix(" \"\"").unwrap_or(&format).to_string());
+        self.emit(depth, &format!("printf({});", arguments.join(", ")));
+    }
+
+    // printf stops at a NUL byte, in the format and in a %s argument alike, so
+    // each NUL of the string literal is a %c of its own, and the padding a %*s
+    // of the empty string in front.
+    fn print_with_nul(&mut self, text: &[u8], width: Option<&Expr>, format: &mut String, arguments: &mut Vec<String>) {
+        if let Some(width) = width {
+            let width = self.expr(width);
+            let length = CExpr { text: text.len().to_string(), infix: false, wide: false };
+            format.push_str("%*s");
+            self.helpers.insert(Helper::Width);
+            arguments.push(self.helper(Helper::Padding, &[width, length]).text);
+            arguments.push("\"\"".to_string());
+        }
+        for (index, piece) in text.split(|&byte| byte == 0).enumerate() {
+            if index > 0 {
+                format.push_str("%c");
+                arguments.push("0".to_string());
+            }
+            format.push_str(&escape(piece, true));
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> CExpr {
+        let simple = |text: String, wide: bool| CExpr { text, infix: false, wide };
+        match &expr.kind {
+            ExprKind::Integer(value) => simple(value.to_string(), false),
+            // Written the shortest way that reads back as the same double
+            ExprKind::Real(value) => simple(format!("{:?}", value), false),
+            ExprKind::Boolean(value) => simple(value.to_string(), false),
+            ExprKind::String(text) => simple(format!("\"{}\"", escape(text, false)), false),
+            ExprKind::Variable(name) => simple(self.names[name].clone(), true),
+            // Literals are at most i64::MAX, so their negation cannot overflow
+            ExprKind::Unary { operator: UnaryOperator::Negate, operand }
+                if let ExprKind::Integer(value) = operand.kind =>
+            {
+                simple(format!("-{}", value), false)
+            }
+            // Real negation cannot overflow; the parentheses keep it from
+            // making -- of a negative operand
+            ExprKind::Unary { operator: UnaryOperator::Negate, operand }
+                if self.analysis.type_of(expr) == Type::Real =>
+            {
+                let operand = self.expr(operand);
+                if operand.text.starts_with('-') {
+                    simple(format!("-({})", operand.text), false)
+                } else {
+                    simple(format!("-{}", operand.operand()), false)
+                }
+            }
+            ExprKind::Unary { operator, operand } => {
+                let operand = self.expr(operand);
+                match operator {
+                    UnaryOperator::Negate => self.helper(Helper::Negate, &[operand]),
+                    UnaryOperator::Not => simple(format!("!{}", operand.operand()), false),
+                }
+            }
+            ExprKind::Binary { left, operator, right }
+                if self.analysis.type_of(left) == Type::Real || self.analysis.type_of(right) == Type::Real =>
+            {
+                let (left, right) = (self.real(left), self.real(right));
+                let infix = |symbol: &str| CExpr {
+                    text: format!("{} {} {}", left.operand(), symbol, right.operand()),
+                    infix: true,
+                    wide: false,
+                };
+                match operator {
+                    BinaryOperator::Add => infix("+"),
+                    BinaryOperator::Subtract => infix("-"),
+                    BinaryOperator::Multiply => infix("*"),
+                    BinaryOperator::Divide => self.helper(Helper::RealDivide, &[left, right]),
+                    BinaryOperator::Equal => infix("=="),
+                    BinaryOperator::NotEqual => infix("!="),
+                    BinaryOperator::Less => infix("<"),
+                    BinaryOperator::LessEqual => infix("<="),
+                    BinaryOperator::Greater => infix(">"),
+                    BinaryOperator::GreaterEqual => infix(">="),
+                    _ => unreachable!("{:?} takes no reals", operator),
+                }
+            }
+            ExprKind::Binary { left, operator, right } => {
+                let (left, right) = (self.expr(left), self.expr(right));
+                let infix = |symbol: &str| CExpr {
+                    text: format!("{} {} {}", left.operand(), symbol, right.operand()),
+                    infix: true,
+                    wide: false,
+                };
+                match operator {
+                    BinaryOperator::Add => self.helper(Helper::Add, &[left, right]),
+                    BinaryOperator::Subtract => self.helper(Helper::Subtract, &[left, right]),
+                    BinaryOperator::Multiply => self.helper(Helper::Multiply, &[left, right]),
+                    // C99 division truncates toward zero, and % takes the sign
+                    // of the dividend
+                    BinaryOperator::Divide | BinaryOperator::Div => self.helper(Helper::Div, &[left, right]),
+                    BinaryOperator::Rem => self.helper(Helper::Rem, &[left, right]),
+                    BinaryOperator::Mod => {
+                        self.helpers.insert(Helper::Rem);
+                        self.helper(Helper::Mod, &[left, right])
+                    }
+                    BinaryOperator::Equal => infix("=="),
+                    BinaryOperator::NotEqual => infix("!="),
+                    BinaryOperator::Less => infix("<"),
+                    BinaryOperator::LessEqual => infix("<="),
+                    BinaryOperator::Greater => infix(">"),
+                    BinaryOperator::GreaterEqual => infix(">="),
+                    BinaryOperator::And => infix("&&"),
+                    BinaryOperator::Or => infix("||"),
+                }
+            }
+        }
+    }
+
+    // `expr` as a double, with an integer converted explicitly.
+    fn real(&mut self, expr: &Expr) -> CExpr {
+        let value = self.expr(expr);
+        match self.analysis.type_of(expr) {
+            Type::Real => value,
+            _ => CExpr { text: format!("(double) {}", value.operand()), infix: false, wide: false },
+        }
+    }
+
+    fn helper(&mut self, helper: Helper, arguments: &[CExpr]) -> CExpr {
+        self.helpers.insert(helper);
+        let arguments: Vec<&str> = arguments.iter().map(|argument| argument.text.as_str()).collect();
+        CExpr { text: format!("{}({})", helper.name(), arguments.join(", ")), infix: false, wide: true }
+    }
+}
+
+// The contents of a C string literal for `text`, with % doubled for a printf
+// format. Bytes outside printable ASCII become three-digit octal escapes, so
+// no digit after them can extend them.
+fn escape(text: &[u8], format: bool) -> String {
+    let mut escaped = String::new();
+    let mut previous = 0;
+    for &byte in text {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            b'\n' => escaped.push_str("\\n"),
+            b'\t' => escaped.push_str("\\t"),
+            b'%' if format => escaped.push_str("%%"),
+            // Keeps ?? from starting a trigraph
+            b'?' if previous == b'?' => escaped.push_str("\\?"),
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{:03o}", byte)),
+        }
+        previous = byte;
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{exit_status, run_with_options, REAL_DIVISION_BY_ZERO, REAL_OUTPUT, REAL_PROGRAM};
+    use crate::{compile, Options, Target};
+
+    fn options() -> Options {
+        Options { target: Target::C, ..Options::default() }
+    }
+
+    #[test]
+    fn test_names_helpers_and_lines() {
+        let source =
+            "var\n    int, ccc_add, i: integer;\n    done: boolean;\nbegin\n    for i := 3 downto int - 1 do\n        \
+                      int := int * 2 mod 5;\n    done := not done;\n    writeln('100%', i:4, done, ' ', 'x':int);\nend";
+        let c = compile(source, &options()).unwrap().assembly;
+        assert!(c.starts_with("#include <inttypes.h>\n#include <limits.h>\n#include <stdbool.h>\n#include <stdio.h>\n"));
+        assert!(!c.contains("ccc_add("));
+        assert!(c.contains(
+            "int main(void) {\n#line 2\n    int64_t int_ = 0, ccc_add_ = 0, i = 0;\n    bool done = false;\n"
+        ));
+        assert!(c.contains(
+            "#line 5\n    {\n        int64_t end = ccc_sub(int_, 1);\n        for (i = 3; i >= end; i--) {\n#line 6\n            \
+             int_ = ccc_mod(ccc_mul(int_, 2), 5);\n            if (i == end) break;\n        }\n    }\n"
+        ));
+        assert!(c.contains(
+            "#line 7\n    done = !done;\n    \
+             printf(\"100%%%4\" PRId64 \"%s %*s\\n\", i, done ? \"TRUE\" : \"FALSE\", ccc_width(int_), \"x\");\n"
+        ));
+        assert!(c.ends_with("    return 0;\n}\n"));
+    }
+
+    const DIVISIONS: &str = "var a, b: integer; begin a := -9223372036854775807 - 1; b := -1; \
+                             writeln(a div b, ' ', a rem b, ' ', a mod b, ' ', 7 div 2); end";
+
+    #[test]
+    fn test_divisions_check_the_divisor() {
+        let c = compile(DIVISIONS, &options()).unwrap().assembly;
+        assert!(c.contains("#include <stdio.h>\n#include <stdlib.h>\n\n"));
+        assert!(c.contains("ccc_div(a, b), ccc_rem(a, b), ccc_mod(a, b), ccc_div(7, 2)"));
+    }
+
+    #[test]
+    #[ignore = "needs cc"]
+    fn test_divisions_run_like_the_native_targets() {
+        let stdout = run_with_options(DIVISIONS, &options());
+        assert_eq!(stdout, b"-9223372036854775808 0 0 3\n");
+
+        let source = "var a, b: integer; begin a := 7; writeln(a div 2); writeln(a mod b); end";
+        let status = exit_status(source, &options());
+        assert_eq!(status.code(), Some(1));
+    }
+
+    #[test]
+    fn test_reals_are_doubles() {
+        let source = "var x: real; n: integer; begin x := n; writeln(-x / 2:8:n, x <> 0.5, 1e300); end";
+        let c = compile(source, &options()).unwrap().assembly;
+        assert!(c.contains("#include <stdlib.h>\n#include <string.h>\n"));
+        assert!(c.contains("    double x = 0;\n"));
+        assert!(c.contains("    x = n;\n"));
+        assert!(c.contains(
+            "printf(\"%8s%s%s\\n\", ccc_real((char[336]) {0}, ccc_fdiv(-x, (double) 2), n), \
+             (x != 0.5) ? \"TRUE\" : \"FALSE\", ccc_real((char[336]) {0}, 1e300, -1));\n"
+        ));
+    }
+
+    #[test]
+    #[ignore = "needs cc"]
+    fn test_reals_run_like_the_native_targets() {
+        let stdout = run_with_options(REAL_PROGRAM, &options());
+        assert_eq!(String::from_utf8(stdout).unwrap(), REAL_OUTPUT);
+        assert_eq!(exit_status(REAL_DIVISION_BY_ZERO, &options()).code(), Some(1));
+    }
+}
@@ -0,0 +1,517 @@
+//! Code generation for RISC-V 64 Linux, as GNU as source for RV64IMFD.
+//!
+//! Values are computed in scratch registers: T1 and T2 hold the operands, T0
+//! the result, and T3, T4 and T5 the intermediate values and addresses of
+//! longer sequences. Reals are operated on in FT0 and FT1. Variables that get a register live in S1 to S11, which
+//! the print routines leave alone; the others are in .data or, for
+//! temporaries, in a stack frame addressed from S0.
+
+use crate::asm::Label;
+use crate::codegen::{generate_text, Isa, TextGenerator};
+use crate::ir::{BinaryOperator, Operand, Program, UnaryOperator, Var};
+use crate::regalloc::Allocation;
+
+/// The registers given to variables, all callee-saved in the calling
+/// convention. S0 is kept as the frame pointer.
+pub const REGISTERS: [&str; 11] = ["s1", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11"];
+
+/// Generates GNU as assembly for RISC-V 64 Linux from the IR, with variables
+/// where `allocation` puts them.
+pub fn generate_code(program: &Program, allocation: &Allocation<&'static str>) -> String {
+    generate_text::<Riscv64>(program, allocation)
+}
+
+// Whether `value` fits the 12-bit signed immediate of addi, slti and friends.
+fn is_immediate(value: i64) -> bool {
+    (-2048..2048).contains(&value)
+}
+
+struct Riscv64;
+
+impl Isa for Riscv64 {
+    const RESULT: &'static str = "t0";
+    const LEFT: &'static str = "t1";
+    const FRAME: &'static str = "s0";
+    const ADDRESS: &'static str = "t5";
+    const ARGUMENTS: [&'static str; 4] = ["a0", "a1", "a2", "a3"];
+    const ZERO: Option<&'static str> = Some("zero");
+    const LOAD: &'static str = "ld";
+    const STORE: &'static str = "sd";
+    const MOVE: &'static str = "mv";
+    const CALL: &'static str = "call";
+    const JUMP: &'static str = "j";
+    const BRANCH_IF_ZERO: &'static str = "beqz";
+    const BRANCH_IF_NOT_ZERO: &'static str = "bnez";
+    const EXIT: &'static str = "li a0, 0\nli a7, 93\necall";
+    const RUNTIME: &'static str = PRINT_RUNTIME;
+
+    fn constant(generator: &mut TextGenerator<'_, Self>, register: &str, value: i64) {
+        generator.emit(&format!("li {}, {}", register, value));
+    }
+
+    // Stack slots beyond the reach of an immediate offset are addressed
+    // through T5.
+    fn memory(generator: &mut TextGenerator<'_, Self>, instruction: &str, register: &str, var: &Var) {
+        match var {
+            Var::Named(name) => {
+                generator.emit(&format!("la t5, {}", Label::Variable(name.clone())));
+                generator.emit(&format!("{} {}, 0(t5)", instruction, register));
+            }
+            Var::Temp(_) => {
+                let offset = 8 * generator.allocation.slots[var] as i64;
+                if is_immediate(offset) {
+                    generator.emit(&format!("{} {}, {}(s0)", instruction, register, offset));
+                } else {
+                    generator.emit(&format!("li t5, {}", offset));
+                    generator.emit("add t5, s0, t5");
+                    generator.emit(&format!("{} {}, 0(t5)", instruction, register));
+                }
+            }
+        }
+    }
+
+    fn address(generator: &mut TextGenerator<'_, Self>, register: &str, label: &Label) {
+        generator.emit(&format!("la {}, {}", register, label));
+    }
+
+    fn unary(operator: UnaryOperator, target: &str, operand: &str) -> String {
+        match operator {
+            UnaryOperator::Negate => format!("neg {}, {}", target, operand),
+            UnaryOperator::Not => format!("xori {}, {}, 1", target, operand),
+            UnaryOperator::RealNegate => format!("fmv.d.x ft0, {}\nfneg.d ft0, ft0\nfmv.x.d {}, ft0", operand, target),
+            UnaryOperator::ToReal => format!("fcvt.d.l ft0, {}\nfmv.x.d {}, ft0", operand, target),
+        }
+    }
+
+    fn binary(
+        generator: &mut TextGenerator<'_, Self>,
+        operator: BinaryOperator,
+        target: &str,
+        left: &str,
+        right: &Operand,
+    ) {
+        use BinaryOperator::*;
+
+        if operator.is_real() {
+            return real_binary(generator, operator, target, left, right);
+        }
+        // Shift counts and small constants added, subtracted or compared with
+        // are immediates
+        match (operator, right) {
+            (ShiftLeft | ShiftRight, Operand::Constant(count)) => {
+                let mnemonic = if operator == ShiftLeft { "slli" } else { "srai" };
+                generator.emit(&format!("{} {}, {}, {}", mnemonic, target, left, count & 63));
+                return;
+            }
+            (Add, Operand::Constant(value)) if is_immediate(*value) => {
+                generator.emit(&format!("addi {}, {}, {}", target, left, value));
+                return;
+            }
+            (Subtract, Operand::Constant(value)) if (-2047..=2048).contains(value) => {
+                generator.emit(&format!("addi {}, {}, {}", target, left, -value));
+                return;
+            }
+            (Less, Operand::Constant(value)) if is_immediate(*value) => {
+                generator.emit(&format!("slti {}, {}, {}", target, left, value));
+                return;
+            }
+            (Equal | NotEqual, Operand::Constant(value)) if (-2047..=2048).contains(value) => {
+                let mnemonic = if operator == Equal { "seqz" } else { "snez" };
+                generator.emit(&format!("addi t3, {}, {}", left, -value));
+                generator.emit(&format!("{} {}, t3", mnemonic, target));
+                return;
+            }
+            _ => {}
+        }
+        let register = generator.load("t2", right);
+        if matches!(operator, Divide | Div | Rem | Mod) {
+            generator.check_divisor(right, register);
+        }
+        let right = register;
+        let three_operand = |mnemonic: &str| format!("{} {}, {}, {}", mnemonic, target, left, right);
+        match operator {
+            Add => generator.emit(&three_operand("add")),
+            Subtract => generator.emit(&three_operand("sub")),
+            Multiply => generator.emit(&three_operand("mul")),
+            And => generator.emit(&three_operand("and")),
+            Or => generator.emit(&three_operand("or")),
+            // Shift counts are taken modulo 64, as on x86-64
+            ShiftLeft => generator.emit(&three_operand("sll")),
+            ShiftRight => generator.emit(&three_operand("sra")),
+            // div truncates toward zero, and rem takes the sign of left
+            Divide | Div => generator.emit(&three_operand("div")),
+            Rem => generator.emit(&three_operand("rem")),
+            // mod takes the sign of the divisor: add the divisor to a non-zero
+            // remainder whose sign differs from it
+            Mod => {
+                generator.emit(&format!("rem t3, {}, {}", left, right));
+                generator.emit(&format!("xor t4, t3, {}", right));
+                generator.emit("srai t4, t4, 63");
+                generator.emit(&format!("and t4, t4, {}", right));
+                generator.emit("snez t5, t3");
+                generator.emit("neg t5, t5");
+                generator.emit("and t4, t4, t5");
+                generator.emit(&format!("add {}, t3, t4", target));
+            }
+            Less => generator.emit(&format!("slt {}, {}, {}", target, left, right)),
+            Greater => generator.emit(&format!("slt {}, {}, {}", target, right, left)),
+            // a <= b is not b < a
+            LessEqual => {
+                generator.emit(&format!("slt t3, {}, {}", right, left));
+                generator.emit(&format!("xori {}, t3, 1", target));
+            }
+            GreaterEqual => {
+                generator.emit(&format!("slt t3, {}, {}", left, right));
+                generator.emit(&format!("xori {}, t3, 1", target));
+            }
+            Equal => {
+                generator.emit(&format!("xor t3, {}, {}", left, right));
+                generator.emit(&format!("seqz {}, t3", target));
+            }
+            NotEqual => {
+                generator.emit(&format!("xor t3, {}, {}", left, right));
+                generator.emit(&format!("snez {}, t3", target));
+            }
+            _ => unreachable!("{} is an operator on reals", operator.symbol()),
+        }
+    }
+}
+
+// Computes the real `left operator right` into `target` through FT0 and FT1.
+fn real_binary(
+    generator: &mut TextGenerator<'_, Riscv64>,
+    operator: BinaryOperator,
+    target: &str,
+    left: &str,
+    right: &Operand,
+) {
+    use BinaryOperator::*;
+
+    let register = generator.load("t2", right);
+    // Divisors that may be 0.0 or -0.0 end the program; the shift drops the sign
+    if operator == RealDivide && !matches!(right, Operand::Constant(divisor) if divisor << 1 != 0) {
+        generator.emit(&format!("slli t3, {}, 1", register));
+        generator.emit("beqz t3, runtime_error");
+    }
+    generator.emit(&format!("fmv.d.x ft0, {}", left));
+    generator.emit(&format!("fmv.d.x ft1, {}", register));
+    // The comparisons give 0 when either operand is NaN, so <> is not =
+    let arithmetic = |mnemonic: &str| format!("{} ft0, ft0, ft1\nfmv.x.d {}, ft0", mnemonic, target);
+    generator.emit(&match operator {
+        RealAdd => arithmetic("fadd.d"),
+        RealSubtract => arithmetic("fsub.d"),
+        RealMultiply => arithmetic("fmul.d"),
+        RealDivide => arithmetic("fdiv.d"),
+        RealEqual => format!("feq.d {}, ft0, ft1", target),
+        RealNotEqual => format!("feq.d t3, ft0, ft1\nxori {}, t3, 1", target),
+        RealLess => format!("flt.d {}, ft0, ft1", target),
+        RealLessEqual => format!("fle.d {}, ft0, ft1", target),
+        RealGreater => format!("flt.d {}, ft1, ft0", target),
+        RealGreaterEqual => format!("fle.d {}, ft1, ft0", target),
+        _ => unreachable!("{} is not an operator on reals", operator.symbol()),
+    });
+}
+
+// Runtime routines shared by all print statements, with the same contracts
+// as on x86-64: print_str writes A2 bytes starting at A1, print_int writes the
+// signed integer in A0 in decimal, print_bool writes TRUE or FALSE for the
+// boolean in A0 and print_newline writes a single line feed. The *_width
+// variants right-align their output in a field of A3 characters. print_real
+// writes the real whose bits are in A0 with A2 digits after the point, or with
+// up to 6 and no trailing zeros when A2 is negative, as print_real_runtime in
+// codegen.rs describes, and also clobbers T3, T4 and FT0 to FT2. All of them
+// clobber A0 to A7. runtime_error ends the program with exit status 1.
+const PRINT_RUNTIME: &str = r#"
+runtime_error:
+    li a0, 1
+    li a7, 93               # exit
+    ecall
+
+print_str:
+    li a0, 1                # stdout
+    li a7, 64               # write
+    ecall
+    ret
+
+print_str_width:
+    sub a3, a3, a2          # Number of spaces needed
+    blez a3, print_str
+    mv a4, a1
+    mv a5, a2
+
+print_padding_loop:
+    mv a2, a3
+    li a6, 16
+    ble a2, a6, print_padding_write
+    mv a2, a6               # At most one spaces buffer per write
+
+print_padding_write:
+    sub a3, a3, a2
+    li a0, 1
+    la a1, spaces
+    li a7, 64
+    ecall
+    bnez a3, print_padding_loop
+    mv a1, a4
+    mv a2, a5
+    j print_str
+
+print_newline:
+    la a1, newline
+    li a2, 1
+    j print_str
+
+print_bool:
+    li a3, 0                # No padding
+
+print_bool_width:
+    la a1, true_text
+    li a2, 4
+    bnez a0, print_str_width
+    la a1, false_text
+    li a2, 5
+    j print_str_width
+
+print_int:
+    li a3, 0                # No padding
+
+print_int_width:
+    mv a4, a0               # Keep the original value for the sign check
+    la a1, int_buffer
+    addi a1, a1, 20         # Digits are written backwards from the end
+    li a5, 10
+    bgez a0, print_int_loop
+    neg a0, a0              # Magnitude; i64::MIN stays 2^63 when read unsigned
+
+print_int_loop:
+    remu a6, a0, a5
+    divu a0, a0, a5
+    addi a6, a6, 48         # Convert remainder to ASCII
+    addi a1, a1, -1         # Move back and store
+    sb a6, 0(a1)
+    bnez a0, print_int_loop
+
+    bgez a4, print_int_write
+    li a6, 45               # '-'
+    addi a1, a1, -1
+    sb a6, 0(a1)
+
+print_int_write:
+    la a2, int_buffer
+    addi a2, a2, 20
+    sub a2, a2, a1          # Length of the converted number
+    j print_str_width
+
+print_real:
+    li a3, 0                # No padding
+
+print_real_width:
+    slli a4, a0, 1
+    srli a4, a4, 1          # Magnitude
+    li a5, 0x7ff0000000000000 # Infinity
+    bgt a4, a5, print_real_nan
+    beq a4, a5, print_real_infinity
+    fmv.d.x ft0, a4
+    li a6, 6                # Digits after the point
+    bltz a2, print_real_digits # Trailing zeros are left out while A2 is negative
+    li a6, 18
+    bge a2, a6, print_real_digits
+    mv a6, a2
+
+print_real_digits:
+    li a7, 0                # Zeros before the point
+    li a5, 1000000000000000000
+    fcvt.d.l ft1, a5
+    li a5, 10
+    fcvt.d.l ft2, a5
+
+print_real_scale:
+    flt.d t3, ft0, ft1
+    bnez t3, print_real_split
+    fdiv.d ft0, ft0, ft2
+    addi a7, a7, 1
+    j print_real_scale
+
+print_real_split:
+    fcvt.l.d a4, ft0, rtz   # The part before the point
+    fcvt.d.l ft1, a4
+    fsub.d ft0, ft0, ft1
+    beqz a7, print_real_fraction
+    fmv.d.x ft0, zero       # Digits that were divided away are written as zeros
+
+print_real_fraction:
+    li a5, 1
+    mv t3, a6
+    li t4, 10
+
+print_real_power:
+    beqz t3, print_real_round
+    mul a5, a5, t4
+    addi t3, t3, -1
+    j print_real_power
+
+print_real_round:
+    fcvt.d.l ft1, a5
+    fmul.d ft0, ft0, ft1    # Scaled by 10 to the number of digits
+    fcvt.l.d t3, ft0, rtz
+    fcvt.d.l ft1, t3
+    fsub.d ft0, ft0, ft1
+    li t4, 0x3fe0000000000000 # 0.5
+    fmv.d.x ft1, t4
+    flt.d t4, ft0, ft1
+    bnez t4, print_real_carry
+    addi t3, t3, 1
+
+print_real_carry:
+    blt t3, a5, print_real_start
+    sub t3, t3, a5
+    addi a4, a4, 1
+
+print_real_start:
+    la a1, real_buffer
+    addi a1, a1, 336        # Written backwards from the end
+    li a5, 10
+
+print_real_fraction_loop:
+    beqz a6, print_real_point
+    remu t4, t3, a5
+    divu t3, t3, a5
+    addi a6, a6, -1
+    bgez a2, print_real_fraction_digit
+    bnez t4, print_real_fraction_digit
+    bnez a6, print_real_fraction_loop # Keep the last digit
+
+print_real_fraction_digit:
+    li a2, 0                # Zeros before a kept digit stay
+    addi t4, t4, 48
+    addi a1, a1, -1
+    sb t4, 0(a1)
+    j print_real_fraction_loop
+
+print_real_point:
+    la t4, real_buffer
+    addi t4, t4, 336
+    beq a1, t4, print_real_zeros
+    li t4, 46               # '.'
+    addi a1, a1, -1
+    sb t4, 0(a1)
+
+print_real_zeros:
+    beqz a7, print_real_whole
+    li t4, 48
+    addi a1, a1, -1
+    sb t4, 0(a1)
+    addi a7, a7, -1
+    j print_real_zeros
+
+print_real_whole:
+    remu t4, a4, a5
+    divu a4, a4, a5
+    addi t4, t4, 48
+    addi a1, a1, -1
+    sb t4, 0(a1)
+    bnez a4, print_real_whole
+
+    bgez a0, print_real_write
+    li t4, 45               # '-'
+    addi a1, a1, -1
+    sb t4, 0(a1)
+
+print_real_write:
+    la a2, real_buffer
+    addi a2, a2, 336
+    sub a2, a2, a1
+    j print_str_width
+
+print_real_nan:
+    la a1, nan_text
+    li a2, 3
+    j print_str_width
+
+print_real_infinity:
+    la a1, infinity_text
+    li a2, 4
+    bltz a0, print_str_width
+    addi a1, a1, 1          # Inf without the sign
+    li a2, 3
+    j print_str_width
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::exit_status;
+    use crate::{compile, Options, Target};
+
+    fn options(opt_level: u8) -> Options {
+        Options { opt_level, target: Target::Riscv64Linux, ..Options::default() }
+    }
+
+    #[test]
+    fn test_variables_in_memory_and_registers() {
+        let source = "var a0, ra: integer; begin for a0 := 1 to 3 do ra := ra + a0; writeln(ra:3); end";
+
+        let unoptimised = compile(source, &options(0)).unwrap().assembly;
+        assert!(unoptimised.contains(".balign 8\nvar_a0: .quad 0\n.balign 8\nvar_ra: .quad 0\n"));
+        assert!(unoptimised.contains("li t0, 1\nla t5, var_a0\nsd t0, 0(t5)\n"));
+        assert!(unoptimised.contains("li a3, 3\ncall print_int_width\n"));
+        assert!(unoptimised.ends_with("j print_str_width\n"));
+
+        let optimised = compile(source, &options(1)).unwrap().assembly;
+        assert!(!optimised.contains("var_"));
+        assert!(optimised.contains("addi s1, s1, 1\n"));
+        assert!(optimised.contains("addi t3, s1, -3\nseqz s4, t3\n"));
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let allocation = Allocation::in_memory(&Program::default());
+        let mut generator = TextGenerator::<Riscv64>::new(&allocation);
+        let constant = |value| Operand::Constant(value);
+        Riscv64::binary(&mut generator, BinaryOperator::Less, "t0", "t1", &constant(-5));
+        Riscv64::binary(&mut generator, BinaryOperator::Less, "t0", "t1", &constant(5000));
+        Riscv64::binary(&mut generator, BinaryOperator::LessEqual, "t0", "t1", &constant(0));
+        Riscv64::binary(&mut generator, BinaryOperator::Greater, "s1", "s1", &constant(2));
+        Riscv64::binary(&mut generator, BinaryOperator::NotEqual, "t0", "t1", &constant(-2048));
+        Riscv64::binary(&mut generator, BinaryOperator::Subtract, "t0", "t1", &constant(2048));
+        assert_eq!(
+            generator.text,
+            "slti t0, t1, -5\nli t2, 5000\nslt t0, t1, t2\nslt t3, zero, t1\nxori t0, t3, 1\nli t2, 2\nslt s1, t2, s1\n\
+             li t2, -2048\nxor t3, t1, t2\nsnez t0, t3\naddi t0, t1, -2048\n"
+        );
+    }
+
+    #[test]
+    fn test_real_operators() {
+        let allocation = Allocation::in_memory(&Program::default());
+        let mut generator = TextGenerator::<Riscv64>::new(&allocation);
+        let two = Operand::Constant(crate::ir::to_bits(2.0));
+        Riscv64::binary(&mut generator, BinaryOperator::RealGreater, "t0", "t1", &two);
+        Riscv64::binary(&mut generator, BinaryOperator::RealNotEqual, "t0", "t1", &two);
+        Riscv64::binary(&mut generator, BinaryOperator::RealDivide, "t0", "t1", &two);
+        assert_eq!(
+            generator.text,
+            "li t2, 4611686018427387904\nfmv.d.x ft0, t1\nfmv.d.x ft1, t2\nflt.d t0, ft1, ft0\n\
+             li t2, 4611686018427387904\nfmv.d.x ft0, t1\nfmv.d.x ft1, t2\nfeq.d t3, ft0, ft1\nxori t0, t3, 1\n\
+             li t2, 4611686018427387904\nfmv.d.x ft0, t1\nfmv.d.x ft1, t2\nfdiv.d ft0, ft0, ft1\nfmv.x.d t0, ft0\n"
+        );
+    }
+
+    const DIVISION_BY_ZERO: &str = "var a, b: integer; begin a := 7; writeln(a div 2, a mod 3); writeln(a rem b); end";
+
+    #[test]
+    fn test_only_divisions_by_variables_are_checked() {
+        let assembly = compile(DIVISION_BY_ZERO, &options(0)).unwrap().assembly;
+        assert_eq!(assembly.matches(", runtime_error").count(), 1);
+        assert!(assembly.contains("beqz t2, runtime_error\n"));
+    }
+
+    #[test]
+    #[ignore = "needs the riscv64-linux-gnu binutils and qemu-riscv64"]
+    fn test_division_by_zero_is_a_runtime_error() {
+        for opt_level in [0, 1, 2] {
+            let status = exit_status(DIVISION_BY_ZERO, &options(opt_level));
+            assert_eq!(status.code(), Some(1));
+        }
+    }
+}
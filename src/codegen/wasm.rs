@@ -0,0 +1,834 @@
+//! Translation of the AST into a WebAssembly text module for WASI, to run in
+//! wasmtime or in a browser with a WASI shim.
+//!
+//! The variables become mutable globals, `i64` for integers, `i32` for
+//! booleans and `f64` for reals, and the string literals one data segment in
+//! linear memory.
+//! Writing goes through WASI `fd_write` on stdout, with a small runtime that
+//! pads fields and turns numbers into digits. Wasm has structured control
+//! flow and wrapping integer arithmetic, so the statements and expressions
+//! translate one to one, except the divisions, which call runtime functions
+//! that check the divisor. Globals, functions and locals have separate name
+//! spaces in the text format, so the Pascal names can be used as they are.
+
+use crate::ast::{BinaryOperator, Block, Expr, ExprKind, PrintItem, Program, Stmt, StmtKind, TypeName, UnaryOperator};
+use crate::semantic::{Analysis, Type};
+
+const INDENT: &str = "  ";
+
+// Linear memory starts with what the runtime uses, laid out there, and the
+// string literals of the program follow.
+const NEWLINE: usize = 57;
+const STRINGS: usize = 448;
+const PAGE_SIZE: usize = 65536;
+
+/// Translates `program` into a WebAssembly text module that exports
+/// `_start` and `memory` as WASI expects.
+pub fn generate_code(program: &Program, analysis: &Analysis, source_name: Option<&str>) -> String {
+    let mut generator =
+        WasmGenerator { analysis, strings: Vec::new(), locals: Vec::new(), labels: 0, out: String::new() };
+    generator.block(&program.body, 2);
+    let strings = generator.strings.concat();
+
+    let mut module = String::new();
+    if let Some(name) = source_name {
+        module.push_str(&format!(";; Generated by ccc from {}\n", name));
+    }
+    module.push_str("(module\n");
+    module.push_str("  (import \"wasi_snapshot_preview1\" \"fd_write\"");
+    module.push_str(" (func $fd_write (param i32 i32 i32 i32) (result i32)))\n");
+    module.push_str("  (import \"wasi_snapshot_preview1\" \"proc_exit\" (func $proc_exit (param i32)))\n");
+    module.push_str(&format!("  (memory (export \"memory\") {})\n", (STRINGS + strings.len()).div_ceil(PAGE_SIZE)));
+    if !strings.is_empty() {
+        module.push_str(&format!("  (data (i32.const {}) \"{}\")\n", STRINGS, escape(&strings)));
+    }
+    module.push('\n');
+    for decl in &program.declarations {
+        let value_type = match decl.type_name {
+            TypeName::Boolean => "i32",
+            TypeName::Real => "f64",
+            TypeName::Integer | TypeName::Int64 => "i64",
+        };
+        for name in &decl.names {
+            module.push_str(&format!("  (global ${} (mut {}) ({}.const 0))\n", name, value_type, value_type));
+        }
+    }
+    if !program.declarations.is_empty() {
+        module.push('\n');
+    }
+    module.push_str("  (func $main (export \"_start\")\n");
+    for local in &generator.locals {
+        module.push_str(&format!("    (local {} i64)\n", local));
+    }
+    module.push_str(&generator.out);
+    module.push_str("  )\n");
+    module.push_str(RUNTIME);
+    module.push_str(")\n");
+    module
+}
+
+struct WasmGenerator<'a> {
+    analysis: &'a Analysis,
+    strings: Vec<Vec<u8>>, // The literals in the data segment, in order
+    locals: Vec<String>,   // Locals of $main holding the end values of for loops
+    labels: usize,
+    out: String,
+}
+
+impl WasmGenerator<'_> {
+    fn emit(&mut self, depth: usize, text: &str) {
+        self.out.push_str(&INDENT.repeat(depth));
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    // A number for the labels of a new block, so nested ones differ.
+    fn label(&mut self) -> usize {
+        self.labels += 1;
+        self.labels - 1
+    }
+
+    fn block(&mut self, block: &Block, depth: usize) {
+        for stmt in &block.statements {
+            self.stmt(stmt, depth);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, depth: usize) {
+        match &stmt.kind {
+            StmtKind::Assign { target, value } => {
+                match self.analysis.variables[target] {
+                    Type::Real => self.real(value, depth),
+                    _ => self.expr(value, depth),
+                }
+                self.emit(depth, &format!("global.set ${}", target));
+            }
+            StmtKind::Print { items, newline } => {
+                for item in items {
+                    self.print_item(item, depth);
+                }
+                if *newline {
+                    self.emit(depth, &format!("i32.const {}", NEWLINE));
+                    self.emit(depth, "i32.const 1");
+                    self.emit(depth, "call $write");
+                }
+            }
+            StmtKind::Compound(block) => self.block(block, depth),
+            StmtKind::If { condition, then_branch, else_branch } => {
+                self.expr(condition, depth);
+                self.emit(depth, "if");
+                self.stmt(then_branch, depth + 1);
+                if let Some(else_branch) = else_branch {
+                    self.emit(depth, "else");
+                    self.stmt(else_branch, depth + 1);
+                }
+                self.emit(depth, "end");
+            }
+            StmtKind::While { condition, body } => {
+                let label = self.label();
+                self.emit(depth, &format!("block $done{}", label));
+                self.emit(depth + 1, &format!("loop $next{}", label));
+                self.expr(condition, depth + 2);
+                self.emit(depth + 2, "i32.eqz");
+                self.emit(depth + 2, &format!("br_if $done{}", label));
+                self.stmt(body, depth + 2);
+                self.emit(depth + 2, &format!("br $next{}", label));
+                self.emit(depth + 1, "end");
+                self.emit(depth, "end");
+            }
+            StmtKind::For { variable, start, end, descending, body } => {
+                // As in the native code, the loop stops at the end value
+                // rather than stepping past it, so the variable keeps that
+                // value afterwards and cannot overflow. Unless it is a
+                // literal, the end value is evaluated first, into a local.
+                let label = self.label();
+                let end = match end.kind {
+                    ExprKind::Integer(value) => format!("i64.const {}", value),
+                    _ => {
+                        let local = format!("$end{}", label);
+                        self.expr(end, depth);
+                        self.emit(depth, &format!("local.set {}", local));
+                        self.locals.push(local.clone());
+                        format!("local.get {}", local)
+                    }
+                };
+                self.expr(start, depth);
+                self.emit(depth, &format!("global.set ${}", variable));
+                self.emit(depth, &format!("block $done{}", label));
+                self.emit(depth + 1, &format!("loop $next{}", label));
+                let (past_end, step) = if *descending { ("i64.lt_s", "i64.sub") } else { ("i64.gt_s", "i64.add") };
+                for instruction in [&format!("global.get ${}", variable), &end, past_end] {
+                    self.emit(depth + 2, instruction);
+                }
+                self.emit(depth + 2, &format!("br_if $done{}", label));
+                self.stmt(body, depth + 2);
+                for instruction in [&format!("global.get ${}", variable), &end, "i64.eq"] {
+                    self.emit(depth + 2, instruction);
+                }
+                self.emit(depth + 2, &format!("br_if $done{}", label));
+                for instruction in [&format!("global.get ${}", variable), "i64.const 1", step] {
+                    self.emit(depth + 2, instruction);
+                }
+                self.emit(depth + 2, &format!("global.set ${}", variable));
+                self.emit(depth + 2, &format!("br $next{}", label));
+                self.emit(depth + 1, "end");
+                self.emit(depth, "end");
+            }
+            StmtKind::Exit => self.emit(depth, "return"),
+        }
+    }
+
+    // Writes one item. The width is evaluated before the precision and the
+    // value, as in the native code.
+    fn print_item(&mut self, item: &PrintItem, depth: usize) {
+        let width = |generator: &mut Self| match &item.width {
+            Some(width) => generator.expr(width, depth),
+            None => generator.emit(depth, "i64.const 0"),
+        };
+        match &item.value.kind {
+            ExprKind::String(text) => {
+                if item.width.is_some() {
+                    width(self);
+                }
+                let address = STRINGS + self.strings.iter().map(Vec::len).sum::<usize>();
+                self.strings.push(text.clone());
+                self.emit(depth, &format!("i32.const {}", address));
+                self.emit(depth, &format!("i32.const {}", text.len()));
+                self.emit(depth, if item.width.is_some() { "call $write_padded" } else { "call $write" });
+            }
+            _ => {
+                width(self);
+                let value_type = self.analysis.type_of(&item.value);
+                if value_type == Type::Real {
+                    match &item.precision {
+                        Some(precision) => self.expr(precision, depth),
+                        None => self.emit(depth, "i64.const -1"),
+                    }
+                }
+                self.expr(&item.value, depth);
+                match value_type {
+                    Type::Boolean => self.emit(depth, "call $write_bool"),
+                    Type::Real => self.emit(depth, "call $write_real"),
+                    Type::Integer | Type::String => self.emit(depth, "call $write_int"),
+                }
+            }
+        }
+    }
+
+    // Leaves the value of `expr` on the stack: an i64 for an integer, an i32
+    // for a boolean and an f64 for a real.
+    fn expr(&mut self, expr: &Expr, depth: usize) {
+        match &expr.kind {
+            ExprKind::Integer(value) => self.emit(depth, &format!("i64.const {}", value)),
+            ExprKind::Real(value) => self.emit(depth, &format!("f64.const {:?}", value)),
+            ExprKind::Boolean(value) => self.emit(depth, &format!("i32.const {}", *value as i32)),
+            ExprKind::String(_) => unreachable!("string literals only appear in write and writeln"),
+            ExprKind::Variable(name) => self.emit(depth, &format!("global.get ${}", name)),
+            ExprKind::Unary { operator: UnaryOperator::Negate, operand }
+                if let ExprKind::Integer(value) = operand.kind =>
+            {
+                self.emit(depth, &format!("i64.const -{}", value))
+            }
+            ExprKind::Unary { operator: UnaryOperator::Negate, operand }
+                if self.analysis.type_of(expr) == Type::Real =>
+            {
+                self.expr(operand, depth);
+                self.emit(depth, "f64.neg");
+            }
+            ExprKind::Unary { operator: UnaryOperator::Negate, operand } => {
+                self.emit(depth, "i64.const 0");
+                self.expr(operand, depth);
+                self.emit(depth, "i64.sub");
+            }
+            ExprKind::Unary { operator: UnaryOperator::Not, operand } => {
+                self.expr(operand, depth);
+                self.emit(depth, "i32.eqz");
+            }
+            // Only evaluated as far as needed, as in the native code, which
+            // matters when the right operand divides by zero
+            ExprKind::Binary { left, operator: operator @ (BinaryOperator::And | BinaryOperator::Or), right } => {
+                self.expr(left, depth);
+                self.emit(depth, "if (result i32)");
+                if *operator == BinaryOperator::And {
+                    self.expr(right, depth + 1);
+                    self.emit(depth, "else");
+                    self.emit(depth + 1, "i32.const 0");
+                } else {
+                    self.emit(depth + 1, "i32.const 1");
+                    self.emit(depth, "else");
+                    self.expr(right, depth + 1);
+                }
+                self.emit(depth, "end");
+            }
+            ExprKind::Binary { left, operator, right }
+                if self.analysis.type_of(left) == Type::Real || self.analysis.type_of(right) == Type::Real =>
+            {
+                self.real(left, depth);
+                self.real(right, depth);
+                let instruction = match operator {
+                    BinaryOperator::Add => "f64.add",
+                    BinaryOperator::Subtract => "f64.sub",
+                    BinaryOperator::Multiply => "f64.mul",
+                    BinaryOperator::Divide => "call $real_div",
+                    BinaryOperator::Equal => "f64.eq",
+                    BinaryOperator::NotEqual => "f64.ne",
+                    BinaryOperator::Less => "f64.lt",
+                    BinaryOperator::LessEqual => "f64.le",
+                    BinaryOperator::Greater => "f64.gt",
+                    BinaryOperator::GreaterEqual => "f64.ge",
+                    _ => unreachable!("{:?} takes no reals", operator),
+                };
+                self.emit(depth, instruction);
+            }
+            ExprKind::Binary { left, operator, right } => {
+                self.expr(left, depth);
+                self.expr(right, depth);
+                let value_type = if self.analysis.type_of(left) == Type::Boolean { "i32" } else { "i64" };
+                let instruction = match operator {
+                    BinaryOperator::Add => "add",
+                    BinaryOperator::Subtract => "sub",
+                    BinaryOperator::Multiply => "mul",
+                    // The divisions check the divisor instead of trapping
+                    BinaryOperator::Divide | BinaryOperator::Div => return self.emit(depth, "call $div"),
+                    BinaryOperator::Rem => return self.emit(depth, "call $rem"),
+                    BinaryOperator::Mod => return self.emit(depth, "call $mod"),
+                    BinaryOperator::Equal => "eq",
+                    BinaryOperator::NotEqual => "ne",
+                    BinaryOperator::Less => "lt_s",
+                    BinaryOperator::LessEqual => "le_s",
+                    BinaryOperator::Greater => "gt_s",
+                    BinaryOperator::GreaterEqual => "ge_s",
+                    BinaryOperator::And | BinaryOperator::Or => unreachable!(),
+                };
+                self.emit(depth, &format!("{}.{}", value_type, instruction));
+            }
+        }
+    }
+
+    // Leaves the value of `expr` on the stack as an f64, converting an
+    // integer.
+    fn real(&mut self, expr: &Expr, depth: usize) {
+        self.expr(expr, depth);
+        if self.analysis.type_of(expr) != Type::Real {
+            self.emit(depth, "f64.convert_i64_s");
+        }
+    }
+}
+
+// The contents of a WAT string for `text`. Bytes outside printable ASCII
+// become two-digit hex escapes.
+fn escape(text: &[u8]) -> String {
+    let mut escaped = String::new();
+    for &byte in text {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{:02x}", byte)),
+        }
+    }
+    escaped
+}
+
+// $write writes bytes from memory to stdout, $write_padded first as many
+// spaces as the field is wider than them. Numbers are written as digits from
+// the end of the buffer at 16 backwards, and reals from the end of the one at
+// 104.
+const RUNTIME: &str = r#"
+  ;; 0: the iovec for fd_write, 8: the count it writes back, 16: the digits of a number,
+  ;; 104 to 440: the text of a real
+  (data (i32.const 48) "TRUEFALSE\0a                                      ")
+  (data (i32.const 96) "NaN-Inf")
+
+  (func $write (param $address i32) (param $length i32)
+    i32.const 0
+    local.get $address
+    i32.store
+    i32.const 4
+    local.get $length
+    i32.store
+    i32.const 1              ;; stdout
+    i32.const 0
+    i32.const 1
+    i32.const 8
+    call $fd_write
+    drop
+  )
+
+  (func $write_padded (param $width i64) (param $address i32) (param $length i32)
+    (local $padding i64)
+    block $done
+      loop $next
+        local.get $width
+        local.get $length
+        i64.extend_i32_u
+        i64.le_s
+        br_if $done
+        local.get $width
+        local.get $length
+        i64.extend_i32_u
+        i64.sub
+        local.set $padding
+        ;; At most as many spaces as are in memory at a time
+        i32.const 58
+        local.get $padding
+        i64.const 38
+        local.get $padding
+        i64.const 38
+        i64.lt_s
+        select
+        i32.wrap_i64
+        call $write
+        local.get $width
+        i64.const 38
+        i64.sub
+        local.set $width
+        br $next
+      end
+    end
+    local.get $address
+    local.get $length
+    call $write
+  )
+
+  (func $write_int (param $width i64) (param $value i64)
+    (local $magnitude i64)
+    (local $position i32)
+    i32.const 40
+    local.set $position
+    ;; Negating as unsigned is right for the most negative value as well
+    i64.const 0
+    local.get $value
+    i64.sub
+    local.get $value
+    local.get $value
+    i64.const 0
+    i64.lt_s
+    select
+    local.set $magnitude
+    loop $next
+      local.get $position
+      i32.const 1
+      i32.sub
+      local.tee $position
+      local.get $magnitude
+      i64.const 10
+      i64.rem_u
+      i64.const 48             ;; '0'
+      i64.add
+      i64.store8
+      local.get $magnitude
+      i64.const 10
+      i64.div_u
+      local.tee $magnitude
+      i64.eqz
+      i32.eqz
+      br_if $next
+    end
+    local.get $value
+    i64.const 0
+    i64.lt_s
+    if
+      local.get $position
+      i32.const 1
+      i32.sub
+      local.tee $position
+      i32.const 45             ;; '-'
+      i32.store8
+    end
+    local.get $width
+    local.get $position
+    i32.const 40
+    local.get $position
+    i32.sub
+    call $write_padded
+  )
+
+  (func $write_bool (param $width i64) (param $value i32)
+    local.get $width
+    i32.const 48             ;; TRUE
+    i32.const 52             ;; FALSE
+    local.get $value
+    select
+    i32.const 4
+    i32.const 5
+    local.get $value
+    select
+    call $write_padded
+  )
+
+  ;; The steps of print_real on the native targets: numbers of 1e18 and more
+  ;; are divided by 10 until they fit in an integer, with a zero written for
+  ;; each division, and the part after the point is scaled to an integer and
+  ;; rounded half up, which may carry into the part before it. A negative
+  ;; precision means up to 6 digits, without trailing zeros.
+  (func $write_real (param $width i64) (param $precision i64) (param $value f64)
+    (local $magnitude f64)
+    (local $scaled f64)
+    (local $digits i64)
+    (local $zeros i64)
+    (local $whole i64)
+    (local $scale i64)
+    (local $part i64)
+    (local $count i64)
+    (local $trim i32)
+    (local $position i32)
+    local.get $value
+    f64.abs
+    local.tee $magnitude
+    local.get $magnitude
+    f64.ne
+    if                       ;; NaN
+      local.get $width
+      i32.const 96
+      i32.const 3
+      call $write_padded
+      return
+    end
+    local.get $magnitude
+    f64.const inf
+    f64.eq
+    if                       ;; Inf, or -Inf from 99 on
+      local.get $width
+      i32.const 100
+      i32.const 99
+      local.get $value
+      i64.reinterpret_f64
+      i64.const 0
+      i64.ge_s
+      local.tee $trim
+      select
+      i32.const 3
+      i32.const 4
+      local.get $trim
+      select
+      call $write_padded
+      return
+    end
+    ;; 6 for a negative precision, else at most 18
+    i64.const 6
+    local.get $precision
+    i64.const 18
+    local.get $precision
+    i64.const 18
+    i64.lt_s
+    select
+    local.get $precision
+    i64.const 0
+    i64.lt_s
+    select
+    local.set $digits
+    block $small
+      loop $next
+        local.get $magnitude
+        f64.const 1e18
+        f64.lt
+        br_if $small
+        local.get $magnitude
+        f64.const 10
+        f64.div
+        local.set $magnitude
+        local.get $zeros
+        i64.const 1
+        i64.add
+        local.set $zeros
+        br $next
+      end
+    end
+    local.get $magnitude
+    i64.trunc_f64_s
+    local.set $whole
+    i64.const 1
+    local.set $scale
+    block $done
+      loop $next
+        local.get $count
+        local.get $digits
+        i64.ge_s
+        br_if $done
+        local.get $scale
+        i64.const 10
+        i64.mul
+        local.set $scale
+        local.get $count
+        i64.const 1
+        i64.add
+        local.set $count
+        br $next
+      end
+    end
+    ;; The digits that were divided away are written as zeros
+    local.get $magnitude
+    local.get $whole
+    f64.convert_i64_s
+    f64.sub
+    f64.const 0
+    local.get $zeros
+    i64.eqz
+    select
+    local.get $scale
+    f64.convert_i64_s
+    f64.mul
+    local.tee $scaled
+    i64.trunc_f64_s
+    local.set $part
+    local.get $part
+    local.get $scaled
+    local.get $part
+    f64.convert_i64_s
+    f64.sub
+    f64.const 0.5
+    f64.ge
+    i64.extend_i32_u
+    i64.add
+    local.set $part
+    local.get $part
+    local.get $scale
+    i64.ge_s
+    if
+      local.get $part
+      local.get $scale
+      i64.sub
+      local.set $part
+      local.get $whole
+      i64.const 1
+      i64.add
+      local.set $whole
+    end
+    i32.const 440
+    local.set $position
+    local.get $precision
+    i64.const 0
+    i64.lt_s
+    local.set $trim
+    block $done
+      loop $next
+        local.get $digits
+        i64.eqz
+        br_if $done
+        local.get $digits
+        i64.const 1
+        i64.sub
+        local.set $digits
+        ;; Zeros are left out while trimming, but for the last digit
+        local.get $trim
+        local.get $part
+        i64.const 10
+        i64.rem_u
+        i64.eqz
+        i32.and
+        local.get $digits
+        i64.const 0
+        i64.ne
+        i32.and
+        i32.eqz
+        if
+          i32.const 0
+          local.set $trim
+          local.get $position
+          i32.const 1
+          i32.sub
+          local.tee $position
+          local.get $part
+          i64.const 10
+          i64.rem_u
+          i64.const 48           ;; '0'
+          i64.add
+          i64.store8
+        end
+        local.get $part
+        i64.const 10
+        i64.div_u
+        local.set $part
+        br $next
+      end
+    end
+    local.get $position
+    i32.const 440
+    i32.ne
+    if
+      local.get $position
+      i32.const 1
+      i32.sub
+      local.tee $position
+      i32.const 46             ;; '.'
+      i32.store8
+    end
+    block $done
+      loop $next
+        local.get $zeros
+        i64.eqz
+        br_if $done
+        local.get $position
+        i32.const 1
+        i32.sub
+        local.tee $position
+        i32.const 48
+        i32.store8
+        local.get $zeros
+        i64.const 1
+        i64.sub
+        local.set $zeros
+        br $next
+      end
+    end
+    loop $next
+      local.get $position
+      i32.const 1
+      i32.sub
+      local.tee $position
+      local.get $whole
+      i64.const 10
+      i64.rem_u
+      i64.const 48
+      i64.add
+      i64.store8
+      local.get $whole
+      i64.const 10
+      i64.div_u
+      local.tee $whole
+      i64.eqz
+      i32.eqz
+      br_if $next
+    end
+    local.get $value
+    i64.reinterpret_f64
+    i64.const 0
+    i64.lt_s
+    if
+      local.get $position
+      i32.const 1
+      i32.sub
+      local.tee $position
+      i32.const 45             ;; '-'
+      i32.store8
+    end
+    local.get $width
+    local.get $position
+    i32.const 440
+    local.get $position
+    i32.sub
+    call $write_padded
+  )
+
+  ;; Division by zero ends the program with exit status 1, as on the native
+  ;; targets, and dividing the most negative integer by -1 wraps around
+  ;; instead of trapping. Division truncates toward zero.
+  (func $check_divisor (param $divisor i64)
+    local.get $divisor
+    i64.eqz
+    if
+      i32.const 1
+      call $proc_exit
+    end
+  )
+
+  (func $div (param $dividend i64) (param $divisor i64) (result i64)
+    local.get $divisor
+    call $check_divisor
+    local.get $divisor
+    i64.const -1
+    i64.eq
+    if (result i64)
+      i64.const 0
+      local.get $dividend
+      i64.sub
+    else
+      local.get $dividend
+      local.get $divisor
+      i64.div_s
+    end
+  )
+
+  ;; rem takes the sign of the dividend
+  (func $rem (param $dividend i64) (param $divisor i64) (result i64)
+    local.get $divisor
+    call $check_divisor
+    local.get $dividend
+    local.get $divisor
+    i64.rem_s
+  )
+
+  ;; Dividing a real by 0.0 or -0.0 ends the program as well
+  (func $real_div (param $dividend f64) (param $divisor f64) (result f64)
+    local.get $divisor
+    f64.const 0
+    f64.eq
+    if
+      i32.const 1
+      call $proc_exit
+    end
+    local.get $dividend
+    local.get $divisor
+    f64.div
+  )
+
+  ;; mod takes the sign of the divisor
+  (func $mod (param $dividend i64) (param $divisor i64) (result i64)
+    (local $remainder i64)
+    local.get $dividend
+    local.get $divisor
+    call $rem
+    local.tee $remainder
+    local.get $divisor
+    i64.add
+    local.get $remainder
+    local.get $remainder
+    local.get $divisor
+    i64.xor
+    i64.const 0
+    i64.lt_s
+    local.get $remainder
+    i64.const 0
+    i64.ne
+    i32.and
+    select
+  )
+"#;
+
+#[cfg(test)]
+mod tests {
+    use crate::{compile, Options, Target};
+
+    fn options() -> Options {
+        Options { target: Target::Wasm, ..Options::default() }
+    }
+
+    #[test]
+    fn test_globals_strings_and_loops() {
+        let source = "var n, i: integer; done: boolean; begin for i := 5 downto n do n := n mod 3; \
+                      done := done or (n < 2); writeln('x', 'é':n, done); end";
+        let module = compile(source, &options()).unwrap().assembly;
+        assert!(module.starts_with("(module\n"));
+        assert!(module.contains("  (memory (export \"memory\") 1)\n  (data (i32.const 448) \"x\\c3\\a9\")\n"));
+        assert!(module.contains(
+            "  (global $n (mut i64) (i64.const 0))\n  (global $i (mut i64) (i64.const 0))\n  \
+             (global $done (mut i32) (i32.const 0))\n\n  (func $main (export \"_start\")\n    (local $end0 i64)\n"
+        ));
+        assert!(module.contains(
+            "    block $done0\n      loop $next0\n        global.get $i\n        local.get $end0\n        \
+             i64.lt_s\n        br_if $done0\n        global.get $n\n        i64.const 3\n        call $mod\n        global.set $n\n"
+        ));
+        assert!(module.contains(
+            "    global.get $done\n    if (result i32)\n      i32.const 1\n    else\n      global.get $n\n      \
+             i64.const 2\n      i64.lt_s\n    end\n"
+        ));
+        assert!(module.contains(
+            "    i32.const 448\n    i32.const 1\n    call $write\n    global.get $n\n    i32.const 449\n    \
+             i32.const 2\n    call $write_padded\n    i64.const 0\n    global.get $done\n    call $write_bool\n"
+        ));
+    }
+
+    #[test]
+    fn test_reals_are_f64() {
+        let source = "var x: real; n: integer; begin x := n; writeln(x / 2:8:n, x <> 0.5); end";
+        let module = compile(source, &options()).unwrap().assembly;
+        assert!(module.contains("  (global $x (mut f64) (f64.const 0))\n"));
+        assert!(module.contains("    global.get $n\n    f64.convert_i64_s\n    global.set $x\n"));
+        assert!(module.contains(
+            "    i64.const 8\n    global.get $n\n    global.get $x\n    i64.const 2\n    f64.convert_i64_s\n    \
+             call $real_div\n    call $write_real\n"
+        ));
+        assert!(module.contains("    global.get $x\n    f64.const 0.5\n    f64.ne\n"));
+    }
+}
@@ -0,0 +1,132 @@
+//! Human-readable dumps of the compiler stages for `--emit`.
+
+use crate::ast::{
+    walk_block, walk_expr, walk_print_item, walk_program, walk_stmt, Block, Decl, Expr, ExprKind, PrintItem, Program,
+    Stmt, StmtKind, Visitor,
+};
+use crate::lexer::SpannedToken;
+
+/// One token per line, prefixed with its line and column.
+pub fn format_tokens(tokens: &[SpannedToken]) -> String {
+    tokens
+        .iter()
+        .map(|spanned| format!("{}:{} {:?}\n", spanned.span.line, spanned.span.column, spanned.token))
+        .collect()
+}
+
+/// The tree with one node per line, children indented below their parent.
+pub fn format_ast(program: &Program) -> String {
+    let mut printer = TreePrinter { out: String::new(), depth: 0 };
+    printer.visit_program(program);
+    printer.out
+}
+
+struct TreePrinter {
+    out: String,
+    depth: usize,
+}
+
+impl TreePrinter {
+    fn line(&mut self, text: &str) {
+        self.out.push_str(&"  ".repeat(self.depth));
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    // Prints `text` and visits the children one level deeper.
+    fn nested(&mut self, text: &str, visit_children: impl FnOnce(&mut Self)) {
+        self.line(text);
+        self.depth += 1;
+        visit_children(self);
+        self.depth -= 1;
+    }
+}
+
+impl Visitor for TreePrinter {
+    fn visit_program(&mut self, program: &Program) {
+        self.nested("Program", |printer| walk_program(printer, program));
+    }
+
+    fn visit_decl(&mut self, decl: &Decl) {
+        self.line(&format!("Decl {}: {}", decl.names.join(", "), decl.type_name.as_str()));
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        self.nested("Block", |printer| walk_block(printer, block));
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        let text = match &stmt.kind {
+            StmtKind::Assign { target, .. } => format!("Assign {}", target),
+            StmtKind::Print { newline: true, .. } => "Print writeln".to_string(),
+            StmtKind::Print { newline: false, .. } => "Print write".to_string(),
+            StmtKind::Compound(_) => "Compound".to_string(),
+            StmtKind::If { .. } => "If".to_string(),
+            StmtKind::While { .. } => "While".to_string(),
+            StmtKind::For { variable, descending: false, .. } => format!("For {} to", variable),
+            StmtKind::For { variable, descending: true, .. } => format!("For {} downto", variable),
+            StmtKind::Exit => "Exit".to_string(),
+        };
+        self.nested(&text, |printer| walk_stmt(printer, stmt));
+    }
+
+    fn visit_print_item(&mut self, item: &PrintItem) {
+        let text = match (&item.width, &item.precision) {
+            (None, _) => "Item",
+            (Some(_), None) => "Item value:width",
+            (Some(_), Some(_)) => "Item value:width:precision",
+        };
+        self.nested(text, |printer| walk_print_item(printer, item));
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        let text = match &expr.kind {
+            ExprKind::Integer(value) => format!("Integer {}", value),
+            ExprKind::Real(value) => format!("Real {:?}", value),
+            ExprKind::Boolean(value) => format!("Boolean {}", value),
+            ExprKind::String(text) => format!("String \"{}\"", text.escape_ascii()),
+            ExprKind::Variable(name) => format!("Variable {}", name),
+            ExprKind::Unary { operator, .. } => format!("Unary {}", operator.symbol()),
+            ExprKind::Binary { operator, .. } => format!("Binary {}", operator.symbol()),
+        };
+        self.nested(&text, |printer| walk_expr(printer, expr));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize_spanned;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_format_ast_as_tree() {
+        let source = "var a, b: integer; begin a := -b * 2; write('a=', a:4); end";
+        let program = Parser::from_spanned(tokenize_spanned(source).unwrap()).parse().unwrap();
+
+        let expected = "\
+Program
+  Decl a, b: integer
+  Block
+    Assign a
+      Binary *
+        Unary -
+          Variable b
+        Integer 2
+    Print write
+      Item
+        String \"a=\"
+      Item value:width
+        Variable a
+        Integer 4
+";
+        assert_eq!(format_ast(&program), expected);
+    }
+
+    #[test]
+    fn test_format_tokens_with_positions() {
+        let tokens = tokenize_spanned("var\n  x").unwrap();
+
+        assert_eq!(format_tokens(&tokens), "1:1 Keyword(\"var\")\n2:3 Identifier(\"x\")\n2:4 EndOfInput\n");
+    }
+}
@@ -0,0 +1,253 @@
+//! ELF64 files for x86-64 Linux, written from encoded machine code.
+//!
+//! [`object`] gives a relocatable file like the one nasm or as would, for
+//! linking with ld; [`executable`] lays the sections out at fixed addresses
+//! itself and gives a static executable that needs no tools at all.
+
+use crate::asm::encode::{MachineCode, Section};
+use crate::asm::Label;
+
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+const SECTION_HEADER_SIZE: usize = 64;
+const SYMBOL_SIZE: usize = 24;
+const RELOCATION_SIZE: usize = 24;
+
+const ET_REL: u16 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 62;
+const PT_LOAD: u32 = 1;
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const SHF_INFO_LINK: u64 = 0x40;
+const STB_GLOBAL: u8 = 1;
+const STT_SECTION: u8 = 3;
+const R_X86_64_PC32: u64 = 2;
+
+/// Where executables are loaded, the usual address for x86-64.
+const BASE_ADDRESS: u64 = 0x400000;
+const PAGE_SIZE: usize = 0x1000;
+
+/// A static executable that starts at the label `_start`. The code is loaded
+/// read-only at the page after the headers, and the data and .bss writable
+/// in the pages after that.
+pub fn executable(code: &MachineCode) -> Vec<u8> {
+    let text_offset = PAGE_SIZE;
+    let data_offset = (text_offset + code.text.len()).next_multiple_of(PAGE_SIZE);
+    let text_address = BASE_ADDRESS + text_offset as u64;
+    let data_address = BASE_ADDRESS + data_offset as u64;
+    let bss_address = (data_address + code.data.len() as u64).next_multiple_of(8);
+    let address = |section: Section| match section {
+        Section::Text => text_address,
+        Section::Data => data_address,
+        Section::Bss => bss_address,
+    };
+
+    let mut text = code.text.clone();
+    for relocation in &code.relocations {
+        let target = address(relocation.section) as i64 + relocation.addend;
+        let value = target - (text_address + relocation.offset as u64) as i64;
+        text[relocation.offset..relocation.offset + 4].copy_from_slice(&(value as i32).to_le_bytes());
+    }
+    let (section, start) = code.labels[&Label::Named("_start")];
+
+    let mut file = Vec::new();
+    header(&mut file, ET_EXEC, address(section) + start as u64, 2, 0, 0);
+    // The first segment covers the headers as well, as ld's do
+    let text_end = text_offset + text.len();
+    program_header(&mut file, 5, 0, BASE_ADDRESS, text_end, text_end);
+    let data_size = (bss_address - data_address) as usize + code.bss_size;
+    program_header(&mut file, 6, data_offset, data_address, code.data.len(), data_size);
+    file.resize(text_offset, 0);
+    file.extend(&text);
+    file.resize(data_offset, 0);
+    file.extend(&code.data);
+    file
+}
+
+/// A relocatable object file with .text, .data and .bss, a symbol for every
+/// label, of which only `_start` is global, and the addresses of data in the
+/// code as relocations against the sections.
+pub fn object(code: &MachineCode) -> Vec<u8> {
+    // The section symbols come first, after the null symbol, in the order
+    // of the sections
+    let section_index = |section: Section| match section {
+        Section::Text => 1,
+        Section::Data => 2,
+        Section::Bss => 3,
+    };
+    let mut labels: Vec<(&Label, &(Section, usize))> = code.labels.iter().collect();
+    labels.sort_by_key(|(label, (section, offset))| (section_index(*section), *offset, label.to_string()));
+    // Locals must come before globals
+    labels.sort_by_key(|(label, _)| **label == Label::Named("_start"));
+
+    let mut strings = vec![0];
+    let mut symbols = vec![0; SYMBOL_SIZE];
+    for index in 1..=3 {
+        symbol(&mut symbols, 0, STT_SECTION, index, 0);
+    }
+    let first_global = 4 + labels.iter().filter(|(label, _)| **label != Label::Named("_start")).count() as u32;
+    for (label, (section, offset)) in &labels {
+        let bind = if **label == Label::Named("_start") { STB_GLOBAL } else { 0 };
+        let name = add_string(&mut strings, &label.to_string());
+        symbol(&mut symbols, name, bind << 4, section_index(*section), *offset as u64);
+    }
+
+    let mut relocations = Vec::new();
+    for relocation in &code.relocations {
+        relocations.extend((relocation.offset as u64).to_le_bytes());
+        let symbol = section_index(relocation.section) as u64;
+        relocations.extend((symbol << 32 | R_X86_64_PC32).to_le_bytes());
+        relocations.extend(relocation.addend.to_le_bytes());
+    }
+
+    let mut section_names = vec![0];
+    let names = [".text", ".data", ".bss", ".symtab", ".strtab", ".rela.text", ".shstrtab"];
+    let names = names.map(|name| add_string(&mut section_names, name));
+
+    // The contents of the sections follow the header, then the section
+    // headers
+    let mut file = vec![0; HEADER_SIZE];
+    let place = |file: &mut Vec<u8>, bytes: &[u8], align: usize| {
+        file.resize(file.len().next_multiple_of(align), 0);
+        let offset = file.len();
+        file.extend(bytes);
+        offset
+    };
+    let text = place(&mut file, &code.text, 16);
+    let data = place(&mut file, &code.data, 8);
+    let symtab = place(&mut file, &symbols, 8);
+    let strtab = place(&mut file, &strings, 1);
+    let rela = place(&mut file, &relocations, 8);
+    let shstrtab = place(&mut file, &section_names, 1);
+    file.resize(file.len().next_multiple_of(8), 0);
+    let section_headers = file.len();
+
+    let mut start = Vec::new();
+    header(&mut start, ET_REL, 0, 0, section_headers, 8);
+    file[..HEADER_SIZE].copy_from_slice(&start);
+
+    let sections = [
+        (0, 0, 0, 0, 0, 0u32, 0, 0, 0),
+        (names[0], SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, text, code.text.len(), 0, 0, 16, 0),
+        (names[1], SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, data, code.data.len(), 0, 0, 8, 0),
+        (names[2], SHT_NOBITS, SHF_ALLOC | SHF_WRITE, data + code.data.len(), code.bss_size, 0, 0, 8, 0),
+        (names[3], SHT_SYMTAB, 0, symtab, symbols.len(), 5, first_global, 8, SYMBOL_SIZE),
+        (names[4], SHT_STRTAB, 0, strtab, strings.len(), 0, 0, 1, 0),
+        (names[5], SHT_RELA, SHF_INFO_LINK, rela, relocations.len(), 4, 1, 8, RELOCATION_SIZE),
+        (names[6], SHT_STRTAB, 0, shstrtab, section_names.len(), 0, 0, 1, 0),
+    ];
+    for (name, kind, flags, offset, size, link, info, align, entry_size) in sections {
+        file.extend(name.to_le_bytes());
+        file.extend(kind.to_le_bytes());
+        file.extend(flags.to_le_bytes());
+        file.extend(0u64.to_le_bytes()); // Address
+        file.extend((offset as u64).to_le_bytes());
+        file.extend((size as u64).to_le_bytes());
+        file.extend(link.to_le_bytes());
+        file.extend(info.to_le_bytes());
+        file.extend((align as u64).to_le_bytes());
+        file.extend((entry_size as u64).to_le_bytes());
+    }
+    file
+}
+
+// The ELF header, followed directly by `program_headers` program headers if
+// there are any.
+fn header(file: &mut Vec<u8>, kind: u16, entry: u64, program_headers: u16, section_headers: usize, sections: u16) {
+    // 64-bit, little-endian, version 1, System V ABI
+    file.extend([0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    file.extend(kind.to_le_bytes());
+    file.extend(EM_X86_64.to_le_bytes());
+    file.extend(1u32.to_le_bytes());
+    file.extend(entry.to_le_bytes());
+    let program_header_offset = if program_headers > 0 { HEADER_SIZE as u64 } else { 0 };
+    file.extend(program_header_offset.to_le_bytes());
+    file.extend((section_headers as u64).to_le_bytes());
+    file.extend(0u32.to_le_bytes()); // Flags
+    file.extend((HEADER_SIZE as u16).to_le_bytes());
+    file.extend((PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    file.extend(program_headers.to_le_bytes());
+    file.extend((SECTION_HEADER_SIZE as u16).to_le_bytes());
+    file.extend(sections.to_le_bytes());
+    // The section names are in the last section
+    file.extend(sections.saturating_sub(1).to_le_bytes());
+}
+
+// A loadable segment with the permission `flags` (4 read, 2 write, 1 execute)
+// of `memory_size` bytes at `address`, the first `file_size` of them from
+// `offset` in the file.
+fn program_header(file: &mut Vec<u8>, flags: u32, offset: usize, address: u64, file_size: usize, memory_size: usize) {
+    file.extend(PT_LOAD.to_le_bytes());
+    file.extend(flags.to_le_bytes());
+    file.extend((offset as u64).to_le_bytes());
+    file.extend(address.to_le_bytes());
+    file.extend(address.to_le_bytes()); // Physical address
+    file.extend((file_size as u64).to_le_bytes());
+    file.extend((memory_size as u64).to_le_bytes());
+    file.extend((PAGE_SIZE as u64).to_le_bytes());
+}
+
+// Appends `string` to a string table, returning its offset there.
+fn add_string(table: &mut Vec<u8>, string: &str) -> u32 {
+    let offset = table.len() as u32;
+    table.extend(string.bytes());
+    table.push(0);
+    offset
+}
+
+fn symbol(symbols: &mut Vec<u8>, name: u32, info: u8, section: u16, value: u64) {
+    symbols.extend(name.to_le_bytes());
+    symbols.push(info);
+    symbols.push(0); // Default visibility
+    symbols.extend(section.to_le_bytes());
+    symbols.extend(value.to_le_bytes());
+    symbols.extend(0u64.to_le_bytes()); // Size
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::Syntax;
+    use crate::testing::{
+        run_builtin, run_builtin_object, run_with_tools, CONFORMANCE_OUTPUT, CONFORMANCE_PROGRAM, REAL_PROGRAM,
+    };
+    use crate::Options;
+
+    #[test]
+    fn test_executables_run_the_conformance_program() {
+        for opt_level in [0, 1, 2] {
+            let options = Options { opt_level, ..Options::default() };
+            assert_eq!(String::from_utf8(run_builtin(CONFORMANCE_PROGRAM, &options)).unwrap(), CONFORMANCE_OUTPUT);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs nasm, as and ld"]
+    fn test_builtin_encoder_matches_the_assemblers() {
+        for source in [CONFORMANCE_PROGRAM, REAL_PROGRAM] {
+            for opt_level in [0, 1, 2] {
+                let builtin = run_builtin(source, &Options { opt_level, ..Options::default() });
+                for asm_syntax in [Syntax::Nasm, Syntax::Gas] {
+                    let options = Options { opt_level, asm_syntax, ..Options::default() };
+                    assert_eq!(run_with_tools(source, &options), builtin, "{:?} at -O{}", asm_syntax, opt_level);
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs ld"]
+    fn test_object_files_link_with_ld() {
+        for opt_level in [0, 2] {
+            let options = Options { opt_level, ..Options::default() };
+            let stdout = run_builtin_object(CONFORMANCE_PROGRAM, &options);
+            assert_eq!(String::from_utf8(stdout).unwrap(), CONFORMANCE_OUTPUT);
+        }
+    }
+}
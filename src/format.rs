@@ -0,0 +1,374 @@
+//! Source formatter behind `ccc fmt`.
+//!
+//! The program is parsed and printed again from the AST with lower-case
+//! keywords, four spaces of indentation per level and single spaces around
+//! operators. Literals keep their original spelling. Comments are taken from
+//! the lexer and placed before the declaration or statement that follows
+//! them, or at the end of the line when they trailed a statement.
+
+use crate::ast::{Block, Decl, Expr, ExprKind, PrintItem, Program, Stmt, StmtKind, UnaryOperator};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{tokenize_with_comments, Comment};
+use crate::parser::Parser;
+
+const INDENT: &str = "    ";
+
+/// Returns `source` in canonical layout.
+pub fn format_source(source: &str) -> Result<String, Diagnostic> {
+    let (tokens, comments) = tokenize_with_comments(source)?;
+    let program = Parser::from_spanned(tokens).parse()?;
+    let mut formatter = Formatter { source, comments, next_comment: 0, out: String::new(), last_line: 0 };
+    formatter.program(&program);
+    Ok(formatter.out)
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    comments: Vec<Comment>,
+    next_comment: usize, // Index of the first comment not yet written
+    out: String,
+    last_line: usize, // Source line where the previously written item ended
+}
+
+impl Formatter<'_> {
+    fn program(&mut self, program: &Program) {
+        self.comments_before(program.span.start, 0);
+        self.out.push_str("var\n");
+        self.last_line = program.span.line;
+        for (i, decl) in program.declarations.iter().enumerate() {
+            let next = program.declarations.get(i + 1).map_or(program.body.span.start, |next| next.span.start);
+            self.decl(decl, next);
+        }
+        self.out.push('\n');
+        self.block(&program.body, 0);
+        self.trailing_comments(self.source.len());
+        self.comments_before(self.source.len(), 0);
+    }
+
+    fn decl(&mut self, decl: &Decl, next: usize) {
+        self.separate_from_previous(decl.span.start);
+        self.comments_before(decl.span.start, 1);
+        self.out.push_str(&format!("{}{}: {};", INDENT, decl.names.join(", "), decl.type_name.as_str()));
+        self.last_line = self.line_of(decl.span.end);
+        self.trailing_comments(next);
+    }
+
+    fn block(&mut self, block: &Block, depth: usize) {
+        self.comments_before(block.span.start, depth);
+        self.out.push_str(&format!("{}begin", INDENT.repeat(depth)));
+        self.block_body(block, depth);
+    }
+
+    // Writes what follows a block's `begin`: the statements one level deeper
+    // and the closing `end`, which is left unterminated.
+    fn block_body(&mut self, block: &Block, depth: usize) {
+        self.last_line = block.span.line;
+        let end_keyword = block.span.end - "end".len();
+        self.trailing_comments(block.statements.first().map_or(end_keyword, |first| first.span.start));
+        for (i, stmt) in block.statements.iter().enumerate() {
+            let next = block.statements.get(i + 1).map_or(end_keyword, |next| next.span.start);
+            self.stmt(stmt, depth + 1, next);
+        }
+        self.separate_from_previous(end_keyword);
+        self.comments_before(end_keyword, depth + 1);
+        self.out.push_str(&format!("{}end", INDENT.repeat(depth)));
+        self.last_line = self.line_of(block.span.end);
+    }
+
+    // A statement of a block, starting on a new line and ending with ';'.
+    fn stmt(&mut self, stmt: &Stmt, depth: usize, next: usize) {
+        self.separate_from_previous(stmt.span.start);
+        self.comments_before(stmt.span.start, depth);
+        self.out.push_str(&INDENT.repeat(depth));
+        self.stmt_text(stmt, depth);
+        self.out.push(';');
+        self.last_line = self.line_of(stmt.span.end);
+        self.trailing_comments(next);
+    }
+
+    // Writes `stmt` from the current position without a terminator. Nested
+    // statements go on lines of their own, except that a compound statement
+    // starts on the line of its if, while or for: `while c do begin`.
+    fn stmt_text(&mut self, stmt: &Stmt, depth: usize) {
+        match &stmt.kind {
+            StmtKind::Assign { target, value } => {
+                self.out.push_str(&format!("{} := {}", target, self.expr(value)));
+            }
+            StmtKind::Print { items, newline } => {
+                self.out.push_str(if *newline { "writeln" } else { "write" });
+                if !items.is_empty() {
+                    let items: Vec<String> = items.iter().map(|item| self.print_item(item)).collect();
+                    self.out.push_str(&format!("({})", items.join(", ")));
+                }
+            }
+            StmtKind::Compound(block) => {
+                self.out.push_str("begin");
+                self.block_body(block, depth);
+            }
+            StmtKind::If { condition, then_branch, else_branch } => {
+                self.out.push_str(&format!("if {} then", self.expr(condition)));
+                self.nested_stmt(then_branch, depth);
+                if let Some(else_branch) = else_branch {
+                    // A comment after the then branch stays on its line, and
+                    // else goes on the next
+                    self.last_line = self.line_of(then_branch.span.end);
+                    if self.has_trailing_comment(else_branch.span.start) {
+                        self.trailing_comments(else_branch.span.start);
+                        self.out.push_str(&format!("{}else", INDENT.repeat(depth)));
+                    } else if matches!(then_branch.kind, StmtKind::Compound(_)) {
+                        self.out.push_str(" else");
+                    } else {
+                        self.out.push_str(&format!("\n{}else", INDENT.repeat(depth)));
+                    }
+                    match else_branch.kind {
+                        // else if chains stay flat
+                        StmtKind::If { .. } if !self.has_comment_before(else_branch.span.start) => {
+                            self.out.push(' ');
+                            self.stmt_text(else_branch, depth);
+                        }
+                        _ => self.nested_stmt(else_branch, depth),
+                    }
+                }
+            }
+            StmtKind::While { condition, body } => {
+                self.out.push_str(&format!("while {} do", self.expr(condition)));
+                self.nested_stmt(body, depth);
+            }
+            StmtKind::For { variable, start, end, descending, body } => {
+                let direction = if *descending { "downto" } else { "to" };
+                let (start, end) = (self.expr(start), self.expr(end));
+                self.out.push_str(&format!("for {} := {} {} {} do", variable, start, direction, end));
+                self.nested_stmt(body, depth);
+            }
+            StmtKind::Exit => self.out.push_str("exit"),
+        }
+    }
+
+    // The body of an if, while or for statement at `depth`.
+    fn nested_stmt(&mut self, stmt: &Stmt, depth: usize) {
+        if let StmtKind::Compound(block) = &stmt.kind {
+            self.out.push_str(" begin");
+            self.block_body(block, depth);
+        } else {
+            self.out.push('\n');
+            self.comments_before(stmt.span.start, depth + 1);
+            self.out.push_str(&INDENT.repeat(depth + 1));
+            self.stmt_text(stmt, depth + 1);
+        }
+    }
+
+    fn print_item(&self, item: &PrintItem) -> String {
+        let mut text = self.expr(&item.value);
+        for format in item.width.iter().chain(&item.precision) {
+            text.push(':');
+            text.push_str(&self.expr(format));
+        }
+        text
+    }
+
+    fn expr(&self, expr: &Expr) -> String {
+        match &expr.kind {
+            // Literals keep their spelling, e.g. $FF or 'It''s'#10
+            ExprKind::Integer(_) | ExprKind::Real(_) | ExprKind::String(_) => {
+                self.source[expr.span.start..expr.span.end].to_string()
+            }
+            ExprKind::Boolean(value) => value.to_string(),
+            ExprKind::Variable(name) => name.clone(),
+            ExprKind::Unary { operator, operand } => {
+                let operand = match operand.kind {
+                    // Parentheses also keep "- -x" from turning into a "--" comment
+                    ExprKind::Binary { .. } => format!("({})", self.expr(operand)),
+                    ExprKind::Unary { .. } if *operator == UnaryOperator::Negate => format!("({})", self.expr(operand)),
+                    _ => self.expr(operand),
+                };
+                match operator {
+                    UnaryOperator::Negate => format!("-{}", operand),
+                    UnaryOperator::Not => format!("not {}", operand),
+                }
+            }
+            ExprKind::Binary { left, operator, right } => {
+                let precedence = operator.precedence();
+                // Operators are left associative, so an equal-precedence right
+                // operand needs parentheses: a - (b - c). Comparisons do not
+                // chain at all: (a = b) = c
+                let left = self.operand(left, |p| p < precedence || (p == precedence && operator.is_comparison()));
+                let right = self.operand(right, |p| p <= precedence);
+                format!("{} {} {}", left, operator.symbol(), right)
+            }
+        }
+    }
+
+    fn operand(&self, expr: &Expr, needs_parentheses: impl Fn(u8) -> bool) -> String {
+        match &expr.kind {
+            ExprKind::Binary { operator, .. } if needs_parentheses(operator.precedence()) => {
+                format!("({})", self.expr(expr))
+            }
+            _ => self.expr(expr),
+        }
+    }
+
+    fn has_comment_before(&self, position: usize) -> bool {
+        self.comments.get(self.next_comment).is_some_and(|comment| comment.span.start < position)
+    }
+
+    // Whether a comment before `next` starts on the line the last item ended on.
+    fn has_trailing_comment(&self, next: usize) -> bool {
+        self.comments.get(self.next_comment).is_some_and(|c| c.span.line <= self.last_line && c.span.start < next)
+    }
+
+    // Writes the comments that start before `position` on lines of their own.
+    fn comments_before(&mut self, position: usize, depth: usize) {
+        while let Some(comment) = self.comments.get(self.next_comment).filter(|c| c.span.start < position) {
+            let line = format!("{}{}\n", INDENT.repeat(depth), comment.text);
+            self.last_line = self.line_of(comment.span.end);
+            self.out.push_str(&line);
+            self.next_comment += 1;
+            self.separate_from_previous(position);
+        }
+    }
+
+    // Appends the comments before `next` that started on the line the last item
+    // ended on, then ends the line.
+    fn trailing_comments(&mut self, next: usize) {
+        while let Some(comment) =
+            self.comments.get(self.next_comment).filter(|c| c.span.line <= self.last_line && c.span.start < next)
+        {
+            self.out.push(' ');
+            self.out.push_str(&comment.text);
+            // A comment trailing a multi-line item starts before its last line
+            self.last_line = self.last_line.max(self.line_of(comment.span.end));
+            self.next_comment += 1;
+        }
+        self.out.push('\n');
+    }
+
+    // Keeps (at most) one blank line where the source had blank lines before
+    // the item or comment starting at `position`.
+    fn separate_from_previous(&mut self, position: usize) {
+        let next = match self.comments.get(self.next_comment) {
+            Some(comment) if comment.span.start < position => comment.span.start,
+            _ => position,
+        };
+        if self.line_of(next) > self.last_line + 1 && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn line_of(&self, offset: usize) -> usize {
+        self.source[..offset].matches('\n').count() + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_canonical_layout() {
+        let source =
+            "VAR a,b:INTEGER;ok : Boolean;BEGIN a:=$FF*(2+b) ;b:=a-(b-1);\nWriteLn('x=',a:8,- -a,TRUE);write;END";
+
+        let expected = "\
+var
+    a, b: integer;
+    ok: boolean;
+
+begin
+    a := $FF * (2 + b);
+    b := a - (b - 1);
+    writeln('x=', a:8, -(-a), true);
+    write;
+end
+";
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_preserves_comments_and_blank_lines() {
+        let source = "\
+{ header }
+var
+  a: integer; // counter
+
+
+  (* second *)
+  b: integer;
+begin
+      a := 1;   { trailing }
+  -- own line
+
+  b := a;
+  // before end
+end
+// footer
+";
+
+        let expected = "\
+{ header }
+var
+    a: integer; // counter
+
+    (* second *)
+    b: integer;
+
+begin
+    a := 1; { trailing }
+    -- own line
+
+    b := a;
+    // before end
+end
+// footer
+";
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_is_idempotent_and_keeps_the_ast() {
+        let source = std::fs::read_to_string("examples/sum.pas").unwrap() + "\n{ trailing }";
+        let parse =
+            |text: &str| Parser::new(crate::lexer::tokenize(text).unwrap()).parse().unwrap().without_locations();
+
+        let once = format_source(&source).unwrap();
+        let twice = format_source(&once).unwrap();
+
+        assert_eq!(once, twice);
+        assert_eq!(parse(&once), parse(&source));
+    }
+
+    #[test]
+    fn test_format_control_flow() {
+        let source = "var i: integer; begin for i:=1 TO 3 do if (i mod 2=0) and not (i>2) then begin writeln(i); end \
+                      else if i=1 then write else while false do i:=i; while i>0 do begin i:=i-1; end ; EXIT; end";
+
+        let expected = "\
+var
+    i: integer;
+
+begin
+    for i := 1 to 3 do
+        if (i mod 2 = 0) and not (i > 2) then begin
+            writeln(i);
+        end else if i = 1 then
+            write
+        else
+            while false do
+                i := i;
+    while i > 0 do begin
+        i := i - 1;
+    end;
+    exit;
+end
+";
+        assert_eq!(format_source(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_minimal_parentheses() {
+        let source = "var x: integer; begin x := (1 - 2) - (3 - 4) * (5 mod 6) + -(7 * 8); end";
+
+        let formatted = format_source(source).unwrap();
+
+        assert!(formatted.contains("x := 1 - 2 - (3 - 4) * (5 mod 6) + -(7 * 8);"), "{}", formatted);
+    }
+}
@@ -0,0 +1,774 @@
+//! Three-address code between the AST and the backends.
+//!
+//! A program is a list of basic blocks, each a run of straight-line
+//! instructions ended by a terminator that passes control to other blocks;
+//! those edges form the control-flow graph. Every instruction reads at most
+//! two operands and writes at most one variable, which is either a variable
+//! of the source program or a numbered temporary. Booleans are 0 and 1, and
+//! reals are the bits of an IEEE double, with operators of their own.
+
+use std::fmt;
+
+use crate::ast;
+
+/// Index of a block in [`Program::blocks`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub usize);
+
+/// Somewhere an instruction can store a value.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Var {
+    /// A variable declared in the source program.
+    Named(String),
+    /// A temporary introduced by lowering, numbered from 0.
+    Temp(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Operand {
+    Constant(i64),
+    Var(Var),
+}
+
+/// The operators of [`Instruction::Unary`]: those of the language, negation
+/// of a real, and the conversion of an integer to the nearest real.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnaryOperator {
+    Negate,
+    Not,
+    RealNegate,
+    ToReal,
+}
+
+/// The operators of [`Instruction::Binary`]: those of the language, with `and`
+/// and `or` as bitwise operations, and the shifts the optimiser uses for
+/// multiplication and division by powers of two. The arithmetic and the
+/// comparisons of reals are separate operators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Div,
+    Mod,
+    Rem,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+    ShiftLeft,
+    ShiftRight, // Arithmetic, keeps the sign
+    RealAdd,
+    RealSubtract,
+    RealMultiply,
+    RealDivide,
+    RealEqual,
+    RealNotEqual,
+    RealLess,
+    RealLessEqual,
+    RealGreater,
+    RealGreaterEqual,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    /// `dest = source`
+    Copy {
+        dest: Var,
+        source: Operand,
+    },
+    /// `dest = operator operand`
+    Unary {
+        dest: Var,
+        operator: UnaryOperator,
+        operand: Operand,
+    },
+    /// `dest = left operator right`. Here `and` and `or` are plain bitwise
+    /// operations; lowering turns the short-circuit operators of the source
+    /// into branches.
+    Binary {
+        dest: Var,
+        operator: BinaryOperator,
+        left: Operand,
+        right: Operand,
+    },
+    /// Writes a value, right-aligned in a field of `width` characters if given.
+    Print {
+        value: PrintValue,
+        width: Option<Operand>,
+    },
+    PrintNewline,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrintValue {
+    Integer(Operand),
+    Boolean(Operand),
+    /// A real, with the number of digits after the point if given.
+    Real(Operand, Option<Operand>),
+    /// Index into [`Program::strings`].
+    String(usize),
+}
+
+/// How control leaves a block.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    /// Continues at `if_true` when `condition` is non-zero, else at `if_false`.
+    Branch {
+        condition: Operand,
+        if_true: BlockId,
+        if_false: BlockId,
+    },
+    /// Ends the program.
+    Exit,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Program {
+    /// The source program's variables, all 64-bit and initially 0.
+    pub variables: Vec<String>,
+    /// String literals referenced by [`PrintValue::String`].
+    pub strings: Vec<Vec<u8>>,
+    /// The first block is the entry; the others follow in layout order.
+    pub blocks: Vec<BasicBlock>,
+    /// Number of temporaries; every `Var::Temp` is below this.
+    pub temp_count: u32,
+}
+
+impl Instruction {
+    /// The variable the instruction writes, if any.
+    pub fn dest(&self) -> Option<&Var> {
+        match self {
+            Instruction::Copy { dest, .. } | Instruction::Unary { dest, .. } | Instruction::Binary { dest, .. } => {
+                Some(dest)
+            }
+            Instruction::Print { .. } | Instruction::PrintNewline => None,
+        }
+    }
+
+    /// The operands the instruction reads.
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Instruction::Copy { source, .. } => vec![source],
+            Instruction::Unary { operand, .. } => vec![operand],
+            Instruction::Binary { left, right, .. } => vec![left, right],
+            Instruction::Print { value, width } => match value {
+                PrintValue::Integer(value) | PrintValue::Boolean(value) => [value].into_iter().chain(width).collect(),
+                PrintValue::Real(value, precision) => [value].into_iter().chain(width).chain(precision).collect(),
+                PrintValue::String(_) => width.iter().collect(),
+            },
+            Instruction::PrintNewline => Vec::new(),
+        }
+    }
+
+    /// Mutable access to the operands, in the same order as [`Instruction::operands`].
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Instruction::Copy { source, .. } => vec![source],
+            Instruction::Unary { operand, .. } => vec![operand],
+            Instruction::Binary { left, right, .. } => vec![left, right],
+            Instruction::Print { value, width } => match value {
+                PrintValue::Integer(value) | PrintValue::Boolean(value) => [value].into_iter().chain(width).collect(),
+                PrintValue::Real(value, precision) => [value].into_iter().chain(width).chain(precision).collect(),
+                PrintValue::String(_) => width.iter_mut().collect(),
+            },
+            Instruction::PrintNewline => Vec::new(),
+        }
+    }
+}
+
+impl UnaryOperator {
+    /// Evaluates the operator with the same semantics as the generated code.
+    pub fn apply(self, operand: i64) -> i64 {
+        match self {
+            UnaryOperator::Negate => operand.wrapping_neg(),
+            UnaryOperator::Not => operand ^ 1,
+            UnaryOperator::RealNegate => to_bits(-from_bits(operand)),
+            UnaryOperator::ToReal => to_bits(operand as f64),
+        }
+    }
+}
+
+impl From<ast::UnaryOperator> for UnaryOperator {
+    fn from(operator: ast::UnaryOperator) -> Self {
+        match operator {
+            ast::UnaryOperator::Negate => UnaryOperator::Negate,
+            ast::UnaryOperator::Not => UnaryOperator::Not,
+        }
+    }
+}
+
+/// The real whose bits an operand holds.
+pub fn from_bits(bits: i64) -> f64 {
+    f64::from_bits(bits as u64)
+}
+
+/// The bits of a real, as an operand holds them.
+pub fn to_bits(value: f64) -> i64 {
+    value.to_bits() as i64
+}
+
+impl BinaryOperator {
+    /// The operator as written in the source. The shifts use their assembler
+    /// names, and the operators on reals have a dot after the symbol.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Div => "div",
+            BinaryOperator::Mod => "mod",
+            BinaryOperator::Rem => "rem",
+            BinaryOperator::Equal => "=",
+            BinaryOperator::NotEqual => "<>",
+            BinaryOperator::Less => "<",
+            BinaryOperator::LessEqual => "<=",
+            BinaryOperator::Greater => ">",
+            BinaryOperator::GreaterEqual => ">=",
+            BinaryOperator::And => "and",
+            BinaryOperator::Or => "or",
+            BinaryOperator::ShiftLeft => "shl",
+            BinaryOperator::ShiftRight => "sar",
+            BinaryOperator::RealAdd => "+.",
+            BinaryOperator::RealSubtract => "-.",
+            BinaryOperator::RealMultiply => "*.",
+            BinaryOperator::RealDivide => "/.",
+            BinaryOperator::RealEqual => "=.",
+            BinaryOperator::RealNotEqual => "<>.",
+            BinaryOperator::RealLess => "<.",
+            BinaryOperator::RealLessEqual => "<=.",
+            BinaryOperator::RealGreater => ">.",
+            BinaryOperator::RealGreaterEqual => ">=.",
+        }
+    }
+
+    /// The operator on reals for an operator of the language, for the
+    /// operands of which at least one is real.
+    pub fn for_reals(self) -> BinaryOperator {
+        match self {
+            BinaryOperator::Add => BinaryOperator::RealAdd,
+            BinaryOperator::Subtract => BinaryOperator::RealSubtract,
+            BinaryOperator::Multiply => BinaryOperator::RealMultiply,
+            BinaryOperator::Divide => BinaryOperator::RealDivide,
+            BinaryOperator::Equal => BinaryOperator::RealEqual,
+            BinaryOperator::NotEqual => BinaryOperator::RealNotEqual,
+            BinaryOperator::Less => BinaryOperator::RealLess,
+            BinaryOperator::LessEqual => BinaryOperator::RealLessEqual,
+            BinaryOperator::Greater => BinaryOperator::RealGreater,
+            BinaryOperator::GreaterEqual => BinaryOperator::RealGreaterEqual,
+            other => unreachable!("{} takes no reals", other.symbol()),
+        }
+    }
+
+    /// Whether the operator works on reals.
+    pub fn is_real(self) -> bool {
+        use BinaryOperator::*;
+
+        matches!(
+            self,
+            RealAdd
+                | RealSubtract
+                | RealMultiply
+                | RealDivide
+                | RealEqual
+                | RealNotEqual
+                | RealLess
+                | RealLessEqual
+                | RealGreater
+                | RealGreaterEqual
+        )
+    }
+
+    /// Evaluates the operator on 64-bit integers, or reals held as their bits,
+    /// with the same semantics as the generated code; booleans are 0 and 1.
+    /// Returns `None` for division by zero, which is a run-time error for
+    /// reals as well. Dividing `i64::MIN` by -1 wraps around to `i64::MIN`,
+    /// with a remainder of 0.
+    pub fn apply(self, left: i64, right: i64) -> Option<i64> {
+        let (real_left, real_right) = (from_bits(left), from_bits(right));
+        match self {
+            BinaryOperator::Add => Some(left.wrapping_add(right)),
+            BinaryOperator::Subtract => Some(left.wrapping_sub(right)),
+            BinaryOperator::Multiply => Some(left.wrapping_mul(right)),
+            BinaryOperator::Divide | BinaryOperator::Div => (right != 0).then(|| left.wrapping_div(right)),
+            BinaryOperator::Rem => (right != 0).then(|| left.wrapping_rem(right)),
+            BinaryOperator::Mod => {
+                let remainder = (right != 0).then(|| left.wrapping_rem(right))?;
+                if remainder != 0 && (remainder < 0) != (right < 0) {
+                    Some(remainder + right)
+                } else {
+                    Some(remainder)
+                }
+            }
+            BinaryOperator::Equal => Some((left == right) as i64),
+            BinaryOperator::NotEqual => Some((left != right) as i64),
+            BinaryOperator::Less => Some((left < right) as i64),
+            BinaryOperator::LessEqual => Some((left <= right) as i64),
+            BinaryOperator::Greater => Some((left > right) as i64),
+            BinaryOperator::GreaterEqual => Some((left >= right) as i64),
+            BinaryOperator::And => Some(left & right),
+            BinaryOperator::Or => Some(left | right),
+            // Like the hardware, only the low six bits of the count are used
+            BinaryOperator::ShiftLeft => Some(left.wrapping_shl(right as u32)),
+            BinaryOperator::ShiftRight => Some(left.wrapping_shr(right as u32)),
+            BinaryOperator::RealAdd => Some(to_bits(real_left + real_right)),
+            BinaryOperator::RealSubtract => Some(to_bits(real_left - real_right)),
+            BinaryOperator::RealMultiply => Some(to_bits(real_left * real_right)),
+            BinaryOperator::RealDivide => (real_right != 0.0).then(|| to_bits(real_left / real_right)),
+            BinaryOperator::RealEqual => Some((real_left == real_right) as i64),
+            BinaryOperator::RealNotEqual => Some((real_left != real_right) as i64),
+            BinaryOperator::RealLess => Some((real_left < real_right) as i64),
+            BinaryOperator::RealLessEqual => Some((real_left <= real_right) as i64),
+            BinaryOperator::RealGreater => Some((real_left > real_right) as i64),
+            BinaryOperator::RealGreaterEqual => Some((real_left >= real_right) as i64),
+        }
+    }
+}
+
+impl From<ast::BinaryOperator> for BinaryOperator {
+    fn from(operator: ast::BinaryOperator) -> Self {
+        match operator {
+            ast::BinaryOperator::Add => BinaryOperator::Add,
+            ast::BinaryOperator::Subtract => BinaryOperator::Subtract,
+            ast::BinaryOperator::Multiply => BinaryOperator::Multiply,
+            ast::BinaryOperator::Divide => BinaryOperator::Divide,
+            ast::BinaryOperator::Div => BinaryOperator::Div,
+            ast::BinaryOperator::Mod => BinaryOperator::Mod,
+            ast::BinaryOperator::Rem => BinaryOperator::Rem,
+            ast::BinaryOperator::Equal => BinaryOperator::Equal,
+            ast::BinaryOperator::NotEqual => BinaryOperator::NotEqual,
+            ast::BinaryOperator::Less => BinaryOperator::Less,
+            ast::BinaryOperator::LessEqual => BinaryOperator::LessEqual,
+            ast::BinaryOperator::Greater => BinaryOperator::Greater,
+            ast::BinaryOperator::GreaterEqual => BinaryOperator::GreaterEqual,
+            ast::BinaryOperator::And => BinaryOperator::And,
+            ast::BinaryOperator::Or => BinaryOperator::Or,
+        }
+    }
+}
+
+impl Terminator {
+    /// The operand a branch tests.
+    pub fn operand(&self) -> Option<&Operand> {
+        match self {
+            Terminator::Branch { condition, .. } => Some(condition),
+            Terminator::Jump(_) | Terminator::Exit => None,
+        }
+    }
+
+    pub fn operand_mut(&mut self) -> Option<&mut Operand> {
+        match self {
+            Terminator::Branch { condition, .. } => Some(condition),
+            Terminator::Jump(_) | Terminator::Exit => None,
+        }
+    }
+
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch { if_true, if_false, .. } => vec![*if_true, *if_false],
+            Terminator::Exit => Vec::new(),
+        }
+    }
+
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch { if_true, if_false, .. } => vec![if_true, if_false],
+            Terminator::Exit => Vec::new(),
+        }
+    }
+}
+
+impl Program {
+    /// The predecessors of every block, indexed by block.
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut predecessors = vec![Vec::new(); self.blocks.len()];
+        for (index, block) in self.blocks.iter().enumerate() {
+            for successor in block.terminator.successors() {
+                if !predecessors[successor.0].contains(&BlockId(index)) {
+                    predecessors[successor.0].push(BlockId(index));
+                }
+            }
+        }
+        predecessors
+    }
+
+    /// The blocks reachable from the entry in reverse postorder, so every block
+    /// comes before its successors except along loop back edges.
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut postorder = Vec::new();
+        // Iterative depth-first search; the flag marks a block whose
+        // successors have all been pushed.
+        let mut stack = vec![(BlockId(0), false)];
+        while let Some((block, done)) = stack.pop() {
+            if done {
+                postorder.push(block);
+                continue;
+            }
+            if visited[block.0] {
+                continue;
+            }
+            visited[block.0] = true;
+            stack.push((block, true));
+            for successor in self.blocks[block.0].terminator.successors().into_iter().rev() {
+                if !visited[successor.0] {
+                    stack.push((successor, false));
+                }
+            }
+        }
+        postorder.reverse();
+        postorder
+    }
+
+    /// The immediate dominator of every block: the closest block that every
+    /// path from the entry passes through. `None` for the entry itself and
+    /// for unreachable blocks.
+    pub fn immediate_dominators(&self) -> Vec<Option<BlockId>> {
+        // The algorithm of Cooper, Harvey and Kennedy: walk the blocks in
+        // reverse postorder, intersecting the dominator tree paths of the
+        // predecessors already visited, until nothing changes.
+        let order = self.reverse_postorder();
+        let mut rank = vec![usize::MAX; self.blocks.len()];
+        for (position, block) in order.iter().enumerate() {
+            rank[block.0] = position;
+        }
+        let predecessors = self.predecessors();
+        let mut dominators: Vec<Option<BlockId>> = vec![None; self.blocks.len()];
+        dominators[0] = Some(BlockId(0));
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in order.iter().skip(1) {
+                let mut processed = predecessors[block.0].iter().copied().filter(|p| dominators[p.0].is_some());
+                let Some(mut dominator) = processed.next() else { continue };
+                for mut other in processed {
+                    while dominator != other {
+                        while rank[dominator.0] > rank[other.0] {
+                            dominator = dominators[dominator.0].unwrap();
+                        }
+                        while rank[other.0] > rank[dominator.0] {
+                            other = dominators[other.0].unwrap();
+                        }
+                    }
+                }
+                if dominators[block.0] != Some(dominator) {
+                    dominators[block.0] = Some(dominator);
+                    changed = true;
+                }
+            }
+        }
+        dominators[0] = None;
+        dominators
+    }
+
+    /// Rearranges the blocks into `order`, which must start with the entry, and
+    /// renumbers the jumps. Blocks missing from `order` are dropped, so they
+    /// must not be reachable from the ones kept.
+    pub fn reorder_blocks(&mut self, order: &[BlockId]) {
+        let mut new_ids = vec![None; self.blocks.len()];
+        for (new_index, old) in order.iter().enumerate() {
+            new_ids[old.0] = Some(BlockId(new_index));
+        }
+        let mut old_blocks: Vec<Option<BasicBlock>> = std::mem::take(&mut self.blocks).into_iter().map(Some).collect();
+        for old in order {
+            let mut block = old_blocks[old.0].take().expect("block listed twice");
+            for target in block.terminator.successors_mut() {
+                *target = new_ids[target.0].expect("jump to a dropped block");
+            }
+            self.blocks.push(block);
+        }
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "block{}", self.0)
+    }
+}
+
+impl fmt::Display for Var {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Var::Named(name) => write!(f, "{}", name),
+            Var::Temp(number) => write!(f, "%{}", number),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Constant(value) => write!(f, "{}", value),
+            Operand::Var(var) => write!(f, "{}", var),
+        }
+    }
+}
+
+// An operand that holds a real, with a constant written as the real rather
+// than its bits.
+struct Real<'a>(&'a Operand);
+
+impl fmt::Display for Real<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Operand::Constant(bits) => write!(f, "{:?}", from_bits(*bits)),
+            var => write!(f, "{}", var),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Copy { dest, source } => write!(f, "{} = {}", dest, source),
+            Instruction::Unary { dest, operator: UnaryOperator::Negate, operand } => {
+                write!(f, "{} = -{}", dest, operand)
+            }
+            Instruction::Unary { dest, operator: UnaryOperator::Not, operand } => {
+                write!(f, "{} = not {}", dest, operand)
+            }
+            Instruction::Unary { dest, operator: UnaryOperator::RealNegate, operand } => {
+                write!(f, "{} = -.{}", dest, Real(operand))
+            }
+            Instruction::Unary { dest, operator: UnaryOperator::ToReal, operand } => {
+                write!(f, "{} = real {}", dest, operand)
+            }
+            Instruction::Binary { dest, operator, left, right } if operator.is_real() => {
+                write!(f, "{} = {} {} {}", dest, Real(left), operator.symbol(), Real(right))
+            }
+            Instruction::Binary { dest, operator, left, right } => {
+                write!(f, "{} = {} {} {}", dest, left, operator.symbol(), right)
+            }
+            Instruction::Print { value, width } => {
+                match value {
+                    PrintValue::Integer(value) => write!(f, "print_int {}", value)?,
+                    PrintValue::Boolean(value) => write!(f, "print_bool {}", value)?,
+                    PrintValue::Real(value, _) => write!(f, "print_real {}", Real(value))?,
+                    PrintValue::String(index) => write!(f, "print_str str{}", index)?,
+                }
+                if let Some(width) = width {
+                    write!(f, " width {}", width)?;
+                }
+                match value {
+                    PrintValue::Real(_, Some(precision)) => write!(f, " precision {}", precision),
+                    _ => Ok(()),
+                }
+            }
+            Instruction::PrintNewline => write!(f, "print_newline"),
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Terminator::Jump(target) => write!(f, "jump {}", target),
+            Terminator::Branch { condition, if_true, if_false } => {
+                write!(f, "branch {}, {}, {}", condition, if_true, if_false)
+            }
+            Terminator::Exit => write!(f, "exit"),
+        }
+    }
+}
+
+/// The textual form used by `--emit=ir`: the variables and strings, then
+/// every block with its instructions indented below its label.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.variables.is_empty() {
+            writeln!(f, "var {}", self.variables.join(", "))?;
+        }
+        for (index, text) in self.strings.iter().enumerate() {
+            writeln!(f, "str{} = \"{}\"", index, text.escape_ascii())?;
+        }
+        for (index, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "\n{}:", BlockId(index))?;
+            for instruction in &block.instructions {
+                writeln!(f, "    {}", instruction)?;
+            }
+            writeln!(f, "    {}", block.terminator)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(terminator: Terminator) -> BasicBlock {
+        BasicBlock { instructions: Vec::new(), terminator }
+    }
+
+    fn branch(if_true: usize, if_false: usize) -> Terminator {
+        Terminator::Branch {
+            condition: Operand::Var(Var::Temp(0)),
+            if_true: BlockId(if_true),
+            if_false: BlockId(if_false),
+        }
+    }
+
+    #[test]
+    fn test_control_flow_graph() {
+        // 0 -> 1 <-> 2, 1 -> 3; block 4 is unreachable and jumps into the loop
+        let mut program = Program {
+            blocks: vec![
+                block(Terminator::Jump(BlockId(1))),
+                block(branch(2, 3)),
+                block(Terminator::Jump(BlockId(1))),
+                block(Terminator::Exit),
+                block(Terminator::Jump(BlockId(2))),
+            ],
+            ..Program::default()
+        };
+
+        let predecessors = program.predecessors();
+        assert_eq!(predecessors[1], [BlockId(0), BlockId(2)]);
+        assert_eq!(predecessors[2], [BlockId(1), BlockId(4)]);
+        assert!(predecessors[0].is_empty());
+
+        let order = program.reverse_postorder();
+        assert_eq!(order, [BlockId(0), BlockId(1), BlockId(3), BlockId(2)]);
+
+        let dominators = program.immediate_dominators();
+        assert_eq!(dominators, [None, Some(BlockId(0)), Some(BlockId(1)), Some(BlockId(1)), None]);
+
+        program.reorder_blocks(&order);
+        assert_eq!(program.blocks.len(), 4);
+        assert_eq!(program.blocks[1].terminator, branch(3, 2));
+        assert_eq!(program.blocks[3].terminator, Terminator::Jump(BlockId(1)));
+    }
+
+    #[test]
+    fn test_division_operators_for_all_sign_combinations() {
+        // (left, right, div, mod, rem)
+        let cases = [
+            (7, 2, 3, 1, 1),
+            (-7, 2, -3, 1, -1),
+            (7, -2, -3, -1, 1),
+            (-7, -2, 3, -1, -1),
+            (6, 3, 2, 0, 0),
+            (-6, 3, -2, 0, 0),
+            (6, -3, -2, 0, 0),
+            (-6, -3, 2, 0, 0),
+            (0, 5, 0, 0, 0),
+            (0, -5, 0, 0, 0),
+            (1, 5, 0, 1, 1),
+            (-1, 5, 0, 4, -1),
+            (1, -5, 0, -4, 1),
+            (-1, -5, 0, -1, -1),
+        ];
+
+        for (left, right, div, modulo, rem) in cases {
+            assert_eq!(BinaryOperator::Div.apply(left, right), Some(div), "{} div {}", left, right);
+            assert_eq!(BinaryOperator::Divide.apply(left, right), Some(div), "{} / {}", left, right);
+            assert_eq!(BinaryOperator::Mod.apply(left, right), Some(modulo), "{} mod {}", left, right);
+            assert_eq!(BinaryOperator::Rem.apply(left, right), Some(rem), "{} rem {}", left, right);
+        }
+
+        assert_eq!(BinaryOperator::Div.apply(1, 0), None);
+        assert_eq!(BinaryOperator::Mod.apply(1, 0), None);
+        assert_eq!(BinaryOperator::Div.apply(i64::MIN, -1), Some(i64::MIN));
+        assert_eq!(BinaryOperator::Mod.apply(i64::MIN, -1), Some(0));
+        assert_eq!(BinaryOperator::Rem.apply(i64::MIN, -1), Some(0));
+    }
+
+    #[test]
+    fn test_real_operators() {
+        let (one, two) = (to_bits(1.0), to_bits(2.0));
+        assert_eq!(BinaryOperator::RealDivide.apply(one, two), Some(to_bits(0.5)));
+        assert_eq!(BinaryOperator::RealSubtract.apply(one, two), Some(to_bits(-1.0)));
+        assert_eq!(BinaryOperator::RealLess.apply(to_bits(-1.0), to_bits(-0.5)), Some(1));
+        assert_eq!(BinaryOperator::RealEqual.apply(to_bits(0.0), to_bits(-0.0)), Some(1));
+        assert_eq!(BinaryOperator::RealDivide.apply(one, to_bits(-0.0)), None);
+        assert_eq!(UnaryOperator::ToReal.apply(-3), to_bits(-3.0));
+        assert_eq!(UnaryOperator::RealNegate.apply(to_bits(0.0)), to_bits(-0.0));
+
+        // A NaN is unordered, and only unequal to everything
+        let nan = to_bits(f64::NAN);
+        for operator in [BinaryOperator::RealEqual, BinaryOperator::RealLess, BinaryOperator::RealGreaterEqual] {
+            assert_eq!(operator.apply(nan, nan), Some(0), "{}", operator.symbol());
+        }
+        assert_eq!(BinaryOperator::RealNotEqual.apply(nan, one), Some(1));
+        assert_eq!(BinaryOperator::Less.for_reals(), BinaryOperator::RealLess);
+
+        let instructions = [
+            Instruction::Binary {
+                dest: Var::Temp(0),
+                operator: BinaryOperator::RealMultiply,
+                left: Operand::Var(Var::Named("x".to_string())),
+                right: Operand::Constant(to_bits(2.5)),
+            },
+            Instruction::Unary { dest: Var::Temp(1), operator: UnaryOperator::ToReal, operand: Operand::Constant(3) },
+            Instruction::Print {
+                value: PrintValue::Real(Operand::Constant(to_bits(-1.0)), Some(Operand::Constant(2))),
+                width: Some(Operand::Var(Var::Temp(0))),
+            },
+        ];
+        let lines: Vec<String> = instructions.iter().map(Instruction::to_string).collect();
+        assert_eq!(lines, ["%0 = x *. 2.5", "%1 = real 3", "print_real -1.0 width %0 precision 2"]);
+    }
+
+    #[test]
+    fn test_display() {
+        let program = Program {
+            variables: vec!["a".to_string(), "b".to_string()],
+            strings: vec![b"x=\n".to_vec()],
+            blocks: vec![
+                BasicBlock {
+                    instructions: vec![
+                        Instruction::Binary {
+                            dest: Var::Temp(0),
+                            operator: BinaryOperator::Mod,
+                            left: Operand::Var(Var::Named("a".to_string())),
+                            right: Operand::Constant(-2),
+                        },
+                        Instruction::Unary {
+                            dest: Var::Named("b".to_string()),
+                            operator: UnaryOperator::Not,
+                            operand: Operand::Var(Var::Temp(0)),
+                        },
+                        Instruction::Print { value: PrintValue::String(0), width: Some(Operand::Constant(4)) },
+                        Instruction::Print { value: PrintValue::Boolean(Operand::Constant(1)), width: None },
+                        Instruction::PrintNewline,
+                    ],
+                    terminator: branch(1, 1),
+                },
+                block(Terminator::Exit),
+            ],
+            temp_count: 1,
+        };
+
+        let expected = "\
+var a, b
+str0 = \"x=\\n\"
+
+block0:
+    %0 = a mod -2
+    b = not %0
+    print_str str0 width 4
+    print_bool 1
+    print_newline
+    branch %0, block1, block1
+
+block1:
+    exit
+";
+        assert_eq!(program.to_string(), expected);
+    }
+}
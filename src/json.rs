@@ -0,0 +1,295 @@
+//! A small JSON writer used to export the AST to external tools.
+
+use crate::ast::{Block, Decl, Expr, ExprKind, PrintItem, Program, Stmt, StmtKind};
+use crate::span::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    /// Written the shortest way that reads back as the same double.
+    Real(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>), // Keeps the field order
+}
+
+impl Json {
+    fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    /// Formats the value with two spaces of indentation per level.
+    pub fn to_pretty_string(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out, 0);
+        out
+    }
+
+    fn write(&self, out: &mut String, indent: usize) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+            Json::Number(value) => out.push_str(&value.to_string()),
+            Json::Real(value) => out.push_str(&format!("{:?}", value)),
+            Json::String(text) => write_string(out, text),
+            Json::Array(items) if items.is_empty() => out.push_str("[]"),
+            Json::Array(items) => {
+                out.push_str("[\n");
+                for (i, item) in items.iter().enumerate() {
+                    out.push_str(&"  ".repeat(indent + 1));
+                    item.write(out, indent + 1);
+                    out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+                }
+                out.push_str(&"  ".repeat(indent));
+                out.push(']');
+            }
+            Json::Object(fields) if fields.is_empty() => out.push_str("{}"),
+            Json::Object(fields) => {
+                out.push_str("{\n");
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    out.push_str(&"  ".repeat(indent + 1));
+                    write_string(out, key);
+                    out.push_str(": ");
+                    value.write(out, indent + 1);
+                    out.push_str(if i + 1 < fields.len() { ",\n" } else { "\n" });
+                }
+                out.push_str(&"  ".repeat(indent));
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn write_string(out: &mut String, text: &str) {
+    out.push('"');
+    for ch in text.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ' '..='~' => out.push(ch),
+            // Everything else is escaped, so the output is plain ASCII
+            ch => {
+                for unit in ch.encode_utf16(&mut [0; 2]) {
+                    out.push_str(&format!("\\u{:04x}", unit));
+                }
+            }
+        }
+    }
+    out.push('"');
+}
+
+/// Conversion of a compiler data structure to JSON. Every AST node becomes an
+/// object with a `"node"` field naming its kind plus its `"id"` and `"span"`.
+pub trait ToJson {
+    fn to_json(&self) -> Json;
+}
+
+impl<T: ToJson> ToJson for Option<T> {
+    fn to_json(&self) -> Json {
+        self.as_ref().map_or(Json::Null, ToJson::to_json)
+    }
+}
+
+impl ToJson for Span {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("start", Json::Number(self.start as i64)),
+            ("end", Json::Number(self.end as i64)),
+            ("line", Json::Number(self.line as i64)),
+            ("column", Json::Number(self.column as i64)),
+        ])
+    }
+}
+
+impl ToJson for Program {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("node", Json::String("Program".to_string())),
+            ("id", Json::Number(self.id.0 as i64)),
+            ("span", self.span.to_json()),
+            ("declarations", Json::Array(self.declarations.iter().map(ToJson::to_json).collect())),
+            ("body", self.body.to_json()),
+        ])
+    }
+}
+
+impl ToJson for Decl {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("node", Json::String("Decl".to_string())),
+            ("id", Json::Number(self.id.0 as i64)),
+            ("span", self.span.to_json()),
+            ("names", Json::Array(self.names.iter().map(|name| Json::String(name.clone())).collect())),
+            ("type", Json::String(self.type_name.as_str().to_string())),
+        ])
+    }
+}
+
+impl ToJson for Block {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("node", Json::String("Block".to_string())),
+            ("id", Json::Number(self.id.0 as i64)),
+            ("span", self.span.to_json()),
+            ("statements", Json::Array(self.statements.iter().map(ToJson::to_json).collect())),
+        ])
+    }
+}
+
+impl ToJson for Stmt {
+    fn to_json(&self) -> Json {
+        let mut fields = vec![("id", Json::Number(self.id.0 as i64)), ("span", self.span.to_json())];
+        let node = match &self.kind {
+            StmtKind::Assign { target, value } => {
+                fields.push(("target", Json::String(target.clone())));
+                fields.push(("value", value.to_json()));
+                "Assign"
+            }
+            StmtKind::Print { items, newline } => {
+                fields.push(("items", Json::Array(items.iter().map(ToJson::to_json).collect())));
+                fields.push(("newline", Json::Bool(*newline)));
+                "Print"
+            }
+            StmtKind::Compound(block) => {
+                fields.push(("block", block.to_json()));
+                "Compound"
+            }
+            StmtKind::If { condition, then_branch, else_branch } => {
+                fields.push(("condition", condition.to_json()));
+                fields.push(("then", then_branch.to_json()));
+                fields.push(("else", else_branch.as_ref().map_or(Json::Null, |stmt| stmt.to_json())));
+                "If"
+            }
+            StmtKind::While { condition, body } => {
+                fields.push(("condition", condition.to_json()));
+                fields.push(("body", body.to_json()));
+                "While"
+            }
+            StmtKind::For { variable, start, end, descending, body } => {
+                fields.push(("variable", Json::String(variable.clone())));
+                fields.push(("start", start.to_json()));
+                fields.push(("end", end.to_json()));
+                fields.push(("descending", Json::Bool(*descending)));
+                fields.push(("body", body.to_json()));
+                "For"
+            }
+            StmtKind::Exit => "Exit",
+        };
+        fields.insert(0, ("node", Json::String(node.to_string())));
+        Json::object(fields)
+    }
+}
+
+impl ToJson for PrintItem {
+    fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("value", self.value.to_json()),
+            ("width", self.width.to_json()),
+            ("precision", self.precision.to_json()),
+        ])
+    }
+}
+
+impl ToJson for Expr {
+    fn to_json(&self) -> Json {
+        let mut fields = vec![("id", Json::Number(self.id.0 as i64)), ("span", self.span.to_json())];
+        let node = match &self.kind {
+            ExprKind::Integer(value) => {
+                fields.push(("value", Json::Number(*value)));
+                "Integer"
+            }
+            ExprKind::Real(value) => {
+                fields.push(("value", Json::Real(*value)));
+                "Real"
+            }
+            ExprKind::Boolean(value) => {
+                fields.push(("value", Json::Bool(*value)));
+                "Boolean"
+            }
+            // Each byte of the literal is the character with that code, so
+            // bytes that are not UTF-8 come through as well, as \u0080..\u00ff
+            ExprKind::String(text) => {
+                fields.push(("value", Json::String(text.iter().map(|&byte| char::from(byte)).collect())));
+                "String"
+            }
+            ExprKind::Variable(name) => {
+                fields.push(("name", Json::String(name.clone())));
+                "Variable"
+            }
+            ExprKind::Unary { operator, operand } => {
+                fields.push(("operator", Json::String(operator.symbol().to_string())));
+                fields.push(("operand", operand.to_json()));
+                "Unary"
+            }
+            ExprKind::Binary { left, operator, right } => {
+                fields.push(("operator", Json::String(operator.symbol().to_string())));
+                fields.push(("left", left.to_json()));
+                fields.push(("right", right.to_json()));
+                "Binary"
+            }
+        };
+        fields.insert(0, ("node", Json::String(node.to_string())));
+        Json::object(fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{BinaryOperator, NodeId};
+
+    #[test]
+    fn test_json_output() {
+        let expr = Expr {
+            id: NodeId(3),
+            kind: ExprKind::Binary {
+                left: Box::new(Expr::new(ExprKind::String(b"say \"hi\"\n\xc3\xa9\0".to_vec()))),
+                operator: BinaryOperator::Mod,
+                right: Box::new(Expr::new(ExprKind::Boolean(false))),
+            },
+            span: Span { start: 4, end: 9, line: 1, column: 5 },
+        };
+
+        let expected = r#"{
+  "node": "Binary",
+  "id": 3,
+  "span": {
+    "start": 4,
+    "end": 9,
+    "line": 1,
+    "column": 5
+  },
+  "operator": "mod",
+  "left": {
+    "node": "String",
+    "id": 0,
+    "span": {
+      "start": 0,
+      "end": 0,
+      "line": 0,
+      "column": 0
+    },
+    "value": "say \"hi\"\n\u00c3\u00a9\u0000"
+  },
+  "right": {
+    "node": "Boolean",
+    "id": 0,
+    "span": {
+      "start": 0,
+      "end": 0,
+      "line": 0,
+      "column": 0
+    },
+    "value": false
+  }
+}"#;
+        assert_eq!(expr.to_json().to_pretty_string(), expected);
+        assert_eq!(Json::Array(vec![Json::Null, Json::Object(Vec::new())]).to_pretty_string(), "[\n  null,\n  {}\n]");
+        assert_eq!(Json::String("\u{1f600}\x7f".to_string()).to_pretty_string(), r#""\ud83d\ude00\u007f""#);
+    }
+}
@@ -1,110 +1,392 @@
+use std::iter::Peekable;
+use std::num::IntErrorKind;
+use std::str::Chars;
+
+use crate::diagnostic::Diagnostic;
+use crate::span::Span;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
-    Keyword(String),       // e.g., "var", "begin", "end"
-    Identifier(String),    // e.g., variable names like "a", "b"
-    IntegerLiteral(i32),   // e.g., numbers like 10, 20
-    StringLiteral(String), // e.g., "The result is: "
-    Symbol(char),          // e.g., ':', ';', '(', ')'
-    Assignment,            // e.g., :=
-    EndOfInput,            // Represents the end of the input
+    Keyword(String),        // e.g., "var", "begin", "end"
+    Identifier(String),     // e.g., variable names like "a", "b"
+    IntegerLiteral(i64),    // e.g., numbers like 10, 20
+    RealLiteral(f64),       // e.g., 1.5, 2e10
+    StringLiteral(Vec<u8>), // e.g., "The result is: ", as bytes
+    Symbol(char),           // e.g., ':', ';', '(', ')'
+    Assignment,             // e.g., :=
+    NotEqual,               // <>
+    LessEqual,              // <=
+    GreaterEqual,           // >=
+    EndOfInput,             // Represents the end of the input
+}
+
+/// A token together with the part of the source it was read from.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+const KEYWORDS: &[&str] = &[
+    "var", "begin", "end", "integer", "int64", "boolean", "real", "true", "false", "write", "writeln", "div", "mod",
+    "rem", "and", "or", "not", "if", "then", "else", "while", "do", "for", "to", "downto", "exit",
+];
+
+pub fn tokenize(input: &str) -> Result<Vec<Token>, Diagnostic> {
+    Ok(tokenize_spanned(input)?.into_iter().map(|spanned| spanned.token).collect())
+}
+
+pub fn tokenize_spanned(input: &str) -> Result<Vec<SpannedToken>, Diagnostic> {
+    Ok(tokenize_with_comments(input)?.0)
+}
+
+/// A comment, kept so tools such as the formatter can reproduce it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Comment {
+    pub text: String, // Including the delimiters
+    pub span: Span,
 }
 
-pub fn tokenize(input: &str) -> Vec<Token> {
+/// Like [`tokenize_spanned`], but also returns the comments in source order.
+pub fn tokenize_with_comments(input: &str) -> Result<(Vec<SpannedToken>, Vec<Comment>), Diagnostic> {
     let mut tokens = Vec::new();
-    let mut chars = input.chars().peekable();
+    let mut comments = Vec::new();
+    let mut chars = Cursor::new(input);
 
-    while let Some(&c) = chars.peek() {
-        match c {
-            // Keywords and identifiers
-            'a'..='z' | 'A'..='Z' => {
-                let mut word = String::new();
-                while let Some(&ch) = chars.peek() {
-                    if ch.is_alphanumeric() {
-                        word.push(ch);
-                        chars.next();
-                    } else {
-                        break;
-                    }
-                }
-                if word == "var" || word == "begin" || word == "end"  || word == "integer" || word == "writeln" {
-                    tokens.push(Token::Keyword(word));
+    loop {
+        let start = chars.position();
+        let lexeme =
+            next_token(&mut chars).map_err(|message| Diagnostic::error(message).with_span(chars.span_from(start)))?;
+        let span = chars.span_from(start);
+        match lexeme {
+            Some(Token::EndOfInput) => {
+                tokens.push(SpannedToken { token: Token::EndOfInput, span });
+                return Ok((tokens, comments));
+            }
+            Some(token) => tokens.push(SpannedToken { token, span }),
+            None if span.start < span.end && !input[span.start..span.end].trim().is_empty() => {
+                comments.push(Comment { text: input[span.start..span.end].to_string(), span });
+            }
+            None => {} // Whitespace
+        }
+    }
+}
+
+// Reads the token starting at the cursor. Returns None after skipping
+// whitespace or a comment.
+fn next_token(chars: &mut Cursor) -> Result<Option<Token>, String> {
+    let Some(&c) = chars.peek() else {
+        return Ok(Some(Token::EndOfInput));
+    };
+    let token = match c {
+        // Comments
+        '{' => {
+            skip_block_comment(chars, "{", "}")?;
+            return Ok(None);
+        }
+        '(' if second_char(chars) == Some('*') => {
+            skip_block_comment(chars, "(*", "*)")?;
+            return Ok(None);
+        }
+        '/' | '-' if second_char(chars) == Some(c) => {
+            skip_line_comment(chars); // A // or -- comment
+            return Ok(None);
+        }
+        // Keywords and identifiers, both case-insensitive
+        'a'..='z' | 'A'..='Z' | '_' => {
+            let mut word = String::new();
+            while let Some(&ch) = chars.peek() {
+                if ch.is_ascii_alphanumeric() || ch == '_' {
+                    word.push(ch.to_ascii_lowercase());
+                    chars.next();
                 } else {
-                    tokens.push(Token::Identifier(word));
+                    break;
                 }
             }
-            // Numbers
-            '0'..='9' => {
-                let mut number = String::new();
-                while let Some(&ch) = chars.peek() {
-                    if ch.is_numeric() {
-                        number.push(ch);
-                        chars.next();
+            if KEYWORDS.contains(&word.as_str()) {
+                Token::Keyword(word)
+            } else {
+                Token::Identifier(word)
+            }
+        }
+        // Numbers: decimal, Ada based (16#FF#) and Pascal $hex, %binary, &octal,
+        // and decimal reals such as 1.5 or 2e-3
+        '0'..='9' => {
+            let number = take_digits(chars);
+            if chars.peek() == Some(&'#') {
+                chars.next(); // Consume the opening '#'
+                let radix = match number.parse::<u32>() {
+                    Ok(radix) if (2..=16).contains(&radix) => radix,
+                    _ => return Err(format!("Invalid base {} in based literal, expected 2..16", number)),
+                };
+                let digits = take_digits(chars);
+                if chars.next() != Some('#') {
+                    return Err(format!("Expected closing '#' in based literal {}#{}", number, digits));
+                }
+                Token::IntegerLiteral(parse_integer(&digits, radix)?)
+            } else if let Some(text) = take_real(chars, &number) {
+                Token::RealLiteral(parse_real(&text)?)
+            } else {
+                Token::IntegerLiteral(parse_integer(&number, 10)?)
+            }
+        }
+        '$' | '%' | '&' => {
+            chars.next(); // Consume the radix prefix
+            let radix = match c {
+                '$' => 16,
+                '%' => 2,
+                _ => 8,
+            };
+            let digits = take_digits(chars);
+            Token::IntegerLiteral(parse_integer(&digits, radix)?)
+        }
+        // String literals, possibly concatenated with control characters
+        '"' | '\'' | '#' => Token::StringLiteral(tokenize_string_literal(chars)?),
+        '+' | '-' | '*' | '/' => {
+            chars.next();
+            Token::Symbol(c)
+        }
+        // Symbols and assignment
+        ':' => {
+            chars.next(); // Consume ':'
+            if chars.peek() == Some(&'=') {
+                chars.next(); // Consume '='
+                Token::Assignment
+            } else {
+                Token::Symbol(':')
+            }
+        }
+        // Comparisons
+        '<' | '>' => {
+            chars.next(); // Consume '<' or '>'
+            match (c, chars.peek()) {
+                ('<', Some('>')) => {
+                    chars.next();
+                    Token::NotEqual
+                }
+                (_, Some('=')) => {
+                    chars.next();
+                    if c == '<' {
+                        Token::LessEqual
                     } else {
-                        break;
+                        Token::GreaterEqual
                     }
                 }
-                tokens.push(Token::IntegerLiteral(number.parse::<i32>().unwrap()));
+                _ => Token::Symbol(c),
             }
-            // String literals
-            '"' => {
+        }
+        // Other symbols
+        ';' | '(' | ')' | ',' | '=' => {
+            chars.next();
+            Token::Symbol(c)
+        }
+        // Whitespace
+        _ if c.is_whitespace() => {
+            chars.next(); // Skip whitespace
+            return Ok(None);
+        }
+        // Unrecognized characters
+        _ => {
+            chars.next();
+            return Err(format!("Unexpected character: {}", c));
+        }
+    };
+    Ok(Some(token))
+}
+
+// Iterates over the source characters while keeping track of the position.
+#[derive(Clone)]
+struct Cursor<'a> {
+    chars: Peekable<Chars<'a>>,
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(input: &'a str) -> Self {
+        Cursor { chars: input.chars().peekable(), offset: 0, line: 1, column: 1 }
+    }
+
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+
+    fn position(&self) -> Span {
+        Span { start: self.offset, end: self.offset, line: self.line, column: self.column }
+    }
+
+    fn span_from(&self, start: Span) -> Span {
+        Span { end: self.offset, ..start }
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.chars.next()?;
+        self.offset += ch.len_utf8();
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(ch)
+    }
+}
+
+// Reads alphanumeric digits and '_' separators; validation happens in parse_integer.
+fn take_digits(chars: &mut Cursor) -> String {
+    let mut digits = String::new();
+    while let Some(&ch) = chars.peek() {
+        if ch.is_ascii_alphanumeric() || ch == '_' {
+            digits.push(ch);
+            chars.next();
+        } else {
+            break;
+        }
+    }
+    digits
+}
+
+// Converts the digits of an integer literal to its value. Underscores may only
+// separate digits, as in 1_000_000, and the value must fit in an int64.
+fn parse_integer(digits: &str, radix: u32) -> Result<i64, String> {
+    if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+        return Err(format!("Misplaced '_' in integer literal {}", digits));
+    }
+    let cleaned = digits.replace('_', "");
+    i64::from_str_radix(&cleaned, radix).map_err(|error| match error.kind() {
+        IntErrorKind::PosOverflow => {
+            format!("Integer literal {} is out of range, the maximum is {}", digits, i64::MAX)
+        }
+        IntErrorKind::Empty => "Expected digits in integer literal".to_string(),
+        _ => format!("Invalid digit in base {} literal {}", radix, digits),
+    })
+}
+
+// Reads the rest of a real literal whose first digits, as read by
+// take_digits, are `number`: a fraction after a '.' and an exponent after an
+// 'e'. Returns None if the literal is an integer.
+fn take_real(chars: &mut Cursor, number: &str) -> Option<String> {
+    let mut text = number.to_string();
+    if chars.peek() == Some(&'.') && second_char(chars).is_some_and(|ch| ch.is_ascii_digit()) {
+        chars.next(); // Consume the '.'
+        text.push('.');
+        text.push_str(&take_digits(chars));
+    } else {
+        let (mantissa, exponent) = number.split_once(['e', 'E'])?;
+        let digits = |part: &str| part.chars().all(|ch| ch.is_ascii_digit() || ch == '_');
+        if !digits(mantissa) || !digits(exponent) {
+            return None;
+        }
+    }
+    // take_digits stops at the sign of an exponent, as in 1.5e-3
+    if text.ends_with(['e', 'E']) && matches!(chars.peek(), Some('+' | '-')) {
+        text.extend(chars.next());
+        text.push_str(&take_digits(chars));
+    }
+    Some(text)
+}
+
+// Converts the text of a real literal to the nearest double. Underscores may
+// only separate digits, as in integer literals.
+fn parse_real(text: &str) -> Result<f64, String> {
+    let misplaced = |part: &str| part.starts_with('_') || part.ends_with('_') || part.contains("__");
+    if text.split(['.', 'e', 'E', '+', '-']).any(misplaced) {
+        return Err(format!("Misplaced '_' in real literal {}", text));
+    }
+    match text.replace('_', "").parse::<f64>() {
+        Ok(value) if value.is_infinite() => Err(format!("Real literal {} is out of range", text)),
+        Ok(value) => Ok(value),
+        Err(_) => Err(format!("Invalid real literal {}", text)),
+    }
+}
+
+fn second_char(chars: &Cursor) -> Option<char> {
+    chars.clone().nth(1)
+}
+
+// Skips a // or -- comment up to (but not including) the end of the line.
+fn skip_line_comment(chars: &mut Cursor) {
+    while let Some(&ch) = chars.peek() {
+        if ch == '\n' {
+            break;
+        }
+        chars.next();
+    }
+}
+
+// Skips a { ... } or (* ... *) comment. Comments of the same kind nest, so
+// { a { b } c } is a single comment, while the other kind is plain text inside
+// it: { (* } ends at the brace.
+fn skip_block_comment(chars: &mut Cursor, open: &str, close: &str) -> Result<(), String> {
+    let mut depth = 0;
+    loop {
+        let rest: String = chars.clone().take(2).collect();
+        if rest.starts_with(open) {
+            depth += 1;
+            chars.nth(open.len() - 1);
+        } else if rest.starts_with(close) {
+            depth -= 1;
+            chars.nth(close.len() - 1);
+            if depth == 0 {
+                return Ok(());
+            }
+        } else if chars.next().is_none() {
+            return Err(format!("Unterminated comment, expected '{}'", close));
+        }
+    }
+}
+
+// Reads a sequence of quoted strings and #n control characters that together
+// form one literal, e.g. 'It''s'#13#10'done'. A quote is included in a quoted
+// string by doubling it. Quoted characters are stored as their UTF-8 bytes
+// and #n adds the single byte n.
+fn tokenize_string_literal(chars: &mut Cursor) -> Result<Vec<u8>, String> {
+    let mut string = Vec::new();
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\'' | '"' => {
                 chars.next(); // Consume the opening quote
-                let mut string = String::new();
-                while let Some(&ch) = chars.peek() {
-                    if ch == '"' {
-                        chars.next(); // Consume the closing quote
-                        break;
-                    } else {
-                        string.push(ch);
-                        chars.next();
+                loop {
+                    match chars.next() {
+                        Some(ch) if ch == c => {
+                            if chars.peek() == Some(&c) {
+                                chars.next(); // Doubled quote stands for the quote itself
+                                string.push(c as u8);
+                            } else {
+                                break; // Closing quote
+                            }
+                        }
+                        Some('\n') | None => return Err("Unterminated string literal".to_string()),
+                        Some(ch) => string.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes()),
                     }
                 }
-                tokens.push(Token::StringLiteral(string));
-            }
-            '+' | '-' | '*' | '/' => {
-                tokens.push(Token::Symbol(c));
-                chars.next();
             }
-            '\'' => {
-                chars.next();
-                let mut string = String::new();
+            '#' => {
+                chars.next(); // Consume '#'
+                let mut digits = String::new();
                 while let Some(&ch) = chars.peek() {
-                    if ch == '\'' {
+                    if ch.is_ascii_digit() {
+                        digits.push(ch);
+                        chars.next();
+                    } else {
                         break;
                     }
-                    string.push(ch);
-                    chars.next();
                 }
-                chars.next();
-                tokens.push(Token::StringLiteral(string));
-            }
-            // Symbols and assignment
-            ':' => {
-                chars.next(); // Consume ':'
-                if chars.peek() == Some(&'=') {
-                    chars.next(); // Consume '='
-                    tokens.push(Token::Assignment);
-                } else {
-                    tokens.push(Token::Symbol(':'));
+                match digits.parse::<u8>() {
+                    Ok(code) => string.push(code),
+                    _ if digits.is_empty() => return Err("Expected character code after '#'".to_string()),
+                    _ => return Err(format!("Character code #{} is out of range 0..255", digits)),
                 }
             }
-            // Other symbols
-            ';' | '(' | ')' | ',' => {
-                tokens.push(Token::Symbol(c));
-                chars.next();
-            }
-            // Whitespace
-            _ if c.is_whitespace() => {
-                chars.next(); // Skip whitespace
-            }
-            // Unrecognized characters
-            _ => {
-                panic!("Unexpected character: {}", c);
-            }
+            _ => break,
         }
     }
-
-    tokens.push(Token::EndOfInput);
-    tokens
+    Ok(string)
 }
 
 #[cfg(test)] // Marks this module as test-only
@@ -126,9 +408,8 @@ mod tests {
             end
         "#;
 
-        let tokens = tokenize(source_code);
+        let tokens = tokenize(source_code).unwrap();
 
-        
         // Expected tokens for the provided source code
         let expected_tokens = vec![
             Token::Keyword("var".to_string()),
@@ -159,7 +440,7 @@ mod tests {
             Token::Symbol(';'),
             Token::Keyword("writeln".to_string()),
             Token::Symbol('('),
-            Token::StringLiteral("The result is: ".to_string()),
+            Token::StringLiteral(b"The result is: ".to_vec()),
             Token::Symbol(','),
             Token::Identifier("result".to_string()),
             Token::Symbol(')'),
@@ -172,6 +453,204 @@ mod tests {
         //     println!("Actual: {:?}, Expected: {:?}", actual, expected);
         // }
 
-        assert_eq!(tokens, expected_tokens, "Token mismatch. Uncomment the printout and check the output for differences.");
+        assert_eq!(
+            tokens, expected_tokens,
+            "Token mismatch. Uncomment the printout and check the output for differences."
+        );
+    }
+
+    #[test]
+    fn test_tokenize_string_literals() {
+        let cases = [
+            ("'It''s'", &b"It's"[..]),
+            ("''", b""),
+            ("''''", b"'"),
+            ("'line'#13#10", b"line\r\n"),
+            ("#9'tab'#9", b"\ttab\t"),
+            ("'a'#39'b'", b"a'b"),
+            ("\"say \"\"hi\"\"\"", b"say \"hi\""),
+            ("#128#200#255", b"\x80\xc8\xff"),
+            ("'caf\u{e9}'#233", b"caf\xc3\xa9\xe9"),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(
+                tokenize(source),
+                Ok(vec![Token::StringLiteral(expected.to_vec()), Token::EndOfInput]),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_tokenize_invalid_string_literals() {
+        for source in ["'unterminated", "'broken\nline'", "'a'#", "#256", "#300"] {
+            assert!(tokenize(source).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_tokenize_comments() {
+        let expected = vec![
+            Token::Identifier("a".to_string()),
+            Token::Symbol('-'),
+            Token::Identifier("b".to_string()),
+            Token::Symbol('/'),
+            Token::Identifier("c".to_string()),
+            Token::EndOfInput,
+        ];
+        let cases = [
+            "a { brace } - b / c",
+            "a (* paren *) - b / c",
+            "a - b // slash\n/ c",
+            "a -- dash\n- b / c",
+            "a { outer { nested } still comment } - b / c",
+            "a (* outer (* nested *) *) - b / c",
+            "a { (* other kind } - b / c",
+            "a (* { other kind *) - b / c",
+            "a { multi\nline } - b / c // trailing",
+        ];
+
+        for source in cases {
+            assert_eq!(tokenize(source), Ok(expected.clone()), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn test_tokenize_unterminated_comments() {
+        for source in ["{ never closed", "(* never closed", "{ outer { inner }", "(* *"] {
+            assert!(tokenize(source).is_err(), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn test_identifiers_are_ascii() {
+        for source in ["naïve", "x²", "café := 1"] {
+            match tokenize(source) {
+                Err(error) => assert!(error.message.starts_with("Unexpected character"), "{}: {}", source, error),
+                Ok(tokens) => panic!("{} tokenized as {:?}", source, tokens),
+            }
+        }
+    }
+
+    #[test]
+    fn test_tokenize_is_case_insensitive() {
+        assert_eq!(
+            tokenize("BEGIN Result := A Mod b_1; End"),
+            Ok(vec![
+                Token::Keyword("begin".to_string()),
+                Token::Identifier("result".to_string()),
+                Token::Assignment,
+                Token::Identifier("a".to_string()),
+                Token::Keyword("mod".to_string()),
+                Token::Identifier("b_1".to_string()),
+                Token::Symbol(';'),
+                Token::Keyword("end".to_string()),
+                Token::EndOfInput,
+            ])
+        );
+    }
+
+    #[test]
+    fn test_tokenize_comparison_operators() {
+        assert_eq!(
+            tokenize("a<>b<=c>=d<e>f=g"),
+            Ok(vec![
+                Token::Identifier("a".to_string()),
+                Token::NotEqual,
+                Token::Identifier("b".to_string()),
+                Token::LessEqual,
+                Token::Identifier("c".to_string()),
+                Token::GreaterEqual,
+                Token::Identifier("d".to_string()),
+                Token::Symbol('<'),
+                Token::Identifier("e".to_string()),
+                Token::Symbol('>'),
+                Token::Identifier("f".to_string()),
+                Token::Symbol('='),
+                Token::Identifier("g".to_string()),
+                Token::EndOfInput,
+            ])
+        );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_tokenize_integer_literals() {
+        let cases = [
+            ("0", 0),
+            ("1_000_000", 1_000_000),
+            ("$FF", 255),
+            ("$ff_ff", 0xffff),
+            ("%1010", 10),
+            ("&17", 15),
+            ("16#FF#", 255),
+            ("2#1010#", 10),
+            ("8#777#", 511),
+            ("16#7FFF_FFFF_FFFF_FFFF#", i64::MAX),
+            ("3000000000", 3_000_000_000),
+            ("9223372036854775807", i64::MAX),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(tokenize(source), Ok(vec![Token::IntegerLiteral(expected), Token::EndOfInput]), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_tokenize_invalid_integer_literals() {
+        let cases = [
+            ("9223372036854775808", "out of range"),
+            ("$1_0000_0000_0000_0000", "out of range"),
+            ("1__0", "Misplaced '_'"),
+            ("10_", "Misplaced '_'"),
+            ("2#102#", "Invalid digit"),
+            ("12abc", "Invalid digit"),
+            ("17#1#", "Invalid base"),
+            ("16#FF", "Expected closing '#'"),
+            ("$", "Expected digits"),
+        ];
+
+        for (source, message) in cases {
+            match tokenize(source) {
+                Err(error) => assert!(error.message.contains(message), "{}: {}", source, error),
+                Ok(tokens) => panic!("{} tokenized as {:?}", source, tokens),
+            }
+        }
+    }
+
+    #[test]
+    fn test_tokenize_real_literals() {
+        let cases = [("1.5", 1.5), ("0.1", 0.1), ("2e10", 2e10), ("1.5E-3", 1.5e-3), ("1_000.000_1e+2", 1000.0001e2)];
+        for (source, expected) in cases {
+            assert_eq!(tokenize(source), Ok(vec![Token::RealLiteral(expected), Token::EndOfInput]), "{}", source);
+        }
+
+        // A '.' without a digit after it is not part of the number
+        let cases = [
+            ("1.", "Unexpected character: ."),
+            ("1e999", "out of range"),
+            ("1.5x", "Invalid real literal"),
+            ("1e", "Invalid real literal"),
+            ("1._5", "Unexpected character: ."),
+            ("1.5__0", "Misplaced '_'"),
+            ("1.5e_3", "Misplaced '_'"),
+        ];
+        for (source, message) in cases {
+            match tokenize(source) {
+                Err(error) => assert!(error.message.contains(message), "{}: {}", source, error),
+                Ok(tokens) => panic!("{} tokenized as {:?}", source, tokens),
+            }
+        }
+    }
+
+    #[test]
+    fn test_tokenize_keeps_comments() {
+        let (tokens, comments) = tokenize_with_comments("a { one }\n-- two\nb (* three *)").unwrap();
+
+        assert_eq!(tokens.len(), 3);
+        let texts: Vec<&str> = comments.iter().map(|comment| comment.text.as_str()).collect();
+        assert_eq!(texts, ["{ one }", "-- two", "(* three *)"]);
+        assert_eq!((comments[1].span.line, comments[1].span.column), (2, 1));
+    }
+}
@@ -0,0 +1,123 @@
+//! CCC, the Christer Carlsson Compiler, as a library.
+//!
+//! [`compile`] runs the whole pipeline on a source text and returns every
+//! intermediate result. The individual stages are available through the
+//! [`lexer`], [`parser`], [`ast`], [`semantic`], [`lower`], [`ir`],
+//! [`codegen`] and [`asm`] modules for tools that only need part of it.
+//!
+//! ```
+//! let output = ccc::compile("var x: integer; begin x := 6 * 7; writeln(x); end", &ccc::Options::default()).unwrap();
+//! assert!(output.assembly.contains("_start:"));
+//! ```
+
+pub mod asm;
+pub mod ast;
+pub mod codegen;
+pub mod diagnostic;
+pub mod dump;
+pub mod elf;
+pub mod format;
+pub mod ir;
+pub mod json;
+pub mod lexer;
+pub mod lower;
+pub mod optimize;
+pub mod parser;
+pub mod peephole;
+pub mod regalloc;
+pub mod semantic;
+pub mod span;
+pub mod toolchain;
+
+#[cfg(test)]
+mod testing;
+
+pub use diagnostic::{Diagnostic, Severity};
+pub use span::Span;
+
+use ast::Program;
+use lexer::SpannedToken;
+
+/// Settings for [`compile`]. Construct with `Options::default()` and set the
+/// fields you need; new fields will always have a default.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Optimisation level from 0 (none) to 2, as set by `-O`.
+    pub opt_level: u8,
+    /// The assembler syntax of [`Output::assembly`], as set by `--asm-syntax`.
+    /// Only x86-64 has a choice; other targets always use GNU as syntax.
+    pub asm_syntax: asm::Syntax,
+    /// The machine to generate code for, as set by `--target`.
+    pub target: Target,
+    /// The name of the source file, for the `#line` directives of C output
+    /// and the header comment of C and WebAssembly output.
+    pub source_name: Option<String>,
+}
+
+/// The platforms code can be generated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Target {
+    #[default]
+    X86_64Linux,
+    Aarch64Linux,
+    Riscv64Linux,
+    /// C99 source, translated from the AST.
+    C,
+    /// A WebAssembly text module for WASI, translated from the AST.
+    Wasm,
+}
+
+/// Everything produced by a successful compilation.
+#[derive(Debug)]
+pub struct Output {
+    pub tokens: Vec<SpannedToken>,
+    pub ast: Program,
+    /// The three-address code the assembly was generated from.
+    pub ir: ir::Program,
+    /// Assembly for the target, in the syntax chosen in the options, C
+    /// source or a WebAssembly text module.
+    pub assembly: String,
+    /// The instructions and data that `assembly` was printed from, for
+    /// x86-64 only.
+    pub code: Option<asm::Assembly>,
+    /// Warnings about the program, such as unreachable code.
+    pub warnings: Vec<Diagnostic>,
+}
+
+/// Compiles `source` to assembly, stopping at the first stage that fails.
+/// Semantic analysis reports all errors it finds, the other stages only the first.
+pub fn compile(source: &str, options: &Options) -> Result<Output, Vec<Diagnostic>> {
+    let tokens = lexer::tokenize_spanned(source).map_err(|diagnostic| vec![diagnostic])?;
+    let ast = parser::Parser::from_spanned(tokens.clone()).parse().map_err(|diagnostic| vec![diagnostic])?;
+    let analysis = semantic::analyze(&ast)?;
+    let mut ir = lower::lower(&ast, &analysis);
+    optimize::optimize(&mut ir, options.opt_level);
+    let (assembly, code) = match options.target {
+        Target::X86_64Linux => {
+            let allocation =
+                if options.opt_level >= 1 { regalloc::allocate(&ir) } else { regalloc::Allocation::in_memory(&ir) };
+            let code = codegen::generate_code(&ir, &allocation, options.opt_level >= 1);
+            (code.to_source(options.asm_syntax), Some(code))
+        }
+        Target::Aarch64Linux => {
+            let allocation = if options.opt_level >= 1 {
+                regalloc::allocate_from(&ir, &codegen::aarch64::REGISTERS)
+            } else {
+                regalloc::Allocation::in_memory(&ir)
+            };
+            (codegen::aarch64::generate_code(&ir, &allocation), None)
+        }
+        Target::Riscv64Linux => {
+            let allocation = if options.opt_level >= 1 {
+                regalloc::allocate_from(&ir, &codegen::riscv64::REGISTERS)
+            } else {
+                regalloc::Allocation::in_memory(&ir)
+            };
+            (codegen::riscv64::generate_code(&ir, &allocation), None)
+        }
+        Target::C => (codegen::c::generate_code(&ast, &analysis, options.source_name.as_deref()), None),
+        Target::Wasm => (codegen::wasm::generate_code(&ast, &analysis, options.source_name.as_deref()), None),
+    };
+    Ok(Output { tokens, ast, ir, assembly, code, warnings: analysis.warnings })
+}
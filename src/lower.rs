@@ -0,0 +1,351 @@
+//! Lowering from the AST to the three-address code of [`crate::ir`].
+
+use crate::ast::{
+    self, BinaryOperator, Block, Decl, Expr, ExprKind, PrintItem, Stmt, StmtKind, UnaryOperator, Visitor,
+};
+use crate::ir::{self, BasicBlock, BlockId, Instruction, Operand, PrintValue, Program, Terminator, Var};
+use crate::semantic::{Analysis, Type};
+
+/// Translates a program that passed semantic analysis. The blocks are laid
+/// out in source order.
+pub fn lower(program: &ast::Program, analysis: &Analysis) -> Program {
+    let mut lowerer = Lowerer { analysis, program: Program::default(), current: BlockId(0), layout: Vec::new() };
+    let entry = lowerer.new_block();
+    lowerer.switch_to(entry);
+    lowerer.visit_program(program);
+    lowerer.terminate(Terminator::Exit);
+
+    let Lowerer { mut program, layout, .. } = lowerer;
+    program.reorder_blocks(&layout);
+    program
+}
+
+struct Lowerer<'a> {
+    analysis: &'a Analysis,
+    program: Program,
+    current: BlockId,     // Block that instructions are appended to
+    layout: Vec<BlockId>, // Blocks in the order they were started
+}
+
+impl Lowerer<'_> {
+    // Creates an empty block; its terminator is set when it is finished.
+    fn new_block(&mut self) -> BlockId {
+        self.program.blocks.push(BasicBlock { instructions: Vec::new(), terminator: Terminator::Exit });
+        BlockId(self.program.blocks.len() - 1)
+    }
+
+    fn switch_to(&mut self, block: BlockId) {
+        self.current = block;
+        self.layout.push(block);
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.program.blocks[self.current.0].instructions.push(instruction);
+    }
+
+    fn terminate(&mut self, terminator: Terminator) {
+        self.program.blocks[self.current.0].terminator = terminator;
+    }
+
+    fn new_temp(&mut self) -> Var {
+        self.program.temp_count += 1;
+        Var::Temp(self.program.temp_count - 1)
+    }
+
+    // Evaluates `expr` and returns where its value can be read.
+    fn expr(&mut self, expr: &Expr) -> Operand {
+        match &expr.kind {
+            ExprKind::Integer(value) => Operand::Constant(*value),
+            ExprKind::Real(value) => Operand::Constant(ir::to_bits(*value)),
+            ExprKind::Boolean(value) => Operand::Constant(*value as i64),
+            ExprKind::Variable(name) => Operand::Var(Var::Named(name.clone())),
+            ExprKind::String(_) => unreachable!("string literals are only allowed as print items"),
+            ExprKind::Unary { operator, operand } => {
+                let operator = match self.analysis.type_of(expr) {
+                    Type::Real => ir::UnaryOperator::RealNegate,
+                    _ => (*operator).into(),
+                };
+                let operand = self.expr(operand);
+                let dest = self.new_temp();
+                self.emit(Instruction::Unary { dest: dest.clone(), operator, operand });
+                Operand::Var(dest)
+            }
+            ExprKind::Binary { left, operator: operator @ (BinaryOperator::And | BinaryOperator::Or), right } => {
+                // The right operand is only evaluated when the left one does
+                // not decide the result
+                let result = self.new_temp();
+                let left = self.expr(left);
+                self.emit(Instruction::Copy { dest: result.clone(), source: left.clone() });
+                let (evaluate_right, done) = (self.new_block(), self.new_block());
+                let (if_true, if_false) =
+                    if *operator == BinaryOperator::And { (evaluate_right, done) } else { (done, evaluate_right) };
+                self.terminate(Terminator::Branch { condition: left, if_true, if_false });
+
+                self.switch_to(evaluate_right);
+                let right = self.expr(right);
+                self.emit(Instruction::Copy { dest: result.clone(), source: right });
+                self.terminate(Terminator::Jump(done));
+
+                self.switch_to(done);
+                Operand::Var(result)
+            }
+            ExprKind::Binary { left, operator, right } => {
+                let real = [left, right].iter().any(|operand| self.analysis.type_of(operand) == Type::Real);
+                let (left, right, operator) = if real {
+                    (self.real_expr(left), self.real_expr(right), ir::BinaryOperator::from(*operator).for_reals())
+                } else {
+                    (self.expr(left), self.expr(right), (*operator).into())
+                };
+                let dest = self.new_temp();
+                self.emit(Instruction::Binary { dest: dest.clone(), operator, left, right });
+                Operand::Var(dest)
+            }
+        }
+    }
+
+    // Evaluates `expr` where a real is expected, converting an integer.
+    fn real_expr(&mut self, expr: &Expr) -> Operand {
+        let value = self.expr(expr);
+        if self.analysis.type_of(expr) != Type::Integer {
+            return value;
+        }
+        let dest = self.new_temp();
+        self.emit(Instruction::Unary { dest: dest.clone(), operator: ir::UnaryOperator::ToReal, operand: value });
+        Operand::Var(dest)
+    }
+
+    // Ends the current block with a jump to `if_true` or `if_false` depending
+    // on the boolean `condition`. `and`, `or` and `not` become jumps instead
+    // of values.
+    fn branch(&mut self, condition: &Expr, if_true: BlockId, if_false: BlockId) {
+        match &condition.kind {
+            ExprKind::Unary { operator: UnaryOperator::Not, operand } => self.branch(operand, if_false, if_true),
+            ExprKind::Binary { left, operator: BinaryOperator::And, right } => {
+                let evaluate_right = self.new_block();
+                self.branch(left, evaluate_right, if_false);
+                self.switch_to(evaluate_right);
+                self.branch(right, if_true, if_false);
+            }
+            ExprKind::Binary { left, operator: BinaryOperator::Or, right } => {
+                let evaluate_right = self.new_block();
+                self.branch(left, if_true, evaluate_right);
+                self.switch_to(evaluate_right);
+                self.branch(right, if_true, if_false);
+            }
+            _ => {
+                let condition = self.expr(condition);
+                self.terminate(Terminator::Branch { condition, if_true, if_false });
+            }
+        }
+    }
+
+    // Lowers `stmt` starting in `block`, then continues at `next`.
+    fn stmt_then_jump(&mut self, stmt: &Stmt, block: BlockId, next: BlockId) {
+        self.switch_to(block);
+        self.visit_stmt(stmt);
+        self.terminate(Terminator::Jump(next));
+    }
+
+    fn for_loop(&mut self, variable: &str, start: &Expr, end: &Expr, descending: bool, body: &Stmt) {
+        // Both bounds are evaluated once, before the loop variable is set. The
+        // variable is compared with the end value before it is stepped, so
+        // the loop ends without overflowing even at the limits of the type.
+        let variable = Var::Named(variable.to_string());
+        let start = self.expr(start);
+        let end = match self.expr(end) {
+            Operand::Var(Var::Named(name)) => {
+                let copy = self.new_temp();
+                self.emit(Instruction::Copy { dest: copy.clone(), source: Operand::Var(Var::Named(name)) });
+                Operand::Var(copy)
+            }
+            end => end,
+        };
+        self.emit(Instruction::Copy { dest: variable.clone(), source: start });
+
+        let (past_end, step) = if descending {
+            (ir::BinaryOperator::Less, ir::BinaryOperator::Subtract)
+        } else {
+            (ir::BinaryOperator::Greater, ir::BinaryOperator::Add)
+        };
+        let (body_block, step_block, exit) = (self.new_block(), self.new_block(), self.new_block());
+        let skip = self.new_temp();
+        self.emit(Instruction::Binary {
+            dest: skip.clone(),
+            operator: past_end,
+            left: Operand::Var(variable.clone()),
+            right: end.clone(),
+        });
+        self.terminate(Terminator::Branch { condition: Operand::Var(skip), if_true: exit, if_false: body_block });
+
+        self.switch_to(body_block);
+        self.visit_stmt(body);
+        let last = self.new_temp();
+        self.emit(Instruction::Binary {
+            dest: last.clone(),
+            operator: ir::BinaryOperator::Equal,
+            left: Operand::Var(variable.clone()),
+            right: end,
+        });
+        self.terminate(Terminator::Branch { condition: Operand::Var(last), if_true: exit, if_false: step_block });
+
+        self.switch_to(step_block);
+        self.emit(Instruction::Binary {
+            dest: variable.clone(),
+            operator: step,
+            left: Operand::Var(variable),
+            right: Operand::Constant(1),
+        });
+        self.terminate(Terminator::Jump(body_block));
+
+        self.switch_to(exit);
+    }
+}
+
+impl Visitor for Lowerer<'_> {
+    fn visit_decl(&mut self, decl: &Decl) {
+        self.program.variables.extend(decl.names.iter().cloned());
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        ast::walk_block(self, block);
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Assign { target, value } => {
+                let value = match self.analysis.variables[target] {
+                    Type::Real => self.real_expr(value),
+                    _ => self.expr(value),
+                };
+                self.emit(Instruction::Copy { dest: Var::Named(target.clone()), source: value });
+            }
+            StmtKind::Print { items, newline } => {
+                for item in items {
+                    self.visit_print_item(item);
+                }
+                if *newline {
+                    self.emit(Instruction::PrintNewline);
+                }
+            }
+            StmtKind::Compound(block) => self.visit_block(block),
+            StmtKind::If { condition, then_branch, else_branch } => {
+                let then_block = self.new_block();
+                let else_block = else_branch.as_ref().map(|_| self.new_block());
+                let join = self.new_block();
+                self.branch(condition, then_block, else_block.unwrap_or(join));
+                self.stmt_then_jump(then_branch, then_block, join);
+                if let (Some(else_branch), Some(else_block)) = (else_branch, else_block) {
+                    self.stmt_then_jump(else_branch, else_block, join);
+                }
+                self.switch_to(join);
+            }
+            StmtKind::While { condition, body } => {
+                let (header, body_block, exit) = (self.new_block(), self.new_block(), self.new_block());
+                self.terminate(Terminator::Jump(header));
+                self.switch_to(header);
+                self.branch(condition, body_block, exit);
+                self.stmt_then_jump(body, body_block, header);
+                self.switch_to(exit);
+            }
+            StmtKind::For { variable, start, end, descending, body } => {
+                self.for_loop(variable, start, end, *descending, body)
+            }
+            StmtKind::Exit => {
+                // Anything after it goes into a block nothing jumps to
+                self.terminate(Terminator::Exit);
+                let unreachable = self.new_block();
+                self.switch_to(unreachable);
+            }
+        }
+    }
+
+    fn visit_print_item(&mut self, item: &PrintItem) {
+        let width = item.width.as_ref().map(|width| self.expr(width));
+        let value = match &item.value.kind {
+            ExprKind::String(text) => {
+                self.program.strings.push(text.clone());
+                PrintValue::String(self.program.strings.len() - 1)
+            }
+            _ => match self.analysis.type_of(&item.value) {
+                Type::Boolean => PrintValue::Boolean(self.expr(&item.value)),
+                Type::Real => {
+                    let precision = item.precision.as_ref().map(|precision| self.expr(precision));
+                    PrintValue::Real(self.expr(&item.value), precision)
+                }
+                Type::Integer | Type::String => PrintValue::Integer(self.expr(&item.value)),
+            },
+        };
+        self.emit(Instruction::Print { value, width });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::lower_source;
+
+    #[test]
+    fn test_lower_straight_line_code() {
+        let ir = lower_source("var a, b: integer; begin a := 2 * (b + 1); writeln('a=', a:b, a > b); end").to_string();
+
+        let expected = "\
+var a, b
+str0 = \"a=\"
+
+block0:
+    %0 = b + 1
+    %1 = 2 * %0
+    a = %1
+    print_str str0
+    print_int a width b
+    %2 = a > b
+    print_bool %2
+    print_newline
+    exit
+";
+        assert_eq!(ir, expected);
+    }
+
+    #[test]
+    fn test_lower_if_and_while() {
+        let ir = lower_source(
+            "var i: integer; begin while (i < 10) and not (i = 5) do i := i + 1; \
+             if i = 5 then write else writeln; end",
+        )
+        .to_string();
+
+        let expected = "\
+var i
+
+block0:
+    jump block1
+
+block1:
+    %0 = i < 10
+    branch %0, block2, block4
+
+block2:
+    %1 = i = 5
+    branch %1, block4, block3
+
+block3:
+    %2 = i + 1
+    i = %2
+    jump block1
+
+block4:
+    %3 = i = 5
+    branch %3, block5, block6
+
+block5:
+    jump block7
+
+block6:
+    print_newline
+    jump block7
+
+block7:
+    exit
+";
+        assert_eq!(ir, expected);
+    }
+}
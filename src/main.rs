@@ -1,34 +1,278 @@
-mod lexer;
-mod parser;
-mod ast;
-mod codegen;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::ExitCode;
 
+use ccc::asm::Syntax;
+use ccc::json::ToJson;
+use ccc::toolchain::{assemble, link, write_executable, write_object};
+use ccc::{compile, dump, format, Options, Output, Target};
 
-use codegen::generate_code;
+const USAGE: &str = "usage: ccc [--emit=tokens|ast|ast-json|ir|asm|obj|exe] [-O0|-O1|-O2] [-o OUTPUT] [FILE]
+           [--target=x86_64-linux|aarch64-linux|riscv64-linux|c|wasm] [--asm-syntax=nasm|gas]
+           [--assembler=builtin|external]
+       ccc fmt [--check] [FILE...]";
 
-use crate::lexer::tokenize;
-use crate::parser::Parser;
+#[derive(Clone, Copy, PartialEq)]
+enum Emit {
+    Tokens,
+    Ast,
+    AstJson,
+    Ir,
+    Asm,
+    Obj,
+    Exe,
+}
 
+// How --emit=obj and --emit=exe are built: by the compiler itself, or with
+// nasm or as, depending on the syntax, and ld. Only x86-64 code can be built
+// in, so other targets default to the external tools.
+#[derive(Clone, Copy, PartialEq)]
+enum Assembler {
+    Builtin,
+    External,
+}
 
-fn main() {
-    let source_code = r#"
-        var
-            a, b: integer;
-            result: integer;
+struct Arguments {
+    emit: Emit,
+    assembler: Option<Assembler>,
+    options: Options,
+    output: Option<PathBuf>,
+    input: Option<String>,
+}
 
-        begin
-            a := 10;
-            b := 20;
-            result := a + b;
-            writeln('The result is: ', result);
-        end
-    "#;
+fn parse_arguments(args: impl Iterator<Item = String>) -> Result<Arguments, String> {
+    let mut arguments =
+        Arguments { emit: Emit::Asm, assembler: None, options: Options::default(), output: None, input: None };
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        if let Some(kind) = arg.strip_prefix("--emit=") {
+            arguments.emit = match kind {
+                "tokens" => Emit::Tokens,
+                "ast" => Emit::Ast,
+                "ast-json" => Emit::AstJson,
+                "ir" => Emit::Ir,
+                "asm" => Emit::Asm,
+                "obj" => Emit::Obj,
+                "exe" => Emit::Exe,
+                _ => return Err(format!("unknown --emit kind '{}'", kind)),
+            };
+        } else if let Some(target) = arg.strip_prefix("--target=") {
+            arguments.options.target = match target {
+                "x86_64-linux" => Target::X86_64Linux,
+                "aarch64-linux" => Target::Aarch64Linux,
+                "riscv64-linux" => Target::Riscv64Linux,
+                "c" => Target::C,
+                "wasm" => Target::Wasm,
+                _ => return Err(format!("unknown target '{}'", target)),
+            };
+        } else if let Some(syntax) = arg.strip_prefix("--asm-syntax=") {
+            arguments.options.asm_syntax = match syntax {
+                "nasm" => Syntax::Nasm,
+                "gas" => Syntax::Gas,
+                _ => return Err(format!("unknown assembler syntax '{}'", syntax)),
+            };
+        } else if let Some(assembler) = arg.strip_prefix("--assembler=") {
+            arguments.assembler = Some(match assembler {
+                "builtin" => Assembler::Builtin,
+                "external" => Assembler::External,
+                _ => return Err(format!("unknown assembler '{}'", assembler)),
+            });
+        } else if let Some(level) = arg.strip_prefix("-O") {
+            arguments.options.opt_level = match level {
+                "0" => 0,
+                "1" => 1,
+                "2" => 2,
+                _ => return Err(format!("unknown optimisation level '{}'", arg)),
+            };
+        } else if arg == "-o" {
+            arguments.output = Some(args.next().ok_or("-o needs a file name")?.into());
+        } else if arg.starts_with('-') && arg != "-" {
+            return Err(format!("unknown option '{}'", arg));
+        } else if arguments.input.is_none() {
+            arguments.input = Some(arg);
+        } else {
+            return Err("only one input file can be given".to_string());
+        }
+    }
+    if arguments.options.target != Target::X86_64Linux {
+        if arguments.assembler == Some(Assembler::Builtin) {
+            return Err("the builtin assembler only supports x86_64-linux".to_string());
+        }
+        if arguments.options.asm_syntax != Syntax::default() {
+            return Err("--asm-syntax only applies to x86_64-linux".to_string());
+        }
+    }
+    arguments.options.source_name = arguments.input.clone().filter(|path| path != "-");
+    Ok(arguments)
+}
 
-    let tokens = tokenize(source_code);
-//    println!("{:?}", tokens); // Debugging: Print the tokens
-    let mut parser = Parser::new(tokens);
-    let ast = parser.parse().unwrap();
-    let asm = generate_code(ast);
-    println!("{}", asm); // Print the generated assembly code
+// Compiles the file given as argument, or standard input when there is none,
+// and writes the stage selected with --emit (assembly by default).
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1).peekable();
+    if args.peek().is_some_and(|arg| arg == "fmt") {
+        return format_files(args.skip(1));
+    }
+    let arguments = match parse_arguments(args) {
+        Ok(arguments) => arguments,
+        Err(error) => {
+            eprintln!("error: {}\n{}", error, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
 
-}
\ No newline at end of file
+    let Some(source_code) = read_source(arguments.input.as_deref()) else {
+        return ExitCode::FAILURE;
+    };
+
+    let name = arguments.input.as_deref().unwrap_or("<stdin>");
+    let output = match compile(&source_code, &arguments.options) {
+        Ok(output) => output,
+        Err(diagnostics) => {
+            for diagnostic in diagnostics {
+                eprintln!("{}:{}", name, diagnostic);
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+    for warning in &output.warnings {
+        eprintln!("{}:{}", name, warning);
+    }
+
+    let text = match arguments.emit {
+        Emit::Tokens => dump::format_tokens(&output.tokens),
+        Emit::Ast => dump::format_ast(&output.ast),
+        Emit::AstJson => output.ast.to_json().to_pretty_string() + "\n",
+        Emit::Ir => output.ir.to_string(),
+        Emit::Asm => output.assembly,
+        Emit::Obj | Emit::Exe => return build(&output, &arguments),
+    };
+    match &arguments.output {
+        Some(path) => {
+            if let Err(error) = std::fs::write(path, text) {
+                eprintln!("error: cannot write {}: {}", path.display(), error);
+                return ExitCode::FAILURE;
+            }
+        }
+        None => print!("{}", text),
+    }
+    ExitCode::SUCCESS
+}
+
+// Reads the file at `path`, or standard input for `None` and "-". Errors are
+// reported here.
+fn read_source(path: Option<&str>) -> Option<String> {
+    match path {
+        Some(path) if path != "-" => match std::fs::read_to_string(path) {
+            Ok(source) => Some(source),
+            Err(error) => {
+                eprintln!("error: cannot read {}: {}", path, error);
+                None
+            }
+        },
+        _ => {
+            let mut source = String::new();
+            if let Err(error) = std::io::stdin().read_to_string(&mut source) {
+                eprintln!("error: cannot read standard input: {}", error);
+                return None;
+            }
+            Some(source)
+        }
+    }
+}
+
+// `ccc fmt`: rewrites each file in canonical layout, or prints standard input
+// formatted. With --check nothing is written; the files that would change are
+// listed and the exit status is non-zero.
+fn format_files(args: impl Iterator<Item = String>) -> ExitCode {
+    let mut check = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        if arg == "--check" {
+            check = true;
+        } else if arg.starts_with('-') && arg != "-" {
+            eprintln!("error: unknown option '{}'\n{}", arg, USAGE);
+            return ExitCode::FAILURE;
+        } else {
+            paths.push(arg);
+        }
+    }
+    if paths.is_empty() {
+        paths.push("-".to_string());
+    }
+
+    let mut success = true;
+    for path in &paths {
+        let Some(source) = read_source(Some(path)) else {
+            success = false;
+            continue;
+        };
+        let name = if path == "-" { "<stdin>" } else { path };
+        let formatted = match format::format_source(&source) {
+            Ok(formatted) => formatted,
+            Err(diagnostic) => {
+                eprintln!("{}:{}", name, diagnostic);
+                success = false;
+                continue;
+            }
+        };
+        if check {
+            if formatted != source {
+                println!("{}", name);
+                success = false;
+            }
+        } else if path == "-" {
+            print!("{}", formatted);
+        } else if formatted != source
+            && let Err(error) = std::fs::write(path, formatted)
+        {
+            eprintln!("error: cannot write {}: {}", path, error);
+            success = false;
+        }
+    }
+    if success {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+// Writes the object file or executable, built in or with nasm or as and ld
+// as selected by --assembler and --asm-syntax.
+fn build(output: &Output, arguments: &Arguments) -> ExitCode {
+    let stem = arguments
+        .input
+        .as_deref()
+        .filter(|path| *path != "-")
+        .and_then(|path| PathBuf::from(path).file_stem().map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from("a"));
+    // A WebAssembly module is both, and is named like one by default
+    let wasm = arguments.options.target == Target::Wasm;
+    let object = match (&arguments.output, arguments.emit) {
+        (Some(path), Emit::Obj) => path.clone(),
+        _ => stem.with_extension(if wasm { "wasm" } else { "o" }),
+    };
+    let executable = arguments.output.clone().unwrap_or_else(|| PathBuf::from(if wasm { "a.wasm" } else { "a.out" }));
+
+    let Options { target, asm_syntax, .. } = arguments.options;
+    let result = match (&output.code, arguments.assembler, arguments.emit) {
+        (Some(code), None | Some(Assembler::Builtin), Emit::Exe) => write_executable(code, &executable),
+        (Some(code), None | Some(Assembler::Builtin), _) => write_object(code, &object),
+        (_, _, emit) => assemble(&output.assembly, target, asm_syntax, &object).and_then(|()| {
+            if emit == Emit::Exe {
+                let linked = link(&object, target, &executable);
+                let _ = std::fs::remove_file(&object);
+                linked
+            } else {
+                Ok(())
+            }
+        }),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
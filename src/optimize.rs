@@ -0,0 +1,121 @@
+//! Optimisation passes over the IR. `-O0` leaves the program as lowered,
+//! `-O1` enables the scalar passes below and `-O2` adds the loop passes.
+
+mod cse;
+pub mod dataflow;
+mod dce;
+mod fold;
+pub mod loops;
+mod propagate;
+
+use crate::ir::Program;
+
+/// Runs the passes enabled at `level` (0 to 2) on `program`.
+pub fn optimize(program: &mut Program, level: u8) {
+    if level >= 1 {
+        simplify(program, 4);
+    }
+    if level >= 2 {
+        loops::unroll_loops(program);
+        loops::hoist_invariants(program);
+        loops::reduce_induction_variables(program);
+        // An unrolled loop folds one copy of its body per round
+        simplify(program, 16);
+    }
+}
+
+// Runs the scalar passes for up to `rounds` rounds. Each pass can expose more
+// work for the others: a propagated constant folds, a folded branch leaves
+// code unreachable. A few rounds are enough in practice.
+fn simplify(program: &mut Program, rounds: usize) {
+    for _ in 0..rounds {
+        let before = program.clone();
+        fold::fold_constants(program);
+        propagate::propagate(program);
+        cse::eliminate_common_subexpressions(program);
+        propagate::propagate(program);
+        dce::eliminate_dead_code(program);
+        if *program == before {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::optimize::loops::find_loops;
+    use crate::testing::{run_builtin_status, run_with_options};
+    use crate::{compile, Options};
+
+    fn options(opt_level: u8) -> Options {
+        Options { opt_level, ..Options::default() }
+    }
+
+    #[test]
+    fn test_o1_keeps_results_and_shrinks_code() {
+        let source = "
+            var a, x, y: integer;
+            begin
+                x := -37;
+                a := 2 * 3 + x * 1 - 0;
+                y := a * 8 + (x + 0) * 0;
+                writeln(a, ' ', y, ' ', x div 4, ' ', x mod 4, ' ', x rem 4, ' ', x / 8, ' ', 100 div 8);
+                if 3 * 4 > 10 then writeln('folded') else writeln('dead');
+            end
+        ";
+
+        let lines = |level| compile(source, &options(level)).unwrap().assembly.lines().count();
+        assert!(lines(1) < lines(0), "{} >= {}", lines(1), lines(0));
+
+        // The runtime, which has an idiv of its own, starts at runtime_error
+        let division = "var x: integer; begin x := x div 8 + x * 4; end";
+        let code = |level| {
+            compile(division, &options(level)).unwrap().assembly.split("runtime_error:").next().unwrap().to_string()
+        };
+        assert!(code(0).contains("idiv"));
+        let reduced = code(1);
+        assert!(!reduced.contains("idiv") && !reduced.contains("imul"), "{}", reduced);
+
+        let stdout = run_with_options(source, &options(0));
+        assert_eq!(String::from_utf8(stdout.clone()).unwrap(), "-31 -248 -9 3 -1 -4 12\nfolded\n");
+        assert_eq!(run_with_options(source, &options(1)), stdout);
+    }
+
+    #[test]
+    fn test_o2_loop_optimisations_keep_results() {
+        let source = "
+            var i, j, n, scale, total, squares: integer;
+            begin
+                for i := 1 to 10 do n := n + i;
+                scale := n div 5;
+                for i := 1 to n do begin
+                    total := total + i * 7 + scale * n;
+                    for j := 1 to 3 do squares := squares + j * j;
+                end;
+                for i := n downto 50 do total := total - i * scale;
+                for i := 2 to 1 do writeln('never');
+                writeln(n, ' ', scale, ' ', total, ' ', squares, ' ', i, ' ', j);
+            end
+        ";
+
+        let ir = compile(source, &options(2)).unwrap().ir;
+        // The inner loop is unrolled and the products of counters are sums
+        assert_eq!(find_loops(&ir).len(), 3, "{}", ir);
+        assert!(!ir.to_string().contains("i * 7"), "{}", ir);
+
+        let stdout = run_with_options(source, &options(0));
+        assert_eq!(String::from_utf8(stdout.clone()).unwrap(), "55 11 40590 770 2 3\n");
+        assert_eq!(run_with_options(source, &options(1)), stdout);
+        assert_eq!(run_with_options(source, &options(2)), stdout);
+    }
+
+    #[test]
+    fn test_optimisations_keep_divisions_by_zero() {
+        let source = "var a, b, c: integer; begin b := 0; c := (a div b) * 0; writeln(c); end";
+
+        for opt_level in [0, 1, 2] {
+            let status = run_builtin_status(source, &options(opt_level));
+            assert_eq!(status.code(), Some(1), "-O{} exited with {}", opt_level, status);
+        }
+    }
+}
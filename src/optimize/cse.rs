@@ -0,0 +1,72 @@
+//! Common subexpression elimination, within and across blocks.
+//!
+//! An expression that was already computed on every path to an instruction,
+//! into a variable that still holds it, is replaced by a copy of that
+//! variable. Copy propagation and dead code elimination then remove the copy.
+
+use crate::ir::{BlockId, Instruction, Operand, Program};
+use crate::optimize::dataflow::{solve, AvailableExpressions, Expression, Location, Problem};
+
+pub fn eliminate_common_subexpressions(program: &mut Program) {
+    let solution = solve(program, &AvailableExpressions);
+    for (block, code) in program.blocks.iter_mut().enumerate() {
+        let mut available = solution.entry[block].clone();
+        for (index, instruction) in code.instructions.iter_mut().enumerate() {
+            if let Some(expression) = Expression::of(instruction) {
+                // The lowest numbered holder, so the result does not depend
+                // on the order of the set
+                let holder = available.iter().filter(|(other, _)| *other == expression).map(|(_, holder)| holder).min();
+                if let (Some(holder), Some(dest)) = (holder, instruction.dest()) {
+                    *instruction = Instruction::Copy { dest: dest.clone(), source: Operand::Var(holder.clone()) };
+                }
+            }
+            AvailableExpressions.instruction(&mut available, instruction, Location { block: BlockId(block), index });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::lower_source;
+
+    fn eliminate(source: &str) -> String {
+        let mut ir = lower_source(source);
+        eliminate_common_subexpressions(&mut ir);
+        ir.to_string()
+    }
+
+    #[test]
+    fn test_eliminate_common_subexpressions() {
+        let ir = eliminate("var a, b, c, x, y: integer; begin x := a * b + c; y := b * a - c; a := 1; y := a * b; end");
+
+        let expected = "\
+var a, b, c, x, y
+
+block0:
+    %0 = a * b
+    %1 = %0 + c
+    x = %1
+    %2 = %0
+    %3 = %2 - c
+    y = %3
+    a = 1
+    %4 = a * b
+    y = %4
+    exit
+";
+        assert_eq!(ir, expected);
+    }
+
+    #[test]
+    fn test_eliminate_across_blocks_only_when_available_on_every_path() {
+        let ir = eliminate(
+            "var a, b, x, y: integer; begin x := a + b; if x > 0 then y := a + b else a := 2; y := a + b; end",
+        );
+
+        // Available in the then branch, but not after the join since the
+        // else branch assigns a
+        assert_eq!(ir.matches("a + b").count(), 2, "{}", ir);
+        assert!(ir.contains("= %0\n"), "{}", ir);
+    }
+}
@@ -0,0 +1,401 @@
+//! A framework for dataflow analyses over the IR, and the analyses the
+//! passes share.
+//!
+//! An analysis is a [`Problem`]: a set of facts per program point, how one
+//! instruction changes them, and how the facts of several control-flow edges
+//! are combined. [`solve`] iterates the problem to a fixed point and returns
+//! the facts at the start and end of every block; a pass that needs them at
+//! each instruction replays the transfer functions through the block.
+
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use crate::ir::{BinaryOperator, BlockId, Instruction, Operand, Program, Terminator, UnaryOperator, Var};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// How the facts of several edges are combined where they meet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Meet {
+    /// A fact holds if it holds on any edge ("may" problems).
+    Union,
+    /// A fact holds only if it holds on every edge ("must" problems).
+    Intersection,
+}
+
+/// The position of an instruction in the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Location {
+    pub block: BlockId,
+    pub index: usize,
+}
+
+pub trait Problem {
+    type Fact: Clone + Eq + Hash;
+    const DIRECTION: Direction;
+    const MEET: Meet;
+
+    /// The facts on entry to the program, or on exit for backward problems.
+    fn boundary(&self, _program: &Program) -> HashSet<Self::Fact> {
+        HashSet::new()
+    }
+
+    /// Every fact that can hold; the starting point of intersection problems.
+    fn universe(&self, _program: &Program) -> HashSet<Self::Fact> {
+        HashSet::new()
+    }
+
+    /// Updates `facts` across `instruction`, in the direction of the problem.
+    fn instruction(&self, facts: &mut HashSet<Self::Fact>, instruction: &Instruction, location: Location);
+
+    /// Updates `facts` across the terminator of a block.
+    fn terminator(&self, _facts: &mut HashSet<Self::Fact>, _terminator: &Terminator) {}
+}
+
+/// The facts at the start and at the end of every block, indexed by block.
+#[derive(Debug)]
+pub struct Solution<F> {
+    pub entry: Vec<HashSet<F>>,
+    pub exit: Vec<HashSet<F>>,
+}
+
+pub fn solve<P: Problem>(program: &Program, problem: &P) -> Solution<P::Fact> {
+    let top = match P::MEET {
+        Meet::Union => HashSet::new(),
+        Meet::Intersection => problem.universe(program),
+    };
+    let count = program.blocks.len();
+    let mut solution = Solution { entry: vec![top.clone(); count], exit: vec![top.clone(); count] };
+    let boundary = problem.boundary(program);
+    let predecessors = program.predecessors();
+
+    // Visiting the blocks in reverse postorder, or its reverse for backward
+    // problems, lets most facts settle in a single sweep. Unreachable blocks
+    // come last so they get facts too.
+    let mut order = program.reverse_postorder();
+    let reachable: HashSet<BlockId> = order.iter().copied().collect();
+    order.extend((0..count).map(BlockId).filter(|block| !reachable.contains(block)));
+    if P::DIRECTION == Direction::Backward {
+        order.reverse();
+    }
+
+    let meet = |sets: Vec<&HashSet<P::Fact>>| -> HashSet<P::Fact> {
+        let mut sets = sets.into_iter();
+        let Some(first) = sets.next() else { return top.clone() };
+        let mut result = first.clone();
+        for set in sets {
+            match P::MEET {
+                Meet::Union => result.extend(set.iter().cloned()),
+                Meet::Intersection => result.retain(|fact| set.contains(fact)),
+            }
+        }
+        result
+    };
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &block in &order {
+            let code = &program.blocks[block.0];
+            match P::DIRECTION {
+                Direction::Forward => {
+                    let mut incoming: Vec<_> = predecessors[block.0].iter().map(|p| &solution.exit[p.0]).collect();
+                    if block == BlockId(0) {
+                        incoming.push(&boundary);
+                    }
+                    let mut facts = meet(incoming);
+                    solution.entry[block.0] = facts.clone();
+                    for (index, instruction) in code.instructions.iter().enumerate() {
+                        problem.instruction(&mut facts, instruction, Location { block, index });
+                    }
+                    problem.terminator(&mut facts, &code.terminator);
+                    if facts != solution.exit[block.0] {
+                        solution.exit[block.0] = facts;
+                        changed = true;
+                    }
+                }
+                Direction::Backward => {
+                    let successors = code.terminator.successors();
+                    let mut facts = if successors.is_empty() {
+                        boundary.clone()
+                    } else {
+                        meet(successors.iter().map(|s| &solution.entry[s.0]).collect())
+                    };
+                    solution.exit[block.0] = facts.clone();
+                    problem.terminator(&mut facts, &code.terminator);
+                    for (index, instruction) in code.instructions.iter().enumerate().rev() {
+                        problem.instruction(&mut facts, instruction, Location { block, index });
+                    }
+                    if facts != solution.entry[block.0] {
+                        solution.entry[block.0] = facts;
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+    solution
+}
+
+fn read_vars(instruction: &Instruction) -> impl Iterator<Item = &Var> {
+    instruction.operands().into_iter().filter_map(|operand| match operand {
+        Operand::Var(var) => Some(var),
+        Operand::Constant(_) => None,
+    })
+}
+
+/// The variables whose current value may still be read. Solved backward, so
+/// the entry facts of a block are its live-in variables.
+pub struct Liveness;
+
+impl Problem for Liveness {
+    type Fact = Var;
+    const DIRECTION: Direction = Direction::Backward;
+    const MEET: Meet = Meet::Union;
+
+    fn instruction(&self, facts: &mut HashSet<Var>, instruction: &Instruction, _location: Location) {
+        if let Some(dest) = instruction.dest() {
+            facts.remove(dest);
+        }
+        facts.extend(read_vars(instruction).cloned());
+    }
+
+    fn terminator(&self, facts: &mut HashSet<Var>, terminator: &Terminator) {
+        if let Some(Operand::Var(var)) = terminator.operand() {
+            facts.insert(var.clone());
+        }
+    }
+}
+
+/// Where the value a variable holds may have been assigned.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Definition {
+    /// The zero a named variable starts with.
+    Initial,
+    At(Location),
+}
+
+/// The definitions that may reach a point without being overwritten, as
+/// pairs of the variable and where it was assigned.
+pub struct ReachingDefinitions;
+
+impl Problem for ReachingDefinitions {
+    type Fact = (Var, Definition);
+    const DIRECTION: Direction = Direction::Forward;
+    const MEET: Meet = Meet::Union;
+
+    fn boundary(&self, program: &Program) -> HashSet<Self::Fact> {
+        program.variables.iter().map(|name| (Var::Named(name.clone()), Definition::Initial)).collect()
+    }
+
+    fn instruction(&self, facts: &mut HashSet<Self::Fact>, instruction: &Instruction, location: Location) {
+        if let Some(dest) = instruction.dest() {
+            facts.retain(|(var, _)| var != dest);
+            facts.insert((dest.clone(), Definition::At(location)));
+        }
+    }
+}
+
+/// A computation whose result can be reused. Commutative operators have
+/// their operands in a fixed order, so `a * b` and `b * a` are the same.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Expression {
+    Unary(UnaryOperator, Operand),
+    Binary(BinaryOperator, Operand, Operand),
+}
+
+impl Expression {
+    /// The expression `instruction` computes, if it is not a plain copy.
+    pub fn of(instruction: &Instruction) -> Option<Expression> {
+        use BinaryOperator::*;
+
+        match instruction {
+            Instruction::Unary { operator, operand, .. } => Some(Expression::Unary(*operator, operand.clone())),
+            Instruction::Binary { operator, left, right, .. } => {
+                let commutative = matches!(
+                    operator,
+                    Add | Multiply | Equal | NotEqual | And | Or | RealAdd | RealMultiply | RealEqual | RealNotEqual
+                );
+                let (left, right) = if commutative && right < left { (right, left) } else { (left, right) };
+                Some(Expression::Binary(*operator, left.clone(), right.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    fn reads(&self, var: &Var) -> bool {
+        let read = |operand: &Operand| matches!(operand, Operand::Var(read) if read == var);
+        match self {
+            Expression::Unary(_, operand) => read(operand),
+            Expression::Binary(_, left, right) => read(left) || read(right),
+        }
+    }
+}
+
+/// The expressions computed on every path to a point, each with a variable
+/// that still holds its value.
+pub struct AvailableExpressions;
+
+impl Problem for AvailableExpressions {
+    type Fact = (Expression, Var);
+    const DIRECTION: Direction = Direction::Forward;
+    const MEET: Meet = Meet::Intersection;
+
+    fn universe(&self, program: &Program) -> HashSet<Self::Fact> {
+        let instructions = program.blocks.iter().flat_map(|block| &block.instructions);
+        instructions
+            .filter_map(|instruction| Some((Expression::of(instruction)?, instruction.dest()?.clone())))
+            .collect()
+    }
+
+    fn instruction(&self, facts: &mut HashSet<Self::Fact>, instruction: &Instruction, _location: Location) {
+        let Some(dest) = instruction.dest() else { return };
+        facts.retain(|(expression, holder)| holder != dest && !expression.reads(dest));
+        if let Some(expression) = Expression::of(instruction)
+            && !expression.reads(dest)
+        {
+            facts.insert((expression, dest.clone()));
+        }
+    }
+}
+
+/// The copies `dest = source` that hold on every path to a point: neither
+/// side has been assigned since.
+pub struct AvailableCopies;
+
+impl Problem for AvailableCopies {
+    type Fact = (Var, Operand);
+    const DIRECTION: Direction = Direction::Forward;
+    const MEET: Meet = Meet::Intersection;
+
+    fn universe(&self, program: &Program) -> HashSet<Self::Fact> {
+        let instructions = program.blocks.iter().flat_map(|block| &block.instructions);
+        instructions
+            .filter_map(|instruction| match instruction {
+                Instruction::Copy { dest, source } => Some((dest.clone(), source.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn instruction(&self, facts: &mut HashSet<Self::Fact>, instruction: &Instruction, _location: Location) {
+        let Some(dest) = instruction.dest() else { return };
+        let overwritten = Operand::Var(dest.clone());
+        facts.retain(|(var, source)| var != dest && *source != overwritten);
+        if let Instruction::Copy { dest, source } = instruction
+            && *source != overwritten
+        {
+            facts.insert((dest.clone(), source.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{BasicBlock, PrintValue};
+
+    fn var(name: &str) -> Var {
+        Var::Named(name.to_string())
+    }
+
+    fn read(name: &str) -> Operand {
+        Operand::Var(var(name))
+    }
+
+    fn binary(dest: Var, operator: BinaryOperator, left: Operand, right: Operand) -> Instruction {
+        Instruction::Binary { dest, operator, left, right }
+    }
+
+    // block0: %0 = i < n; branch to block1 (i = i + 1, loop) or block2 (print n)
+    fn counting_loop() -> Program {
+        let block = |instructions, terminator| BasicBlock { instructions, terminator };
+        Program {
+            variables: vec!["i".to_string(), "n".to_string()],
+            blocks: vec![
+                block(
+                    vec![binary(Var::Temp(0), BinaryOperator::Less, read("i"), read("n"))],
+                    Terminator::Branch {
+                        condition: Operand::Var(Var::Temp(0)),
+                        if_true: BlockId(1),
+                        if_false: BlockId(2),
+                    },
+                ),
+                block(
+                    vec![binary(var("i"), BinaryOperator::Add, read("i"), Operand::Constant(1))],
+                    Terminator::Jump(BlockId(0)),
+                ),
+                block(
+                    vec![Instruction::Print { value: PrintValue::Integer(read("n")), width: None }],
+                    Terminator::Exit,
+                ),
+            ],
+            temp_count: 1,
+            ..Program::default()
+        }
+    }
+
+    #[test]
+    fn test_liveness() {
+        let solution = solve(&counting_loop(), &Liveness);
+
+        let vars = |vars: &[Var]| vars.iter().cloned().collect::<HashSet<_>>();
+        assert_eq!(solution.entry[0], vars(&[var("i"), var("n")]));
+        assert_eq!(solution.exit[0], vars(&[var("i"), var("n")]));
+        assert_eq!(solution.exit[1], vars(&[var("i"), var("n")]));
+        assert_eq!(solution.entry[2], vars(&[var("n")]));
+        assert!(solution.exit[2].is_empty());
+    }
+
+    #[test]
+    fn test_reaching_definitions() {
+        let solution = solve(&counting_loop(), &ReachingDefinitions);
+
+        // Both the initial zero of i and its increment reach the loop test
+        let increment = Definition::At(Location { block: BlockId(1), index: 0 });
+        let reaching_i: HashSet<_> =
+            solution.entry[0].iter().filter(|(v, _)| *v == var("i")).map(|(_, d)| d.clone()).collect();
+        assert_eq!(reaching_i, HashSet::from([Definition::Initial, increment.clone()]));
+        assert!(solution.exit[1].contains(&(var("i"), increment)));
+        assert!(!solution.exit[1].contains(&(var("i"), Definition::Initial)));
+        assert!(solution.entry[2].contains(&(var("n"), Definition::Initial)));
+    }
+
+    #[test]
+    fn test_available_expressions_and_copies() {
+        // block0: %0 = a * b; x = %0; branch to block1 (a = 1) or block2,
+        // both continuing at block3
+        let mut program = counting_loop();
+        program.blocks = vec![
+            BasicBlock {
+                instructions: vec![
+                    binary(Var::Temp(0), BinaryOperator::Multiply, read("a"), read("b")),
+                    Instruction::Copy { dest: var("x"), source: Operand::Var(Var::Temp(0)) },
+                ],
+                terminator: Terminator::Branch { condition: read("c"), if_true: BlockId(1), if_false: BlockId(2) },
+            },
+            BasicBlock {
+                instructions: vec![Instruction::Copy { dest: var("a"), source: Operand::Constant(1) }],
+                terminator: Terminator::Jump(BlockId(3)),
+            },
+            BasicBlock { instructions: Vec::new(), terminator: Terminator::Jump(BlockId(3)) },
+            BasicBlock { instructions: Vec::new(), terminator: Terminator::Exit },
+        ];
+
+        let expressions = solve(&program, &AvailableExpressions);
+        let product = (Expression::Binary(BinaryOperator::Multiply, read("a"), read("b")), Var::Temp(0));
+        assert_eq!(Expression::of(&program.blocks[0].instructions[0]), Some(product.0.clone()));
+        assert!(expressions.entry[2].contains(&product));
+        assert!(!expressions.exit[1].contains(&product)); // a was assigned
+        assert!(expressions.entry[3].is_empty());
+
+        let copies = solve(&program, &AvailableCopies);
+        let copy = (var("x"), Operand::Var(Var::Temp(0)));
+        assert!(copies.entry[3].contains(&copy));
+        assert!(!copies.entry[3].contains(&(var("a"), Operand::Constant(1))));
+    }
+}
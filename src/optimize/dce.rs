@@ -0,0 +1,176 @@
+//! Dead code elimination: blocks that cannot be reached, instructions whose
+//! result is never read, and variables that are no longer used at all.
+
+use std::collections::HashSet;
+
+use crate::ir::{from_bits, BinaryOperator, Instruction, Operand, PrintValue, Program, Var};
+use crate::optimize::dataflow::{solve, Liveness};
+
+pub fn eliminate_dead_code(program: &mut Program) {
+    remove_unreachable_blocks(program);
+    remove_dead_stores(program);
+    remove_unused_variables(program);
+    remove_unused_strings(program);
+}
+
+// Drops the blocks that cannot be reached from the entry, keeping the others
+// in their current order.
+fn remove_unreachable_blocks(program: &mut Program) {
+    let mut reachable = program.reverse_postorder();
+    reachable.sort();
+    program.reorder_blocks(&reachable);
+}
+
+// Removes the instructions that write a variable which is not live after
+// them. Removing one can make the writes of its operands dead in turn, in the
+// same block or in a predecessor, so this repeats until nothing changes.
+fn remove_dead_stores(program: &mut Program) {
+    loop {
+        let live_out = solve(program, &Liveness).exit;
+        let mut changed = false;
+        for (block, mut live) in program.blocks.iter_mut().zip(live_out) {
+            if let Some(Operand::Var(var)) = block.terminator.operand() {
+                live.insert(var.clone());
+            }
+            let mut kept = Vec::with_capacity(block.instructions.len());
+            for instruction in std::mem::take(&mut block.instructions).into_iter().rev() {
+                if let Some(dest) = instruction.dest() {
+                    if !live.contains(dest) && !may_trap(&instruction) {
+                        changed = true;
+                        continue;
+                    }
+                    live.remove(dest);
+                }
+                for operand in instruction.operands() {
+                    if let Operand::Var(var) = operand {
+                        live.insert(var.clone());
+                    }
+                }
+                kept.push(instruction);
+            }
+            kept.reverse();
+            block.instructions = kept;
+        }
+        if !changed {
+            return;
+        }
+    }
+}
+
+// Whether the instruction can stop the program, as a division by zero does
+// even if its result is never used.
+pub(super) fn may_trap(instruction: &Instruction) -> bool {
+    use BinaryOperator::*;
+
+    match instruction {
+        Instruction::Binary { operator: Div | Divide | Mod | Rem, right, .. } => {
+            !matches!(right, Operand::Constant(divisor) if *divisor != 0)
+        }
+        Instruction::Binary { operator: RealDivide, right, .. } => {
+            !matches!(right, Operand::Constant(divisor) if from_bits(*divisor) != 0.0)
+        }
+        _ => false,
+    }
+}
+
+// Drops the declared variables that no instruction reads or writes, so they
+// get no storage.
+fn remove_unused_variables(program: &mut Program) {
+    let mut used = HashSet::new();
+    for block in &program.blocks {
+        for instruction in &block.instructions {
+            let read = instruction.operands().into_iter().filter_map(|operand| match operand {
+                Operand::Var(var) => Some(var),
+                Operand::Constant(_) => None,
+            });
+            used.extend(read.chain(instruction.dest()).cloned());
+        }
+        if let Some(Operand::Var(var)) = block.terminator.operand() {
+            used.insert(var.clone());
+        }
+    }
+    program.variables.retain(|name| used.contains(&Var::Named(name.clone())));
+}
+
+// Drops the string literals no print refers to any more and renumbers the
+// others.
+fn remove_unused_strings(program: &mut Program) {
+    let mut new_index = vec![None; program.strings.len()];
+    for block in &program.blocks {
+        for instruction in &block.instructions {
+            if let Instruction::Print { value: PrintValue::String(index), .. } = instruction {
+                new_index[*index] = Some(0);
+            }
+        }
+    }
+    for (kept, index) in new_index.iter_mut().flatten().enumerate() {
+        *index = kept;
+    }
+
+    let strings = std::mem::take(&mut program.strings);
+    program.strings =
+        strings.into_iter().zip(&new_index).filter(|(_, index)| index.is_some()).map(|(string, _)| string).collect();
+    for block in &mut program.blocks {
+        for instruction in &mut block.instructions {
+            if let Instruction::Print { value: PrintValue::String(index), .. } = instruction {
+                *index = new_index[*index].unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimize::fold::fold_constants;
+    use crate::testing::lower_source;
+
+    fn optimize(source: &str) -> String {
+        let mut ir = lower_source(source);
+        fold_constants(&mut ir);
+        eliminate_dead_code(&mut ir);
+        ir.to_string()
+    }
+
+    #[test]
+    fn test_remove_unreachable_blocks_and_unused_variables() {
+        let ir = optimize(
+            "var a, unused, never: integer; begin a := 1; if false then writeln('no', a) else writeln(a + 1, 'yes'); \
+             unused := a * 2; exit; writeln(never, 'never'); end",
+        );
+
+        let expected = "\
+var a
+str0 = \"yes\"
+
+block0:
+    a = 1
+    jump block1
+
+block1:
+    %0 = a + 1
+    print_int %0
+    print_str str0
+    print_newline
+    jump block2
+
+block2:
+    exit
+";
+        assert_eq!(ir, expected);
+    }
+
+    #[test]
+    fn test_remove_dead_stores_across_blocks() {
+        let ir = optimize(
+            "var i, x, y, z: integer; begin x := 5 * i; y := x - 1; \
+             for i := 1 to 3 do z := z + y; x := i div 0; y := i div 2; end",
+        );
+
+        // x and y are only read after the loop through z; the division by
+        // zero stays because it stops the program
+        assert!(ir.contains("var i, x, y, z\n"), "{}", ir);
+        assert!(ir.contains("div 0\n") && !ir.contains("sar"), "{}", ir);
+        assert!(!ir.contains("print"), "{}", ir);
+    }
+}
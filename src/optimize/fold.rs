@@ -0,0 +1,217 @@
+//! Constant folding, algebraic identities and strength reduction.
+//!
+//! Works on one block at a time, tracking which variables hold a known
+//! constant since the start of the block, so `a := 2; b := a * 3` folds as
+//! well. Temporaries that end up unused are removed.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::{BinaryOperator, Instruction, Operand, Program, Terminator, Var};
+use crate::optimize::dce::may_trap;
+
+pub fn fold_constants(program: &mut Program) {
+    for index in 0..program.blocks.len() {
+        let instructions = std::mem::take(&mut program.blocks[index].instructions);
+        // Constants held by any variable, and the values copied into
+        // temporaries, e.g. `x` after `x * 1` became `%1 = x`
+        let mut known: HashMap<Var, Operand> = HashMap::new();
+        let mut folded = Vec::with_capacity(instructions.len());
+        for mut instruction in instructions {
+            substitute(instruction.operands_mut(), &known);
+            for instruction in simplify(instruction, &mut program.temp_count) {
+                if let Some(dest) = instruction.dest() {
+                    let overwritten = Operand::Var(dest.clone());
+                    known.retain(|var, value| var != dest && *value != overwritten);
+                }
+                if let Instruction::Copy { dest, source } = &instruction
+                    && (matches!(source, Operand::Constant(_)) || matches!(dest, Var::Temp(_)))
+                {
+                    known.insert(dest.clone(), source.clone());
+                }
+                folded.push(instruction);
+            }
+        }
+
+        let block = &mut program.blocks[index];
+        block.instructions = folded;
+        substitute(block.terminator.operand_mut().into_iter().collect(), &known);
+        if let Terminator::Branch { condition: Operand::Constant(value), if_true, if_false } = block.terminator {
+            block.terminator = Terminator::Jump(if value != 0 { if_true } else { if_false });
+        }
+    }
+    remove_unused_temps(program);
+}
+
+fn substitute(operands: Vec<&mut Operand>, known: &HashMap<Var, Operand>) {
+    for operand in operands {
+        if let Operand::Var(var) = operand
+            && let Some(value) = known.get(var)
+        {
+            *operand = value.clone();
+        }
+    }
+}
+
+// Drops the instructions that write a temporary nobody reads, unless they
+// can trap: `(a div b) * 0` still fails when b is zero. Removing one can
+// leave another temporary unread, so this repeats until nothing changes.
+fn remove_unused_temps(program: &mut Program) {
+    loop {
+        let mut used = HashSet::new();
+        for block in &program.blocks {
+            let operands = block.instructions.iter().flat_map(|instruction| instruction.operands());
+            for operand in operands.chain(block.terminator.operand()) {
+                if let Operand::Var(var @ Var::Temp(_)) = operand {
+                    used.insert(var.clone());
+                }
+            }
+        }
+        let mut changed = false;
+        for block in &mut program.blocks {
+            block.instructions.retain(|instruction| match instruction.dest() {
+                Some(var @ Var::Temp(_)) if !used.contains(var) && !may_trap(instruction) => {
+                    changed = true;
+                    false
+                }
+                _ => true,
+            });
+        }
+        if !changed {
+            return;
+        }
+    }
+}
+
+// Returns the instructions that replace `instruction`: usually one, several
+// for a division by a power of two.
+fn simplify(instruction: Instruction, temp_count: &mut u32) -> Vec<Instruction> {
+    use BinaryOperator::*;
+    use Operand::Constant;
+
+    let (dest, operator, left, right) = match instruction {
+        Instruction::Unary { dest, operator, operand: Constant(value) } => {
+            return vec![Instruction::Copy { dest, source: Constant(operator.apply(value)) }];
+        }
+        Instruction::Binary { dest, operator, left, right } => (dest, operator, left, right),
+        other => return vec![other],
+    };
+    let copy = |source| vec![Instruction::Copy { dest: dest.clone(), source }];
+    let binary = |operator, left, right| vec![Instruction::Binary { dest: dest.clone(), operator, left, right }];
+
+    match (operator, &left, &right) {
+        // Division by zero is left to fail at run time
+        (_, Constant(l), Constant(r)) => match operator.apply(*l, *r) {
+            Some(value) => copy(Constant(value)),
+            None => binary(operator, left, right),
+        },
+        (Add, x, Constant(0)) | (Add, Constant(0), x) | (Subtract, x, Constant(0)) => copy(x.clone()),
+        (Multiply, x, Constant(1)) | (Multiply, Constant(1), x) => copy(x.clone()),
+        (Multiply, _, Constant(0)) | (Multiply, Constant(0), _) => copy(Constant(0)),
+        (Div | Divide, x, Constant(1)) => copy(x.clone()),
+        (Mod | Rem, _, Constant(1)) => copy(Constant(0)),
+        // The same variable on both sides
+        (Subtract | NotEqual | Less | Greater, Operand::Var(x), Operand::Var(y)) if x == y => copy(Constant(0)),
+        (Equal | LessEqual | GreaterEqual, Operand::Var(x), Operand::Var(y)) if x == y => copy(Constant(1)),
+        (Multiply, x, Constant(n)) | (Multiply, Constant(n), x) if is_power_of_two(*n) => {
+            binary(ShiftLeft, x.clone(), Constant(n.trailing_zeros() as i64))
+        }
+        // The floored remainder by a positive power of two is just its low bits
+        (Mod, x, Constant(n)) if is_power_of_two(*n) => binary(And, x.clone(), Constant(n - 1)),
+        (Div | Divide, x, Constant(n)) if is_power_of_two(*n) => {
+            // An arithmetic shift rounds toward minus infinity, so negative
+            // dividends are biased by n - 1 first to truncate toward zero:
+            // sign = x sar 63; bias = sign and (n - 1); dest = (x + bias) sar k
+            let mut temp = || {
+                *temp_count += 1;
+                Var::Temp(*temp_count - 1)
+            };
+            let (sign, bias, biased) = (temp(), temp(), temp());
+            let var = |var: &Var| Operand::Var(var.clone());
+            vec![
+                Instruction::Binary { dest: sign.clone(), operator: ShiftRight, left: x.clone(), right: Constant(63) },
+                Instruction::Binary { dest: bias.clone(), operator: And, left: var(&sign), right: Constant(n - 1) },
+                Instruction::Binary { dest: biased.clone(), operator: Add, left: x.clone(), right: var(&bias) },
+                Instruction::Binary {
+                    dest: dest.clone(),
+                    operator: ShiftRight,
+                    left: var(&biased),
+                    right: Constant(n.trailing_zeros() as i64),
+                },
+            ]
+        }
+        _ => binary(operator, left, right),
+    }
+}
+
+// Powers of two from 2 up; 1 is handled by the identities.
+fn is_power_of_two(value: i64) -> bool {
+    value > 1 && value & (value - 1) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::lower_source;
+
+    fn fold(source: &str) -> String {
+        let mut ir = lower_source(source);
+        fold_constants(&mut ir);
+        ir.to_string()
+    }
+
+    #[test]
+    fn test_fold_constants_and_identities() {
+        let ir = fold(
+            "var a, x: integer; begin a := 2 * 3 + x * 1; x := -a + (x - 0) * 0; a := 7 div 0; \
+             writeln(a + 0:4 * 2); end",
+        );
+
+        let expected = "\
+var a, x
+
+block0:
+    %2 = 6 + x
+    a = %2
+    %3 = -a
+    x = %3
+    %7 = 7 div 0
+    a = %7
+    print_int a width 8
+    print_newline
+    exit
+";
+        assert_eq!(ir, expected);
+    }
+
+    #[test]
+    fn test_fold_branches_and_constants_across_instructions() {
+        let ir = fold("var a: integer; begin a := 4; if a * 2 > 7 then a := a - 1 else a := 0; end");
+
+        // The else branch is now unreachable but still there
+        assert!(ir.contains("block0:\n    a = 4\n    jump block1\n"), "{}", ir);
+    }
+
+    #[test]
+    fn test_strength_reduction() {
+        let ir = fold("var x: integer; begin x := x * 8 + 16 * x; x := x mod 4 + x div 2; end");
+
+        let expected = "\
+var x
+
+block0:
+    %0 = x shl 3
+    %1 = x shl 4
+    %2 = %0 + %1
+    x = %2
+    %3 = x and 3
+    %6 = x sar 63
+    %7 = %6 and 1
+    %8 = x + %7
+    %4 = %8 sar 1
+    %5 = %3 + %4
+    x = %5
+    exit
+";
+        assert_eq!(ir, expected);
+    }
+}
@@ -0,0 +1,585 @@
+//! Loop optimisations, enabled at `-O2`.
+//!
+//! Loops are found on the control-flow graph as natural loops: a back edge is
+//! a jump to a block that dominates its source, and its loop is that block,
+//! the header, together with every block that reaches the back edge without
+//! passing through the header. Three passes work on them:
+//!
+//! - [`unroll_loops`] replaces a `for` loop that runs a small, constant
+//!   number of times by that many copies of its body.
+//! - [`hoist_invariants`] moves computations whose operands do not change in
+//!   the loop to a preheader, a block that runs once before it.
+//! - [`reduce_induction_variables`] turns a multiplication of a loop counter
+//!   into an addition kept up to date as the counter steps.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::ir::{BasicBlock, BinaryOperator, BlockId, Instruction, Operand, Program, Terminator, Var};
+use crate::optimize::dataflow::{solve, Definition, Liveness, Location, ReachingDefinitions};
+use crate::optimize::dce::may_trap;
+
+/// The most iterations of a loop that is unrolled.
+const UNROLL_TRIPS: i64 = 8;
+/// The most instructions a loop may have once unrolled.
+const UNROLL_SIZE: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loop {
+    pub header: BlockId,
+    /// Every block of the loop, the header included.
+    pub blocks: BTreeSet<BlockId>,
+    /// The blocks that jump back to the header.
+    pub latches: Vec<BlockId>,
+}
+
+impl Loop {
+    /// The edges that leave the loop, as the block in the loop and its
+    /// successor outside it.
+    pub fn exits(&self, program: &Program) -> Vec<(BlockId, BlockId)> {
+        let mut exits = Vec::new();
+        for &block in &self.blocks {
+            for successor in program.blocks[block.0].terminator.successors() {
+                if !self.blocks.contains(&successor) && !exits.contains(&(block, successor)) {
+                    exits.push((block, successor));
+                }
+            }
+        }
+        exits
+    }
+
+    // Where the instructions of the loop write each variable.
+    fn definitions(&self, program: &Program) -> HashMap<Var, Vec<Location>> {
+        let mut definitions: HashMap<Var, Vec<Location>> = HashMap::new();
+        for &block in &self.blocks {
+            for (index, instruction) in program.blocks[block.0].instructions.iter().enumerate() {
+                if let Some(dest) = instruction.dest() {
+                    definitions.entry(dest.clone()).or_default().push(Location { block, index });
+                }
+            }
+        }
+        definitions
+    }
+
+    fn size(&self, program: &Program) -> usize {
+        self.blocks.iter().map(|block| program.blocks[block.0].instructions.len() + 1).sum()
+    }
+}
+
+/// The natural loops of `program`, smallest first, so an inner loop comes
+/// before the loops around it. Loops sharing a header are merged.
+pub fn find_loops(program: &Program) -> Vec<Loop> {
+    let dominators = program.immediate_dominators();
+    let predecessors = program.predecessors();
+    let order = program.reverse_postorder();
+    let reachable: HashSet<BlockId> = order.iter().copied().collect();
+
+    let mut loops: Vec<Loop> = Vec::new();
+    for &block in &order {
+        for header in program.blocks[block.0].terminator.successors() {
+            if !dominates(&dominators, header, block) {
+                continue;
+            }
+            let index = match loops.iter().position(|found| found.header == header) {
+                Some(index) => index,
+                None => {
+                    loops.push(Loop { header, blocks: BTreeSet::from([header]), latches: Vec::new() });
+                    loops.len() - 1
+                }
+            };
+            let found = &mut loops[index];
+            found.latches.push(block);
+            // Everything that reaches the latch without going through the header
+            let mut stack = vec![block];
+            while let Some(member) = stack.pop() {
+                if found.blocks.insert(member) {
+                    stack.extend(predecessors[member.0].iter().filter(|p| reachable.contains(p)));
+                }
+            }
+        }
+    }
+    loops.sort_by_key(|found| found.blocks.len());
+    loops
+}
+
+fn dominates(dominators: &[Option<BlockId>], dominator: BlockId, mut block: BlockId) -> bool {
+    loop {
+        if block == dominator {
+            return true;
+        }
+        match dominators[block.0] {
+            Some(parent) => block = parent,
+            None => return false,
+        }
+    }
+}
+
+// Runs `transform` once on every loop, inner loops first. A loop is found
+// again before it is transformed, as transforming the loops inside it can
+// add blocks to it. The preheaders added along the way are placed in front
+// of their loops at the end.
+fn for_each_loop(program: &mut Program, mut transform: impl FnMut(&mut Program, &Loop)) {
+    let first_added = program.blocks.len();
+    let mut done = HashSet::new();
+    while let Some(found) = find_loops(program).into_iter().find(|found| !done.contains(&found.header)) {
+        done.insert(found.header);
+        transform(program, &found);
+    }
+
+    let mut order = Vec::with_capacity(program.blocks.len());
+    for block in (0..first_added).map(BlockId) {
+        let added = (first_added..program.blocks.len()).map(BlockId);
+        order.extend(added.filter(|preheader| program.blocks[preheader.0].terminator == Terminator::Jump(block)));
+        order.push(block);
+    }
+    program.reorder_blocks(&order);
+}
+
+// A block that runs right before the loop and only jumps to its header. A new
+// one is added, and the jumps into the loop are redirected to it, unless the
+// header has a single predecessor outside the loop that already is one.
+fn preheader(program: &mut Program, found: &Loop) -> Option<BlockId> {
+    if found.header == BlockId(0) {
+        return None;
+    }
+    let outside: Vec<BlockId> =
+        program.predecessors()[found.header.0].iter().copied().filter(|block| !found.blocks.contains(block)).collect();
+    if let [single] = outside[..]
+        && program.blocks[single.0].terminator == Terminator::Jump(found.header)
+    {
+        return Some(single);
+    }
+
+    let preheader = BlockId(program.blocks.len());
+    program.blocks.push(BasicBlock { instructions: Vec::new(), terminator: Terminator::Jump(found.header) });
+    for block in outside {
+        for target in program.blocks[block.0].terminator.successors_mut() {
+            if *target == found.header {
+                *target = preheader;
+            }
+        }
+    }
+    Some(preheader)
+}
+
+/// Moves the computations that give the same result in every iteration of a
+/// loop out of it.
+pub fn hoist_invariants(program: &mut Program) {
+    for_each_loop(program, hoist_from_loop);
+}
+
+fn hoist_from_loop(program: &mut Program, found: &Loop) {
+    let live_in = solve(program, &Liveness).entry;
+    let dominators = program.immediate_dominators();
+    let exits = found.exits(program);
+    let definitions = found.definitions(program);
+
+    // An instruction is invariant when its operands are constants, variables
+    // the loop does not write, or the results of invariant instructions. It
+    // can move when it is the only write of its destination in the loop, no
+    // read in the loop sees an older value, and the value it leaves after
+    // the loop is the same: either it is not read there or the instruction
+    // runs before every exit. Instructions that can trap must stay put.
+    let mut hoisted: Vec<Location> = Vec::new();
+    let mut invariant: HashSet<Var> = HashSet::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &block in &found.blocks {
+            for (index, instruction) in program.blocks[block.0].instructions.iter().enumerate() {
+                let Some(dest) = instruction.dest() else { continue };
+                let operands_invariant = instruction.operands().into_iter().all(|operand| match operand {
+                    Operand::Constant(_) => true,
+                    Operand::Var(var) => !definitions.contains_key(var) || invariant.contains(var),
+                });
+                if operands_invariant
+                    && !invariant.contains(dest)
+                    && definitions[dest].len() == 1
+                    && !live_in[found.header.0].contains(dest)
+                    && !may_trap(instruction)
+                    && exits
+                        .iter()
+                        .all(|(from, to)| !live_in[to.0].contains(dest) || dominates(&dominators, block, *from))
+                {
+                    hoisted.push(Location { block, index });
+                    invariant.insert(dest.clone());
+                    changed = true;
+                }
+            }
+        }
+    }
+    if hoisted.is_empty() {
+        return;
+    }
+    let Some(preheader) = preheader(program, found) else { return };
+
+    // In the order they were found, which puts every instruction after the
+    // ones it reads
+    let moved: Vec<Instruction> =
+        hoisted.iter().map(|location| program.blocks[location.block.0].instructions[location.index].clone()).collect();
+    hoisted.sort();
+    for location in hoisted.into_iter().rev() {
+        program.blocks[location.block.0].instructions.remove(location.index);
+    }
+    program.blocks[preheader.0].instructions.extend(moved);
+}
+
+/// Replaces each product of a loop counter and a value the loop does not
+/// change by a running sum, which grows by a multiple of that value whenever
+/// the counter steps.
+pub fn reduce_induction_variables(program: &mut Program) {
+    for_each_loop(program, reduce_in_loop);
+}
+
+fn reduce_in_loop(program: &mut Program, found: &Loop) {
+    let definitions = found.definitions(program);
+    let instruction_at = |location: &Location| &program.blocks[location.block.0].instructions[location.index];
+
+    // The counters: variables only ever written in the loop by adding or
+    // subtracting a constant, with the constant of each write
+    let mut counters: HashMap<&Var, Vec<(Location, i64)>> = HashMap::new();
+    for (var, locations) in &definitions {
+        let steps: Option<Vec<(Location, i64)>> =
+            locations
+                .iter()
+                .map(|location| match instruction_at(location) {
+                    Instruction::Binary {
+                        operator, left: Operand::Var(left), right: Operand::Constant(step), ..
+                    } if left == var => match operator {
+                        BinaryOperator::Add => Some((*location, *step)),
+                        BinaryOperator::Subtract => Some((*location, step.wrapping_neg())),
+                        _ => None,
+                    },
+                    Instruction::Binary {
+                        operator: BinaryOperator::Add,
+                        left: Operand::Constant(step),
+                        right: Operand::Var(right),
+                        ..
+                    } if right == var => Some((*location, *step)),
+                    _ => None,
+                })
+                .collect();
+        if let Some(steps) = steps {
+            counters.insert(var, steps);
+        }
+    }
+
+    // The products to replace, grouped by counter and factor
+    let mut products: Vec<((Var, Operand), Vec<Location>)> = Vec::new();
+    for &block in &found.blocks {
+        for (index, instruction) in program.blocks[block.0].instructions.iter().enumerate() {
+            let Instruction::Binary { operator: BinaryOperator::Multiply, left, right, .. } = instruction else {
+                continue;
+            };
+            let product = match (left, right) {
+                (Operand::Var(counter), factor) | (factor, Operand::Var(counter)) if counters.contains_key(counter) => {
+                    match factor {
+                        Operand::Var(var) if definitions.contains_key(var) => continue,
+                        factor => (counter.clone(), factor.clone()),
+                    }
+                }
+                _ => continue,
+            };
+            let location = Location { block, index };
+            match products.iter_mut().find(|(found, _)| *found == product) {
+                Some((_, locations)) => locations.push(location),
+                None => products.push((product, vec![location])),
+            }
+        }
+    }
+    if products.is_empty() {
+        return;
+    }
+    let Some(preheader) = preheader(program, found) else { return };
+
+    let mut temp = || {
+        program.temp_count += 1;
+        Var::Temp(program.temp_count - 1)
+    };
+    let mut setup = Vec::new();
+    let mut updates: Vec<(Location, Instruction)> = Vec::new();
+    let mut replacements = Vec::new();
+    for ((counter, factor), locations) in products {
+        let sum = temp();
+        setup.push(Instruction::Binary {
+            dest: sum.clone(),
+            operator: BinaryOperator::Multiply,
+            left: Operand::Var(counter.clone()),
+            right: factor.clone(),
+        });
+        for &(location, step) in &counters[&counter] {
+            let increment = match &factor {
+                Operand::Constant(factor) => Operand::Constant(factor.wrapping_mul(step)),
+                factor if step == 1 => factor.clone(),
+                factor => {
+                    let increment = temp();
+                    setup.push(Instruction::Binary {
+                        dest: increment.clone(),
+                        operator: BinaryOperator::Multiply,
+                        left: factor.clone(),
+                        right: Operand::Constant(step),
+                    });
+                    Operand::Var(increment)
+                }
+            };
+            let update = Instruction::Binary {
+                dest: sum.clone(),
+                operator: BinaryOperator::Add,
+                left: Operand::Var(sum.clone()),
+                right: increment,
+            };
+            updates.push((location, update));
+        }
+        replacements.extend(locations.into_iter().map(|location| (location, sum.clone())));
+    }
+
+    for (location, sum) in replacements {
+        let instruction = &mut program.blocks[location.block.0].instructions[location.index];
+        let dest = instruction.dest().unwrap().clone();
+        *instruction = Instruction::Copy { dest, source: Operand::Var(sum) };
+    }
+    // From the back, so the locations still to come stay valid
+    updates.sort_by_key(|(location, _)| *location);
+    for (location, update) in updates.into_iter().rev() {
+        program.blocks[location.block.0].instructions.insert(location.index + 1, update);
+    }
+    program.blocks[preheader.0].instructions.extend(setup);
+}
+
+/// Replaces the `for` loops that run a few times, known at compile time, by
+/// a copy of the body for each iteration.
+pub fn unroll_loops(program: &mut Program) {
+    // Unrolling renumbers the blocks, so the loops are found again each time
+    while let Some((found, trips)) = find_loops(program).into_iter().find_map(|found| {
+        let trips = trip_count(program, &found)?;
+        Some((found, trips))
+    }) {
+        unroll(program, &found, trips);
+    }
+    merge_blocks(program);
+}
+
+// Appends to each block ending in a jump the block it jumps to, when it is
+// the only way into that block. The copies of an unrolled loop are mostly
+// such chains. The blocks emptied this way are left unreachable.
+fn merge_blocks(program: &mut Program) {
+    // Unreachable blocks, like the latch of the last copy, would still count
+    // as predecessors
+    let mut reachable = program.reverse_postorder();
+    reachable.sort();
+    program.reorder_blocks(&reachable);
+
+    let mut predecessors = program.predecessors();
+    for block in 0..program.blocks.len() {
+        while let Terminator::Jump(target) = program.blocks[block].terminator
+            && target.0 != block
+            && target != BlockId(0)
+            && predecessors[target.0] == [BlockId(block)]
+        {
+            let merged = std::mem::replace(
+                &mut program.blocks[target.0],
+                BasicBlock { instructions: Vec::new(), terminator: Terminator::Exit },
+            );
+            for successor in merged.terminator.successors() {
+                for predecessor in &mut predecessors[successor.0] {
+                    if *predecessor == target {
+                        *predecessor = BlockId(block);
+                    }
+                }
+            }
+            predecessors[target.0].clear();
+            program.blocks[block].instructions.extend(merged.instructions);
+            program.blocks[block].terminator = merged.terminator;
+        }
+    }
+}
+
+// The number of iterations of a loop shaped like a lowered `for` loop: its
+// only exit tests the counter against a constant end, and the latch, reached
+// only when the test fails, steps the counter by one, the only write to it in
+// the loop. The counter must hold the same constant whenever the loop is
+// entered. None for other loops, or ones too big to unroll.
+fn trip_count(program: &Program, found: &Loop) -> Option<i64> {
+    use BinaryOperator::*;
+
+    let [latch] = found.latches[..] else { return None };
+    let [(exiting, exit)] = found.exits(program)[..] else { return None };
+    let step = &program.blocks[latch.0];
+    let [Instruction::Binary { dest: counter, operator: operator @ (Add | Subtract), left, right: Operand::Constant(1) }] =
+        &step.instructions[..]
+    else {
+        return None;
+    };
+    if *left != Operand::Var(counter.clone()) || found.definitions(program)[counter].len() != 1 {
+        return None;
+    }
+
+    let Terminator::Branch { condition: Operand::Var(condition), if_true, if_false } =
+        &program.blocks[exiting.0].terminator
+    else {
+        return None;
+    };
+    if (*if_true, *if_false) != (exit, latch) || program.predecessors()[latch.0] != [exiting] {
+        return None;
+    }
+    let test =
+        program.blocks[exiting.0].instructions.iter().rev().find(|instruction| instruction.dest() == Some(condition));
+    let Some(Instruction::Binary { operator: Equal, left, right: Operand::Constant(end), .. }) = test else {
+        return None;
+    };
+    if *left != Operand::Var(counter.clone()) {
+        return None;
+    }
+
+    let outside: Vec<BlockId> =
+        program.predecessors()[found.header.0].iter().copied().filter(|block| !found.blocks.contains(block)).collect();
+    let [entry] = outside[..] else { return None };
+    let reaching = solve(program, &ReachingDefinitions).exit.swap_remove(entry.0);
+    let mut starts = reaching.iter().filter(|(var, _)| var == counter).map(|(_, definition)| match definition {
+        Definition::Initial => Some(0),
+        Definition::At(location) => match &program.blocks[location.block.0].instructions[location.index] {
+            Instruction::Copy { source: Operand::Constant(value), .. } => Some(*value),
+            _ => None,
+        },
+    });
+    let start = starts.next()??;
+    if !starts.all(|other| other == Some(start)) {
+        return None;
+    }
+
+    let trips = match operator {
+        Add => end.checked_sub(start)?.checked_add(1)?,
+        _ => start.checked_sub(*end)?.checked_add(1)?,
+    };
+    let small = (1..=UNROLL_TRIPS).contains(&trips) && found.size(program) * trips as usize <= UNROLL_SIZE;
+    small.then_some(trips)
+}
+
+// Lays out `trips` copies of the loop one after the other, the loop itself
+// being the first. Each copy's test of the counter jumps to its latch, whose
+// step leads into the next copy; the last copy's test leaves the loop.
+fn unroll(program: &mut Program, found: &Loop, trips: i64) {
+    let [latch] = found.latches[..] else { unreachable!("unrolling a loop with several latches") };
+    let [(exiting, exit)] = found.exits(program)[..] else { unreachable!("unrolling a loop with several exits") };
+    let blocks: Vec<BlockId> = found.blocks.iter().copied().collect();
+    let originals: Vec<BasicBlock> = blocks.iter().map(|block| program.blocks[block.0].clone()).collect();
+
+    let first_added = program.blocks.len();
+    let copies: Vec<HashMap<BlockId, BlockId>> = (0..trips as usize)
+        .map(|copy| {
+            let id = |position: usize| match copy {
+                0 => blocks[position],
+                _ => BlockId(first_added + (copy - 1) * blocks.len() + position),
+            };
+            blocks.iter().enumerate().map(|(position, block)| (*block, id(position))).collect()
+        })
+        .collect();
+
+    for (copy, ids) in copies.iter().enumerate() {
+        let next = copies.get(copy + 1);
+        for (block, original) in blocks.iter().zip(&originals) {
+            let mut code = original.clone();
+            if *block == exiting {
+                code.terminator = Terminator::Jump(if next.is_some() { ids[&latch] } else { exit });
+            } else if *block == latch {
+                code.terminator = Terminator::Jump(next.map_or(exit, |next| next[&found.header]));
+            } else {
+                for target in code.terminator.successors_mut() {
+                    *target = ids.get(target).copied().unwrap_or(*target);
+                }
+            }
+            match copy {
+                0 => program.blocks[block.0] = code,
+                _ => program.blocks.push(code),
+            }
+        }
+    }
+
+    // The copies go right after the loop
+    let last = blocks.iter().max().unwrap().0;
+    let order: Vec<BlockId> =
+        (0..=last).chain(first_added..program.blocks.len()).chain(last + 1..first_added).map(BlockId).collect();
+    program.reorder_blocks(&order);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimize::optimize;
+    use crate::testing::lower_source;
+
+    // The programs start by summing 1 to 20 into n, so it holds a value only
+    // known at run time, as far as the passes can tell.
+    fn optimized(source: &str) -> Program {
+        let mut ir = lower_source(source);
+        optimize(&mut ir, 1);
+        ir
+    }
+
+    #[test]
+    fn test_find_nested_loops() {
+        let program = optimized(
+            "var i, j, k, n: integer; begin for k := 1 to 20 do n := n + k; \
+             for i := 1 to n do begin j := 0; while j < i do j := j + 1; end; end",
+        );
+        let loops = find_loops(&program);
+
+        // The loop counting n, then the inner and the outer loop
+        assert_eq!(loops.len(), 3, "{}", program);
+        assert!(loops[1].blocks.is_subset(&loops[2].blocks));
+        assert!(loops[2].blocks.len() > loops[1].blocks.len());
+        for found in &loops {
+            assert_eq!(found.latches.len(), 1);
+            assert!(!found.exits(&program).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_hoist_invariants() {
+        let mut program = optimized(
+            "var a, i, k, n, total: integer; begin for k := 1 to 20 do n := n + k; for i := 1 to n do a := a + i; \
+             for i := 1 to n do total := total + a * n - i; writeln(total); end",
+        );
+        hoist_invariants(&mut program);
+
+        // a * n moved to the preheader of the last loop; n is there as the
+        // temporary its final value was computed into
+        let multiply = program.to_string().lines().position(|line| line.contains(" = a * ")).unwrap();
+        let found = find_loops(&program);
+        let header = found.iter().map(|found| found.header).max().unwrap();
+        let header_line = program.to_string().lines().position(|line| line == format!("{}:", header)).unwrap();
+        assert!(multiply < header_line, "{}", program);
+    }
+
+    #[test]
+    fn test_reduce_induction_variables() {
+        let mut program = optimized(
+            "var i, k, n, total: integer; begin for k := 1 to 20 do n := n + k; \
+             for i := 1 to n do total := total + i * 3; writeln(total); end",
+        );
+        reduce_induction_variables(&mut program);
+
+        let found = find_loops(&program).into_iter().max_by_key(|found| found.header).unwrap();
+        let in_loop: Vec<String> = found
+            .blocks
+            .iter()
+            .flat_map(|block| &program.blocks[block.0].instructions)
+            .map(|instruction| instruction.to_string())
+            .collect();
+        assert!(!in_loop.iter().any(|line| line.contains('*')), "{}", program);
+        assert!(in_loop.iter().any(|line| line.ends_with(" + 3")), "{}", program);
+    }
+
+    #[test]
+    fn test_unroll_small_constant_loops() {
+        let mut program = optimized(
+            "var i, j, k, n: integer; begin for k := 1 to 20 do n := n + k; for i := 1 to 4 do writeln(i); \
+             for j := 3 downto 1 do writeln(j); for i := 1 to 100 do n := n + i; for i := 1 to n do writeln(i); end",
+        );
+        unroll_loops(&mut program);
+
+        // Only the loops of 20, 100 and n iterations are left
+        assert_eq!(find_loops(&program).len(), 3, "{}", program);
+        assert_eq!(program.to_string().matches("print_int i").count(), 4 + 1, "{}", program);
+        assert_eq!(program.to_string().matches("print_int j").count(), 3, "{}", program);
+    }
+}
@@ -0,0 +1,123 @@
+//! Global constant and copy propagation.
+//!
+//! A variable is replaced by a constant when every definition reaching the
+//! read assigns that same constant, and by another variable when a copy from
+//! it is available on every path. The copies and constants themselves are
+//! left for dead code elimination.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::{BlockId, Instruction, Operand, Program, Var};
+use crate::optimize::dataflow::{solve, AvailableCopies, Definition, Location, Problem, ReachingDefinitions};
+
+pub fn propagate(program: &mut Program) {
+    let definitions = solve(program, &ReachingDefinitions);
+    let copies = solve(program, &AvailableCopies);
+    // The definitions that assign a constant, found before anything changes
+    let mut constants = HashMap::new();
+    for (block, code) in program.blocks.iter().enumerate() {
+        for (index, instruction) in code.instructions.iter().enumerate() {
+            if let Instruction::Copy { source: Operand::Constant(value), .. } = instruction {
+                constants.insert(Location { block: BlockId(block), index }, *value);
+            }
+        }
+    }
+
+    for (block, code) in program.blocks.iter_mut().enumerate() {
+        let mut reaching = definitions.entry[block].clone();
+        let mut available = copies.entry[block].clone();
+        for (index, instruction) in code.instructions.iter_mut().enumerate() {
+            for operand in instruction.operands_mut() {
+                replace(operand, &reaching, &available, &constants);
+            }
+            let location = Location { block: BlockId(block), index };
+            ReachingDefinitions.instruction(&mut reaching, instruction, location);
+            AvailableCopies.instruction(&mut available, instruction, location);
+        }
+        if let Some(operand) = code.terminator.operand_mut() {
+            replace(operand, &reaching, &available, &constants);
+        }
+    }
+}
+
+fn replace(
+    operand: &mut Operand,
+    reaching: &HashSet<(Var, Definition)>,
+    available: &HashSet<(Var, Operand)>,
+    constants: &HashMap<Location, i64>,
+) {
+    let Operand::Var(var) = operand else { return };
+    let mut values = reaching.iter().filter(|(defined, _)| defined == var).map(|(_, definition)| match definition {
+        Definition::Initial => Some(0),
+        Definition::At(location) => constants.get(location).copied(),
+    });
+    if let Some(Some(value)) = values.next()
+        && values.all(|other| other == Some(value))
+    {
+        *operand = Operand::Constant(value);
+        return;
+    }
+
+    // Follows chains of copies such as `b = a; c = b` back to `a`. On a path
+    // each step goes to a variable assigned earlier; the bound only matters
+    // in unreachable blocks, where every copy counts as available.
+    for _ in 0..available.len() {
+        let source = available.iter().find_map(|(dest, source)| match source {
+            Operand::Var(source) if Operand::Var(dest.clone()) == *operand => Some(source.clone()),
+            _ => None,
+        });
+        match source {
+            Some(source) => *operand = Operand::Var(source),
+            None => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::lower_source;
+
+    fn propagated(source: &str) -> String {
+        let mut ir = lower_source(source);
+        propagate(&mut ir);
+        ir.to_string()
+    }
+
+    #[test]
+    fn test_propagate_constants_across_blocks() {
+        let ir = propagated(
+            "var a, b, n: integer; begin a := 3; if n > 0 then b := 1 else b := 1; writeln(a + b); \
+             if n > 1 then b := 2; writeln(b); end",
+        );
+
+        // Both branches assign 1 to b, but after the second if b is 1 or 2
+        assert!(ir.contains(" = 3 + 1\n"), "{}", ir);
+        assert!(ir.contains("print_int b\n"), "{}", ir);
+    }
+
+    #[test]
+    fn test_propagate_copies() {
+        let ir = propagated(
+            "var a, b, c, n, x: integer; begin a := n * 2; b := a; c := b; x := c + 1; a := 5; x := c + x; end",
+        );
+
+        // n is never assigned and so 0; c holds the old a to the end
+        let expected = "\
+var a, b, c, n, x
+
+block0:
+    %0 = 0 * 2
+    a = %0
+    b = %0
+    c = %0
+    %1 = %0 + 1
+    x = %1
+    a = 5
+    %2 = %0 + %1
+    x = %2
+    exit
+";
+        assert_eq!(ir, expected);
+    }
+}
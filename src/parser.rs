@@ -1,182 +1,400 @@
-use crate::lexer::Token;
-use crate::ast::{ASTNode, VariableDeclaration, Statement, Expression};
+use crate::ast::{
+    BinaryOperator, Block, Decl, Expr, ExprKind, NodeId, PrintItem, Program, Stmt, StmtKind, TypeName, UnaryOperator,
+};
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{SpannedToken, Token};
+use crate::span::Span;
 
 pub struct Parser {
-    tokens: Vec<Token>,
+    tokens: Vec<SpannedToken>,
     current: usize,
+    next_id: u32,
 }
 
 impl Parser {
+    /// Creates a parser for tokens without source locations; every span in the
+    /// resulting tree is empty.
     pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, current: 0 }
+        Parser::from_spanned(tokens.into_iter().map(|token| SpannedToken { token, span: Span::default() }).collect())
     }
 
-    pub fn parse(&mut self) -> Result<ASTNode, String> {
-        self.parse_program().map_err(|e| format!("Error at token {:?}: {}", self.peek(), e))
+    pub fn from_spanned(tokens: Vec<SpannedToken>) -> Self {
+        Parser { tokens, current: 0, next_id: 0 }
     }
 
-    fn parse_program(&mut self) -> Result<ASTNode, String> {
-        self.expect_keyword("var")?;
-        let variable_section = self.parse_variable_section()?;
-        self.expect_keyword("begin")?;
-        let statement_section = self.parse_statement_section()?;
-        self.expect_keyword("end")?;
-        Ok(ASTNode::Program {
-            variable_section,
-            statement_section,
+    pub fn parse(&mut self) -> Result<Program, Diagnostic> {
+        self.parse_program().map_err(|e| {
+            let diagnostic = Diagnostic::error(format!("{} but found {}", e, describe(self.peek())));
+            match self.tokens.get(self.current) {
+                Some(token) => diagnostic.with_span(token.span),
+                None => diagnostic,
+            }
         })
     }
 
-    fn parse_variable_section(&mut self) -> Result<Vec<VariableDeclaration>, String> {
+    fn parse_program(&mut self) -> Result<Program, String> {
+        let start = self.peek_span();
+        self.expect_keyword("var")?;
+        let declarations = self.parse_variable_section()?;
+        let body = self.parse_block()?;
+        self.expect_end_of_input()?;
+        Ok(Program { id: self.new_id(), declarations, body, span: start.to(self.previous_span()) })
+    }
+
+    fn parse_variable_section(&mut self) -> Result<Vec<Decl>, String> {
         let mut declarations = Vec::new();
-        while self.peek() != Some(&Token::Keyword("begin".to_string())) {
+        while !self.match_keyword("begin") {
+            let start = self.peek_span();
             let names = self.parse_identifier_list()?;
             self.expect_symbol(':')?;
-            let type_name = self.expect_keyword("integer").map(|_| "integer".to_string())?;
+            let type_name = self.parse_type_name()?;
             self.expect_symbol(';')?;
-            declarations.push(VariableDeclaration { names, type_name });
+            declarations.push(Decl { id: self.new_id(), names, type_name, span: start.to(self.previous_span()) });
         }
         Ok(declarations)
     }
 
-    fn parse_statement_section(&mut self) -> Result<Vec<Statement>, String> {
+    fn parse_block(&mut self) -> Result<Block, String> {
+        let start = self.peek_span();
+        self.expect_keyword("begin")?;
         let mut statements = Vec::new();
-        while self.peek() != Some(&Token::Keyword("end".to_string())) {
+        while !self.match_keyword("end") {
             statements.push(self.parse_statement()?);
+            self.expect_symbol(';')?; // Every statement in a block ends with ';'
         }
-        Ok(statements)
+        self.expect_keyword("end")?;
+        Ok(Block { id: self.new_id(), statements, span: start.to(self.previous_span()) })
     }
 
-    fn parse_statement(&mut self) -> Result<Statement, String> {
-        if let Some(Token::Identifier(var)) = self.peek().cloned() {
+    fn parse_statement(&mut self) -> Result<Stmt, String> {
+        let start = self.peek_span();
+        let kind = if let Some(Token::Identifier(target)) = self.peek().cloned() {
             self.consume(); // Consume the identifier
             self.expect_assignment()?; // Expect :=
-            let expression = self.parse_expression()?; // Parse the expression
-            self.expect_symbol(';')?; // Expect and consume the semicolon
-            return Ok(Statement::Assignment(var, expression));
-        }
-
-        if self.match_keyword("writeln") {
-            self.consume(); // Consume 'writeln'
-            self.expect_symbol('(')?; // Expect opening parenthesis
-            let items = self.parse_expression_list()?; // Parse the list of expressions
-            self.expect_symbol(')')?; // Expect closing parenthesis
-            self.expect_symbol(';')?; // Expect and consume the semicolon
-            return Ok(Statement::Print(items));
-        }
-
-        Err(format!("Unexpected statement: {:?}", self.peek()))
-    }
-
-    fn parse_expression(&mut self) -> Result<Expression, String> {
-        let mut left = self.parse_term()?;
-        while let Some(op) = self.peek().and_then(|token| {
-            if let Token::Symbol(op) = token {
-                Some(*op)
+            let value = self.parse_expression()?; // Parse the expression
+            StmtKind::Assign { target, value }
+        } else if self.match_keyword("writeln") || self.match_keyword("write") {
+            let newline = self.match_keyword("writeln");
+            self.consume(); // Consume 'writeln' or 'write'
+            let mut items = Vec::new();
+            if let Some(Token::Symbol('(')) = self.peek() {
+                self.consume(); // Consume the opening parenthesis
+                if self.peek() != Some(&Token::Symbol(')')) {
+                    items = self.parse_print_items()?; // Parse the list of formatted expressions
+                }
+                self.expect_symbol(')')?; // Expect closing parenthesis
+            }
+            StmtKind::Print { items, newline }
+        } else if self.match_keyword("begin") {
+            StmtKind::Compound(self.parse_block()?)
+        } else if self.match_keyword("if") {
+            self.consume(); // Consume 'if'
+            let condition = self.parse_expression()?;
+            self.expect_keyword("then")?;
+            let then_branch = Box::new(self.parse_statement()?);
+            // A dangling else belongs to the innermost if
+            let else_branch = if self.match_keyword("else") {
+                self.consume(); // Consume 'else'
+                Some(Box::new(self.parse_statement()?))
             } else {
                 None
+            };
+            StmtKind::If { condition, then_branch, else_branch }
+        } else if self.match_keyword("while") {
+            self.consume(); // Consume 'while'
+            let condition = self.parse_expression()?;
+            self.expect_keyword("do")?;
+            StmtKind::While { condition, body: Box::new(self.parse_statement()?) }
+        } else if self.match_keyword("for") {
+            self.consume(); // Consume 'for'
+            let variable = self.expect_identifier()?;
+            self.expect_assignment()?;
+            let start = self.parse_expression()?;
+            let descending = self.match_keyword("downto");
+            if descending {
+                self.consume(); // Consume 'downto'
+            } else {
+                self.expect_keyword("to")?;
             }
-        }) {
-            if op == '+' || op == '-' || op == '*' || op == '/' {
+            let end = self.parse_expression()?;
+            self.expect_keyword("do")?;
+            StmtKind::For { variable, start, end, descending, body: Box::new(self.parse_statement()?) }
+        } else if self.match_keyword("exit") {
+            self.consume(); // Consume 'exit'
+            StmtKind::Exit
+        } else {
+            return Err("Expected a statement".to_string());
+        };
+        Ok(Stmt { id: self.new_id(), kind, span: start.to(self.previous_span()) })
+    }
+
+    // A comparison is not associative: `a < b < c` is an error, as in Pascal.
+    fn parse_expression(&mut self) -> Result<Expr, String> {
+        let left = self.parse_simple_expression()?;
+        match self.peek_comparison_operator() {
+            Some(operator) => {
                 self.consume(); // Consume the operator
-                let right = self.parse_term()?; // Parse the right-hand side
-                left = Expression::BinaryOperation {
-                    left: Box::new(left),
-                    operator: op,
-                    right: Box::new(right),
-                };
-            } else {
-                break;
+                let right = self.parse_simple_expression()?;
+                Ok(self.binary(left, operator, right))
             }
+            None => Ok(left),
+        }
+    }
+
+    fn parse_simple_expression(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_term()?;
+        while let Some(operator) = self.peek_additive_operator() {
+            self.consume(); // Consume the operator
+            let right = self.parse_term()?; // Parse the right-hand side
+            left = self.binary(left, operator, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_term(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_factor()?;
+        while let Some(operator) = self.peek_multiplicative_operator() {
+            self.consume(); // Consume the operator
+            let right = self.parse_factor()?;
+            left = self.binary(left, operator, right);
         }
         Ok(left)
     }
 
-    fn parse_term(&mut self) -> Result<Expression, String> {
-        if let Some(token) = self.consume() {
-            match token {
-                Token::IntegerLiteral(value) => Ok(Expression::IntegerLiteral(value)),
-                Token::Identifier(name) => Ok(Expression::Variable(name)),
-                Token::StringLiteral(text) => Ok(Expression::StringLiteral(text)),
-                _ => Err(format!("Unexpected term: {:?}", token)),
+    fn parse_factor(&mut self) -> Result<Expr, String> {
+        let start = self.peek_span();
+        let kind = match self.peek().cloned() {
+            Some(Token::IntegerLiteral(value)) => ExprKind::Integer(value),
+            Some(Token::RealLiteral(value)) => ExprKind::Real(value),
+            Some(Token::Identifier(name)) => ExprKind::Variable(name),
+            Some(Token::StringLiteral(text)) => ExprKind::String(text),
+            Some(Token::Keyword(k)) if k == "true" => ExprKind::Boolean(true),
+            Some(Token::Keyword(k)) if k == "false" => ExprKind::Boolean(false),
+            Some(Token::Symbol('-')) => return self.parse_unary(UnaryOperator::Negate),
+            Some(Token::Keyword(k)) if k == "not" => return self.parse_unary(UnaryOperator::Not),
+            Some(Token::Symbol('(')) => {
+                self.consume(); // Consume '('
+                let expression = self.parse_expression()?;
+                self.expect_symbol(')')?;
+                return Ok(expression);
             }
-        } else {
-            Err("Unexpected end of input while parsing term".to_string())
+            _ => return Err("Expected an expression".to_string()),
+        };
+        self.consume();
+        Ok(Expr { id: self.new_id(), kind, span: start })
+    }
+
+    fn parse_unary(&mut self, operator: UnaryOperator) -> Result<Expr, String> {
+        let start = self.peek_span();
+        self.consume(); // Consume the operator
+        let operand = Box::new(self.parse_factor()?);
+        let span = start.to(operand.span);
+        Ok(Expr { id: self.new_id(), kind: ExprKind::Unary { operator, operand }, span })
+    }
+
+    fn binary(&mut self, left: Expr, operator: BinaryOperator, right: Expr) -> Expr {
+        let span = left.span.to(right.span);
+        Expr {
+            id: self.new_id(),
+            kind: ExprKind::Binary { left: Box::new(left), operator, right: Box::new(right) },
+            span,
+        }
+    }
+
+    fn peek_additive_operator(&self) -> Option<BinaryOperator> {
+        match self.peek() {
+            Some(Token::Symbol('+')) => Some(BinaryOperator::Add),
+            Some(Token::Symbol('-')) => Some(BinaryOperator::Subtract),
+            Some(Token::Keyword(k)) if k == "or" => Some(BinaryOperator::Or),
+            _ => None,
+        }
+    }
+
+    fn peek_multiplicative_operator(&self) -> Option<BinaryOperator> {
+        match self.peek() {
+            Some(Token::Symbol('*')) => Some(BinaryOperator::Multiply),
+            Some(Token::Symbol('/')) => Some(BinaryOperator::Divide),
+            Some(Token::Keyword(k)) if k == "div" => Some(BinaryOperator::Div),
+            Some(Token::Keyword(k)) if k == "mod" => Some(BinaryOperator::Mod),
+            Some(Token::Keyword(k)) if k == "rem" => Some(BinaryOperator::Rem),
+            Some(Token::Keyword(k)) if k == "and" => Some(BinaryOperator::And),
+            _ => None,
+        }
+    }
+
+    fn peek_comparison_operator(&self) -> Option<BinaryOperator> {
+        match self.peek() {
+            Some(Token::Symbol('=')) => Some(BinaryOperator::Equal),
+            Some(Token::NotEqual) => Some(BinaryOperator::NotEqual),
+            Some(Token::Symbol('<')) => Some(BinaryOperator::Less),
+            Some(Token::LessEqual) => Some(BinaryOperator::LessEqual),
+            Some(Token::Symbol('>')) => Some(BinaryOperator::Greater),
+            Some(Token::GreaterEqual) => Some(BinaryOperator::GreaterEqual),
+            _ => None,
         }
     }
 
-    fn parse_expression_list(&mut self) -> Result<Vec<Expression>, String> {
-        let mut expressions = Vec::new();
-        expressions.push(self.parse_expression()?); // Parse the first expression
+    fn parse_print_items(&mut self) -> Result<Vec<PrintItem>, String> {
+        let mut items = vec![self.parse_print_item()?]; // Parse the first item
         while let Some(Token::Symbol(',')) = self.peek() {
             self.consume(); // Consume the comma
-            expressions.push(self.parse_expression()?); // Parse the next expression
+            items.push(self.parse_print_item()?); // Parse the next item
+        }
+        Ok(items)
+    }
+
+    // value [ ':' width [ ':' precision ] ]
+    fn parse_print_item(&mut self) -> Result<PrintItem, String> {
+        let value = self.parse_expression()?;
+        let mut width = None;
+        let mut precision = None;
+        if let Some(Token::Symbol(':')) = self.peek() {
+            self.consume(); // Consume ':'
+            width = Some(self.parse_expression()?);
+            if let Some(Token::Symbol(':')) = self.peek() {
+                self.consume(); // Consume ':'
+                precision = Some(self.parse_expression()?);
+            }
         }
-        Ok(expressions)
+        Ok(PrintItem { value, width, precision })
+    }
+
+    fn parse_type_name(&mut self) -> Result<TypeName, String> {
+        let type_name = match self.peek() {
+            Some(Token::Keyword(k)) if k == "integer" => TypeName::Integer,
+            Some(Token::Keyword(k)) if k == "int64" => TypeName::Int64,
+            Some(Token::Keyword(k)) if k == "boolean" => TypeName::Boolean,
+            Some(Token::Keyword(k)) if k == "real" => TypeName::Real,
+            _ => return Err("Expected type 'integer', 'int64', 'boolean' or 'real'".to_string()),
+        };
+        self.consume();
+        Ok(type_name)
     }
 
-    // Utility functions
+    // Utility functions. The expect_* functions only consume the token when it
+    // matches, so errors point at the unexpected token.
     fn expect_keyword(&mut self, keyword: &str) -> Result<(), String> {
-        match self.consume() {
-            Some(Token::Keyword(k)) if k == keyword => Ok(()),
-            _ => Err(format!("Expected keyword '{}'", keyword)),
+        if self.match_keyword(keyword) {
+            self.consume();
+            Ok(())
+        } else {
+            Err(format!("Expected keyword '{}'", keyword))
         }
     }
 
     fn expect_symbol(&mut self, symbol: char) -> Result<(), String> {
-        match self.consume() {
-            Some(Token::Symbol(s)) if s == symbol => Ok(()),
+        match self.peek() {
+            Some(Token::Symbol(s)) if *s == symbol => {
+                self.consume();
+                Ok(())
+            }
             _ => Err(format!("Expected symbol '{}'", symbol)),
         }
     }
 
     fn expect_assignment(&mut self) -> Result<(), String> {
-        match self.consume() {
-            Some(Token::Assignment) => Ok(()),
+        match self.peek() {
+            Some(Token::Assignment) => {
+                self.consume();
+                Ok(())
+            }
             _ => Err("Expected assignment operator ':='".to_string()),
         }
     }
 
+    fn expect_end_of_input(&mut self) -> Result<(), String> {
+        match self.peek() {
+            Some(Token::EndOfInput) | None => Ok(()),
+            _ => Err("Expected end of input".to_string()),
+        }
+    }
+
     fn match_keyword(&self, keyword: &str) -> bool {
         matches!(self.peek(), Some(Token::Keyword(k)) if k == keyword)
     }
 
     fn parse_identifier_list(&mut self) -> Result<Vec<String>, String> {
-        let mut names = Vec::new();
-        if let Some(Token::Identifier(name)) = self.consume() {
-            names.push(name);
-        } else {
-            return Err("Expected identifier".to_string());
-        }
+        let mut names = vec![self.expect_identifier()?];
         while let Some(Token::Symbol(',')) = self.peek() {
             self.consume(); // Consume ','
-            if let Some(Token::Identifier(name)) = self.consume() {
-                names.push(name);
-            } else {
-                return Err("Expected identifier after ','".to_string());
-            }
+            names.push(self.expect_identifier()?);
         }
         Ok(names)
     }
 
+    fn expect_identifier(&mut self) -> Result<String, String> {
+        match self.peek().cloned() {
+            Some(Token::Identifier(name)) => {
+                self.consume();
+                Ok(name)
+            }
+            _ => Err("Expected identifier".to_string()),
+        }
+    }
+
     fn consume(&mut self) -> Option<Token> {
         if self.current < self.tokens.len() {
             self.current += 1;
-            Some(self.tokens[self.current - 1].clone())
+            Some(self.tokens[self.current - 1].token.clone())
         } else {
             None
         }
     }
 
     fn peek(&self) -> Option<&Token> {
-        self.tokens.get(self.current)
+        self.tokens.get(self.current).map(|spanned| &spanned.token)
+    }
+
+    fn peek_span(&self) -> Span {
+        self.tokens.get(self.current).map(|spanned| spanned.span).unwrap_or_default()
+    }
+
+    fn previous_span(&self) -> Span {
+        match self.current {
+            0 => Span::default(),
+            current => self.tokens[current - 1].span,
+        }
+    }
+
+    fn new_id(&mut self) -> NodeId {
+        self.next_id += 1;
+        NodeId(self.next_id)
+    }
+}
+
+// Describes a token for error messages, e.g. "keyword 'end'".
+fn describe(token: Option<&Token>) -> String {
+    match token {
+        Some(Token::Keyword(k)) => format!("keyword '{}'", k),
+        Some(Token::Identifier(name)) => format!("identifier '{}'", name),
+        Some(Token::IntegerLiteral(value)) => format!("integer {}", value),
+        Some(Token::RealLiteral(value)) => format!("real {:?}", value),
+        Some(Token::StringLiteral(text)) => format!("string '{}'", String::from_utf8_lossy(text)),
+        Some(Token::Symbol(symbol)) => format!("symbol '{}'", symbol),
+        Some(Token::Assignment) => "':='".to_string(),
+        Some(Token::NotEqual) => "symbol '<>'".to_string(),
+        Some(Token::LessEqual) => "symbol '<='".to_string(),
+        Some(Token::GreaterEqual) => "symbol '>='".to_string(),
+        Some(Token::EndOfInput) | None => "end of input".to_string(),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::lexer::tokenize;
+    use crate::lexer::{tokenize, tokenize_spanned};
+
+    fn parse(source: &str) -> Program {
+        Parser::new(tokenize(source).unwrap()).parse().unwrap().without_locations()
+    }
+
+    fn expr(kind: ExprKind) -> Box<Expr> {
+        Box::new(Expr::new(kind))
+    }
+
+    fn print_item(value: ExprKind, width: Option<ExprKind>, precision: Option<ExprKind>) -> PrintItem {
+        PrintItem { value: Expr::new(value), width: width.map(Expr::new), precision: precision.map(Expr::new) }
+    }
 
     #[test]
     fn test_parser_with_simple_program() {
@@ -193,45 +411,213 @@ mod tests {
             end
         "#;
 
-        let tokens = tokenize(source_code);
-        let mut parser = Parser::new(tokens);
-        let ast = parser.parse().unwrap();
+        let ast = parse(source_code);
 
-        let expected_ast = ASTNode::Program {
-            variable_section: vec![
-                VariableDeclaration {
+        let expected_ast = Program {
+            id: NodeId::default(),
+            declarations: vec![
+                Decl {
+                    id: NodeId::default(),
                     names: vec!["a".to_string(), "b".to_string()],
-                    type_name: "integer".to_string(),
+                    type_name: TypeName::Integer,
+                    span: Span::default(),
                 },
-                VariableDeclaration {
+                Decl {
+                    id: NodeId::default(),
                     names: vec!["result".to_string()],
-                    type_name: "integer".to_string(),
+                    type_name: TypeName::Integer,
+                    span: Span::default(),
                 },
             ],
-            statement_section: vec![
-                Statement::Assignment(
-                    "a".to_string(),
-                    Expression::IntegerLiteral(10),
-                ),
-                Statement::Assignment(
-                    "b".to_string(),
-                    Expression::IntegerLiteral(20),
-                ),
-                Statement::Assignment(
-                    "result".to_string(),
-                    Expression::BinaryOperation {
-                        left: Box::new(Expression::Variable("a".to_string())),
-                        operator: '+',
-                        right: Box::new(Expression::Variable("b".to_string())),
-                    },
+            body: Block {
+                id: NodeId::default(),
+                statements: vec![
+                    Stmt::new(StmtKind::Assign { target: "a".to_string(), value: Expr::new(ExprKind::Integer(10)) }),
+                    Stmt::new(StmtKind::Assign { target: "b".to_string(), value: Expr::new(ExprKind::Integer(20)) }),
+                    Stmt::new(StmtKind::Assign {
+                        target: "result".to_string(),
+                        value: Expr::new(ExprKind::Binary {
+                            left: expr(ExprKind::Variable("a".to_string())),
+                            operator: BinaryOperator::Add,
+                            right: expr(ExprKind::Variable("b".to_string())),
+                        }),
+                    }),
+                    Stmt::new(StmtKind::Print {
+                        items: vec![
+                            print_item(ExprKind::String(b"The result is: ".to_vec()), None, None),
+                            print_item(ExprKind::Variable("result".to_string()), None, None),
+                        ],
+                        newline: true,
+                    }),
+                ],
+                span: Span::default(),
+            },
+            span: Span::default(),
+        };
+
+        assert_eq!(ast, expected_ast);
+    }
+
+    #[test]
+    fn test_parser_operator_precedence() {
+        let ast = parse("var x: integer; begin x := 1 - 2 * -3 mod (4 + 5); end");
+
+        let expected = ExprKind::Binary {
+            left: expr(ExprKind::Integer(1)),
+            operator: BinaryOperator::Subtract,
+            right: expr(ExprKind::Binary {
+                left: expr(ExprKind::Binary {
+                    left: expr(ExprKind::Integer(2)),
+                    operator: BinaryOperator::Multiply,
+                    right: expr(ExprKind::Unary {
+                        operator: UnaryOperator::Negate,
+                        operand: expr(ExprKind::Integer(3)),
+                    }),
+                }),
+                operator: BinaryOperator::Mod,
+                right: expr(ExprKind::Binary {
+                    left: expr(ExprKind::Integer(4)),
+                    operator: BinaryOperator::Add,
+                    right: expr(ExprKind::Integer(5)),
+                }),
+            }),
+        };
+
+        assert_eq!(
+            ast.body.statements[0],
+            Stmt::new(StmtKind::Assign { target: "x".to_string(), value: Expr::new(expected) })
+        );
+    }
+
+    #[test]
+    fn test_parser_comparison_binds_loosest() {
+        let ast = parse("var b: boolean; begin b := not b or 1 < 2 and b; end");
+
+        let expected = ExprKind::Binary {
+            left: expr(ExprKind::Binary {
+                left: expr(ExprKind::Unary {
+                    operator: UnaryOperator::Not,
+                    operand: expr(ExprKind::Variable("b".to_string())),
+                }),
+                operator: BinaryOperator::Or,
+                right: expr(ExprKind::Integer(1)),
+            }),
+            operator: BinaryOperator::Less,
+            right: expr(ExprKind::Binary {
+                left: expr(ExprKind::Integer(2)),
+                operator: BinaryOperator::And,
+                right: expr(ExprKind::Variable("b".to_string())),
+            }),
+        };
+
+        assert_eq!(
+            ast.body.statements[0],
+            Stmt::new(StmtKind::Assign { target: "b".to_string(), value: Expr::new(expected) })
+        );
+        assert!(Parser::new(tokenize("var b: boolean; begin b := 1 < 2 < 3; end").unwrap()).parse().is_err());
+    }
+
+    #[test]
+    fn test_parser_control_flow_statements() {
+        let ast = parse(
+            "var i: integer; begin
+                if i > 0 then if i > 1 then write else writeln;
+                while i <> 0 do begin i := i - 1; end;
+                for i := 10 downto 1 do write(i);
+                exit;
+            end",
+        );
+
+        let variable = || Expr::new(ExprKind::Variable("i".to_string()));
+        let compare = |operator, value| {
+            Expr::new(ExprKind::Binary { left: Box::new(variable()), operator, right: expr(ExprKind::Integer(value)) })
+        };
+        let print = |newline| Box::new(Stmt::new(StmtKind::Print { items: Vec::new(), newline }));
+        let expected = [
+            StmtKind::If {
+                condition: compare(BinaryOperator::Greater, 0),
+                // The else belongs to the inner if
+                then_branch: Box::new(Stmt::new(StmtKind::If {
+                    condition: compare(BinaryOperator::Greater, 1),
+                    then_branch: print(false),
+                    else_branch: Some(print(true)),
+                })),
+                else_branch: None,
+            },
+            StmtKind::While {
+                condition: compare(BinaryOperator::NotEqual, 0),
+                body: Box::new(Stmt::new(StmtKind::Compound(Block {
+                    id: NodeId::default(),
+                    statements: vec![Stmt::new(StmtKind::Assign {
+                        target: "i".to_string(),
+                        value: compare(BinaryOperator::Subtract, 1),
+                    })],
+                    span: Span::default(),
+                }))),
+            },
+            StmtKind::For {
+                variable: "i".to_string(),
+                start: Expr::new(ExprKind::Integer(10)),
+                end: Expr::new(ExprKind::Integer(1)),
+                descending: true,
+                body: Box::new(Stmt::new(StmtKind::Print {
+                    items: vec![print_item(ExprKind::Variable("i".to_string()), None, None)],
+                    newline: false,
+                })),
+            },
+            StmtKind::Exit,
+        ];
+
+        let statements: Vec<StmtKind> = ast.body.statements.into_iter().map(|stmt| stmt.kind).collect();
+        assert_eq!(statements, expected);
+    }
+
+    #[test]
+    fn test_parser_print_item_formats() {
+        let ast = parse("var x: integer; begin writeln(x:8, 'name':x + 2, x:1:2); end");
+
+        let expected = StmtKind::Print {
+            items: vec![
+                print_item(ExprKind::Variable("x".to_string()), Some(ExprKind::Integer(8)), None),
+                print_item(
+                    ExprKind::String(b"name".to_vec()),
+                    Some(ExprKind::Binary {
+                        left: expr(ExprKind::Variable("x".to_string())),
+                        operator: BinaryOperator::Add,
+                        right: expr(ExprKind::Integer(2)),
+                    }),
+                    None,
                 ),
-                Statement::Print(vec![
-                    Expression::StringLiteral("The result is: ".to_string()),
-                    Expression::Variable("result".to_string()),
-                ]),
+                print_item(ExprKind::Variable("x".to_string()), Some(ExprKind::Integer(1)), Some(ExprKind::Integer(2))),
             ],
+            newline: true,
         };
 
-        assert_eq!(format!("{:?}", ast), format!("{:?}", expected_ast)); 
+        assert_eq!(ast.body.statements[0], Stmt::new(expected));
+    }
+
+    #[test]
+    fn test_parser_records_spans_and_unique_ids() {
+        let source = "var x: integer;\nbegin\n  x := 1 + 22;\nend";
+        let ast = Parser::from_spanned(tokenize_spanned(source).unwrap()).parse().unwrap();
+
+        let statement = &ast.body.statements[0];
+        assert_eq!(&source[statement.span.start..statement.span.end], "x := 1 + 22");
+        assert_eq!((statement.span.line, statement.span.column), (3, 3));
+        let StmtKind::Assign { value, .. } = &statement.kind else { panic!("Expected an assignment") };
+        assert_eq!(&source[value.span.start..value.span.end], "1 + 22");
+
+        let mut ids = vec![ast.id, ast.body.id, ast.declarations[0].id, statement.id, value.id];
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 5);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parser_errors_point_at_the_unexpected_token() {
+        let source = "var x: integer;\nbegin\n  x := ;\nend";
+        let error = Parser::from_spanned(tokenize_spanned(source).unwrap()).parse().unwrap_err();
+
+        assert_eq!(error.to_string(), "3:8: error: Expected an expression but found symbol ';'");
+    }
+}
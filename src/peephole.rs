@@ -0,0 +1,170 @@
+//! Peephole optimisation of the generated assembly.
+//!
+//! Short windows of the instructions are rewritten until none of the patterns
+//! below is left:
+//!
+//! - `push x` followed by `pop y` becomes `mov y, x`, or nothing when they
+//!   are the same register;
+//! - `mov x, x` goes, and so does a `mov` that copies back what the one just
+//!   before copied, as in `mov [a], rax` / `mov rax, [a]`;
+//! - a `mov` to a register that the next instruction overwrites with a
+//!   `mov` without reading it goes;
+//! - a jump to a label that follows it, with nothing but labels in between,
+//!   goes.
+
+use crate::asm::{Instr, Operand};
+
+/// Optimises `code` in place.
+pub fn optimize(code: &mut Vec<Instr>) {
+    while simplify(code) {}
+}
+
+// Applies every pattern once along the code. Returns whether anything changed.
+fn simplify(code: &mut Vec<Instr>) -> bool {
+    let mut changed = false;
+    let mut index = 0;
+    while index < code.len() {
+        // How many instructions from `index` on are replaced, and by what
+        let rewrite = match (&code[index], code.get(index + 1)) {
+            (Instr::Mov(dest, source), _) if dest == source => Some((1, Vec::new())),
+            (Instr::Push(pushed), Some(Instr::Pop(popped))) => Some((
+                2,
+                if pushed == popped {
+                    Vec::new()
+                } else {
+                    vec![Instr::Mov(Operand::Register(*popped), Operand::Register(*pushed))]
+                },
+            )),
+            (first @ Instr::Mov(a_dest, a_source), Some(second @ Instr::Mov(b_dest, b_source))) => {
+                if a_dest == b_source && a_source == b_dest && !reads(a_source, a_dest) {
+                    // The second copies back what the first copied
+                    Some((2, vec![first.clone()]))
+                } else if a_dest == b_dest && matches!(a_dest, Operand::Register(_)) && !reads(b_source, a_dest) {
+                    // The first is overwritten before it is read
+                    Some((2, vec![second.clone()]))
+                } else {
+                    None
+                }
+            }
+            (Instr::Jmp(target) | Instr::Jcc(_, target), _) => {
+                let mut labels_after = code[index + 1..].iter().map_while(|instr| match instr {
+                    Instr::Label(label) => Some(label),
+                    _ => None,
+                });
+                labels_after.any(|label| label == target).then(|| (1, Vec::new()))
+            }
+            _ => None,
+        };
+        match rewrite {
+            Some((count, replacement)) => {
+                code.splice(index..index + count, replacement);
+                changed = true;
+            }
+            None => index += 1,
+        }
+    }
+    changed
+}
+
+// Whether `operand` reads the register `written`, directly or in an address.
+fn reads(operand: &Operand, written: &Operand) -> bool {
+    match written {
+        Operand::Register(register) => operand.uses(*register),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{AluOp, Condition, Label, Memory, Register};
+    use crate::ir::BlockId;
+    use Register::{Rax, Rbx, Rcx};
+
+    fn register(register: Register) -> Operand {
+        Operand::Register(register)
+    }
+
+    fn variable(name: &str) -> Operand {
+        Operand::Memory(Memory::label(Label::Variable(name.to_string())))
+    }
+
+    fn mov(dest: Operand, source: Operand) -> Instr {
+        Instr::Mov(dest, source)
+    }
+
+    fn optimized(mut code: Vec<Instr>) -> Vec<Instr> {
+        optimize(&mut code);
+        code
+    }
+
+    #[test]
+    fn test_remove_redundant_moves() {
+        let code = vec![
+            mov(variable("x"), register(Rax)),
+            mov(register(Rax), variable("x")),
+            mov(register(Rbx), register(Rbx)),
+            mov(register(Rcx), Operand::Immediate(1)),
+            mov(register(Rcx), Operand::Immediate(2)),
+            mov(register(Rax), variable("y")),
+            mov(variable("y"), register(Rax)),
+        ];
+
+        let expected = [
+            mov(variable("x"), register(Rax)),
+            mov(register(Rcx), Operand::Immediate(2)),
+            mov(register(Rax), variable("y")),
+        ];
+        assert_eq!(optimized(code), expected);
+    }
+
+    #[test]
+    fn test_keep_moves_that_matter() {
+        // The second move reads the register the first wrote, and a load
+        // through a register changes what the address means
+        let through_rbx = || Operand::Memory(Memory::register(Rbx, 0));
+        let code = vec![
+            mov(register(Rax), Operand::Immediate(1)),
+            mov(register(Rbx), Operand::Memory(Memory::register(Rax, 8))),
+            mov(register(Rax), register(Rbx)),
+            mov(register(Rbx), through_rbx()),
+            mov(through_rbx(), register(Rbx)),
+        ];
+
+        assert_eq!(optimized(code.clone()), code);
+    }
+
+    #[test]
+    fn test_remove_push_pop_pairs() {
+        let add = Instr::Alu(AluOp::Add, register(Rax), register(Rbx));
+        let code = vec![Instr::Push(Rax), Instr::Pop(Rbx), Instr::Push(Rcx), Instr::Pop(Rcx), add.clone()];
+
+        assert_eq!(optimized(code), [mov(register(Rbx), register(Rax)), add]);
+    }
+
+    #[test]
+    fn test_remove_jumps_to_the_next_label() {
+        let block = |number| Label::Block(BlockId(number));
+        let print = Instr::Call(Label::Named("print_newline"));
+        let code = vec![
+            Instr::Jmp(block(2)),
+            Instr::Label(block(1)),
+            Instr::Label(block(2)),
+            Instr::Jcc(Condition::Zero, block(3)),
+            print.clone(),
+            Instr::Label(block(3)),
+            Instr::Jcc(Condition::NotZero, block(4)),
+            Instr::Label(block(4)),
+        ];
+
+        let expected = [
+            Instr::Label(block(1)),
+            Instr::Label(block(2)),
+            Instr::Jcc(Condition::Zero, block(3)),
+            print,
+            Instr::Label(block(3)),
+            Instr::Label(block(4)),
+        ];
+        assert_eq!(optimized(code), expected);
+    }
+}
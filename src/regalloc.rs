@@ -0,0 +1,185 @@
+//! Register allocation by linear scan over the IR.
+//!
+//! Each variable gets a single live interval: the range of positions, counting
+//! instructions and terminators with the blocks in layout order, between the
+//! first and the last point where it is live. The intervals are visited in
+//! order of their start; when more of them overlap than there are registers,
+//! the one that ends last is spilled to memory.
+//!
+//! [`allocate`] uses the x86-64 registers; other targets pass their own to
+//! [`allocate_from`].
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::asm::Register;
+use crate::ir::{Operand, Program, Var};
+use crate::optimize::dataflow::{solve, Liveness, Solution};
+
+/// The registers given to variables. The print routines clobber RAX, RCX,
+/// RDX, RSI, RDI, R8 and R11, code generation uses RAX, RBX, RCX and RDX as
+/// scratch registers and RBP addresses the stack slots, so none of those.
+pub const REGISTERS: [Register; 6] =
+    [Register::R9, Register::R10, Register::R12, Register::R13, Register::R14, Register::R15];
+
+/// Where every variable of a program lives. Variables without a register are
+/// in memory: named ones in .data, temporaries in a stack slot.
+#[derive(Debug)]
+pub struct Allocation<R = Register> {
+    pub registers: HashMap<Var, R>,
+    /// Stack slot of each temporary kept in memory, numbered from 0.
+    pub slots: HashMap<Var, u32>,
+    pub slot_count: u32,
+    /// Registers of named variables that can be read before they are
+    /// assigned, and so must be cleared like the .data they replace.
+    pub zeroed: Vec<R>,
+}
+
+impl<R> Default for Allocation<R> {
+    fn default() -> Self {
+        Allocation { registers: HashMap::new(), slots: HashMap::new(), slot_count: 0, zeroed: Vec::new() }
+    }
+}
+
+impl<R> Allocation<R> {
+    /// Keeps every variable in memory, each temporary in the slot of its number.
+    pub fn in_memory(program: &Program) -> Allocation<R> {
+        let slots = (0..program.temp_count).map(|number| (Var::Temp(number), number)).collect();
+        Allocation { slots, slot_count: program.temp_count, ..Allocation::default() }
+    }
+}
+
+/// Assigns registers to as many variables of `program` as fit.
+pub fn allocate(program: &Program) -> Allocation {
+    allocate_from(program, &REGISTERS)
+}
+
+/// Assigns `registers`, taken in order, to as many variables of `program` as
+/// fit.
+pub fn allocate_from<R: Copy + Eq + Hash + Ord>(program: &Program, registers: &[R]) -> Allocation<R> {
+    let mut intervals: Vec<(usize, usize, Var)> =
+        live_intervals(program).into_iter().map(|(var, (start, end))| (start, end, var)).collect();
+    intervals.sort();
+
+    let mut allocation = Allocation::default();
+    let mut spilled = Vec::new();
+    // Intervals holding a register, and the registers free for the next one.
+    // A register is only reused after the end of the interval holding it, so
+    // an instruction never writes a register that one of its operands is in.
+    let mut active: Vec<(usize, Var)> = Vec::new();
+    let mut free: Vec<R> = registers.iter().rev().copied().collect();
+    for (start, end, var) in intervals {
+        active.retain(|(active_end, var)| {
+            let expired = *active_end < start;
+            if expired {
+                free.push(allocation.registers[var]);
+            }
+            !expired
+        });
+        let register = match free.pop() {
+            Some(register) => register,
+            None => {
+                let last = (0..active.len()).max_by_key(|&index| &active[index]).unwrap();
+                if active[last].0 <= end {
+                    spilled.push(var);
+                    continue;
+                }
+                let (_, victim) = active.swap_remove(last);
+                let register = allocation.registers.remove(&victim).unwrap();
+                spilled.push(victim);
+                register
+            }
+        };
+        allocation.registers.insert(var.clone(), register);
+        active.push((end, var));
+    }
+
+    spilled.sort();
+    for var in spilled {
+        if let Var::Temp(_) = var {
+            allocation.slots.insert(var, allocation.slot_count);
+            allocation.slot_count += 1;
+        }
+    }
+    let live_in = solve(program, &Liveness).entry;
+    allocation.zeroed = live_in[0].iter().filter_map(|var| allocation.registers.get(var).copied()).collect();
+    allocation.zeroed.sort();
+    allocation
+}
+
+// The first and last position at which each variable is live, counting one
+// position per instruction and terminator. A block's live-in variables are
+// live at its first position and its live-out ones at its terminator.
+fn live_intervals(program: &Program) -> HashMap<Var, (usize, usize)> {
+    let Solution { entry: live_in, exit: live_out } = solve(program, &Liveness);
+    let mut intervals: HashMap<Var, (usize, usize)> = HashMap::new();
+    let mut extend = |var: &Var, position: usize| {
+        let interval = intervals.entry(var.clone()).or_insert((position, position));
+        interval.0 = interval.0.min(position);
+        interval.1 = interval.1.max(position);
+    };
+
+    let mut position = 0;
+    for (index, block) in program.blocks.iter().enumerate() {
+        for var in &live_in[index] {
+            extend(var, position);
+        }
+        for instruction in &block.instructions {
+            let read = instruction.operands().into_iter().filter_map(|operand| match operand {
+                Operand::Var(var) => Some(var),
+                Operand::Constant(_) => None,
+            });
+            for var in read.chain(instruction.dest()) {
+                extend(var, position);
+            }
+            position += 1;
+        }
+        if let Some(Operand::Var(var)) = block.terminator.operand() {
+            extend(var, position);
+        }
+        for var in &live_out[index] {
+            extend(var, position);
+        }
+        position += 1;
+    }
+    intervals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::lower_source;
+
+    #[test]
+    fn test_allocate_registers() {
+        let program = lower_source("var a, b: integer; begin b := a * 2 + 1; writeln(b); end");
+        let allocation = allocate(&program);
+
+        assert_eq!(allocation.registers.len(), 4, "{:?}", allocation);
+        assert_eq!(allocation.slot_count, 0);
+        // a is read before it is assigned
+        assert_eq!(allocation.zeroed, vec![allocation.registers[&Var::Named("a".to_string())]]);
+    }
+
+    #[test]
+    fn test_allocate_spills_the_interval_ending_last() {
+        // Eight values live at once, each read in the opposite order
+        let mut source = String::from("var a, b, c, d, e, f, g, h: integer; begin ");
+        for (value, name) in ["a", "b", "c", "d", "e", "f", "g", "h"].iter().enumerate() {
+            source.push_str(&format!("{} := {}; ", name, value));
+        }
+        source.push_str("writeln(h, g, f, e, d, c, b, a); end");
+        let program = lower_source(&source);
+        let allocation = allocate(&program);
+
+        let in_register: Vec<&str> = ["a", "b", "c", "d", "e", "f", "g", "h"]
+            .into_iter()
+            .filter(|name| allocation.registers.contains_key(&Var::Named(name.to_string())))
+            .collect();
+        assert_eq!(in_register, ["c", "d", "e", "f", "g", "h"]);
+        let mut used: Vec<Register> = allocation.registers.values().copied().collect();
+        used.sort();
+        used.dedup();
+        assert_eq!(used.len(), REGISTERS.len());
+    }
+}
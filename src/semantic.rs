@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+
+use crate::ast::{
+    BinaryOperator, Block, Decl, Expr, ExprKind, NodeId, PrintItem, Program, Stmt, StmtKind, TypeName, UnaryOperator,
+    Visitor,
+};
+use crate::diagnostic::Diagnostic;
+use crate::ir;
+use crate::span::Span;
+
+/// The type of a value. `integer` and `int64` are both 64-bit integers, and
+/// `real` is a double.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type {
+    Integer,
+    Real,
+    Boolean,
+    String,
+}
+
+impl Type {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Type::Integer => "integer",
+            Type::Real => "real",
+            Type::Boolean => "boolean",
+            Type::String => "string",
+        }
+    }
+}
+
+impl From<TypeName> for Type {
+    fn from(type_name: TypeName) -> Self {
+        match type_name {
+            TypeName::Integer | TypeName::Int64 => Type::Integer,
+            TypeName::Boolean => Type::Boolean,
+            TypeName::Real => Type::Real,
+        }
+    }
+}
+
+/// What later stages need to know about a checked program.
+#[derive(Debug, Default)]
+pub struct Analysis {
+    /// Declared type of every variable.
+    pub variables: HashMap<String, Type>,
+    /// Type of every expression, by node id.
+    pub types: HashMap<NodeId, Type>,
+    /// Warnings about a program that is valid, such as unreachable code.
+    pub warnings: Vec<Diagnostic>,
+}
+
+impl Analysis {
+    pub fn type_of(&self, expr: &Expr) -> Type {
+        self.types[&expr.id]
+    }
+}
+
+/// Resolves names and checks types, reporting every error it finds. Warnings
+/// do not stop the compilation and are returned in the analysis.
+pub fn analyze(program: &Program) -> Result<Analysis, Vec<Diagnostic>> {
+    let mut checker = Checker {
+        analysis: Analysis::default(),
+        diagnostics: Vec::new(),
+        loop_variables: Vec::new(),
+        unreachable: false,
+    };
+    checker.visit_program(program);
+    if checker.diagnostics.is_empty() {
+        Ok(checker.analysis)
+    } else {
+        Err(checker.diagnostics)
+    }
+}
+
+struct Checker {
+    analysis: Analysis,
+    diagnostics: Vec<Diagnostic>,
+    loop_variables: Vec<String>, // Variables of the enclosing for loops
+    unreachable: bool,           // Inside code already reported as unreachable
+}
+
+impl Checker {
+    fn error(&mut self, message: String, span: Span) {
+        self.diagnostics.push(Diagnostic::error(message).with_span(span));
+    }
+
+    // Reports an error unless `expr` has the `expected` type, or is an integer
+    // where a real is expected, which is converted. Expressions whose type is
+    // unknown because of an earlier error are not reported again.
+    fn expect_type(&mut self, expr: &Expr, expected: Type, context: &str) {
+        match self.analysis.types.get(&expr.id) {
+            Some(Type::Integer) if expected == Type::Real => {}
+            Some(&actual) if actual != expected => {
+                self.error(format!("{} must be {}, found {}", context, expected.as_str(), actual.as_str()), expr.span)
+            }
+            _ => {}
+        }
+    }
+
+    // Checks statements that can never run, warning about the first unless
+    // they are inside code that was already reported.
+    fn visit_unreachable(&mut self, statements: &[Stmt]) {
+        let Some(first) = statements.first() else { return };
+        if self.unreachable {
+            statements.iter().for_each(|stmt| self.visit_stmt(stmt));
+            return;
+        }
+        self.analysis.warnings.push(Diagnostic::warning("Unreachable code").with_span(first.span));
+        self.unreachable = true;
+        statements.iter().for_each(|stmt| self.visit_stmt(stmt));
+        self.unreachable = false;
+    }
+}
+
+// Whether control never gets past `stmt`.
+fn always_exits(stmt: &Stmt) -> bool {
+    match &stmt.kind {
+        StmtKind::Exit => true,
+        StmtKind::Compound(block) => block.statements.iter().any(always_exits),
+        StmtKind::If { condition, then_branch, else_branch } => {
+            let else_exits = else_branch.as_deref().is_some_and(always_exits);
+            match constant_value(condition) {
+                Some(0) => else_exits,
+                Some(_) => always_exits(then_branch),
+                None => always_exits(then_branch) && else_exits,
+            }
+        }
+        _ => false,
+    }
+}
+
+// The value of an expression made only of literals, such as `false` or
+// `not (1 < 2)`. Booleans are 0 and 1.
+fn constant_value(expr: &Expr) -> Option<i64> {
+    match &expr.kind {
+        ExprKind::Integer(value) => Some(*value),
+        ExprKind::Boolean(value) => Some(*value as i64),
+        ExprKind::Unary { operator, operand } => Some(operator.apply(constant_value(operand)?)),
+        ExprKind::Binary { left, operator, right } => {
+            ir::BinaryOperator::from(*operator).apply(constant_value(left)?, constant_value(right)?)
+        }
+        ExprKind::Real(_) | ExprKind::String(_) | ExprKind::Variable(_) => None,
+    }
+}
+
+impl Visitor for Checker {
+    fn visit_block(&mut self, block: &Block) {
+        match block.statements.iter().position(always_exits) {
+            Some(last) => {
+                block.statements[..=last].iter().for_each(|stmt| self.visit_stmt(stmt));
+                self.visit_unreachable(&block.statements[last + 1..]);
+            }
+            None => block.statements.iter().for_each(|stmt| self.visit_stmt(stmt)),
+        }
+    }
+
+    fn visit_decl(&mut self, decl: &Decl) {
+        for name in &decl.names {
+            if self.analysis.variables.insert(name.clone(), decl.type_name.into()).is_some() {
+                self.error(format!("Variable '{}' is declared more than once", name), decl.span);
+            }
+        }
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Assign { target, value } => {
+                self.visit_expr(value);
+                match self.analysis.variables.get(target) {
+                    Some(&target_type) => {
+                        self.expect_type(value, target_type, &format!("Value assigned to '{}'", target))
+                    }
+                    None => self.error(format!("Undeclared variable '{}'", target), stmt.span),
+                }
+                if self.loop_variables.contains(target) {
+                    self.error(format!("Loop variable '{}' cannot be assigned inside its loop", target), stmt.span);
+                }
+            }
+            StmtKind::Print { items, .. } => {
+                for item in items {
+                    self.visit_print_item(item);
+                }
+            }
+            StmtKind::Compound(block) => self.visit_block(block),
+            StmtKind::If { condition, then_branch, else_branch } => {
+                self.visit_expr(condition);
+                self.expect_type(condition, Type::Boolean, "Condition of 'if'");
+                let value = constant_value(condition);
+                if value == Some(0) {
+                    self.visit_unreachable(std::slice::from_ref(then_branch));
+                } else {
+                    self.visit_stmt(then_branch);
+                }
+                if let Some(else_branch) = else_branch {
+                    if value.is_some_and(|value| value != 0) {
+                        self.visit_unreachable(std::slice::from_ref(else_branch));
+                    } else {
+                        self.visit_stmt(else_branch);
+                    }
+                }
+            }
+            StmtKind::While { condition, body } => {
+                self.visit_expr(condition);
+                self.expect_type(condition, Type::Boolean, "Condition of 'while'");
+                if constant_value(condition) == Some(0) {
+                    self.visit_unreachable(std::slice::from_ref(body));
+                } else {
+                    self.visit_stmt(body);
+                }
+            }
+            StmtKind::For { variable, start, end, body, .. } => {
+                match self.analysis.variables.get(variable) {
+                    Some(Type::Integer) => {}
+                    Some(other) => self.error(
+                        format!("Loop variable '{}' must be integer, found {}", variable, other.as_str()),
+                        stmt.span,
+                    ),
+                    None => self.error(format!("Undeclared variable '{}'", variable), stmt.span),
+                }
+                if self.loop_variables.contains(variable) {
+                    self.error(format!("Loop variable '{}' cannot be assigned inside its loop", variable), stmt.span);
+                }
+                for bound in [start, end] {
+                    self.visit_expr(bound);
+                    self.expect_type(bound, Type::Integer, "Bound of 'for'");
+                }
+                self.loop_variables.push(variable.clone());
+                self.visit_stmt(body);
+                self.loop_variables.pop();
+            }
+            StmtKind::Exit => {}
+        }
+    }
+
+    fn visit_print_item(&mut self, item: &PrintItem) {
+        self.visit_expr(&item.value);
+        if let Some(width) = &item.width {
+            self.visit_expr(width);
+            self.expect_type(width, Type::Integer, "Field width");
+        }
+        if let Some(precision) = &item.precision {
+            self.visit_expr(precision);
+            self.expect_type(precision, Type::Integer, "Precision");
+            match self.analysis.types.get(&item.value.id) {
+                Some(Type::Real) | None => {}
+                Some(other) => self.error(
+                    format!("Precision ':n:m' is only allowed for reals, found {}", other.as_str()),
+                    precision.span,
+                ),
+            }
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        let expr_type = match &expr.kind {
+            ExprKind::Integer(_) => Some(Type::Integer),
+            ExprKind::Real(_) => Some(Type::Real),
+            ExprKind::Boolean(_) => Some(Type::Boolean),
+            ExprKind::String(_) => Some(Type::String),
+            ExprKind::Variable(name) => {
+                let variable_type = self.analysis.variables.get(name).copied();
+                if variable_type.is_none() {
+                    self.error(format!("Undeclared variable '{}'", name), expr.span);
+                }
+                variable_type
+            }
+            ExprKind::Unary { operator, operand } => {
+                self.visit_expr(operand);
+                let operand_type = match operator {
+                    UnaryOperator::Negate if self.analysis.types.get(&operand.id) == Some(&Type::Real) => Type::Real,
+                    UnaryOperator::Negate => Type::Integer,
+                    UnaryOperator::Not => Type::Boolean,
+                };
+                self.expect_type(operand, operand_type, &format!("Operand of '{}'", operator.symbol()));
+                Some(operand_type)
+            }
+            ExprKind::Binary { left, operator, right } => {
+                self.visit_expr(left);
+                self.visit_expr(right);
+                // `=` and `<>` compare two numbers or two booleans. Arithmetic
+                // and comparisons are on reals when either operand is real,
+                // and the other is converted if it is an integer; div, mod
+                // and rem only take integers.
+                let is = |expr: &Expr, expected| self.analysis.types.get(&expr.id) == Some(&expected);
+                let operand_type = match operator {
+                    BinaryOperator::And | BinaryOperator::Or => Type::Boolean,
+                    BinaryOperator::Equal | BinaryOperator::NotEqual if is(left, Type::Boolean) => Type::Boolean,
+                    BinaryOperator::Div | BinaryOperator::Mod | BinaryOperator::Rem => Type::Integer,
+                    _ if is(left, Type::Real) || is(right, Type::Real) => Type::Real,
+                    _ => Type::Integer,
+                };
+                let context = format!("Operand of '{}'", operator.symbol());
+                self.expect_type(left, operand_type, &context);
+                self.expect_type(right, operand_type, &context);
+                if operator.is_comparison() {
+                    Some(Type::Boolean)
+                } else {
+                    Some(operand_type)
+                }
+            }
+        };
+        if let Some(expr_type) = expr_type {
+            self.analysis.types.insert(expr.id, expr_type);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize_spanned;
+    use crate::parser::Parser;
+
+    fn check(source: &str) -> Result<Analysis, Vec<String>> {
+        let program = Parser::from_spanned(tokenize_spanned(source).unwrap()).parse().unwrap();
+        analyze(&program).map_err(|diagnostics| diagnostics.iter().map(|d| d.to_string()).collect())
+    }
+
+    #[test]
+    fn test_analyze_records_expression_types() {
+        let analysis =
+            check("var n: int64; ok: boolean; begin n := 1 + n; ok := true; writeln('x', n:3, ok); end").unwrap();
+
+        assert_eq!(analysis.variables["n"], Type::Integer);
+        assert_eq!(analysis.variables["ok"], Type::Boolean);
+        let count = |expected: Type| analysis.types.values().filter(|&&t| t == expected).count();
+        assert_eq!(count(Type::Integer), 5); // 1, n, 1 + n, n and 3
+        assert_eq!(count(Type::Boolean), 2); // true and ok
+        assert_eq!(count(Type::String), 1); // 'x'
+    }
+
+    #[test]
+    fn test_analyze_reports_every_error() {
+        let errors = check(
+            "var a: integer; a, b: boolean;\nbegin\n  c := 1;\n  a := true;\n  b := a + b;\n  writeln(d:true, a:1:2);\nend",
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            errors,
+            [
+                "1:17: error: Variable 'a' is declared more than once",
+                "3:3: error: Undeclared variable 'c'",
+                "5:8: error: Operand of '+' must be integer, found boolean",
+                "5:12: error: Operand of '+' must be integer, found boolean",
+                "5:8: error: Value assigned to 'b' must be boolean, found integer",
+                "6:11: error: Undeclared variable 'd'",
+                "6:13: error: Field width must be integer, found boolean",
+                "6:23: error: Precision ':n:m' is only allowed for reals, found boolean",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_converts_integers_to_reals() {
+        let analysis =
+            check("var x: real; n: integer; begin x := n; x := n / 2 + x; writeln(-x:8:n, x > 1); end").unwrap();
+        assert_eq!(analysis.variables["x"], Type::Real);
+
+        let errors = check(
+            "var x: real; n: integer;\nbegin\n  n := x;\n  n := x div 2;\n  writeln(n:8:2, x:1:true, not x);\nend",
+        )
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            [
+                "3:8: error: Value assigned to 'n' must be integer, found real",
+                "4:8: error: Operand of 'div' must be integer, found real",
+                "5:15: error: Precision ':n:m' is only allowed for reals, found integer",
+                "5:22: error: Precision must be integer, found boolean",
+                "5:32: error: Operand of 'not' must be boolean, found real",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_checks_conditions_and_loops() {
+        let errors = check(
+            "var i: integer; b: boolean;\nbegin\n  if i then b := not i;\n  while b = 1 do b := b and (i < 2);\n  \
+             for b := 1 to true do i := 0;\n  for i := 1 to 2 do for i := 3 to 4 do i := 5;\nend",
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            errors,
+            [
+                "3:6: error: Condition of 'if' must be boolean, found integer",
+                "3:22: error: Operand of 'not' must be boolean, found integer",
+                "4:13: error: Operand of '=' must be boolean, found integer",
+                "5:3: error: Loop variable 'b' must be integer, found boolean",
+                "5:17: error: Bound of 'for' must be integer, found boolean",
+                "6:22: error: Loop variable 'i' cannot be assigned inside its loop",
+                "6:41: error: Loop variable 'i' cannot be assigned inside its loop",
+            ]
+        );
+        assert!(check("var i: integer; ok: boolean; begin ok := (i = 1) <> not ok; end").is_ok());
+    }
+
+    #[test]
+    fn test_analyze_warns_about_unreachable_code() {
+        let analysis = check(
+            "var i: integer;\nbegin\n  if false then i := 1;\n  while not (1 < 2) do i := 2;\n  \
+             if i > 0 then i := 0 else begin i := 3; exit; i := 4; end;\n  if true then i := 5 else i := 6;\n  \
+             if i = 7 then exit;\n  if i > 0 then exit else begin exit; end;\n  i := 8;\n  exit;\nend",
+        )
+        .unwrap();
+
+        let warnings: Vec<String> = analysis.warnings.iter().map(|warning| warning.to_string()).collect();
+        assert_eq!(
+            warnings,
+            [
+                "3:17: warning: Unreachable code",
+                "4:24: warning: Unreachable code",
+                "5:49: warning: Unreachable code",
+                "6:28: warning: Unreachable code",
+                "9:3: warning: Unreachable code", // Both branches exit; reported once for the rest
+            ]
+        );
+    }
+}
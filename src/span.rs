@@ -0,0 +1,19 @@
+/// A region of the source text. `start` and `end` are byte offsets, `line` and
+/// `column` (both starting at 1) locate `start` for messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        if other.start < self.start {
+            return other.to(self);
+        }
+        Span { start: self.start, end: other.end.max(self.end), line: self.line, column: self.column }
+    }
+}
@@ -0,0 +1,221 @@
+//! Helpers shared by the tests that lower source to IR or run compiled
+//! programs.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::ir::Program;
+use crate::lexer::tokenize_spanned;
+use crate::lower::lower;
+use crate::parser::Parser;
+use crate::semantic::analyze;
+use crate::toolchain::{assemble, link, write_executable, write_object, ToolError};
+use crate::{compile, Options, Target};
+
+static PROGRAM_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// A program using every statement and print routine, division of every sign,
+// wrapping arithmetic and names that are keywords in C, which every target
+// must run with the output in CONFORMANCE_OUTPUT.
+pub const CONFORMANCE_PROGRAM: &str = r#"
+    var
+        i, n, sum, static: integer;
+        big: int64;
+        seen: boolean;
+    begin
+        for i := 1 to 3 do n := n + i * 5;
+        while sum < 100 do begin
+            sum := sum + n;
+            if sum mod 7 = 4 then seen := true;
+        end;
+        big := 3_000_000_000 * n;
+        writeln('n=', n, ' sum=', sum:6, ' ', seen, not seen:6, ' ', -big, ' ', -big div 7, ' ', n - 31 rem 4);
+        for i := -4 to 4 do write(i mod -3, ' ', i rem 3, ' ', i div -3, ' ', i >= 1, ';');
+        writeln;
+        static := 9223372036854775807;
+        writeln(static, ' ', static + 1, '|', 'x':20, '|', '"50%"\', 'y':-3, 7:static - static - 1, '|', 'z':45);
+        for i := 9223372036854775806 to 9223372036854775807 do write(i, ' ');
+        writeln;
+        write('It''s'#9'done');
+        writeln;
+        exit;
+        writeln('not reached');
+    end
+"#;
+
+pub const CONFORMANCE_OUTPUT: &str = concat!(
+    "n=30 sum=   120 TRUE FALSE -90000000000 -12857142857 27\n",
+    "-1 -1 1 FALSE;0 0 1 FALSE;-2 -2 0 FALSE;-1 -1 0 FALSE;0 0 0 FALSE;",
+    "-2 1 0 TRUE;-1 2 0 TRUE;0 0 -1 TRUE;-2 1 -1 TRUE;\n",
+    "9223372036854775807 -9223372036854775808|                   x|\"50%\"\\y7|",
+    "                                            z\n",
+    "9223372036854775806 9223372036854775807 \n",
+    "It's\tdone\n",
+);
+
+// A program using reals with and without a width and precision, integers
+// converted to reals, NaN, the infinities and -0.0, which every target must
+// run with the output in REAL_OUTPUT.
+pub const REAL_PROGRAM: &str = r#"
+    var x, y, z: real; i: integer;
+    begin
+        x := 1.5; y := 2; i := 7;
+        writeln(x, ' ', y, ' ', x + y, ' ', x * i, ' ', i / 2, ' ', i / 2.0, ' ', -x, ' ', x / 3);
+        writeln(x:8:2, '|', 2.675:0:2, '|', 0.1 + 0.2, '|', 1e20, '|', 123456789.987654321:0:9, '|', -x:i:i - 8);
+        z := 1e300 * 1e10;
+        writeln(z, ' ', -z, ' ', z - z, ' ', -0.0, ' ', 0.0, ' ', z:5, '|', z - z:i:2);
+        writeln(x / 3:0:18, ' ', 0.9999999:0:3, ' ', 9.5:0:0, ' ', 5e-7, '|', 4.9999995e-7:0:6);
+        writeln(x < y, x > y, x = 1.5, x <> 1.5, x <= i, x >= 2, z - z <> z - z, z - z = z - z);
+    end
+"#;
+
+pub const REAL_OUTPUT: &str = concat!(
+    "1.5 2.0 3.5 10.5 3 3.5 -1.5 0.5\n",
+    "    1.50|2.67|0.3|100000000000000000000.0|123456789.987654328|   -1.5\n",
+    "Inf -Inf NaN -0.0 0.0   Inf|    NaN\n",
+    "0.500000000000000000 1.000 10 0.000001|0.000000\n",
+    "TRUEFALSETRUEFALSETRUEFALSETRUEFALSE\n",
+);
+
+// Divides a real by zero, which ends the program with exit status 1 after the
+// first line.
+pub const REAL_DIVISION_BY_ZERO: &str = "var x, y: real; begin x := 1; writeln('a'); writeln(x / y); end";
+
+// Parses, analyzes and lowers `source` to IR, without optimising it.
+pub fn lower_source(source: &str) -> Program {
+    let program = Parser::from_spanned(tokenize_spanned(source).unwrap()).parse().unwrap();
+    lower(&program, &analyze(&program).unwrap())
+}
+
+// Compiles, assembles and runs `source`, returning what it wrote to stdout.
+// When the assembler or ld is not installed, the program is built with the
+// built-in encoder instead, so the x86-64 tests always run on an x86-64 host.
+pub fn run_program(source: &str) -> Vec<u8> {
+    run_with_options(source, &Options::default())
+}
+
+// Code for other targets is assembled with their tools and, for other
+// machines than the one running the tests, run under qemu-user, or wasmtime
+// for WebAssembly. The tests that need those are #[ignore]d, and fail rather
+// than pass when run with --ignored where a tool is missing.
+pub fn run_with_options(source: &str, options: &Options) -> Vec<u8> {
+    successful_stdout(build_and_run(source, options))
+}
+
+// Like run_with_options, but returns how the program exited, for the tests
+// of programs that stop with a run-time error.
+pub fn exit_status(source: &str, options: &Options) -> ExitStatus {
+    build_and_run(source, options).status
+}
+
+// Like run_with_options, but always builds with the assembler and linker,
+// for comparing them with the built-in encoder.
+pub fn run_with_tools(source: &str, options: &Options) -> Vec<u8> {
+    let base = temp_path();
+    build_with_tools(source, options, &base).unwrap_or_else(|tool| panic!("{}", missing(&tool)));
+    successful_stdout(run_on(options.target, &base))
+}
+
+fn build_and_run(source: &str, options: &Options) -> Output {
+    let base = temp_path();
+    match build_with_tools(source, options, &base) {
+        Ok(()) => run_on(options.target, &base),
+        Err(_) if options.target == Target::X86_64Linux && std::env::consts::ARCH == "x86_64" => {
+            write_executable(&compile(source, options).unwrap().code.unwrap(), &base).unwrap();
+            run_executable(&base)
+        }
+        Err(tool) => panic!("{}", missing(&tool)),
+    }
+}
+
+// Assembles and links `source` into an executable at `base`, or returns the
+// name of the tool that is not installed.
+fn build_with_tools(source: &str, options: &Options, base: &Path) -> Result<(), String> {
+    let asm = compile(source, options).unwrap().assembly;
+    let obj_path = base.with_extension("o");
+    let target = options.target;
+    let result = assemble(&asm, target, options.asm_syntax, &obj_path).and_then(|()| link(&obj_path, target, base));
+    let _ = std::fs::remove_file(&obj_path);
+    match result {
+        Ok(()) => Ok(()),
+        Err(ToolError::NotFound(tool)) => Err(tool),
+        Err(error) => panic!("{}\n{}", error, asm),
+    }
+}
+
+// The failure message for a tool that a test needs and is not installed.
+fn missing(tool: &str) -> String {
+    format!(
+        "{} not found; the tests that need it are #[ignore]d, run them with --ignored only where it is installed",
+        tool
+    )
+}
+
+// Builds `source` into an executable with the built-in encoder and ELF
+// writer, which needs no tools, and runs it.
+pub fn run_builtin(source: &str, options: &Options) -> Vec<u8> {
+    let base = temp_path();
+    write_executable(&compile(source, options).unwrap().code.unwrap(), &base).unwrap();
+    successful_stdout(run_executable(&base))
+}
+
+// Like run_builtin, but returns how the program exited.
+pub fn run_builtin_status(source: &str, options: &Options) -> ExitStatus {
+    let base = temp_path();
+    write_executable(&compile(source, options).unwrap().code.unwrap(), &base).unwrap();
+    run_executable(&base).status
+}
+
+// Like run_builtin, but writes an object file and links it with ld.
+pub fn run_builtin_object(source: &str, options: &Options) -> Vec<u8> {
+    let base = temp_path();
+    let obj_path = base.with_extension("o");
+    write_object(&compile(source, options).unwrap().code.unwrap(), &obj_path).unwrap();
+    let result = link(&obj_path, Target::X86_64Linux, &base);
+    let _ = std::fs::remove_file(&obj_path);
+    match result {
+        Ok(()) => successful_stdout(run_executable(&base)),
+        Err(ToolError::NotFound(tool)) => panic!("{}", missing(&tool)),
+        Err(error) => panic!("{}", error),
+    }
+}
+
+fn temp_path() -> PathBuf {
+    let id = PROGRAM_COUNTER.fetch_add(1, Ordering::SeqCst);
+    std::env::temp_dir().join(format!("ccc_test_{}_{}", std::process::id(), id))
+}
+
+// Runs the executable for `target` at `path` directly or, when it is for
+// another machine, with qemu-user or wasmtime.
+fn run_on(target: Target, path: &Path) -> Output {
+    let (architecture, runner) = match target {
+        Target::C => return run_executable(path),
+        Target::X86_64Linux => (Some("x86_64"), "qemu-x86_64"),
+        Target::Aarch64Linux => (Some("aarch64"), "qemu-aarch64"),
+        Target::Riscv64Linux => (Some("riscv64"), "qemu-riscv64"),
+        Target::Wasm => (None, "wasmtime"),
+    };
+    if architecture == Some(std::env::consts::ARCH) {
+        return run_executable(path);
+    }
+    let output = Command::new(runner).arg(path).output();
+    let _ = std::fs::remove_file(path);
+    match output {
+        Ok(output) => output,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => panic!("{}", missing(runner)),
+        Err(error) => panic!("cannot run {}: {}", runner, error),
+    }
+}
+
+// Runs the executable at `path` and deletes it.
+fn run_executable(path: &Path) -> Output {
+    let output = Command::new(path).output().unwrap();
+    let _ = std::fs::remove_file(path);
+    output
+}
+
+fn successful_stdout(output: Output) -> Vec<u8> {
+    assert!(output.status.success(), "program exited with {}", output.status);
+    output.stdout
+}
@@ -0,0 +1,125 @@
+//! Builds object files and executables from generated code, with the
+//! built-in encoder or by running the external assembler and linker.
+
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+
+use crate::asm::encode::encode;
+use crate::asm::{Assembly, Syntax};
+use crate::elf;
+use crate::Target;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolError {
+    /// The tool is not installed.
+    NotFound(String),
+    /// The tool ran but reported an error.
+    Failed(String),
+}
+
+impl fmt::Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolError::NotFound(tool) => write!(f, "{} not found, is it installed?", tool),
+            ToolError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ToolError {}
+
+/// Assembles `assembly` into the ELF64 object file `object`. x86-64 code is
+/// assembled with nasm or with GNU as depending on its `syntax`, code for
+/// other targets with their GNU as, and C source is compiled with cc. A
+/// WebAssembly text module becomes a binary module, with wat2wasm.
+pub fn assemble(assembly: &str, target: Target, syntax: Syntax, object: &Path) -> Result<(), ToolError> {
+    let nasm = target == Target::X86_64Linux && syntax == Syntax::Nasm;
+    let source = object.with_extension(match target {
+        _ if nasm => "asm",
+        Target::C => "c",
+        Target::Wasm => "wat",
+        _ => "s",
+    });
+    std::fs::write(&source, assembly)
+        .map_err(|error| ToolError::Failed(format!("cannot write {}: {}", source.display(), error)))?;
+    let result = match target {
+        Target::X86_64Linux if nasm => {
+            run(Command::new("nasm").arg("-f").arg("elf64").arg("-o").arg(object).arg(&source))
+        }
+        Target::X86_64Linux => run(Command::new("as").arg("--64").arg("-o").arg(object).arg(&source)),
+        Target::Aarch64Linux => run(Command::new(tool(target, "as")).arg("-o").arg(object).arg(&source)),
+        // The code keeps to RV64IMFD, using doubles for reals but no compressed instructions
+        Target::Riscv64Linux => {
+            run(Command::new(tool(target, "as")).args(["-march=rv64imfd", "-mabi=lp64", "-o"]).arg(object).arg(&source))
+        }
+        Target::C => run(Command::new("cc").args(["-std=c99", "-c", "-o"]).arg(object).arg(&source)),
+        Target::Wasm => run(Command::new("wat2wasm").arg(&source).arg("-o").arg(object)),
+    };
+    let _ = std::fs::remove_file(&source);
+    result
+}
+
+/// Links the object file into a static executable. The program has its own
+/// `_start` and makes system calls directly, so no C library is needed.
+/// Compiled C is the exception, and is linked by cc with the C library. A
+/// WebAssembly module is complete already and is only renamed.
+pub fn link(object: &Path, target: Target, executable: &Path) -> Result<(), ToolError> {
+    if target == Target::Wasm {
+        return std::fs::rename(object, executable)
+            .map_err(|error| ToolError::Failed(format!("cannot write {}: {}", executable.display(), error)));
+    }
+    let linker = if target == Target::C { "cc".to_string() } else { tool(target, "ld") };
+    run(Command::new(linker).arg("-o").arg(executable).arg(object))
+}
+
+// The name of a binutils program for `target`: the plain one when building
+// for the machine the compiler runs on, otherwise the cross version.
+fn tool(target: Target, name: &str) -> String {
+    let (architecture, prefix) = match target {
+        Target::X86_64Linux | Target::C | Target::Wasm => return name.to_string(),
+        Target::Aarch64Linux => ("aarch64", "aarch64-linux-gnu-"),
+        Target::Riscv64Linux => ("riscv64", "riscv64-linux-gnu-"),
+    };
+    if std::env::consts::ARCH == architecture {
+        name.to_string()
+    } else {
+        format!("{}{}", prefix, name)
+    }
+}
+
+/// Writes `code` as the ELF64 object file `object` without an assembler.
+pub fn write_object(code: &Assembly, object: &Path) -> Result<(), ToolError> {
+    write(object, &elf::object(&encode(code)))
+}
+
+/// Writes `code` as a static executable without an assembler or linker.
+pub fn write_executable(code: &Assembly, executable: &Path) -> Result<(), ToolError> {
+    write(executable, &elf::executable(&encode(code)))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(executable, std::fs::Permissions::from_mode(0o755)).map_err(|error| {
+            ToolError::Failed(format!("cannot make {} executable: {}", executable.display(), error))
+        })?;
+    }
+    Ok(())
+}
+
+fn write(path: &Path, bytes: &[u8]) -> Result<(), ToolError> {
+    std::fs::write(path, bytes)
+        .map_err(|error| ToolError::Failed(format!("cannot write {}: {}", path.display(), error)))
+}
+
+fn run(command: &mut Command) -> Result<(), ToolError> {
+    let tool = command.get_program().to_string_lossy().into_owned();
+    let output = command.output().map_err(|error| match error.kind() {
+        std::io::ErrorKind::NotFound => ToolError::NotFound(tool.clone()),
+        _ => ToolError::Failed(format!("cannot run {}: {}", tool, error)),
+    })?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(ToolError::Failed(format!("{} failed:\n{}", tool, String::from_utf8_lossy(&output.stderr))))
+    }
+}
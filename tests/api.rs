@@ -0,0 +1,42 @@
+use ccc::ast::StmtKind;
+use ccc::lexer::Token;
+use ccc::{compile, Options, Severity};
+
+#[test]
+fn test_compile_exposes_every_stage() {
+    let source = std::fs::read_to_string("examples/sum.pas").unwrap();
+
+    let output = compile(&source, &Options::default()).unwrap();
+
+    assert_eq!(output.tokens.first().unwrap().token, Token::Keyword("var".to_string()));
+    assert_eq!(output.tokens.last().unwrap().token, Token::EndOfInput);
+    let last = output.ast.body.statements.last().unwrap();
+    assert!(matches!(last.kind, StmtKind::Print { newline: true, .. }));
+    assert_eq!(last.span.line, 9);
+    assert!(output.assembly.contains("global _start"));
+    assert!(output.warnings.is_empty());
+}
+
+#[test]
+fn test_compile_returns_warnings_with_the_output() {
+    let output = compile("var x: integer; begin exit; x := 1; end", &Options::default()).unwrap();
+
+    assert_eq!(output.warnings.len(), 1);
+    assert_eq!(output.warnings[0].severity, Severity::Warning);
+    assert_eq!(output.warnings[0].to_string(), "1:29: warning: Unreachable code");
+}
+
+#[test]
+fn test_compile_reports_errors_as_diagnostics() {
+    for source in [
+        "var x: integer; begin x := 'open; end",
+        "var x integer; begin end",
+        "var x: integer; begin writeln(x:1:2); end",
+    ] {
+        let diagnostics = compile(source, &Options::default()).unwrap_err();
+        assert_eq!(diagnostics.len(), 1, "{}", source);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert!(diagnostics[0].span.is_some(), "{}", source);
+        assert!(diagnostics[0].to_string().starts_with("1:"), "{}", diagnostics[0]);
+    }
+}
@@ -0,0 +1,119 @@
+use std::process::{Command, Output};
+
+fn ccc(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ccc")).args(args).output().unwrap()
+}
+
+#[test]
+fn test_emit_stages() {
+    let tokens = ccc(&["--emit=tokens", "examples/sum.pas"]);
+    assert!(tokens.status.success());
+    assert!(String::from_utf8(tokens.stdout).unwrap().starts_with("1:1 Keyword(\"var\")\n"));
+
+    let ast = ccc(&["--emit=ast", "examples/sum.pas"]);
+    assert!(String::from_utf8(ast.stdout).unwrap().starts_with("Program\n  Decl a, b: integer\n"));
+
+    let json = ccc(&["--emit=ast-json", "examples/sum.pas"]);
+    assert!(String::from_utf8(json.stdout).unwrap().starts_with("{\n  \"node\": \"Program\",\n"));
+
+    let ir = ccc(&["--emit=ir", "examples/sum.pas"]);
+    assert!(String::from_utf8(ir.stdout)
+        .unwrap()
+        .starts_with("var a, b, result\nstr0 = \"The result is: \"\n\nblock0:\n"));
+
+    let optimised = ccc(&["-O1", "--emit=ir", "examples/sum.pas"]);
+    assert!(optimised.status.success());
+
+    let asm = ccc(&["examples/sum.pas"]);
+    assert!(String::from_utf8(asm.stdout).unwrap().contains("global _start"));
+
+    let gas = ccc(&["--asm-syntax=gas", "examples/sum.pas"]);
+    assert!(String::from_utf8(gas.stdout).unwrap().contains(".globl _start\n"));
+
+    let aarch64 = ccc(&["--target=aarch64-linux", "examples/sum.pas"]);
+    assert!(String::from_utf8(aarch64.stdout).unwrap().contains("mov x8, #93\nsvc #0\n"));
+
+    let riscv64 = ccc(&["--target=riscv64-linux", "examples/sum.pas"]);
+    assert!(String::from_utf8(riscv64.stdout).unwrap().contains("li a7, 93\necall\n"));
+
+    let c = ccc(&["--target=c", "examples/sum.pas"]);
+    assert!(String::from_utf8(c.stdout).unwrap().contains("int main(void) {\n#line 2 \"examples/sum.pas\"\n"));
+
+    let wasm = ccc(&["--target=wasm", "examples/sum.pas"]);
+    assert!(String::from_utf8(wasm.stdout).unwrap().contains("(func $main (export \"_start\")\n"));
+}
+
+#[test]
+fn test_builtin_executable_runs() {
+    let path = std::env::temp_dir().join(format!("ccc-exe-{}", std::process::id()));
+    let path_arg = path.to_str().unwrap();
+
+    assert!(ccc(&["-O1", "--emit=exe", "-o", path_arg, "examples/sum.pas"]).status.success());
+    let output = Command::new(&path).output().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "The result is: 30\n");
+}
+
+#[test]
+fn test_invalid_arguments_and_programs_fail() {
+    let unknown = ccc(&["--emit=nothing", "examples/sum.pas"]);
+    assert!(!unknown.status.success());
+    assert!(String::from_utf8(unknown.stderr).unwrap().contains("usage: ccc"));
+
+    let level = ccc(&["-O3", "examples/sum.pas"]);
+    assert!(String::from_utf8(level.stderr).unwrap().contains("unknown optimisation level '-O3'"));
+
+    let syntax = ccc(&["--asm-syntax=masm", "examples/sum.pas"]);
+    assert!(String::from_utf8(syntax.stderr).unwrap().contains("unknown assembler syntax 'masm'"));
+
+    let assembler = ccc(&["--assembler=yasm", "--emit=exe", "examples/sum.pas"]);
+    assert!(String::from_utf8(assembler.stderr).unwrap().contains("unknown assembler 'yasm'"));
+
+    let target = ccc(&["--target=mips-linux", "examples/sum.pas"]);
+    assert!(String::from_utf8(target.stderr).unwrap().contains("unknown target 'mips-linux'"));
+
+    let builtin = ccc(&["--target=aarch64-linux", "--assembler=builtin", "--emit=exe", "examples/sum.pas"]);
+    assert!(String::from_utf8(builtin.stderr).unwrap().contains("the builtin assembler only supports x86_64-linux"));
+
+    let missing = ccc(&["does/not/exist.pas"]);
+    assert!(!missing.status.success());
+}
+
+#[test]
+fn test_fmt_check_and_rewrite() {
+    let clean = ccc(&["fmt", "--check", "examples/sum.pas"]);
+    assert!(clean.status.success(), "{}", String::from_utf8_lossy(&clean.stdout));
+
+    let path = std::env::temp_dir().join(format!("ccc-fmt-{}.pas", std::process::id()));
+    let source = "VAR x:integer;BEGIN x:=1 { one };\nIF x>0 THEN writeln('a', x:3) -- note\nELSE BEGIN x:=2; END;\n\
+                  -- next\nx:=3;END";
+    std::fs::write(&path, source).unwrap();
+    let path_arg = path.to_str().unwrap();
+
+    let dirty = ccc(&["fmt", "--check", path_arg]);
+    assert!(!dirty.status.success());
+    assert_eq!(String::from_utf8(dirty.stdout).unwrap(), format!("{}\n", path_arg));
+
+    assert!(ccc(&["fmt", path_arg]).status.success());
+    let formatted = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        formatted,
+        "var\n    x: integer;\n\nbegin\n    x := 1; { one }\n    if x > 0 then\n        writeln('a', x:3) -- note\n    \
+         else begin\n        x := 2;\n    end;\n    -- next\n    x := 3;\nend\n"
+    );
+}
+
+#[test]
+fn test_warnings_do_not_stop_compilation() {
+    let path = std::env::temp_dir().join(format!("ccc-warn-{}.pas", std::process::id()));
+    std::fs::write(&path, "var x: integer;\nbegin\n    if false then\n        x := 1;\nend\n").unwrap();
+    let path_arg = path.to_str().unwrap();
+
+    let output = ccc(&["-O1", path_arg]);
+    std::fs::remove_file(&path).unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), format!("{}:4:9: warning: Unreachable code\n", path_arg));
+    // x is never read, so it is not given any storage
+    assert!(!String::from_utf8(output.stdout).unwrap().contains("var_x dq 0"));
+}